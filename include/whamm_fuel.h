@@ -0,0 +1,78 @@
+/*
+ * The C API of whamm-fuel (the `ffi` feature), see `src/ffi.rs`.
+ *
+ *     WhammFuelConfig config = whamm_fuel_default_config();
+ *     config.init_fuel = 1000;
+ *     WhammFuelOutput *out = whamm_fuel_analyze_with(wasm, wasm_len, &config);
+ *     if (!out) { fprintf(stderr, "%s\n", whamm_fuel_last_error()); return 1; }
+ *     const uint8_t *max; size_t max_len;
+ *     if (whamm_fuel_wasm(out, WHAMM_FUEL_MAX, &max, &max_len) != WHAMM_FUEL_OK) { ... }
+ *     puts(whamm_fuel_report(out));
+ *     whamm_fuel_free(out);
+ */
+#ifndef WHAMM_FUEL_H
+#define WHAMM_FUEL_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define WHAMM_FUEL_MAX 0
+#define WHAMM_FUEL_MIN 1
+
+#define WHAMM_FUEL_EXACT 0
+#define WHAMM_FUEL_APPROX 1
+#define WHAMM_FUEL_EPOCH 2
+#define WHAMM_FUEL_BACK_EDGE 3
+
+#define WHAMM_FUEL_OK 0
+#define WHAMM_FUEL_ERR_NULL (-1)
+#define WHAMM_FUEL_ERR_VARIANT (-2)
+
+/* The options of the analysis, start from whamm_fuel_default_config() */
+typedef struct WhammFuelConfig {
+    /* WHAMM_FUEL_EXACT, WHAMM_FUEL_APPROX, WHAMM_FUEL_EPOCH or WHAMM_FUEL_BACK_EDGE */
+    uint32_t comp_type;
+    /* The budget the generated functions count down from, negative to count up from 0 */
+    int64_t init_fuel;
+    /* Call the imported env.on_out_of_fuel() when a charge exhausts the fuel, needs an init_fuel */
+    bool on_out_of_fuel;
+    /* Round every charge up to a multiple of it, 0 or 1 for the exact cost */
+    uint64_t granularity;
+    bool export_static_costs;
+    bool keep_going;
+    bool strict;
+} WhammFuelConfig;
+
+/* The results of an analysis, the buffers it hands out live until it's freed */
+typedef struct WhammFuelOutput WhammFuelOutput;
+
+WhammFuelConfig whamm_fuel_default_config(void);
+
+/* Analyze the module in wasm[0..len] with the default config, NULL if it fails (see whamm_fuel_last_error) */
+WhammFuelOutput *whamm_fuel_analyze(const uint8_t *wasm, size_t len);
+
+/* Analyze the module in wasm[0..len] with the config (the default one if it's NULL), NULL if it fails */
+WhammFuelOutput *whamm_fuel_analyze_with(const uint8_t *wasm, size_t len, const WhammFuelConfig *config);
+
+/* The generated module of a variant (WHAMM_FUEL_MAX or WHAMM_FUEL_MIN): WHAMM_FUEL_OK, or
+ * WHAMM_FUEL_ERR_NULL / WHAMM_FUEL_ERR_VARIANT (see whamm_fuel_last_error) */
+int32_t whamm_fuel_wasm(const WhammFuelOutput *output, uint32_t variant, const uint8_t **data, size_t *len);
+
+/* The JSON report (as written by --report-json), NULL if the output is NULL */
+const char *whamm_fuel_report(const WhammFuelOutput *output);
+
+void whamm_fuel_free(WhammFuelOutput *output);
+
+/* Why the last call on this thread failed, NULL if it didn't */
+const char *whamm_fuel_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* WHAMM_FUEL_H */
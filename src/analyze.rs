@@ -1,12 +1,15 @@
+use std::collections::BTreeMap;
 use wirm::ir::id::{FunctionID, TypeID};
 use wirm::ir::module::module_types::Types;
-use wirm::iterator::module_iterator::ModuleIterator;
-use wirm::{Location, Module};
-use wirm::iterator::iterator_trait::Iterator;
-use wirm::wasmparser::Operator;
-use crate::utils::stack_effects;
-
-#[derive(Debug, Default, Clone)]
+use wirm::Module;
+use wirm::wasmparser::{Catch, Operator};
+use crate::error::{fail, AnalysisError};
+use crate::purity::{func_effects, Effects};
+use crate::utils::{is_tail_call, local_func, local_ops, stack_effects, try_stack_effects};
+
+/// Where a value that an instruction consumes comes from, as tracked through the operand stack and
+/// the locals (see `InstrInfo::inputs`)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub enum Origin {
     /// Value produced by an instruction index
     Instr {
@@ -17,23 +20,39 @@ pub enum Origin {
         instr_idx: usize,
         gid: u32,
     },
-    /// Function parameter (index)
+    /// Function parameter (index), or a local read in a loop that was set before the loop
     Param {
         instr_idx: usize,
         lid: u32,
     },
-    /// Memory load at instruction index
+    /// Memory load at instruction index (from the memory at `memory`)
     Load {
-        instr_idx: usize
+        instr_idx: usize,
+        memory: u32
     },
     /// Direct call at instruction index
     Call {
         result_idx: usize,
         instr_idx: usize
     },
-    /// Indirect call at instruction index
+    /// Indirect call at instruction index (through the table at `table_index`)
     CallIndirect {
         result_idx: usize,
+        instr_idx: usize,
+        table_index: u32
+    },
+    /// Call through a typed function reference (`call_ref`) at instruction index
+    CallRef {
+        result_idx: usize,
+        instr_idx: usize
+    },
+    /// Reference read from the table at `table_index` (`table.get`) at instruction index
+    TableGet {
+        instr_idx: usize,
+        table_index: u32
+    },
+    /// Field of a struct or element of an array (`struct.get*`, `array.get*`, `array.len`) read at instruction index
+    FieldLoad {
         instr_idx: usize
     },
 
@@ -41,35 +60,133 @@ pub enum Origin {
     #[default]
     Untracked
 }
+impl Origin {
+    /// The instruction that produced the value (if it's tracked)
+    pub fn instr_idx(&self) -> Option<usize> {
+        match self {
+            Origin::Instr { instr_idx } | Origin::Global { instr_idx, .. } | Origin::Param { instr_idx, .. } |
+            Origin::Load { instr_idx, .. } | Origin::Call { instr_idx, .. } | Origin::CallIndirect { instr_idx, .. } |
+            Origin::CallRef { instr_idx, .. } | Origin::TableGet { instr_idx, .. } | Origin::FieldLoad { instr_idx } => Some(*instr_idx),
+            Origin::Untracked => None
+        }
+    }
+}
 
 /// Operator we care about for slicing & identification.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpKind {
-    Control,      // br_if, if, br_table, br, select (select we treat specially)
+    /// Decides the control flow (the sinks of the slices): the conditional branches (`br_if`,
+    /// `br_table`, `br_on_*`), `if` and `return`. Their inputs are only the condition.
+    Control,
+    /// Any other op
     Other,
 }
 
 /// Record for each instruction we saw.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct InstrInfo {
     pub(crate) kind: OpKind,
     /// immediate origins used as inputs by this instruction (in order popped)
     pub(crate) inputs: Vec<Origin>
 }
+impl InstrInfo {
+    /// Whether the instruction is a control-flow sink of the slices
+    pub fn kind(&self) -> &OpKind {
+        &self.kind
+    }
+
+    /// Where the values that the instruction consumes come from
+    pub fn inputs(&self) -> &[Origin] {
+        &self.inputs
+    }
+}
+
+/// Hooks into `analyze_with`: called as the analysis simulates the operand stack, so that other
+/// tools (e.g. a coverage or security auditor) can reuse the taint of each instruction.
+pub trait AnalysisVisitor {
+    /// The instruction at `instr_idx` of the function `fid` was analyzed
+    fn visit_instr(&mut self, fid: u32, instr_idx: usize, op: &Operator, info: &InstrInfo);
+
+    /// The analysis of the function is done (`func.fallback` is set if it stopped at an op that it can't model)
+    fn visit_func(&mut self, _func: &FuncState) {}
+}
+
+/// The visitor of `analyze`, it doesn't do anything
+pub(crate) struct NoVisitor;
+impl AnalysisVisitor for NoVisitor {
+    fn visit_instr(&mut self, _fid: u32, _instr_idx: usize, _op: &Operator, _info: &InstrInfo) {}
+}
 
+/// The taint of a local function: the `InstrInfo` of each instruction of its body, by instr_idx
 pub struct FuncState {
     pub(crate) fid: u32,
     pub(crate) total_params: usize,
     pub(crate) instrs: Vec<InstrInfo>,         // information about instrs (used to create the slice)
+    /// The op that the analysis can't model (the function falls back to its worst-case cost),
+    /// `instrs` stops right before it.
+    pub(crate) fallback: Option<String>,
+    /// The side effects of the function (and its callees)
+    pub(crate) effects: Effects,
 }
 impl FuncState {
-    fn new(taint_state: FuncTaint) -> Self {
+    fn new(taint_state: FuncTaint, effects: &BTreeMap<u32, Effects>) -> Self {
         Self {
             fid: taint_state.fid,
             total_params: taint_state.total_params,
-            instrs: taint_state.instrs
+            instrs: taint_state.instrs,
+            fallback: taint_state.fallback,
+            effects: effects.get(&taint_state.fid).copied().unwrap_or_default()
         }
     }
+
+    /// The ID of the analyzed function
+    pub fn fid(&self) -> u32 {
+        self.fid
+    }
+
+    pub fn num_params(&self) -> usize {
+        self.total_params
+    }
+
+    /// The number of instructions that were analyzed (up to the `fallback` op if there's one)
+    pub fn num_instrs(&self) -> usize {
+        self.instrs.len()
+    }
+
+    /// The taint of the analyzed instructions, by instr_idx
+    pub fn instrs(&self) -> &[InstrInfo] {
+        &self.instrs
+    }
+
+    /// The taint of the instruction at `instr_idx`, None if it wasn't analyzed
+    pub fn instr(&self, instr_idx: usize) -> Option<&InstrInfo> {
+        self.instrs.get(instr_idx)
+    }
+
+    /// The op that the analysis can't model, the function is charged the worst-case cost of its body
+    pub fn fallback(&self) -> Option<&str> {
+        self.fallback.as_deref()
+    }
+
+    /// Whether the function touches memory, globals or tables, or calls out (see `func_effects`)
+    pub fn effects(&self) -> &Effects {
+        &self.effects
+    }
+}
+
+/// The stack state of a block that the analysis is in
+struct ControlFrame {
+    /// The height of the operand stack when the block was entered
+    height: usize,
+    num_results: usize,
+    /// Branches to a loop go back to its start, they don't produce its results
+    is_loop: bool,
+    /// The instruction index of the block's `block`/`loop`/`if`
+    start: usize,
+    was_unreachable: bool,
+    /// The results that reach the block's `end` from the other paths than falling through it: the
+    /// `then` arm of an `if` and the branches to the block. They're merged at the `end`.
+    merged: Vec<Origin>
 }
 
 #[derive(Default)]
@@ -84,32 +201,40 @@ struct FuncTaint {
     // Some tracking metadata
     // operand stack: each element is an Origin indicating where the value came from.
     stack: Vec<Origin>,                 // current stack
-    control_stack: Vec<(usize, usize)>, // (orig_stack_size, num_results): used to remember stack state for nested blocks
+    control_stack: Vec<ControlFrame>,   // used to remember stack state for nested blocks
+    // whether the rest of the current block is unreachable (after a `return`), the stack is polymorphic here
+    unreachable: bool,
     instrs: Vec<InstrInfo>,             // information about instrs (used to create the slice)
+    // the op that the analysis can't model, the rest of the function is skipped
+    fallback: Option<String>,
 }
 impl FuncTaint {
-    fn new(wasm: &Module, fid: FunctionID) -> FuncTaint {
+    fn new(wasm: &Module, fid: FunctionID) -> Result<FuncTaint, AnalysisError> {
         // number of locals is total_params + num_locals!
-        let lf = wasm.functions.unwrap_local(FunctionID(*fid));
+        let lf = local_func(*fid, wasm)?;
         let Some(Types::FuncType { params: total_params, results: total_results , ..}) = wasm.types.get(lf.ty_id) else {
-            panic!("Should have found a function type!");
+            fail!(AnalysisError::MissingFuncType { tid: *lf.ty_id });
         };
         let total_locals = total_params.len() + lf.body.num_locals as usize;
 
-        Self {
+        Ok(Self {
             fid: *fid,
             local_origin: vec![Origin::default(); total_locals],
             total_params: total_params.len(),
             total_results: total_results.len(),
             ..Default::default()
-        }
+        })
     }
 
+    /// A local that's set before the innermost loop is read like a param: the loop's body is sliced
+    /// on its own and can't replay the instruction that set it.
     fn get_local_origin(&mut self, i: u32, instr_idx: usize) -> Origin {
-        if i < self.total_params as u32 {
+        let loop_start = self.control_stack.iter().rev().find(|frame| frame.is_loop).map(|frame| frame.start);
+        let origin = &self.local_origin[i as usize];
+        if i < self.total_params as u32 || loop_start.is_some_and(|start| origin.instr_idx().is_some_and(|idx| idx < start)) {
             Origin::Param {instr_idx, lid: i}
         } else {
-            self.local_origin[i as usize].clone()
+            origin.clone()
         }
     }
 
@@ -117,17 +242,38 @@ impl FuncTaint {
         self.local_origin[i as usize] = origins;
     }
 
-    fn push_control(&mut self, num_results: usize) {
-        self.control_stack.push((self.stack.len(), num_results));
+    fn push_control(&mut self, num_results: usize, is_loop: bool, start: usize) {
+        self.control_stack.push(ControlFrame {
+            height: self.stack.len(),
+            num_results,
+            is_loop,
+            start,
+            was_unreachable: self.unreachable,
+            merged: vec![]
+        });
+        self.unreachable = false;
     }
 
-    fn pop_control(&mut self) -> (usize, usize) {
-        let (orig_stack_height, num_results) = self.control_stack.pop().unwrap();
+    /// Exit the current block at its `end` (`instr_idx`), only its results are left on the stack.
+    /// Returns the inputs of the `end`: if the results can come from several paths, the `end` merges
+    /// them (it consumes the results of each path and produces the block's results).
+    fn pop_control(&mut self, instr_idx: usize) -> Result<Vec<Origin>, AnalysisError> {
+        let Some(ControlFrame { height: orig_stack_height, num_results, was_unreachable, merged, .. }) = self.control_stack.pop() else {
+            fail!(AnalysisError::UnbalancedControl { fid: self.fid, instr_idx });
+        };
         let res_stack_height = orig_stack_height + num_results;
+        let falls_through = !self.unreachable;
+        if self.unreachable {
+            // the block's results were never produced on this path
+            while self.stack.len() < res_stack_height {
+                self.stack.push(Origin::Untracked);
+            }
+        }
+        self.unreachable = was_unreachable;
         let curr_stack_height = self.stack.len();
 
         if curr_stack_height < res_stack_height {
-            panic!("Something went horribly wrong in the analysis OR your Wasm module is invalid!");
+            fail!(AnalysisError::UnbalancedControl { fid: self.fid, instr_idx });
         }
 
         let num_pops = curr_stack_height - res_stack_height;
@@ -135,38 +281,149 @@ impl FuncTaint {
             self.stack.pop();
         }
 
-        (orig_stack_height, num_results)
+        if merged.is_empty() {
+            // a single path produces the results, they keep their origins
+            return Ok(vec![]);
+        }
+        let mut inputs = merged;
+        let results = self.stack.split_off(orig_stack_height);
+        if falls_through {
+            inputs.extend(results);
+        }
+        self.stack.extend((0..num_results).map(|_| Origin::Instr { instr_idx }));
+        Ok(inputs)
     }
-}
 
-pub fn analyze(wasm: &mut Module) -> Vec<FuncState> {
-    let mut mi = ModuleIterator::new(wasm, &vec![]);
-    let mut funcs: Vec<FuncState> = Vec::new();
-
-    let mut first = true;
-    let mut state = FuncTaint::default();
-    while first || mi.next().is_some() {
-        let (
-            Location::Module {func_idx, instr_idx} |
-            Location::Component {func_idx, instr_idx, ..},
-            is_func_end
-        ) = mi.curr_loc();
-
-        if instr_idx == 0 {
-            // we're at the start of a new function! --> reset state
-            if !first {
-                // only save if this isn't the first function we're visiting
-                assert!(state.stack.len() == state.total_results || state.stack.is_empty(), "still had stack values leftover: {:?}", state.stack);
-                funcs.push(FuncState::new(state));
-            }
+    fn frame_height(&self) -> usize {
+        self.control_stack.last().map(|frame| frame.height).unwrap_or_default()
+    }
 
-            state = FuncTaint::new(mi.module, func_idx);
-            first = false;
+    /// A branch to the block at `relative_depth`: the values on top of the stack are its results
+    /// (unless it's a loop, or the function's body: that's a return).
+    fn branch_to(&mut self, relative_depth: u32) {
+        if self.unreachable {
+            // the branch never runs
+            return;
+        }
+        let Some(target) = self.control_stack.len().checked_sub(relative_depth as usize + 1) else {
+            return;
+        };
+        let frame = &mut self.control_stack[target];
+        if !frame.is_loop {
+            let carried = self.stack.len().saturating_sub(frame.num_results);
+            frame.merged.extend_from_slice(&self.stack[carried..]);
         }
+    }
 
-        let op = mi.curr_op().unwrap_or_else(|| {
-            panic!("Unable to get current operator");
-        });
+    /// A handler of a `try_table` that branches to the block at `relative_depth` (from outside the
+    /// `try_table`): the exception's payload are its results, they aren't tracked. This doesn't
+    /// depend on whether anything in the `try_table` throws, so the target's results are always untracked.
+    fn catch_to(&mut self, relative_depth: u32) {
+        if self.unreachable {
+            // the try_table never runs
+            return;
+        }
+        let Some(target) = self.control_stack.len().checked_sub(relative_depth as usize + 1) else {
+            return;
+        };
+        let frame = &mut self.control_stack[target];
+        if !frame.is_loop {
+            frame.merged.extend((0..frame.num_results).map(|_| Origin::Untracked));
+        }
+    }
+
+    /// Pop an operand, if the current block is unreachable, the stack is polymorphic
+    /// (popping past the block's base produces an untracked value).
+    fn pop_operand(&mut self) -> Option<Origin> {
+        if self.unreachable && self.stack.len() <= self.frame_height() {
+            return Some(Origin::Untracked);
+        }
+        self.stack.pop()
+    }
+
+    /// Pop an operand that the instruction at `instr_idx` consumes.
+    fn pop_input(&mut self, instr_idx: usize) -> Result<Origin, AnalysisError> {
+        let Some(origin) = self.pop_operand() else {
+            fail!(AnalysisError::StackUnderflow { fid: self.fid, instr_idx });
+        };
+        Ok(origin)
+    }
+
+    /// The function is done, only its results should be on the stack.
+    fn check_leftover(&self) -> Result<(), AnalysisError> {
+        if self.fallback.is_some() {
+            // the stack wasn't tracked to the end
+            return Ok(());
+        }
+        if !(self.stack.len() == self.total_results || self.stack.is_empty()) {
+            fail!(AnalysisError::LeftoverStack { fid: self.fid, height: self.stack.len() });
+        }
+        Ok(())
+    }
+
+    /// The rest of the current block is unreachable, drop its operands.
+    fn set_unreachable(&mut self) {
+        let height = self.frame_height();
+        self.stack.truncate(height);
+        self.unreachable = true;
+    }
+
+    /// Entering the `else` arm: the results of the `then` arm reach the `end` (they're merged with the
+    /// `else` arm's there), reset the stack to the base of the `if` block.
+    fn enter_else(&mut self) {
+        // the `then` arm falls through to the `end` like a branch
+        self.branch_to(0);
+        let height = self.frame_height();
+        self.stack.truncate(height);
+        self.unreachable = false;
+    }
+}
+
+/// Run the taint analysis on every local function of the module: where the inputs of each
+/// instruction come from (params, globals, loads, call results...), in order of the local functions.
+pub fn analyze(wasm: &mut Module) -> Result<Vec<FuncState>, AnalysisError> {
+    analyze_except(wasm, &[], &mut NoVisitor)
+}
+
+/// `analyze`, calling the `visitor` with the taint of each instruction as it's analyzed
+pub fn analyze_with(wasm: &mut Module, visitor: &mut dyn AnalysisVisitor) -> Result<Vec<FuncState>, AnalysisError> {
+    analyze_except(wasm, &[], visitor)
+}
+
+/// The taint analysis on its own, for the tools that only need the taint facts (not the fuel):
+/// where the inputs of each instruction come from (see `FuncState::instrs`), in order of the local
+/// functions. Unlike `analyze` it doesn't fail, the functions that the analysis fails on are left
+/// out (`analyze` reports why) and the module isn't borrowed mutably.
+pub fn analyze_taint(wasm: &Module) -> Vec<FuncState> {
+    let effects = func_effects(wasm);
+    local_fids(wasm)
+        .filter_map(|fid| analyze_func(wasm, fid, &effects, &mut NoVisitor).ok())
+        .collect()
+}
+
+/// `analyze`, but the functions in `skip` aren't analyzed (and have no `FuncState`)
+pub(crate) fn analyze_except(wasm: &Module, skip: &[FunctionID], visitor: &mut dyn AnalysisVisitor) -> Result<Vec<FuncState>, AnalysisError> {
+    let effects = func_effects(wasm);
+    local_fids(wasm)
+        .filter(|fid| !skip.contains(fid))
+        .map(|fid| analyze_func(wasm, fid, &effects, visitor))
+        .collect()
+}
+
+fn local_fids<'a>(wasm: &'a Module) -> impl Iterator<Item = FunctionID> + 'a {
+    (0..wasm.functions.iter().count() as u32).map(FunctionID).filter(|fid| wasm.functions.is_local(*fid))
+}
+
+/// Simulate the operand stack through the body of the local function `fid`
+pub(crate) fn analyze_func(wasm: &Module, fid: FunctionID, effects: &BTreeMap<u32, Effects>, visitor: &mut dyn AnalysisVisitor) -> Result<FuncState, AnalysisError> {
+    let mut state = FuncTaint::new(wasm, fid)?;
+    let body = local_ops(*fid, wasm)?;
+    for (instr_idx, op) in body.iter().enumerate() {
+        let is_func_end = instr_idx + 1 == body.len();
+        if state.fallback.is_some() {
+            // can't model this function, the rest of it is skipped
+            break;
+        }
 
         match op {
             // ---------------- Locals ----------------
@@ -182,7 +439,7 @@ pub fn analyze(wasm: &mut Module) -> Vec<FuncState> {
 
             Operator::LocalSet { local_index } => {
                 // consumes one value and stores into local
-                let val = state.stack.pop().unwrap();
+                let val = state.pop_input(instr_idx)?;
                 state.set_local_origin(*local_index, val.clone());
                 state.instrs.push(InstrInfo {
                     kind: OpKind::Other,
@@ -192,7 +449,7 @@ pub fn analyze(wasm: &mut Module) -> Vec<FuncState> {
 
             Operator::LocalTee { local_index } => {
                 // consumes one value, stores into local, and leaves it on stack
-                let val = state.stack.pop().unwrap();
+                let val = state.pop_input(instr_idx)?;
                 state.set_local_origin(*local_index, val.clone());
                 // push same origin back
                 state.stack.push(val.clone());
@@ -212,7 +469,7 @@ pub fn analyze(wasm: &mut Module) -> Vec<FuncState> {
             }
 
             Operator::GlobalSet { .. } => {
-                let val = state.stack.pop().unwrap();
+                let val = state.pop_input(instr_idx)?;
                 state.instrs.push(InstrInfo {
                     kind: OpKind::Other,
                     inputs: vec![val]
@@ -221,75 +478,171 @@ pub fn analyze(wasm: &mut Module) -> Vec<FuncState> {
 
             // ---------------- Loads ----------------
             // All loads consume an address (i32) and produce a value.
-            Operator::I32Load { .. }
-            | Operator::I64Load { .. }
-            | Operator::F32Load { .. }
-            | Operator::F64Load { .. }
-            | Operator::I32Load8S { .. }
-            | Operator::I32Load8U { .. }
-            | Operator::I32Load16S { .. }
-            | Operator::I32Load16U { .. }
-            | Operator::I64Load8S { .. }
-            | Operator::I64Load8U { .. }
-            | Operator::I64Load16S { .. }
-            | Operator::I64Load16U { .. }
-            | Operator::I64Load32S { .. }
-            | Operator::I64Load32U { .. } => {
-                let addr_origin = state.stack.pop().unwrap();
+            Operator::I32Load { memarg }
+            | Operator::I64Load { memarg }
+            | Operator::F32Load { memarg }
+            | Operator::F64Load { memarg }
+            | Operator::I32Load8S { memarg }
+            | Operator::I32Load8U { memarg }
+            | Operator::I32Load16S { memarg }
+            | Operator::I32Load16U { memarg }
+            | Operator::I64Load8S { memarg }
+            | Operator::I64Load8U { memarg }
+            | Operator::I64Load16S { memarg }
+            | Operator::I64Load16U { memarg }
+            | Operator::I64Load32S { memarg }
+            | Operator::I64Load32U { memarg } => {
+                let addr_origin = state.pop_input(instr_idx)?;
                 // mark produced value as coming from this load instruction (instr_idx)
-                state.stack.push(Origin::Load {instr_idx});
+                state.stack.push(Origin::Load {instr_idx, memory: memarg.memory});
                 state.instrs.push(InstrInfo {
                     kind: OpKind::Other,
                     inputs: vec![addr_origin]
                 });
             }
 
+            // The size of the memory is state too: it's tracked like a loaded value
+            Operator::MemorySize { mem } | Operator::MemoryGrow { mem } => {
+                let (pops, _) = stack_effects(op, FunctionID(state.fid), wasm)?;
+                let mut inputs = Vec::new();
+                for _ in 0..pops {
+                    inputs.push(state.pop_input(instr_idx)?);
+                }
+                state.stack.push(Origin::Load {instr_idx, memory: *mem});
+                state.instrs.push(InstrInfo {
+                    kind: OpKind::Other,
+                    inputs
+                });
+            }
+
+            // ---------------- Tables ----------------
+            // Consumes the index, the reference read from the table is tracked like a loaded value.
+            Operator::TableGet { table } => {
+                let index_origin = state.pop_input(instr_idx)?;
+                state.stack.push(Origin::TableGet { instr_idx, table_index: *table });
+                state.instrs.push(InstrInfo {
+                    kind: OpKind::Other,
+                    inputs: vec![index_origin]
+                });
+            }
+
+            // ---------------- GC ----------------
+            // Consumes the reference (and the index of the element), the field is tracked like a loaded value:
+            // the heap isn't tracked through `struct.set`/`array.set`.
+            Operator::StructGet { .. }
+            | Operator::StructGetS { .. }
+            | Operator::StructGetU { .. }
+            | Operator::ArrayGet { .. }
+            | Operator::ArrayGetS { .. }
+            | Operator::ArrayGetU { .. }
+            | Operator::ArrayLen => {
+                let (pops, _) = stack_effects(op, FunctionID(state.fid), wasm)?;
+                let mut inputs = Vec::new();
+                for _ in 0..pops {
+                    inputs.insert(0, state.pop_input(instr_idx)?);
+                }
+                state.stack.push(Origin::FieldLoad { instr_idx });
+                state.instrs.push(InstrInfo {
+                    kind: OpKind::Other,
+                    inputs
+                });
+            }
+
             // ---------------- Branch / Control ----------------
-            Operator::BrIf { .. } | Operator::BrTable { .. }
-            | Operator::BrOnNull {..} | Operator::BrOnNonNull {..}
-            | Operator::BrOnCast {..} | Operator::BrOnCastFail {..} => {
+            Operator::BrIf { .. } | Operator::BrTable { .. } => {
                 // pops condition
-                let cond = state.stack.pop().unwrap();
+                let cond = state.pop_input(instr_idx)?;
+                // The values under it are the results of the target block when the branch is taken.
+                // A `br_if` leaves them on the stack when it isn't, with the same origins.
+                match op {
+                    Operator::BrIf { relative_depth } => state.branch_to(*relative_depth),
+                    Operator::BrTable { targets } => {
+                        for relative_depth in targets.targets().flatten().chain([targets.default()]) {
+                            state.branch_to(relative_depth);
+                        }
+                    }
+                    _ => {}
+                }
                 state.instrs.push(InstrInfo {
                     kind: OpKind::Control,
                     inputs: vec![cond]
                 });
+                if matches!(op, Operator::BrTable { .. }) {
+                    // always taken, the stack is polymorphic for the rest of the block
+                    state.set_unreachable();
+                }
             }
 
-            // ---------------- Calls ----------------
-            Operator::Call {..} | Operator::CallIndirect {..} => {
-                let (tid, kind) = if let Operator::Call { function_index } = op {
-                    (mi.module.functions.get(FunctionID(*function_index)).get_type_id(), OpKind::Other)
-                } else if let Operator::CallIndirect {type_index, ..} = op {
-                    (TypeID(*type_index), OpKind::Other)
+            // The reference decides the branch. `br_on_null` branches with the values under it, the others
+            // also pass the reference to the target. It's left on the stack when they fall through
+            // (as the same value), except by `br_on_non_null`.
+            Operator::BrOnNull { relative_depth } | Operator::BrOnNonNull { relative_depth }
+            | Operator::BrOnCast { relative_depth, .. } | Operator::BrOnCastFail { relative_depth, .. } => {
+                let reference = state.pop_input(instr_idx)?;
+                if matches!(op, Operator::BrOnNull { .. }) {
+                    state.branch_to(*relative_depth);
                 } else {
-                    unreachable!()
+                    state.stack.push(reference.clone());
+                    state.branch_to(*relative_depth);
+                    state.stack.pop();
+                }
+                if !matches!(op, Operator::BrOnNonNull { .. }) {
+                    state.stack.push(reference.clone());
+                }
+                state.instrs.push(InstrInfo {
+                    kind: OpKind::Control,
+                    inputs: vec![reference]
+                });
+            }
+
+            Operator::Br { relative_depth } => {
+                state.branch_to(*relative_depth);
+                // the stack is polymorphic for the rest of the block
+                state.set_unreachable();
+                state.instrs.push(InstrInfo {
+                    kind: OpKind::Other,
+                    inputs: vec![]
+                });
+            }
+
+            // ---------------- Calls ----------------
+            Operator::Call {..} | Operator::CallIndirect {..} | Operator::CallRef {..} => {
+                let (tid, kind) = match op {
+                    Operator::Call { function_index } => (wasm.functions.get(FunctionID(*function_index)).get_type_id(), OpKind::Other),
+                    Operator::CallIndirect { type_index, .. } => (TypeID(*type_index), OpKind::Other),
+                    Operator::CallRef { type_index } => (TypeID(*type_index), OpKind::Other),
+                    _ => fail!(AnalysisError::UnsupportedOp { op: format!("{op:?}") })
                 };
-                let (pops, pushes) = if let Some(Types::FuncType { params , results, ..}) = mi.module.types.get(tid) {
-                    (params.len(), results.len())
+                // a `call_indirect` also pops the table index and a `call_ref` the function reference
+                // (on top of the arguments)
+                let num_ref = usize::from(matches!(op, Operator::CallIndirect { .. } | Operator::CallRef { .. }));
+                let (pops, pushes) = if let Some(Types::FuncType { params , results, ..}) = wasm.types.get(tid) {
+                    (params.len() + num_ref, results.len())
                 } else {
-                    panic!("Should have found a function type!");
+                    fail!(AnalysisError::MissingFuncType { tid: *tid });
                 };
                 // conservative: assume 1 arg popped and 1 result produced (not precise)
                 // ideally, use type information to know the real parameter count and results
                 let mut inputs = Vec::new();
                 for _ in 0..pops {
-                    inputs.insert(0, state.stack.pop().unwrap());
+                    inputs.insert(0, state.pop_input(instr_idx)?);
                 }
 
                 for i in 0..pushes {
-                    state.stack.push(if let Operator::Call { .. } = op {
-                        Origin::Call {
+                    state.stack.push(match op {
+                        Operator::CallIndirect { table_index, .. } => Origin::CallIndirect {
+                            result_idx: i,
+                            instr_idx,
+                            table_index: *table_index
+                        },
+                        Operator::CallRef { .. } => Origin::CallRef {
                             result_idx: i,
                             instr_idx
-                        }
-                    } else if let Operator::CallIndirect {..} = op {
-                        Origin::CallIndirect {
+                        },
+                        _ => Origin::Call {
                             result_idx: i,
                             instr_idx
                         }
-                    } else {
-                        unreachable!()
                     })
                 }
                 state.instrs.push(InstrInfo {
@@ -298,26 +651,55 @@ pub fn analyze(wasm: &mut Module) -> Vec<FuncState> {
                 });
             }
 
-            Operator::Return {..} => {
-                for _ in 0..state.total_results {
-                    state.stack.pop();
+            // a tail call ends the frame like a `return` (the callee is charged by its own fuel
+            // function), its arguments don't influence the control flow of this function
+            Operator::Return {..} | Operator::Unreachable | Operator::ReturnCall {..} | Operator::ReturnCallIndirect {..} | Operator::ReturnCallRef {..} => {
+                let (pops, _) = stack_effects(op, FunctionID(state.fid), wasm)?;
+                for _ in 0..pops {
+                    state.pop_operand();
                 }
+                // the stack is polymorphic for the rest of the block
+                state.set_unreachable();
+                state.instrs.push(InstrInfo {
+                    kind: if matches!(op, Operator::Return) || is_tail_call(op) { OpKind::Control } else { OpKind::Other },
+                    inputs: vec![]
+                });
+            }
+
+            // the exception unwinds to a handler (see `catch_to`) or out of the function, its
+            // operands are dropped with the rest of the block's
+            Operator::Throw { .. } | Operator::ThrowRef => {
+                state.set_unreachable();
                 state.instrs.push(InstrInfo {
                     kind: OpKind::Control,
                     inputs: vec![]
                 });
             }
 
-            Operator::If { .. } | Operator::Block { .. } | Operator::Loop { .. } => {
+            Operator::Else => {
+                state.enter_else();
+                state.instrs.push(InstrInfo {
+                    kind: OpKind::Other,
+                    inputs: vec![]
+                });
+            }
+
+            Operator::If { .. } | Operator::Block { .. } | Operator::Loop { .. } | Operator::TryTable { .. } => {
+                if let Operator::TryTable { try_table } = op {
+                    for catch in try_table.catches.iter() {
+                        let (Catch::One { label, .. } | Catch::OneRef { label, .. } | Catch::All { label } | Catch::AllRef { label }) = catch;
+                        state.catch_to(*label);
+                    }
+                }
                 let (inputs, kind) = if matches!(op, Operator::If { .. }) {
                     // pops condition
-                    let cond = state.stack.pop().unwrap();
+                    let cond = state.pop_input(instr_idx)?;
                     (vec![cond], OpKind::Control)
                 } else {
                     (vec![], OpKind::Other)
                 };
-                let (_, num_results) = stack_effects(op, mi.module);
-                state.push_control(num_results);
+                let (_, num_results) = stack_effects(op, FunctionID(state.fid), wasm)?;
+                state.push_control(num_results, matches!(op, Operator::Loop { .. }), instr_idx);
                 state.instrs.push(InstrInfo {
                     kind,
                     inputs
@@ -327,23 +709,27 @@ pub fn analyze(wasm: &mut Module) -> Vec<FuncState> {
             Operator::End => {
                 // We reach an end if we're exiting a control block!
                 // need to pop the appropriate values off the stack
-                if !is_func_end {
-                    state.pop_control();
-                }
+                let inputs = if is_func_end {
+                    vec![]
+                } else {
+                    state.pop_control(instr_idx)?
+                };
                 state.instrs.push(InstrInfo {
                     kind: OpKind::Other,
-                    inputs: vec![]
+                    inputs
                 });
             },
 
             // ---------------- Others ----------------
             _ => {
-                let (pops, pushes) = stack_effects(op, mi.module);
+                let Some((pops, pushes)) = try_stack_effects(op, FunctionID(state.fid), wasm)? else {
+                    // the function falls back to the worst-case cost of its body
+                    state.fallback = Some(format!("{op:?}"));
+                    continue;
+                };
                 let mut inputs = Vec::new();
-                for i in 0..pops {
-                    inputs.insert(0, state.stack.pop().unwrap_or_else( || {
-                        unreachable!("Issue when popping @{} for opcode: {op:?}", i)
-                    }));
+                for _ in 0..pops {
+                    inputs.insert(0, state.pop_input(instr_idx)?);
                 }
 
                 for _ in 0..pushes {
@@ -355,10 +741,12 @@ pub fn analyze(wasm: &mut Module) -> Vec<FuncState> {
                 });
             }
         }
+        if let Some(info) = state.instrs.get(instr_idx) {
+            visitor.visit_instr(state.fid, instr_idx, op, info);
+        }
     }
-    // push the state of the final function
-    assert!(state.stack.len() == state.total_results || state.stack.is_empty(), "still had stack values leftover: {:?}", state.stack);
-    funcs.push(FuncState::new(state));
-
-    funcs
+    state.check_leftover()?;
+    let func = FuncState::new(state, effects);
+    visitor.visit_func(&func);
+    Ok(func)
 }
\ No newline at end of file
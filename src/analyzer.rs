@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+use anyhow::anyhow;
+use crate::cert::sha256;
+use crate::patch::{gen_patches, FuelPatch};
+use crate::run::{run_analysis, AnalysisConfig, AnalysisOutput};
+use crate::sidecar::Sidecar;
+
+/// The (owned) results of analyzing a single module.
+#[derive(Debug)]
+pub struct Analysis {
+    /// The slices, per-function costs, and the generated function manifest
+    pub results: Sidecar,
+    pub patches: Vec<FuelPatch>,
+    pub max_wasm: Vec<u8>,
+    pub min_wasm: Vec<u8>
+}
+
+/// A handle for embedding the analysis in a server, can be shared across threads with `Arc<Analyzer>`.
+/// Results are cached on the hash of the module and the config (compared by its `Debug` description),
+/// so resubmitting a module with the same config does not rerun the analysis.
+#[derive(Debug, Default)]
+pub struct Analyzer {
+    cache: RwLock<HashMap<(String, String), Arc<Analysis>>>
+}
+impl Analyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Analyze the module with the `config` as `run::run_analysis` does, unless it's cached
+    pub fn analyze(&self, wasm_bytes: &[u8], config: &AnalysisConfig) -> anyhow::Result<Arc<Analysis>> {
+        let key = (sha256(wasm_bytes), format!("{config:?}"));
+        if let Some(analysis) = self.cache.read().map_err(|_| poisoned())?.get(&key) {
+            return Ok(analysis.clone());
+        }
+
+        // don't hold the lock while analyzing, if another thread beat us to it, keep theirs
+        let AnalysisOutput { max_wasm, min_wasm, sidecar, .. } = run_analysis(wasm_bytes, config)?;
+        let patches = gen_patches(wasm_bytes, sidecar.functions.iter().map(|func| (func.fid, &func.costs)))?;
+        let analysis = Arc::new(Analysis { results: sidecar, patches, max_wasm, min_wasm });
+        Ok(self.cache.write().map_err(|_| poisoned())?.entry(key).or_insert(analysis).clone())
+    }
+
+    /// The number of (module, config) pairs with cached results
+    pub fn num_cached(&self) -> usize {
+        self.cache.read().map_or(0, |cache| cache.len())
+    }
+
+    pub fn clear(&self) {
+        // clearing also recovers a cache that a panicking thread poisoned
+        self.cache.write().unwrap_or_else(PoisonError::into_inner).clear();
+        self.cache.clear_poison();
+    }
+}
+
+fn poisoned() -> anyhow::Error {
+    anyhow!("The analysis cache is poisoned: a thread panicked while holding it (`Analyzer::clear` resets it)")
+}
@@ -0,0 +1,215 @@
+//! Comparing two generated modules (`compare-artifacts`), the counterpart of `baseline` for the users
+//! who only keep the binaries rather than the JSON reports. The generated functions are matched by
+//! their export name, the stable ID of their slice (`<comp_type><fid><spec_name>`, e.g.
+//! `exact3_loop_at_12`). Their cost maps are recovered from the charges in their bodies, and their
+//! requested state from the `state_layout` section (if the modules were generated with `--state-section`).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use wirm::wasmparser::{ExternalKind, FuncType, Operator, Parser, Payload, TypeRef, ValType};
+use crate::state_layout::{decode_state_layout, StateRecord, STATE_SECTION};
+
+/// Where a generated function charges fuel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChargeSite {
+    /// The fuel point at this instr_idx of the original function (labeled with `--checkpoints`)
+    FuelPoint(usize),
+    /// The n-th charge in the body (the fuel points aren't labeled)
+    Nth(usize)
+}
+impl Display for ChargeSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChargeSite::FuelPoint(instr_idx) => write!(f, "@{instr_idx}"),
+            ChargeSite::Nth(n) => write!(f, "charge #{n}")
+        }
+    }
+}
+
+/// What's compared between two versions of a generated function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactFunc {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+    /// The static cost charged at each site (the charges for the lengths of the bulk ops aren't static)
+    pub costs: BTreeMap<ChargeSite, u64>,
+    /// The state that each param takes, None if the module has no state section
+    pub state: Option<Vec<StateRecord>>
+}
+impl ArtifactFunc {
+    pub fn signature(&self) -> String {
+        let join = |tys: &[ValType]| tys.iter().map(|ty| ty.to_string()).collect::<Vec<_>>().join(", ");
+        format!("({}) -> ({})", join(&self.params), join(&self.results))
+    }
+}
+
+/// A generated function that changed between the artifacts, `None` if it's not in one of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactDelta {
+    /// The export name of the function
+    pub name: String,
+    pub was: Option<ArtifactFunc>,
+    pub now: Option<ArtifactFunc>
+}
+impl ArtifactDelta {
+    pub fn signature_changed(&self) -> bool {
+        matches!((&self.was, &self.now), (Some(was), Some(now)) if (&was.params, &was.results) != (&now.params, &now.results))
+    }
+
+    /// The charge sites whose cost changed: (site, the cost before, the cost now)
+    pub fn changed_costs(&self) -> Vec<(ChargeSite, Option<u64>, Option<u64>)> {
+        let (Some(was), Some(now)) = (&self.was, &self.now) else {
+            return vec![];
+        };
+        let sites: BTreeSet<ChargeSite> = was.costs.keys().chain(now.costs.keys()).copied().collect();
+        sites.into_iter()
+            .map(|site| (site, was.costs.get(&site).copied(), now.costs.get(&site).copied()))
+            .filter(|(_, was, now)| was != now)
+            .collect()
+    }
+
+    /// The state that's requested now but wasn't before (whichever param takes it), empty unless both
+    /// artifacts have a state section
+    pub fn new_state(&self) -> Vec<StateRecord> {
+        let (Some(Some(was)), Some(Some(now))) = (self.was.as_ref().map(|f| &f.state), self.now.as_ref().map(|f| &f.state)) else {
+            return vec![];
+        };
+        let key = |record: &StateRecord| (record.kind, record.instr_idx, record.memory, record.is_arg, record.num);
+        let requested: BTreeSet<_> = was.iter().map(key).collect();
+        now.iter().filter(|record| !requested.contains(&key(record))).copied().collect()
+    }
+}
+
+/// Maps from the export name of each generated function in the module -> what's compared
+pub fn read_artifact(wasm_bytes: &[u8]) -> anyhow::Result<BTreeMap<String, ArtifactFunc>> {
+    let mut types: Vec<FuncType> = vec![];
+    // the type of each function (the imported ones first)
+    let mut func_types: Vec<u32> = vec![];
+    let mut exports: Vec<(String, u32)> = vec![];
+    let mut bodies: Vec<Vec<Operator>> = vec![];
+    let mut layouts = None;
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::TypeSection(reader) => {
+                for ty in reader.into_iter_err_on_gc_types() {
+                    types.push(ty?);
+                }
+            }
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    if let TypeRef::Func(tid) = import?.ty {
+                        func_types.push(tid);
+                    }
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                for tid in reader {
+                    func_types.push(tid?);
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for exp in reader {
+                    let exp = exp?;
+                    if exp.kind == ExternalKind::Func {
+                        exports.push((exp.name.to_string(), exp.index));
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let mut ops = vec![];
+                let mut reader = body.get_operators_reader()?;
+                while !reader.eof() {
+                    ops.push(reader.read()?);
+                }
+                bodies.push(ops);
+            }
+            Payload::CustomSection(reader) if reader.name() == STATE_SECTION => {
+                layouts = Some(decode_state_layout(reader.data())?);
+            }
+            _ => {}
+        }
+    }
+
+    let num_imported = func_types.len() - bodies.len();
+    let mut funcs = BTreeMap::new();
+    for (name, fid) in exports {
+        let (Some(ty), Some(ops)) = (func_types.get(fid as usize).and_then(|tid| types.get(*tid as usize)), (fid as usize).checked_sub(num_imported).and_then(|idx| bodies.get(idx))) else {
+            // an imported function, it wasn't generated
+            continue;
+        };
+        let state = layouts.as_ref().map(|layouts: &Vec<_>| layouts.iter()
+            .find(|layout| layout.fid == fid)
+            .map(|layout| layout.records.clone())
+            .unwrap_or_default());
+        let costs = charges(ops, orig_fid(&name));
+        funcs.insert(name, ArtifactFunc {
+            params: ty.params().to_vec(),
+            results: ty.results().to_vec(),
+            costs,
+            state
+        });
+    }
+    Ok(funcs)
+}
+
+/// The static charges in the body of a generated function. A charge is `local.get $fuel;
+/// i64.const <cost>; i64.add (or i64.sub, counting down); local.set $fuel`, preceded by the label of
+/// its fuel point with `--checkpoints` (`i64.const <checkpoint>; drop`, see `sidecar::checkpoint_id`).
+/// The functions that return a fixed cost only have the `i64.const <cost>`. Only a label of a fuel
+/// point of `orig_fid` is one, any other dropped constant is replayed from the original function.
+fn charges(ops: &[Operator], orig_fid: Option<u32>) -> BTreeMap<ChargeSite, u64> {
+    let mut costs = BTreeMap::new();
+    // the fuel point of the last label that wasn't charged yet (its block cost nothing)
+    let mut label: Option<usize> = None;
+    let mut i = 0;
+    while i < ops.len() {
+        match &ops[i..] {
+            [Operator::I64Const { value }, Operator::Drop, ..] if orig_fid.is_some_and(|fid| (*value as u64) >> 32 == fid as u64) => {
+                if let Some(fuel_point) = label.replace((*value as u64 & u32::MAX as u64) as usize) {
+                    costs.insert(ChargeSite::FuelPoint(fuel_point), 0);
+                }
+                i += 2;
+            }
+            [Operator::LocalGet { local_index }, Operator::I64Const { value }, Operator::I64Add | Operator::I64Sub, Operator::LocalSet { local_index: set }, ..]
+                if local_index == set => {
+                let site = match label.take() {
+                    Some(fuel_point) => ChargeSite::FuelPoint(fuel_point),
+                    None => ChargeSite::Nth(costs.len())
+                };
+                costs.insert(site, *value as u64);
+                i += 4;
+            }
+            _ => i += 1
+        }
+    }
+    if costs.is_empty() {
+        if let [Operator::I64Const { value }, Operator::End] = &ops[ops.len().saturating_sub(2)..] {
+            // a fixed cost (or the remaining fuel, with an initial fuel)
+            costs.insert(label.take().map(ChargeSite::FuelPoint).unwrap_or(ChargeSite::Nth(0)), *value as u64);
+        }
+    }
+    if let Some(fuel_point) = label {
+        costs.insert(ChargeSite::FuelPoint(fuel_point), 0);
+    }
+    costs
+}
+
+/// The original function of a generated function, from the fid in its export name (see the module docs)
+fn orig_fid(name: &str) -> Option<u32> {
+    let digits: String = name.chars().skip_while(|c| !c.is_ascii_digit()).take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+/// The generated functions that changed between the `old` and the `new` artifact (in order of their
+/// export names) and the number of generated functions in `new`
+pub fn compare_artifacts(old: &[u8], new: &[u8]) -> anyhow::Result<(Vec<ArtifactDelta>, usize)> {
+    let mut was = read_artifact(old)?;
+    let mut now = read_artifact(new)?;
+    let num_funcs = now.len();
+    let names: BTreeSet<String> = was.keys().chain(now.keys()).cloned().collect();
+    let deltas = names.into_iter().filter_map(|name| {
+        let delta = ArtifactDelta { was: was.remove(&name), now: now.remove(&name), name };
+        (delta.was != delta.now).then_some(delta)
+    }).collect();
+    Ok((deltas, num_funcs))
+}
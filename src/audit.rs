@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use wasmtime::{Config, Engine, Func, Instance, Module, Store, Val, ValType};
+use anyhow::Context;
+
+/// The most combinations of branch decisions that are enumerated for a min slice,
+/// past this the decisions are sampled (and a divergence is only likely).
+const MAX_DECISION_COMBOS: u64 = 4096;
+/// The fuel (of the engine) each call to a generated function gets, guards against non-termination
+const AUDIT_ENGINE_FUEL: u64 = 10_000_000;
+/// The lengths passed to the bulk ops are drawn from 0..MAX_AUDIT_LENGTH
+const MAX_AUDIT_LENGTH: u64 = 4;
+
+/// How to drive the generated functions during an audit.
+#[derive(Debug, Clone, Copy)]
+pub struct AuditConfig {
+    /// The number of random inputs each max slice is run on
+    pub cases: usize,
+    pub seed: u64
+}
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self { cases: 256, seed: 0 }
+    }
+}
+
+/// What the audit needs to know about a (max, min) pair of generated functions.
+pub(crate) struct AuditTarget {
+    pub(crate) fname: String,
+    /// min gen_param_id -> the number of decisions of that branch (2 for `if`/`br_if`, targets + 1 for `br_table`)
+    pub(crate) decisions: BTreeMap<u32, u64>,
+    /// (max gen_param_id, min gen_param_id) of the length of each bulk op
+    pub(crate) lengths: Vec<(u32, u32)>
+}
+
+#[derive(Debug)]
+pub enum AuditOutcome {
+    /// Every cost of the max slice is computed by the min slice for some branch decisions
+    Agrees { cases: usize, traps: usize },
+    /// The max slice computed `fuel` on `inputs`, but no branch decisions of the min slice do
+    Diverges { inputs: Vec<String>, fuel: i64, exhaustive: bool },
+    Skipped { reason: String }
+}
+
+#[derive(Debug)]
+pub struct FuncAudit {
+    pub fname: String,
+    pub outcome: AuditOutcome
+}
+impl FuncAudit {
+    /// Whether this is a divergence that was checked against every branch decision of the min slice
+    pub fn is_confirmed_divergence(&self) -> bool {
+        matches!(self.outcome, AuditOutcome::Diverges { exhaustive: true, .. })
+    }
+}
+impl Display for FuncAudit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            AuditOutcome::Agrees { cases, traps } => write!(f, "{}: ok ({cases} cases, {traps} trapped)", self.fname),
+            AuditOutcome::Diverges { inputs, fuel, exhaustive } => write!(f, "{}: DIVERGES{}, the max slice computes {fuel} for ({}) which no branch decisions of the min slice compute",
+                                                                          self.fname, if *exhaustive { "" } else { " (sampled decisions)" }, inputs.join(", ")),
+            AuditOutcome::Skipped { reason } => write!(f, "{}: skipped, {reason}", self.fname)
+        }
+    }
+}
+
+/// Run the max slices on random inputs and check each result against the results of the
+/// min slices over their branch decisions. The min slice only replays the decisions of the
+/// branches, so a cost it can't reproduce points to a bug in the reductions (see `reduce.rs`).
+pub(crate) fn audit(bytes_max: &[u8], bytes_min: &[u8], targets: &[AuditTarget], config: &AuditConfig) -> anyhow::Result<Vec<FuncAudit>> {
+    let mut engine_config = Config::new();
+    engine_config.consume_fuel(true);
+    let engine = Engine::new(&engine_config)?;
+    let mut store = Store::new(&engine, ());
+    let max = Instance::new(&mut store, &Module::new(&engine, bytes_max)?, &[])?;
+    let min = Instance::new(&mut store, &Module::new(&engine, bytes_min)?, &[])?;
+
+    let mut rng = SplitMix64(config.seed);
+    let mut results = Vec::new();
+    for target in targets.iter() {
+        let (Some(max_func), Some(min_func)) = (max.get_func(&mut store, &target.fname), min.get_func(&mut store, &target.fname)) else {
+            results.push(FuncAudit { fname: target.fname.clone(), outcome: AuditOutcome::Skipped { reason: "not generated for both slices".to_string() } });
+            continue;
+        };
+        let outcome = audit_func(&mut store, max_func, min_func, target, config, &mut rng)?;
+        results.push(FuncAudit { fname: target.fname.clone(), outcome });
+    }
+    Ok(results)
+}
+
+fn audit_func(store: &mut Store<()>, max_func: Func, min_func: Func, target: &AuditTarget, config: &AuditConfig, rng: &mut SplitMix64) -> anyhow::Result<AuditOutcome> {
+    let max_params: Vec<ValType> = max_func.ty(&*store).params().collect();
+    let min_params: Vec<ValType> = min_func.ty(&*store).params().collect();
+    // a length is an `i64` for a 64-bit memory or table
+    let length_val = |ty: &ValType, len: u64| if matches!(ty, ValType::I64) { Val::I64(len as i64) } else { Val::I32(len as i32) };
+    if let Some(ty) = max_params.iter().find(|ty| !matches!(ty, ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64)) {
+        return Ok(AuditOutcome::Skipped { reason: format!("can't generate inputs of type {ty:?}") });
+    }
+
+    let combos = target.decisions.values().try_fold(1u64, |acc, n| acc.checked_mul(*n)).unwrap_or(u64::MAX);
+    let exhaustive = combos <= MAX_DECISION_COMBOS;
+    // the lengths of the bulk ops -> what the min slice can compute with them
+    let mut min_results: HashMap<Vec<u64>, HashSet<i64>> = HashMap::new();
+
+    let (mut cases, mut traps) = (0, 0);
+    for _ in 0..config.cases {
+        let lengths: Vec<u64> = target.lengths.iter().map(|_| rng.next() % MAX_AUDIT_LENGTH).collect();
+        let mut inputs: Vec<Val> = max_params.iter().map(|ty| rng.gen_val(ty)).collect::<Option<_>>().context("Only numeric params are audited")?;
+        for ((max_id, _), len) in target.lengths.iter().zip(lengths.iter()) {
+            inputs[*max_id as usize] = length_val(&max_params[*max_id as usize], *len);
+        }
+        let Some(fuel) = call_fuel(store, max_func, &inputs)? else {
+            traps += 1;
+            continue;
+        };
+        cases += 1;
+
+        if !min_results.contains_key(&lengths) {
+            let decisions = if exhaustive {
+                all_decisions(&target.decisions)
+            } else {
+                (0..config.cases).map(|_| target.decisions.iter().map(|(id, n)| (*id, rng.next() % n)).collect()).collect()
+            };
+            let mut computed = HashSet::new();
+            for decision in decisions.iter() {
+                let mut min_inputs = vec![Val::I32(0); min_params.len()];
+                for (id, choice) in decision.iter() {
+                    min_inputs[*id as usize] = Val::I32(*choice as i32);
+                }
+                for ((_, min_id), len) in target.lengths.iter().zip(lengths.iter()) {
+                    min_inputs[*min_id as usize] = length_val(&min_params[*min_id as usize], *len);
+                }
+                if let Some(fuel) = call_fuel(store, min_func, &min_inputs)? {
+                    computed.insert(fuel);
+                }
+            }
+            min_results.insert(lengths.clone(), computed);
+        }
+        if !min_results[&lengths].contains(&fuel) {
+            return Ok(AuditOutcome::Diverges {
+                inputs: inputs.iter().map(fmt_val).collect(),
+                fuel,
+                exhaustive
+            });
+        }
+    }
+    Ok(AuditOutcome::Agrees { cases, traps })
+}
+
+/// Returns `None` if the function trapped (or ran out of engine fuel)
+fn call_fuel(store: &mut Store<()>, func: Func, inputs: &[Val]) -> anyhow::Result<Option<i64>> {
+    store.set_fuel(AUDIT_ENGINE_FUEL)?;
+    let mut results = vec![Val::I64(0)];
+    if func.call(&mut *store, inputs, &mut results).is_err() {
+        return Ok(None);
+    }
+    Ok(results.first().and_then(|res| res.i64()))
+}
+
+/// Every combination of the branch decisions: gen_param_id -> decision
+fn all_decisions(decisions: &BTreeMap<u32, u64>) -> Vec<Vec<(u32, u64)>> {
+    let mut combos: Vec<Vec<(u32, u64)>> = vec![vec![]];
+    for (id, n) in decisions.iter() {
+        combos = combos.into_iter()
+            .flat_map(|combo| (0..*n).map(move |choice| {
+                let mut combo = combo.clone();
+                combo.push((*id, choice));
+                combo
+            }))
+            .collect();
+    }
+    combos
+}
+
+fn fmt_val(val: &Val) -> String {
+    match val {
+        Val::I32(v) => format!("{v}"),
+        Val::I64(v) => format!("{v}"),
+        Val::F32(bits) => format!("{}", f32::from_bits(*bits)),
+        Val::F64(bits) => format!("{}", f64::from_bits(*bits)),
+        other => format!("{other:?}")
+    }
+}
+
+/// A small, seedable PRNG so that an audit can be reproduced
+struct SplitMix64(u64);
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+    /// Half of the values are small (these are the usual edge cases of conditions)
+    fn gen_int(&mut self) -> i64 {
+        let raw = self.next();
+        if raw & 1 == 0 { ((raw >> 1) % 5) as i64 - 1 } else { raw as i64 }
+    }
+    /// `None` for the types that aren't numeric
+    fn gen_val(&mut self, ty: &ValType) -> Option<Val> {
+        match ty {
+            ValType::I32 => Some(Val::I32(self.gen_int() as i32)),
+            ValType::I64 => Some(Val::I64(self.gen_int())),
+            ValType::F32 => Some(Val::F32((self.gen_int() as f32).to_bits())),
+            ValType::F64 => Some(Val::F64((self.gen_int() as f64).to_bits())),
+            _ => None
+        }
+    }
+}
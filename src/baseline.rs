@@ -0,0 +1,77 @@
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "fs")]
+use std::path::Path;
+#[cfg(feature = "fs")]
+use anyhow::Context;
+use crate::sidecar::JsonReport;
+
+/// What's compared between two analyses of a function (see `--baseline`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FuncSummary {
+    /// The number of instructions in its (max) slices
+    pub slice_size: usize,
+    /// The number of values that its generated (max) functions request as state
+    pub req_state: usize,
+    /// Maps from the instr_idx of a fuel point -> the cost of the block that's charged there
+    pub costs: BTreeMap<usize, u64>
+}
+impl FuncSummary {
+    pub fn total_cost(&self) -> u64 {
+        self.costs.values().sum()
+    }
+}
+
+/// A function whose analysis changed since the baseline, `None` if it's not in one of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncDelta {
+    pub fid: u32,
+    pub was: Option<FuncSummary>,
+    pub now: Option<FuncSummary>
+}
+impl FuncDelta {
+    /// The fuel points whose cost changed: (instr_idx, the cost in the baseline, the cost now)
+    pub fn changed_costs(&self) -> Vec<(usize, Option<u64>, Option<u64>)> {
+        let (Some(was), Some(now)) = (&self.was, &self.now) else {
+            return vec![];
+        };
+        let fuel_points: BTreeSet<usize> = was.costs.keys().chain(now.costs.keys()).copied().collect();
+        fuel_points.into_iter()
+            .map(|instr_idx| (instr_idx, was.costs.get(&instr_idx).copied(), now.costs.get(&instr_idx).copied()))
+            .filter(|(_, was, now)| was != now)
+            .collect()
+    }
+}
+
+/// Load a report that was saved with `--report-json`
+#[cfg(feature = "fs")]
+pub fn load_json_report(path: &Path) -> anyhow::Result<JsonReport> {
+    let data = std::fs::read_to_string(path).with_context(|| format!("Failed to read the baseline {}", path.display()))?;
+    serde_json::from_str(&data).with_context(|| format!("Malformed baseline {}", path.display()))
+}
+
+/// Maps from fid -> the summary of the function's analysis in the report
+pub fn summarize(report: &JsonReport) -> BTreeMap<u32, FuncSummary> {
+    let generated = report.fid_mapping.get("max");
+    report.functions.iter().map(|func| {
+        let req_state = generated.and_then(|mapping| mapping.get(&func.fid)).into_iter().flatten()
+            .flat_map(|entry| entry.req_state.values().flat_map(|reqs| reqs.values()))
+            .map(|vals| vals.len())
+            .sum();
+        (func.fid, FuncSummary {
+            slice_size: func.slices.values().map(|slice| slice.max_slice.len()).sum(),
+            req_state,
+            costs: func.costs.clone()
+        })
+    }).collect()
+}
+
+/// The functions whose analysis changed between the `baseline` and the `current` report
+pub fn compare(baseline: &JsonReport, current: &JsonReport) -> Vec<FuncDelta> {
+    let mut was = summarize(baseline);
+    let mut now = summarize(current);
+    let fids: BTreeSet<u32> = was.keys().chain(now.keys()).copied().collect();
+    fids.into_iter().filter_map(|fid| {
+        let delta = FuncDelta { fid, was: was.remove(&fid), now: now.remove(&fid) };
+        (delta.was != delta.now).then_some(delta)
+    }).collect()
+}
@@ -0,0 +1,432 @@
+//! Bounded model checking of the exact fuel of the small loop-free functions: the exact-mode generated
+//! function has to return the cost of the instructions that the original function executes, for every
+//! combination of its inputs from a bounded domain (the small values and the boundaries of each type).
+//! The original function is simulated bit-precisely on the same inputs.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use wasmtime::{Engine, Func, Instance, Module, Store, Val};
+use wirm::DataType;
+use wirm::wasmparser::{BlockType, Operator};
+use crate::codegen::{GeneratedFunc, StateType};
+use crate::cost_model::OpCostModel;
+
+/// The largest function body (in instructions) that's checked
+const MAX_BMC_INSTRS: usize = 256;
+/// The most input combinations that are enumerated for a function, past this it's skipped
+const MAX_BMC_CASES: usize = 1 << 16;
+/// Every value in -SMALL_BMC_VALUES..=SMALL_BMC_VALUES is in the domain of each input
+const SMALL_BMC_VALUES: i64 = 16;
+
+/// What the check needs to know about an exact-mode generated function.
+pub(crate) struct BmcTarget<'a> {
+    pub(crate) fname: String,
+    /// The body of the original function
+    pub(crate) body: &'a [Operator<'a>],
+    /// The param of the original function (and its type) that each param of the generated function takes
+    pub(crate) param_locals: Vec<(u32, DataType)>
+}
+impl<'a> BmcTarget<'a> {
+    /// The check of the generated function of `body`, or why it can't be checked: only the loop-free
+    /// functions whose generated function only requests their (integer) params are
+    pub(crate) fn new(func: &GeneratedFunc, body: &'a [Operator<'a>]) -> Result<Self, String> {
+        if func.fallback.is_some() {
+            return Err("the analysis doesn't model the function".to_string());
+        }
+        if body.len() > MAX_BMC_INSTRS {
+            return Err(format!("more than {MAX_BMC_INSTRS} instructions"));
+        }
+        if let Some(op) = body.iter().find(|op| !is_modeled(op)) {
+            return Err(format!("the model doesn't support {op:?}"));
+        }
+        if let Some((ty, _)) = func.req_state.iter().find(|(ty, reqs)| **ty != StateType::Param && !reqs.is_empty()) {
+            return Err(format!("requests state other than params ({ty:?})"));
+        }
+
+        let mut param_locals = vec![(0, DataType::I32); func.params.len()];
+        for (instr_idx, req) in func.req_state.get(&StateType::Param).into_iter().flatten() {
+            let Some(Operator::LocalGet { local_index }) = body.get(*instr_idx) else {
+                return Err(format!("the param requested @{instr_idx} isn't read by a local.get"));
+            };
+            for val in req.req_state.iter() {
+                let ty = func.params[val.gen_param_id() as usize];
+                if !matches!(ty, DataType::I32 | DataType::I64) {
+                    return Err(format!("requests a param of type {ty:?}"));
+                }
+                param_locals[val.gen_param_id() as usize] = (*local_index, ty);
+            }
+        }
+        Ok(Self { fname: func.fname.clone(), body, param_locals })
+    }
+}
+
+#[derive(Debug)]
+pub enum BmcOutcome {
+    /// The generated function returned the cost of the original path on every input combination
+    Exact { cases: usize, traps: usize },
+    /// The generated function returned `computed` on `inputs`, but the original path costs `expected`
+    Mismatch { inputs: Vec<String>, expected: u64, computed: i64 },
+    /// The generated function trapped on `inputs`, but the original path doesn't
+    Trapped { inputs: Vec<String>, error: String },
+    Skipped { reason: String }
+}
+
+#[derive(Debug)]
+pub struct FuncBmc {
+    pub fname: String,
+    pub outcome: BmcOutcome
+}
+impl Display for FuncBmc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            BmcOutcome::Exact { cases, traps } => write!(f, "{}: exact ({cases} input combinations, {traps} trapped)", self.fname),
+            BmcOutcome::Mismatch { inputs, expected, computed } => write!(f, "{}: MISMATCH, computes {computed} for ({}) but the original path costs {expected}",
+                                                                          self.fname, inputs.join(", ")),
+            BmcOutcome::Trapped { inputs, error } => write!(f, "{}: TRAPPED on ({}): {error}", self.fname, inputs.join(", ")),
+            BmcOutcome::Skipped { reason } => write!(f, "{}: skipped, {reason}", self.fname)
+        }
+    }
+}
+
+/// Run each target's generated function (in the exact-mode module `bytes_max`) on every combination
+/// of its inputs from the bounded domain, against the cost of the path of the original function
+/// (with the `cost_model` that the module was generated with). A generated function that traps is
+/// reported as its outcome, the others are still checked.
+pub(crate) fn model_check(bytes_max: &[u8], targets: &[BmcTarget], cost_model: &dyn OpCostModel) -> anyhow::Result<Vec<FuncBmc>> {
+    let engine = Engine::default();
+    let mut store = Store::new(&engine, ());
+    let max = Instance::new(&mut store, &Module::new(&engine, bytes_max)?, &[])?;
+
+    let mut results = Vec::new();
+    for target in targets.iter() {
+        let Some(func) = max.get_func(&mut store, &target.fname) else {
+            results.push(FuncBmc { fname: target.fname.clone(), outcome: BmcOutcome::Skipped { reason: "not exported".to_string() } });
+            continue;
+        };
+        let outcome = check_func(&mut store, func, target, cost_model);
+        results.push(FuncBmc { fname: target.fname.clone(), outcome });
+    }
+    Ok(results)
+}
+
+fn check_func(store: &mut Store<()>, func: Func, target: &BmcTarget, cost_model: &dyn OpCostModel) -> BmcOutcome {
+    // the params of the original function that are enumerated (the others are 0)
+    let locals: BTreeMap<u32, DataType> = target.param_locals.iter().copied().collect();
+    let domains: Vec<Vec<u64>> = locals.values().map(|ty| domain(*ty)).collect();
+    let num_cases = domains.iter().try_fold(1usize, |acc, domain| acc.checked_mul(domain.len())).unwrap_or(usize::MAX);
+    if num_cases > MAX_BMC_CASES {
+        return BmcOutcome::Skipped { reason: format!("{num_cases} input combinations, more than {MAX_BMC_CASES}") };
+    }
+
+    let (mut cases, mut traps) = (0, 0);
+    for case in 0..num_cases {
+        // the case-th combination, in mixed radix
+        let mut rest = case;
+        let mut values: HashMap<u32, u64> = HashMap::new();
+        for (lid, domain) in locals.keys().zip(domains.iter()) {
+            values.insert(*lid, domain[rest % domain.len()]);
+            rest /= domain.len();
+        }
+        let Some(expected) = path_cost(target.body, values.clone(), cost_model) else {
+            traps += 1;
+            continue;
+        };
+        cases += 1;
+
+        let inputs: Vec<Val> = target.param_locals.iter().map(|(lid, ty)| match ty {
+            DataType::I64 => Val::I64(values[lid] as i64),
+            _ => Val::I32(values[lid] as u32 as i32)
+        }).collect();
+        let named_inputs = || locals.iter().map(|(lid, ty)| match ty {
+            DataType::I64 => format!("local{lid} = {}", values[lid] as i64),
+            _ => format!("local{lid} = {}", values[lid] as u32 as i32)
+        }).collect();
+        let mut results = vec![Val::I64(0)];
+        if let Err(err) = func.call(&mut *store, &inputs, &mut results) {
+            return BmcOutcome::Trapped { inputs: named_inputs(), error: format!("{err:#}") };
+        }
+        let computed = results.first().and_then(|res| res.i64()).unwrap_or_default();
+        if computed != expected as i64 {
+            return BmcOutcome::Mismatch { inputs: named_inputs(), expected, computed };
+        }
+    }
+    BmcOutcome::Exact { cases, traps }
+}
+
+/// The bounded domain of an input: the small values, the powers of two (and their neighbours) and the
+/// extremes of the type, as raw bits (an i32 is zero-extended)
+fn domain(ty: DataType) -> Vec<u64> {
+    let bits = if ty == DataType::I64 { 64 } else { 32 };
+    let mut values: Vec<i64> = (-SMALL_BMC_VALUES..=SMALL_BMC_VALUES).collect();
+    for exp in 5..bits - 1 {
+        let pow = 1i64 << exp;
+        values.extend([pow - 1, pow, pow + 1, -pow]);
+    }
+    let (min, max) = if bits == 64 { (i64::MIN, i64::MAX) } else { (i32::MIN as i64, i32::MAX as i64) };
+    values.extend([min, min + 1, max - 1, max]);
+    let mut values: Vec<u64> = values.into_iter()
+        .map(|v| if bits == 64 { v as u64 } else { v as i32 as u32 as u64 })
+        .collect();
+    values.sort();
+    values.dedup();
+    values
+}
+
+/// The instructions that `path_cost` simulates
+fn is_modeled(op: &Operator) -> bool {
+    match op {
+        Operator::Block { blockty } | Operator::If { blockty } => !matches!(blockty, BlockType::FuncType(_)),
+        Operator::Else | Operator::End | Operator::Br { .. } | Operator::BrIf { .. } | Operator::BrTable { .. } |
+        Operator::Return | Operator::Unreachable | Operator::Nop | Operator::Drop | Operator::Select |
+        Operator::LocalGet { .. } | Operator::LocalSet { .. } | Operator::LocalTee { .. } => true,
+        op => {
+            let mut stack = vec![0; 2];
+            !matches!(numeric(op, &mut stack), Step::Unsupported)
+        }
+    }
+}
+
+/// The block around the instructions that are simulated
+struct Frame {
+    /// The instr_idx of its `end`
+    end: usize,
+    /// The height of the stack when it was entered
+    height: usize,
+    /// The number of its results
+    arity: usize
+}
+
+/// Simulate the loop-free `body` with the params in `locals` (the other locals are 0), returns the cost
+/// of the instructions it executes (None if it traps). A taken branch resumes after the `end` of its
+/// target and the `then` arm of an `if` resumes after its `end` at the `else`, these `end`s aren't
+/// executed (as in the generated code, which charges a block's cost before its `else`/`end`).
+fn path_cost(body: &[Operator], mut locals: HashMap<u32, u64>, cost_model: &dyn OpCostModel) -> Option<u64> {
+    // the `else` and `end` of each block
+    let mut elses: HashMap<usize, usize> = HashMap::new();
+    let mut ends: HashMap<usize, usize> = HashMap::new();
+    let mut open: Vec<usize> = vec![];
+    for (instr_idx, op) in body.iter().enumerate() {
+        match op {
+            Operator::Block { .. } | Operator::If { .. } => open.push(instr_idx),
+            Operator::Else => { elses.insert(*open.last()?, instr_idx); }
+            Operator::End => if let Some(start) = open.pop() { ends.insert(start, instr_idx); },
+            _ => {}
+        }
+    }
+
+    let mut cost = 0u64;
+    let mut stack: Vec<u64> = vec![];
+    let mut frames: Vec<Frame> = vec![];
+    let mut pc = 0;
+    while let Some(op) = body.get(pc) {
+        cost = cost.saturating_add(cost_model.cost(op));
+        pc = match op {
+            Operator::Block { blockty } | Operator::If { blockty } => {
+                // the condition is popped before the `if` is entered, it isn't part of the frame
+                let taken = match op {
+                    Operator::If { .. } => stack.pop()? as u32 != 0,
+                    _ => true
+                };
+                let frame = Frame { end: ends[&pc], height: stack.len(), arity: usize::from(matches!(blockty, BlockType::Type(_))) };
+                let end = frame.end;
+                frames.push(frame);
+                if taken {
+                    pc + 1
+                } else {
+                    match elses.get(&pc) {
+                        Some(else_idx) => else_idx + 1,
+                        None => {
+                            frames.pop();
+                            end + 1
+                        }
+                    }
+                }
+            }
+            // the end of the `then` arm
+            Operator::Else => branch(0, &mut frames, &mut stack)?,
+            Operator::End => {
+                if frames.pop().is_none() {
+                    // the end of the function
+                    return Some(cost);
+                }
+                pc + 1
+            }
+            Operator::Br { relative_depth } => branch(*relative_depth, &mut frames, &mut stack)?,
+            Operator::BrIf { relative_depth } => {
+                if stack.pop()? as u32 != 0 {
+                    branch(*relative_depth, &mut frames, &mut stack)?
+                } else {
+                    pc + 1
+                }
+            }
+            Operator::BrTable { targets } => {
+                let idx = stack.pop()? as u32;
+                let depth = targets.targets().nth(idx as usize).and_then(Result::ok).unwrap_or(targets.default());
+                branch(depth, &mut frames, &mut stack)?
+            }
+            Operator::Return => return Some(cost),
+            Operator::Unreachable => return None,
+            Operator::LocalGet { local_index } => {
+                stack.push(locals.get(local_index).copied().unwrap_or_default());
+                pc + 1
+            }
+            Operator::LocalSet { local_index } => {
+                locals.insert(*local_index, stack.pop()?);
+                pc + 1
+            }
+            Operator::LocalTee { local_index } => {
+                locals.insert(*local_index, *stack.last()?);
+                pc + 1
+            }
+            Operator::Drop => {
+                stack.pop()?;
+                pc + 1
+            }
+            Operator::Nop => pc + 1,
+            Operator::Select => {
+                let (cond, b, a) = (stack.pop()?, stack.pop()?, stack.pop()?);
+                stack.push(if cond as u32 != 0 { a } else { b });
+                pc + 1
+            }
+            op => match numeric(op, &mut stack) {
+                Step::Done => pc + 1,
+                Step::Trap | Step::Unsupported => return None
+            }
+        };
+    }
+    Some(cost)
+}
+
+/// Branch to the block `depth` levels out, returns where it resumes (`usize::MAX` out of the function)
+fn branch(depth: u32, frames: &mut Vec<Frame>, stack: &mut Vec<u64>) -> Option<usize> {
+    let Some(target) = frames.len().checked_sub(depth as usize + 1) else {
+        // out of the function, like a `return`
+        return Some(usize::MAX);
+    };
+    let frame = &frames[target];
+    let results = stack.split_off(stack.len().checked_sub(frame.arity)?);
+    stack.truncate(frame.height);
+    stack.extend(results);
+    let resume = frame.end + 1;
+    frames.truncate(target);
+    Some(resume)
+}
+
+enum Step {
+    Done,
+    Trap,
+    Unsupported
+}
+
+/// Apply the integer op to the stack (the values are raw bits, an i32 is zero-extended)
+fn numeric(op: &Operator, stack: &mut Vec<u64>) -> Step {
+    macro_rules! un32 {
+        ($f:expr) => {{
+            let f: fn(u32) -> u32 = $f;
+            let a = stack.pop().unwrap_or_default() as u32;
+            stack.push(f(a) as u64);
+        }};
+    }
+    macro_rules! bin32 {
+        ($f:expr) => {{
+            let f: fn(u32, u32) -> Option<u32> = $f;
+            let (b, a) = (stack.pop().unwrap_or_default() as u32, stack.pop().unwrap_or_default() as u32);
+            match f(a, b) {
+                Some(v) => stack.push(v as u64),
+                None => return Step::Trap
+            }
+        }};
+    }
+    macro_rules! un64 {
+        ($f:expr) => {{
+            let f: fn(u64) -> u64 = $f;
+            let a = stack.pop().unwrap_or_default();
+            stack.push(f(a));
+        }};
+    }
+    macro_rules! bin64 {
+        ($f:expr) => {{
+            let f: fn(u64, u64) -> Option<u64> = $f;
+            let (b, a) = (stack.pop().unwrap_or_default(), stack.pop().unwrap_or_default());
+            match f(a, b) {
+                Some(v) => stack.push(v),
+                None => return Step::Trap
+            }
+        }};
+    }
+    match op {
+        Operator::I32Const { value } => stack.push(*value as u32 as u64),
+        Operator::I64Const { value } => stack.push(*value as u64),
+
+        Operator::I32Eqz => un32!(|a| u32::from(a == 0)),
+        Operator::I32Eq => bin32!(|a, b| Some(u32::from(a == b))),
+        Operator::I32Ne => bin32!(|a, b| Some(u32::from(a != b))),
+        Operator::I32LtS => bin32!(|a, b| Some(u32::from((a as i32) < (b as i32)))),
+        Operator::I32LtU => bin32!(|a, b| Some(u32::from(a < b))),
+        Operator::I32GtS => bin32!(|a, b| Some(u32::from((a as i32) > (b as i32)))),
+        Operator::I32GtU => bin32!(|a, b| Some(u32::from(a > b))),
+        Operator::I32LeS => bin32!(|a, b| Some(u32::from((a as i32) <= (b as i32)))),
+        Operator::I32LeU => bin32!(|a, b| Some(u32::from(a <= b))),
+        Operator::I32GeS => bin32!(|a, b| Some(u32::from((a as i32) >= (b as i32)))),
+        Operator::I32GeU => bin32!(|a, b| Some(u32::from(a >= b))),
+        Operator::I32Clz => un32!(|a| a.leading_zeros()),
+        Operator::I32Ctz => un32!(|a| a.trailing_zeros()),
+        Operator::I32Popcnt => un32!(|a| a.count_ones()),
+        Operator::I32Add => bin32!(|a, b| Some(a.wrapping_add(b))),
+        Operator::I32Sub => bin32!(|a, b| Some(a.wrapping_sub(b))),
+        Operator::I32Mul => bin32!(|a, b| Some(a.wrapping_mul(b))),
+        Operator::I32DivS => bin32!(|a, b| (a as i32).checked_div(b as i32).map(|v| v as u32)),
+        Operator::I32DivU => bin32!(|a, b| a.checked_div(b)),
+        Operator::I32RemS => bin32!(|a, b| (b != 0).then(|| (a as i32).wrapping_rem(b as i32) as u32)),
+        Operator::I32RemU => bin32!(|a, b| a.checked_rem(b)),
+        Operator::I32And => bin32!(|a, b| Some(a & b)),
+        Operator::I32Or => bin32!(|a, b| Some(a | b)),
+        Operator::I32Xor => bin32!(|a, b| Some(a ^ b)),
+        Operator::I32Shl => bin32!(|a, b| Some(a.wrapping_shl(b))),
+        Operator::I32ShrS => bin32!(|a, b| Some((a as i32).wrapping_shr(b) as u32)),
+        Operator::I32ShrU => bin32!(|a, b| Some(a.wrapping_shr(b))),
+        Operator::I32Rotl => bin32!(|a, b| Some(a.rotate_left(b % 32))),
+        Operator::I32Rotr => bin32!(|a, b| Some(a.rotate_right(b % 32))),
+
+        Operator::I64Eqz => un64!(|a| u64::from(a == 0)),
+        Operator::I64Eq => bin64!(|a, b| Some(u64::from(a == b))),
+        Operator::I64Ne => bin64!(|a, b| Some(u64::from(a != b))),
+        Operator::I64LtS => bin64!(|a, b| Some(u64::from((a as i64) < (b as i64)))),
+        Operator::I64LtU => bin64!(|a, b| Some(u64::from(a < b))),
+        Operator::I64GtS => bin64!(|a, b| Some(u64::from((a as i64) > (b as i64)))),
+        Operator::I64GtU => bin64!(|a, b| Some(u64::from(a > b))),
+        Operator::I64LeS => bin64!(|a, b| Some(u64::from((a as i64) <= (b as i64)))),
+        Operator::I64LeU => bin64!(|a, b| Some(u64::from(a <= b))),
+        Operator::I64GeS => bin64!(|a, b| Some(u64::from((a as i64) >= (b as i64)))),
+        Operator::I64GeU => bin64!(|a, b| Some(u64::from(a >= b))),
+        Operator::I64Clz => un64!(|a| a.leading_zeros() as u64),
+        Operator::I64Ctz => un64!(|a| a.trailing_zeros() as u64),
+        Operator::I64Popcnt => un64!(|a| a.count_ones() as u64),
+        Operator::I64Add => bin64!(|a, b| Some(a.wrapping_add(b))),
+        Operator::I64Sub => bin64!(|a, b| Some(a.wrapping_sub(b))),
+        Operator::I64Mul => bin64!(|a, b| Some(a.wrapping_mul(b))),
+        Operator::I64DivS => bin64!(|a, b| (a as i64).checked_div(b as i64).map(|v| v as u64)),
+        Operator::I64DivU => bin64!(|a, b| a.checked_div(b)),
+        Operator::I64RemS => bin64!(|a, b| (b != 0).then(|| (a as i64).wrapping_rem(b as i64) as u64)),
+        Operator::I64RemU => bin64!(|a, b| a.checked_rem(b)),
+        Operator::I64And => bin64!(|a, b| Some(a & b)),
+        Operator::I64Or => bin64!(|a, b| Some(a | b)),
+        Operator::I64Xor => bin64!(|a, b| Some(a ^ b)),
+        Operator::I64Shl => bin64!(|a, b| Some(a.wrapping_shl(b as u32))),
+        Operator::I64ShrS => bin64!(|a, b| Some((a as i64).wrapping_shr(b as u32) as u64)),
+        Operator::I64ShrU => bin64!(|a, b| Some(a.wrapping_shr(b as u32))),
+        Operator::I64Rotl => bin64!(|a, b| Some(a.rotate_left((b % 64) as u32))),
+        Operator::I64Rotr => bin64!(|a, b| Some(a.rotate_right((b % 64) as u32))),
+
+        Operator::I32WrapI64 => un64!(|a| a as u32 as u64),
+        Operator::I64ExtendI32S => un64!(|a| a as u32 as i32 as i64 as u64),
+        Operator::I64ExtendI32U => un64!(|a| a as u32 as u64),
+        Operator::I32Extend8S => un32!(|a| a as i8 as i32 as u32),
+        Operator::I32Extend16S => un32!(|a| a as i16 as i32 as u32),
+        Operator::I64Extend8S => un64!(|a| a as i8 as i64 as u64),
+        Operator::I64Extend16S => un64!(|a| a as i16 as i64 as u64),
+        Operator::I64Extend32S => un64!(|a| a as i32 as i64 as u64),
+        _ => return Step::Unsupported
+    }
+    Step::Done
+}
@@ -0,0 +1,127 @@
+// the certificates are only written to (and read from) files
+#![cfg_attr(not(feature = "fs"), allow(dead_code))]
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wirm::Module;
+use wirm::wasmparser::Operator;
+use crate::analyze::FuncState;
+use crate::error::AnalysisError;
+use crate::run::AnalysisConfig;
+use crate::utils::local_ops;
+
+pub const TOOL_NAME: &str = "whamm-fuel";
+
+/// An audit artifact for gas certification: ties the static worst-case costs of each
+/// function to the exact module and configuration they were computed for.
+/// Signing is left to the attestation tooling, which should sign the `digest`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuelCertificate {
+    pub tool: String,
+    pub version: String,
+    /// sha256 of the analyzed module
+    pub input_sha256: String,
+    pub config: CertConfig,
+    pub functions: Vec<FuncCost>,
+    /// sha256 of the certificate's JSON encoding (with this field empty)
+    pub digest: String
+}
+
+/// The options of the `AnalysisConfig` that the certified costs depend on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertConfig {
+    pub comp_type: String,
+    pub charge_dropped: bool,
+    /// The `Debug` description of the cost model, e.g. `Uniform(2)`
+    #[serde(default)]
+    pub cost_model: String,
+    #[serde(default)]
+    pub granularity: Option<u64>,
+    #[serde(default)]
+    pub loop_depth_multiplier: u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuncCost {
+    pub fid: u32,
+    /// The worst-case fuel for a single execution of the function body (excluding callees).
+    /// None if the function contains a loop, the cost is then bounded by the input.
+    pub worst_case: Option<u64>
+}
+
+impl FuelCertificate {
+    /// The certificate of the `cost_maps` that were generated with the `config`
+    pub fn new(wasm_bytes: &[u8], funcs: &[FuncState], cost_maps: &[BTreeMap<usize, u64>], config: &AnalysisConfig, wasm: &Module) -> Result<Self, AnalysisError> {
+        let mut cert = Self {
+            tool: TOOL_NAME.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            input_sha256: sha256(wasm_bytes),
+            config: CertConfig {
+                comp_type: config.comp_type.to_string(),
+                charge_dropped: config.charge_dropped,
+                cost_model: format!("{:?}", config.cost_model),
+                granularity: config.granularity,
+                loop_depth_multiplier: config.loop_depth_multiplier
+            },
+            functions: worst_case_costs(funcs, cost_maps, wasm)?,
+            digest: String::new()
+        };
+        cert.digest = cert.compute_digest();
+        Ok(cert)
+    }
+
+    pub fn compute_digest(&self) -> String {
+        let mut unsigned = self.clone();
+        unsigned.digest.clear();
+        sha256(serde_json::to_string(&unsigned).unwrap().as_bytes())
+    }
+
+    /// Compare against a freshly recomputed certificate, returns a description of each mismatch.
+    pub fn diff(&self, expected: &FuelCertificate) -> Vec<String> {
+        let mut mismatches = vec![];
+        if self.digest != self.compute_digest() {
+            mismatches.push(format!("digest does not match the certificate contents: {}", self.digest));
+        }
+        if self.input_sha256 != expected.input_sha256 {
+            mismatches.push(format!("input hash: certified {}, actual {}", self.input_sha256, expected.input_sha256));
+        }
+        if self.tool != expected.tool || self.version != expected.version {
+            mismatches.push(format!("tool: certified {} {}, actual {} {}", self.tool, self.version, expected.tool, expected.version));
+        }
+        if self.config != expected.config {
+            mismatches.push(format!("config: certified {:?}, actual {:?}", self.config, expected.config));
+        }
+        let actual: BTreeMap<u32, Option<u64>> = expected.functions.iter().map(|f| (f.fid, f.worst_case)).collect();
+        for func in self.functions.iter() {
+            match actual.get(&func.fid) {
+                Some(cost) if *cost == func.worst_case => {}
+                Some(cost) => mismatches.push(format!("function #{}: certified {:?}, actual {:?}", func.fid, func.worst_case, cost)),
+                None => mismatches.push(format!("function #{}: not in the module", func.fid))
+            }
+        }
+        if self.functions.len() != expected.functions.len() {
+            mismatches.push(format!("certified {} functions, module has {}", self.functions.len(), expected.functions.len()));
+        }
+        mismatches
+    }
+}
+
+fn worst_case_costs(funcs: &[FuncState], cost_maps: &[BTreeMap<usize, u64>], wasm: &Module) -> Result<Vec<FuncCost>, AnalysisError> {
+    let mut costs = vec![];
+    for (func, cost_map) in funcs.iter().zip(cost_maps.iter()) {
+        let body = local_ops(func.fid, wasm)?;
+        // without loops, every instruction is charged at most once per execution
+        let worst_case = if body.iter().any(|op| matches!(op, Operator::Loop {..})) {
+            None
+        } else {
+            Some(cost_map.values().sum())
+        };
+        costs.push(FuncCost { fid: func.fid, worst_case });
+    }
+    Ok(costs)
+}
+
+pub(crate) fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
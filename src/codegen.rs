@@ -1,51 +1,207 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::{Display, Formatter};
-use std::hash::Hash;
+use serde::{Deserialize, Serialize};
 use wirm::{DataType, Module, Opcode};
 use wirm::ir::function::FunctionBuilder;
-use wirm::ir::id::{FunctionID, LocalID};
-use wirm::ir::types::BlockType;
+use wirm::ir::id::{FunctionID, GlobalID, LocalID};
+use wirm::ir::module::module_globals::{GlobalKind, ImportedGlobal, LocalGlobal};
+use wirm::ir::types::{BlockType, InitExpr, InitInstr, Value};
 use wirm::module_builder::AddLocal;
-use wirm::wasmparser::Operator;
+use wirm::opcode::Inject;
+use wirm::wasmparser::{self, Catch, HeapType, Operator, ValType};
 use crate::analyze::FuncState;
-use crate::run::CompType;
-use crate::slice::{Slice, SliceResult};
-use crate::utils::is_branching_op;
+use crate::codegen::dispatch::gen_dispatch;
+use crate::cost_expr::CostExpr;
+use crate::cost_model::OpCostModel;
+use crate::error::AnalysisError;
+use crate::globals::ConstValue;
+use crate::replay::copy_replayed;
+use crate::run::{AnalysisConfig, BlockWrapper, CompType};
+use crate::serial::data_types;
+use crate::sidecar::checkpoint_id;
+use crate::slice::{GlobalSource, Slice, SliceResult};
+use crate::utils::{dyn_cost_per_elem, is_branching_op, is_tail_call, fuel_const, local_func, local_ops};
 
-pub fn codegen<'a, 'b>(ty: &CompType, slices: &mut [SliceResult],
+/// The host function that the generated functions call when a charge exhausts the fuel (with
+/// `AnalysisConfig::on_out_of_fuel`): `env.on_out_of_fuel() -> i64`, the fuel it returns is added
+/// back to the remaining fuel (e.g. after yielding to a scheduler), 0 to leave it exhausted.
+pub const OUT_OF_FUEL_MODULE: &str = "env";
+pub const OUT_OF_FUEL_FUNC: &str = "on_out_of_fuel";
+/// The immutable i64 global holding the worst-case cost of function N (if it's fully static) is
+/// exported as `max_static_cost_fn_<N>` (with `AnalysisConfig::export_static_costs`)
+pub const STATIC_COST_PREFIX: &str = "max_static_cost_fn_";
+/// The generated functions store the value that would be written to the mutation sink N (see
+/// `AnalysisConfig::mutation_sinks`) into the mutable global exported as `mutation_global_<N>`
+pub const MUTATION_PREFIX: &str = "mutation_global_";
+
+#[allow(clippy::too_many_arguments)]
+pub fn codegen<'a, 'b>(config: &AnalysisConfig, slices: &mut [SliceResult],
                        new_state: fn(&Slice) -> (CodeGenState, Vec<DataType>),
                        in_slice: fn(usize, &Slice) -> bool,
                        gen_op: fn(usize, &Operator<'a>, &LocalID, &CodeGenState, &mut FunctionBuilder<'b>),
-                       funcs: &[FuncState], wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> CodeGenResult where 'a : 'b {
-    let mut func_map = HashMap::new();
+                       funcs: &[FuncState], wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> Result<CodeGenResult, AnalysisError> where 'a : 'b {
+    let mut func_map = BTreeMap::new();
     // maps from `instr_idx` -> cost of block
     let mut cost_maps = Vec::new();
+    // maps from `instr_idx` -> the instructions whose cost was accumulated into that block
+    let mut charge_maps = Vec::new();
+    let mut fids = Vec::new();
+    let shared = SharedGen::new(config, slices, wasm, gen_wasm)?;
     for (func_slices, func) in slices.iter_mut().zip(funcs.iter()) {
-        // We're going to have one instance of cost_map per function because it's tied to the
-        // ORIGINAL function, not the generated functions (there can be many per original function
-        // due to how we handle `loop` blocks.
-        let mut cost_map = HashMap::new();
-        let lf = wasm.functions.unwrap_local(FunctionID(func.fid));
-
-        let body = &lf.body.instructions;
-
-        let generated_funcs = gen_from_slices(func.fid, body.get_ops(), func_slices, new_state, in_slice, gen_op, &mut cost_map, ty, gen_wasm);
+        fids.push(func.fid);
+        if func_slices.shim.is_some() {
+            // toolchain glue isn't metered, nothing is generated for it
+            cost_maps.push(BTreeMap::new());
+            charge_maps.push(BTreeMap::new());
+            continue;
+        }
+        let FuncGen { cost_map, charge_map, generated_funcs } = gen_for_func(config, func_slices, new_state, in_slice, gen_op, &shared, wasm, gen_wasm)?;
         func_map.insert(func.fid, generated_funcs);
-
         cost_maps.push(cost_map);
+        charge_maps.push(charge_map);
+    }
+    if config.export_static_costs {
+        // the callees are composed in, so every function is needed first
+        let mut formulas: BTreeMap<u32, CostExpr> = BTreeMap::new();
+        for (fid, charge_map) in fids.iter().zip(charge_maps.iter()) {
+            if let Some(formula) = static_formula(*fid, local_ops(*fid, wasm)?, charge_map) {
+                formulas.insert(*fid, formula);
+            }
+        }
+        // the toolchain glue isn't metered (it costs nothing to its callers) and gets no export
+        for fid in func_map.keys() {
+            export_static_cost(*fid, &formulas, gen_wasm);
+        }
+    }
+    if config.dispatch {
+        // Only export a single entry point that calls the generated functions
+        gen_dispatch(&mut func_map, gen_wasm);
     }
 
-    CodeGenResult {
+    Ok(CodeGenResult {
         cost_maps,
+        charge_maps,
+        fids,
         func_map
+    })
+}
+
+/// What the generated functions of every original function share in the generated module
+pub(crate) struct SharedGen {
+    // imported before any function is generated, see `OUT_OF_FUEL_FUNC`
+    out_of_fuel: Option<FunctionID>,
+    // the replayed calls call a copy of their callee
+    replayed: BTreeMap<u32, FunctionID>,
+    mutations: BTreeMap<u32, GlobalID>
+}
+impl SharedGen {
+    /// Adds the shared pieces to `gen_wasm`, the callees of the replayed calls in `slices` are copied
+    pub(crate) fn new<'a, 'b>(config: &AnalysisConfig, slices: &[SliceResult], wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> Result<Self, AnalysisError> where 'a: 'b {
+        // the hook only makes sense when counting down
+        let out_of_fuel = (config.on_out_of_fuel && config.init_fuel.is_some()).then(|| {
+            let ty = gen_wasm.types.add_func_type(&[], &[DataType::I64]);
+            gen_wasm.add_import_func(OUT_OF_FUEL_MODULE.to_string(), OUT_OF_FUEL_FUNC.to_string(), ty).0
+        });
+        let replayed = if config.replay_pure_calls { copy_replayed(slices, wasm, gen_wasm)? } else { BTreeMap::new() };
+        let mutations = gen_mutation_globals(config, wasm, gen_wasm);
+        Ok(Self {
+            out_of_fuel,
+            replayed,
+            mutations
+        })
+    }
+}
+
+/// The code generated for a single original function
+pub(crate) struct FuncGen {
+    pub(crate) cost_map: BTreeMap<usize, u64>,
+    pub(crate) charge_map: BTreeMap<usize, Vec<(usize, u64)>>,
+    pub(crate) generated_funcs: Vec<GeneratedFunc>
+}
+
+/// Generate the fuel computations of the slices of a single function (`func_slices`)
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn gen_for_func<'a, 'b>(config: &AnalysisConfig, func_slices: &SliceResult,
+                                   new_state: fn(&Slice) -> (CodeGenState, Vec<DataType>),
+                                   in_slice: fn(usize, &Slice) -> bool,
+                                   gen_op: fn(usize, &Operator<'a>, &LocalID, &CodeGenState, &mut FunctionBuilder<'b>),
+                                   shared: &SharedGen, wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> Result<FuncGen, AnalysisError> where 'a : 'b {
+    let fid = func_slices.fid;
+    // We're going to have one instance of cost_map per function because it's tied to the
+    // ORIGINAL function, not the generated functions (there can be many per original function
+    // due to how we handle `loop` blocks.
+    let mut cost_map = BTreeMap::new();
+    let mut charge_map = BTreeMap::new();
+    let lf = local_func(fid, wasm)?;
+
+    let body = &lf.body.instructions;
+
+    let generated_funcs = gen_from_slices(fid, body.get_ops(), func_slices, new_state, in_slice, gen_op, &mut cost_map, &mut charge_map, config, shared.out_of_fuel, &shared.replayed, &shared.mutations, gen_wasm);
+    if !config.dispatch {
+        // Export the functions so they can be called externally
+        for generated in generated_funcs.iter() {
+            gen_wasm.exports.add_export_func(generated.fname.clone(), generated.fid);
+        }
+    }
+    Ok(FuncGen {
+        cost_map,
+        charge_map,
+        generated_funcs
+    })
+}
+
+/// The copies of the mutation sinks (original GID -> GID of the copy), zeroed and exported
+fn gen_mutation_globals(config: &AnalysisConfig, wasm: &Module, gen_wasm: &mut Module) -> BTreeMap<u32, GlobalID> {
+    let mut mutations = BTreeMap::new();
+    for gid in config.mutation_sinks.iter() {
+        let (GlobalKind::Local(LocalGlobal { ty, .. }) |
+            GlobalKind::Import(ImportedGlobal { ty, .. })) = wasm.globals.get_kind(GlobalID(*gid));
+        let ty = DataType::from(ty.content_type);
+        let zero = match ty {
+            DataType::I32 => Value::I32(0),
+            DataType::I64 => Value::I64(0),
+            DataType::F32 => Value::F32(0.0),
+            DataType::F64 => Value::F64(0.0),
+            // rejected before slicing
+            _ => continue
+        };
+        let copy = gen_wasm.add_global(InitExpr::new(vec![InitInstr::Value(zero)]), ty, true, false);
+        gen_wasm.exports.add_export_global(format!("{MUTATION_PREFIX}{gid}"), *copy);
+        mutations.insert(*gid, copy);
     }
+    mutations
 }
 
+/// The formula of the worst-case cost of a single execution of function `fid` (see `CostExpr::of_func`),
+/// None if it can never be static: an op whose cost depends on a runtime length or an indirect call
+/// (the callee is unknown).
+pub(crate) fn static_formula(fid: u32, body: &[Operator], charge_map: &BTreeMap<usize, Vec<(usize, u64)>>) -> Option<CostExpr> {
+    let indirect = |op: &Operator| matches!(op, Operator::CallIndirect {..} | Operator::ReturnCallIndirect {..} | Operator::CallRef {..} | Operator::ReturnCallRef {..});
+    if body.iter().any(|op| indirect(op) || dyn_cost_per_elem(op).is_some()) {
+        return None;
+    }
+    Some(CostExpr::of_func(fid, body, charge_map))
+}
+
+/// Export the worst-case cost of a single execution of function `fid` (including its callees) if it
+/// doesn't depend on the inputs: the worst arm of each `if`, without loops and with the worst case of
+/// every callee in `formulas` (see `static_formula`). The functions that call an import, a function
+/// without a formula or themselves (recursively) aren't exported.
+pub(crate) fn export_static_cost(fid: u32, formulas: &BTreeMap<u32, CostExpr>, gen_wasm: &mut Module) {
+    let Some(cost) = formulas.get(&fid).and_then(|formula| formula.compose(formulas).eval(&|_, _| None)) else {
+        return;
+    };
+    let gid = gen_wasm.add_global(InitExpr::new(vec![InitInstr::Value(Value::I64(fuel_const(cost)))]), DataType::I64, false, false);
+    gen_wasm.exports.add_export_global(format!("{STATIC_COST_PREFIX}{fid}"), *gid);
+}
+
+#[allow(clippy::too_many_arguments)]
 fn gen_from_slices<'a, 'b>(orig_fid: u32, body: &[Operator<'a>], func_slices: &SliceResult,
                            new_state: fn(&Slice) -> (CodeGenState, Vec<DataType>),
                            in_slice: fn(usize, &Slice) -> bool,
                            gen_op: fn(usize, &Operator<'a>, &LocalID, &CodeGenState, &mut FunctionBuilder<'b>),
-                           cost_map: &mut HashMap<usize, u64>, ty: &CompType, gen_wasm: &mut Module<'b>) -> Vec<GeneratedFunc> where 'a: 'b {
+                           cost_map: &mut BTreeMap<usize, u64>, charge_map: &mut BTreeMap<usize, Vec<(usize, u64)>>, config: &AnalysisConfig, out_of_fuel: Option<FunctionID>,
+                           replayed: &BTreeMap<u32, FunctionID>, mutations: &BTreeMap<u32, GlobalID>, gen_wasm: &mut Module<'b>) -> Vec<GeneratedFunc> where 'a: 'b {
     let mut generated_funcs = vec![];
 
     let mut i = 0;
@@ -53,7 +209,7 @@ fn gen_from_slices<'a, 'b>(orig_fid: u32, body: &[Operator<'a>], func_slices: &S
         if let Some(slice) = func_slices.slices.get(&i) {
             // I know I need to generate a function for this slice!
             let subsec = &body[slice.start_instr_idx..slice.end_instr_idx];
-            gen_func(slice.start_instr_idx, &slice.spec_name, cost_map, orig_fid, subsec, slice, new_state, in_slice, gen_op, func_slices, ty, gen_wasm, &mut generated_funcs);
+            gen_func(slice.start_instr_idx, &slice.spec_name, cost_map, charge_map, orig_fid, subsec, slice, new_state, in_slice, gen_op, func_slices, config, out_of_fuel, replayed, mutations, gen_wasm, &mut generated_funcs);
         }
         i += 1;
     }
@@ -61,89 +217,481 @@ fn gen_from_slices<'a, 'b>(orig_fid: u32, body: &[Operator<'a>], func_slices: &S
     generated_funcs
 }
 
-fn gen_func<'a, 'b>(true_start_idx: usize, spec_name: &str, cost_map: &mut HashMap<usize, u64>, orig_fid: u32, body: &[Operator<'a>], slice: &Slice,
+#[allow(clippy::too_many_arguments)]
+fn gen_func<'a, 'b>(true_start_idx: usize, spec_name: &str, cost_map: &mut BTreeMap<usize, u64>, charge_map: &mut BTreeMap<usize, Vec<(usize, u64)>>, orig_fid: u32, body: &[Operator<'a>], slice: &Slice,
                     new_state: fn(&Slice) -> (CodeGenState, Vec<DataType>),
                     in_slice: fn(usize, &Slice) -> bool,
                     gen_op: fn(usize, &Operator<'a>, &LocalID, &CodeGenState, &mut FunctionBuilder<'b>),
-                    func_slices: &SliceResult, ty: &CompType, gen_wasm: &mut Module<'b>, generated_funcs: &mut Vec<GeneratedFunc>) where 'a: 'b {
-    let (mut state, used_params) = new_state(slice);     // one instance of state per function!
+                    func_slices: &SliceResult, config: &AnalysisConfig, out_of_fuel: Option<FunctionID>, replayed: &BTreeMap<u32, FunctionID>, mutations: &BTreeMap<u32, GlobalID>,
+                    gen_wasm: &mut Module<'b>, generated_funcs: &mut Vec<GeneratedFunc>) where 'a: 'b {
+    let (ty, init_fuel, cost_model) = (&config.comp_type_of(orig_fid), config.init_fuel, config.cost_model.as_ref());
+    let epoch = matches!(ty, CompType::Epoch);
+    let coarse = matches!(ty, CompType::Epoch | CompType::BackEdge);
+    let (mut state, used_params) = if coarse {
+        // only charges a static estimate, no state is requested
+        (CodeGenState::default(), vec![])
+    } else {
+        new_state(slice)     // one instance of state per function!
+    };
     let fuel_ty = DataType::I64;
-    let mut new_func = FunctionBuilder::new(&used_params, &[fuel_ty.clone()]);
-    let fuel = new_func.add_local(fuel_ty.clone());
+    let mut new_func = FunctionBuilder::new(&used_params, &[fuel_ty]);
+    state.fallback = func_slices.fallback.clone();
+    state.init_fuel = init_fuel;
+    state.out_of_fuel = out_of_fuel;
+    state.replayed = replayed.clone();
+    state.granularity = config.granularity;
+    state.cost_multiplier = match ty {
+        CompType::Exact | CompType::Epoch | CompType::BackEdge => 1,
+        CompType::Approx => config.loop_depth_multiplier.saturating_pow(slice.loop_depth)
+    };
+    if coarse || func_slices.fallback.is_some() || matches!(body, [] | [Operator::End]) {
+        // Degenerate slice (e.g. a function body that's only `end`), a function that the analysis
+        // can't model or coarse metering: there's nothing to compute, the generated function just
+        // returns a fixed cost. The epoch metering charges it on entry (into the function, or into
+        // each iteration of the loop), otherwise the whole body is charged at its last instruction
+        // (e.g. the back edge of the loop).
+        let fuel_point = body.len().checked_sub(1).map(|last| if epoch { true_start_idx } else { true_start_idx + last });
+        if let Some(fuel_point) = fuel_point {
+            gen_checkpoint(checkpoint_id(orig_fid, fuel_point), config, &mut state, &mut new_func);
+        }
+        gen_const_func(true_start_idx, fuel_point, body, slice, func_slices, cost_map, charge_map, cost_model, &mut state, &mut new_func);
+    } else {
+        let fuel = new_func.add_local(fuel_ty);
+        if let Some(init_fuel) = init_fuel {
+            // count down from the budget, the function returns the remaining fuel
+            // (checked to fit an i64 before the analysis)
+            new_func.i64_const(init_fuel as i64);
+            new_func.local_set(fuel);
+        }
+        state.shared_consts = share_consts(body, slice, in_slice, func_slices, config.dedup_const_min_uses, &mut new_func);
+        state.throw_targets = throw_targets(body, true_start_idx);
 
-    // Wrap the function with a block/end to simplify handling of branching from a function
-    // (through br depth rather than return opcode)
-    // new_func.block(BlockType::Type(fuel_ty));
-    new_func.block(BlockType::Empty);
+        // Wrap the function with a block/end to simplify handling of branching from a function
+        // (through br depth rather than return opcode), see `BlockWrapper`
+        let depths = nesting_depths(body);
+        // the typed blocks whose results the slice doesn't compute are generated without them
+        let untyped_blocks: HashSet<usize> = block_ends(body).into_iter()
+            .map(|(start, end)| (true_start_idx + start, true_start_idx + end))
+            .filter(|(_, end)| !slice.merged_results.get(end).is_some_and(|producers| {
+                producers.iter().any(|instr_idx| in_slice(*instr_idx, slice) || slice.instrs_support.contains(instr_idx))
+            }))
+            .map(|(start, _)| start)
+            .collect();
+        // a loop body can branch out of the loop (to the labels around it), these branches end the
+        // iteration: they exit the empty blocks that stand in for the labels
+        let outer_labels = body.iter().zip(depths.iter()).map(|(op, depth)| labels_beyond(op, *depth)).max().unwrap_or(0);
+        let typed = config.block_wrapper == BlockWrapper::Typed && outer_labels == 0 && body.iter().zip(depths.iter())
+            .all(|(op, depth)| matches!(op, Operator::Br { .. } | Operator::BrIf { .. }) || !exits_wrapper(op, *depth));
+        for _ in 0..outer_labels {
+            new_func.block(BlockType::Empty);
+        }
+        new_func.block(if typed { BlockType::Type(fuel_ty) } else { BlockType::Empty });
 
-    let mut i = 0;
-    while i < body.len() {
-        let mut true_instr_idx = true_start_idx + i;
-        if true_instr_idx != slice.start_instr_idx {
-            if let Some(subslice) = func_slices.slices.get(&true_instr_idx) {
-                // if there's a subslice here, skip over its instructions
-                i = subslice.end_instr_idx + 1;
-                true_instr_idx = true_start_idx + i;
+        let mut i = 0;
+        while i < body.len() {
+            let mut true_instr_idx = true_start_idx + i;
+            let mut skipped_loop = None;
+            if true_instr_idx != slice.start_instr_idx {
+                if let Some(subslice) = func_slices.slices.get(&true_instr_idx) {
+                    // if there's a subslice here, skip over its instructions (it's a loop's body):
+                    // resume at the loop's `end`, which runs once on the way out so this slice charges it
+                    i = subslice.end_instr_idx - true_start_idx;
+                    true_instr_idx = subslice.end_instr_idx;
+                    skipped_loop = Some(subslice.start_instr_idx - 1);
+                }
             }
-        }
 
-        let op = &body[i];
+            let op = &body[i];
 
-        let in_slice = in_slice(true_instr_idx, slice);
-        let in_support = slice.instrs_support.contains(&true_instr_idx);
-        let do_fuel_before = calc_op_cost(in_slice | in_support, i == body.len() - 1, op, &mut state);
+            if let Some(inputs) = slice.criteria.get(&true_instr_idx) {
+                let computed = inputs.iter().filter(|input| in_slice(**input, slice) || slice.instrs_support.contains(input)).count();
+                match op {
+                    Operator::GlobalSet { global_index } if computed == 1 && mutations.contains_key(global_index) => {
+                        // A mutation sink, keep the value that would be written in its copy
+                        new_func.global_set(mutations[global_index]);
+                    }
+                    _ => {
+                        // A user-specified criterion (`--slice-at`) isn't replayed, drop the inputs that were computed
+                        for _ in 0..computed {
+                            new_func.drop();
+                        }
+                    }
+                }
+            }
 
-        if do_fuel_before {
-            // Generate the fuel decrement
-            let cost = state.curr_cost;
-            gen_fuel_comp(&fuel, ty, &mut state, &mut new_func);
-            state.reset_cost();
-            cost_map.insert(true_instr_idx, cost);
-        }
+            let (in_slice, in_support) = match skipped_loop {
+                // the loop's `end` is only emitted along with its `loop`
+                Some(loop_idx) => (false, in_slice(loop_idx, slice) || slice.instrs_support.contains(&loop_idx)),
+                None => (in_slice(true_instr_idx, slice), slice.instrs_support.contains(&true_instr_idx))
+            };
+            let is_dropped = !config.charge_dropped && func_slices.dropped.contains(&true_instr_idx);
+            let do_fuel_before = calc_op_cost(true_instr_idx, is_dropped, in_slice | in_support, i == body.len() - 1, op, cost_model, &mut state);
+
+            if do_fuel_before {
+                // Generate the fuel decrement
+                gen_checkpoint(checkpoint_id(orig_fid, true_instr_idx), config, &mut state, &mut new_func);
+                let (cost, charged) = state.take_charge();
+                gen_fuel_comp(&fuel, ty, cost, &state, &mut new_func);
+                cost_map.insert(true_instr_idx, cost);
+                charge_map.insert(true_instr_idx, charged);
+            }
 
-        if in_slice | in_support {
-            // Generate opcode that needs to be placed here in the generated function
-            gen_op(true_instr_idx, op, &fuel, &state, &mut new_func);
+            if let (Some(req), Some(_)) = (state.for_lengths.get(&true_instr_idx), dyn_cost_per_elem(op)) {
+                // Charge for the length of the bulk op (passed in as a param)
+                gen_dyn_cost(&fuel, cost_model.cost_per_elem(op), req, slice.lengths[&true_instr_idx], &state, &mut new_func);
+            }
+
+            if in_slice | in_support {
+                if let Some(local) = state.shared_consts.get(&true_instr_idx) {
+                    // The constant was computed once at the start of the function
+                    new_func.local_get(*local);
+                } else if typed && exits_wrapper(op, depths[i]) {
+                    // The typed block only yields the fuel, return it instead of branching
+                    if let Operator::BrIf { .. } = op {
+                        new_func.if_stmt(BlockType::Empty);
+                    }
+                    new_func.local_get(fuel);
+                    new_func.return_stmt();
+                    if let Operator::BrIf { .. } = op {
+                        new_func.end();
+                    }
+                } else if untyped_blocks.contains(&true_instr_idx) {
+                    gen_op(true_instr_idx, &without_results(op), &fuel, &state, &mut new_func);
+                } else {
+                    // Generate opcode that needs to be placed here in the generated function
+                    gen_op(true_instr_idx, op, &fuel, &state, &mut new_func);
+                }
+            }
+            i += 1;
+        }
+        if typed {
+            // the block yields the fuel count
+            new_func.local_get(fuel);
+            new_func.end();
+        } else {
+            // END the added, wrapping block (see above)
+            new_func.end();
+            for _ in 0..outer_labels {
+                new_func.end();
+            }
+            // return the fuel count
+            new_func.local_get(fuel);
         }
-        i += 1;
     }
-    // END the added, wrapping block (see above)
-    new_func.end();
-    // return the fuel count
-    new_func.local_get(fuel);
 
     // add the function to the `gen_wasm` and save the fid mapping
     let new_fid = new_func.finish_module(gen_wasm);
 
-    // Export the function so it can be called externally
-    // Gets named tyN, where:
+    // The function gets named tyN (it's exported with this name, see `codegen`), where:
     // - ty is the name of the type of gas calculation (exact or approximate)
     // - N is the original function's ID
     let fname = format!("{}{}{}", ty, orig_fid, spec_name);
-    gen_wasm.exports.add_export_func(
-        fname.clone(),
-        *new_fid
-    );
     state.fid = *new_fid;
     state.fname = fname.clone();
+    state.params = used_params;
 
     generated_funcs.push(GeneratedFunc::from(state));
 }
 
+/// The number of blocks around each op of `body` (its own block excluded)
+fn nesting_depths(body: &[Operator]) -> Vec<u32> {
+    let mut depth: u32 = 0;
+    body.iter().map(|op| {
+        if let Operator::End = op {
+            depth = depth.saturating_sub(1);
+        }
+        let at = depth;
+        if matches!(op, Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } | Operator::TryTable { .. }) {
+            depth += 1;
+        }
+        at
+    }).collect()
+}
+
+/// The index of each block (`block`, `loop`, `if`, `try_table`) of the `body` and of its `end`
+fn block_ends(body: &[Operator]) -> Vec<(usize, usize)> {
+    let mut open = vec![];
+    let mut ends = vec![];
+    for (instr_idx, op) in body.iter().enumerate() {
+        match op {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } | Operator::TryTable { .. } => open.push(instr_idx),
+            Operator::End => if let Some(start) = open.pop() {
+                ends.push((start, instr_idx));
+            }
+            _ => {}
+        }
+    }
+    ends
+}
+
+/// The block `op` without its params and results
+fn without_results<'a>(op: &Operator<'a>) -> Operator<'a> {
+    match op {
+        Operator::Block { .. } => Operator::Block { blockty: wasmparser::BlockType::Empty },
+        Operator::Loop { .. } => Operator::Loop { blockty: wasmparser::BlockType::Empty },
+        Operator::If { .. } => Operator::If { blockty: wasmparser::BlockType::Empty },
+        Operator::TryTable { try_table } => {
+            let mut try_table = try_table.clone();
+            try_table.ty = wasmparser::BlockType::Empty;
+            Operator::TryTable { try_table }
+        }
+        _ => op.clone()
+    }
+}
+
+/// Whether the op at `depth` branches to the label around the body (the function's, or the loop's
+/// for a loop body), it exits the wrapping block of the generated function.
+fn exits_wrapper(op: &Operator, depth: u32) -> bool {
+    match op {
+        Operator::Br { relative_depth } | Operator::BrIf { relative_depth }
+        | Operator::BrOnNull { relative_depth } | Operator::BrOnNonNull { relative_depth }
+        | Operator::BrOnCast { relative_depth, .. } | Operator::BrOnCastFail { relative_depth, .. } => *relative_depth == depth,
+        Operator::BrTable { targets } => targets.targets().flatten().chain([targets.default()]).any(|target| target == depth),
+        _ => false
+    }
+}
+
+/// How many labels beyond the wrapping block the op at `depth` branches to: only a loop body has
+/// labels around it (its loop's label is the wrapper)
+fn labels_beyond(op: &Operator, depth: u32) -> u32 {
+    match op {
+        Operator::Br { relative_depth } | Operator::BrIf { relative_depth }
+        | Operator::BrOnNull { relative_depth } | Operator::BrOnNonNull { relative_depth }
+        | Operator::BrOnCast { relative_depth, .. } | Operator::BrOnCastFail { relative_depth, .. } => relative_depth.saturating_sub(depth),
+        Operator::BrTable { targets } => targets.targets().flatten().chain([targets.default()]).map(|target| target.saturating_sub(depth)).max().unwrap_or(0),
+        _ => 0
+    }
+}
+
+/// Record the checkpoint of a fuel point, if requested it's also labeled in the generated code
+/// with a no-op: `i64.const <checkpoint>; drop`
+fn gen_checkpoint(checkpoint: u64, config: &AnalysisConfig, state: &mut CodeGenState, func: &mut FunctionBuilder) {
+    state.checkpoints.push(checkpoint);
+    if config.checkpoints {
+        func.i64_const(checkpoint as i64);
+        func.drop();
+    }
+}
+
+/// The worst-case cost of the body (ignoring control flow), charged at the `fuel_point`.
+/// The nested loops are charged by their own generated functions.
+#[allow(clippy::too_many_arguments)]
+fn gen_const_func(true_start_idx: usize, fuel_point: Option<usize>, body: &[Operator], slice: &Slice, func_slices: &SliceResult, cost_map: &mut BTreeMap<usize, u64>,
+                  charge_map: &mut BTreeMap<usize, Vec<(usize, u64)>>, cost_model: &dyn OpCostModel, state: &mut CodeGenState, func: &mut FunctionBuilder) {
+    for (i, op) in body.iter().enumerate() {
+        if !in_subslice(true_start_idx + i, slice, func_slices) {
+            state.add_cost(true_start_idx + i, cost_model.cost(op));
+        }
+    }
+    let (cost, charged) = state.take_charge();
+    match state.init_fuel {
+        Some(init_fuel) => {
+            let remaining = (init_fuel as i64).saturating_sub(fuel_const(cost));
+            func.i64_const(remaining);
+            if let (true, Some(hook)) = (remaining < 0, state.out_of_fuel) {
+                func.call(hook);
+                func.i64_add();
+            }
+        }
+        None => {
+            func.i64_const(fuel_const(cost));
+        }
+    };
+    if let Some(fuel_point) = fuel_point {
+        cost_map.insert(fuel_point, cost);
+        charge_map.insert(fuel_point, charged);
+    }
+}
+
+/// Where a `throw` goes in the generated code: nothing throws there, so it branches to its handler
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ThrowTarget {
+    /// The label that the handler branches to, relative to the `throw`
+    relative_depth: u32,
+    /// The type of the payload that the handler passes to the label (it's not tracked, a default
+    /// value stands in for it)
+    payload: Option<ValType>
+}
+
+/// The handler in `body` that each `throw`/`throw_ref` (instr_idx) is caught by, in the generated
+/// code they branch to its label. A `throw_ref` only matches a `catch_all`, its tag isn't known
+/// statically. The exceptions that aren't caught in `body` leave it (like a `return`), so do the
+/// ones whose handler passes more than one value to its label.
+fn throw_targets(body: &[Operator], true_start_idx: usize) -> BTreeMap<usize, ThrowTarget> {
+    let mut targets = BTreeMap::new();
+    // the open blocks, innermost last
+    let mut blocks: Vec<&Operator> = vec![];
+    for (i, op) in body.iter().enumerate() {
+        match op {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } | Operator::TryTable { .. } => blocks.push(op),
+            Operator::End => { blocks.pop(); }
+            Operator::Throw { .. } | Operator::ThrowRef => {
+                if let Some(target) = catch_target(op, &blocks) {
+                    targets.insert(true_start_idx + i, target);
+                }
+            }
+            _ => {}
+        }
+    }
+    targets
+}
+
+fn catch_target(throw: &Operator, blocks: &[&Operator]) -> Option<ThrowTarget> {
+    // the innermost try_table with a matching handler catches it
+    let (relative_depth, label) = blocks.iter().rev().enumerate().find_map(|(depth, block)| {
+        let Operator::TryTable { try_table } = block else {
+            return None;
+        };
+        try_table.catches.iter().find_map(|catch| match (catch, throw) {
+            (Catch::One { tag, label } | Catch::OneRef { tag, label }, Operator::Throw { tag_index }) if tag == tag_index => Some((depth, *label)),
+            (Catch::All { label } | Catch::AllRef { label }, _) => Some((depth, *label)),
+            _ => None
+        })
+    })?;
+    // the labels of the handlers are relative to the block around the try_table
+    let relative_depth = relative_depth as u32 + 1 + label;
+    let target = blocks.len().checked_sub(relative_depth as usize + 1)?;
+    let payload = match blocks[target] {
+        // a branch to a loop goes back to its start, it doesn't take its results
+        Operator::Loop { blockty: wasmparser::BlockType::Empty | wasmparser::BlockType::Type(_) } => None,
+        Operator::Block { blockty } | Operator::If { blockty } => block_payload(blockty)?,
+        Operator::TryTable { try_table } => block_payload(&try_table.ty)?,
+        _ => return None
+    };
+    Some(ThrowTarget { relative_depth, payload })
+}
+
+/// The payload of a branch to a block of type `blockty`, None if the generated code can't stand in for it
+fn block_payload(blockty: &wasmparser::BlockType) -> Option<Option<ValType>> {
+    match blockty {
+        wasmparser::BlockType::Empty => Some(None),
+        wasmparser::BlockType::Type(ValType::V128) => None,
+        wasmparser::BlockType::Type(ValType::Ref(ty)) if !matches!(ty.heap_type(), HeapType::Abstract { .. }) => None,
+        wasmparser::BlockType::Type(ty) => Some(Some(*ty)),
+        wasmparser::BlockType::FuncType(_) => None
+    }
+}
+
+/// Emit the `throw` as a branch to its handler (with a stand-in for the payload), or as a return of
+/// the fuel if it isn't caught in the body
+pub(crate) fn gen_throw(target: Option<&ThrowTarget>, fuel: &LocalID, func: &mut FunctionBuilder) {
+    let Some(ThrowTarget { relative_depth, payload }) = target else {
+        func.local_get(*fuel);
+        func.return_stmt();
+        return;
+    };
+    match payload {
+        Some(ValType::I32) => { func.i32_const(0); }
+        Some(ValType::I64) => { func.i64_const(0); }
+        Some(ValType::F32) => { func.f32_const(0.0); }
+        Some(ValType::F64) => { func.f64_const(0.0); }
+        Some(ValType::Ref(ty)) => { func.inject(Operator::RefNull { hty: ty.heap_type() }); }
+        Some(ValType::V128) | None => {}
+    }
+    func.br(*relative_depth);
+}
+
+/// A `br_on_*` (at `opidx`) passes the reference to its label, but the generated code strips the
+/// labels of their results (see `without_results`): it's replayed as a `br_if` on the same test of
+/// the reference, leaving the reference on the stack when it falls through (except for
+/// `br_on_non_null`). The min slice only replays whether the branch is taken (it's requested).
+pub(crate) fn gen_br_on_ref<'a, 'b>(opidx: usize, op: &Operator<'a>, state: &CodeGenState, func: &mut FunctionBuilder<'b>) where 'a: 'b {
+    match op {
+        Operator::BrOnNull { relative_depth } | Operator::BrOnNonNull { relative_depth }
+        | Operator::BrOnCast { relative_depth, .. } | Operator::BrOnCastFail { relative_depth, .. } if state.for_taken.contains_key(&opidx) => {
+            func.br_if(*relative_depth);
+        }
+        Operator::BrOnNonNull { relative_depth } => {
+            func.ref_is_null();
+            func.i32_eqz();
+            func.br_if(*relative_depth);
+        }
+        Operator::BrOnCast { relative_depth, from_ref_type, to_ref_type } | Operator::BrOnCastFail { relative_depth, from_ref_type, to_ref_type } => {
+            let reference = func.add_local(DataType::from(ValType::Ref(*from_ref_type)));
+            func.local_tee(reference);
+            let hty = to_ref_type.heap_type();
+            func.inject(if to_ref_type.is_nullable() { Operator::RefTestNullable { hty } } else { Operator::RefTestNonNull { hty } });
+            if let Operator::BrOnCastFail { .. } = op {
+                func.i32_eqz();
+            }
+            func.br_if(*relative_depth);
+            func.local_get(reference);
+        }
+        // `br_on_null` only passes the values under the reference
+        _ => {
+            func.inject(op.clone());
+        }
+    }
+}
+
+/// Whether the instruction belongs to a slice nested in `slice` (it's emitted in its own function)
+fn in_subslice(instr_idx: usize, slice: &Slice, func_slices: &SliceResult) -> bool {
+    func_slices.slices.values().any(|sub| sub.start_instr_idx > slice.start_instr_idx
+        && (sub.start_instr_idx..sub.end_instr_idx).contains(&instr_idx))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ConstVal {
+    I32(i32),
+    I64(i64)
+}
+
+/// Value numbering for the constants that the slice replays: a constant that's emitted at least
+/// `min_uses` times (`AnalysisConfig::dedup_const_min_uses`) is computed once (at the start of the function) into a shared local.
+/// Returns: instr_idx -> the local holding the constant's value
+fn share_consts(body: &[Operator], slice: &Slice, in_slice: fn(usize, &Slice) -> bool, func_slices: &SliceResult, min_uses: usize, func: &mut FunctionBuilder) -> BTreeMap<usize, LocalID> {
+    let mut uses: BTreeMap<ConstVal, Vec<usize>> = BTreeMap::new();
+    for (i, op) in body.iter().enumerate() {
+        let instr_idx = slice.start_instr_idx + i;
+        // the instructions of nested slices are emitted in their own function
+        if in_subslice(instr_idx, slice, func_slices) || !(in_slice(instr_idx, slice) || slice.instrs_support.contains(&instr_idx)) {
+            continue;
+        }
+        // small constants already encode in as few bytes as a `local.get`
+        match op {
+            Operator::I32Const { value } if !(-64..64).contains(value) => uses.entry(ConstVal::I32(*value)).or_default().push(instr_idx),
+            Operator::I64Const { value } if !(-64..64).contains(value) => uses.entry(ConstVal::I64(*value)).or_default().push(instr_idx),
+            _ => {}
+        }
+    }
+
+    let mut shared = BTreeMap::new();
+    for (val, instrs) in uses.iter().filter(|(_, instrs)| instrs.len() >= min_uses) {
+        let local = match val {
+            ConstVal::I32(value) => {
+                let local = func.add_local(DataType::I32);
+                func.i32_const(*value);
+                local
+            }
+            ConstVal::I64(value) => {
+                let local = func.add_local(DataType::I64);
+                func.i64_const(*value);
+                local
+            }
+        };
+        func.local_set(local);
+        for instr_idx in instrs.iter() {
+            shared.insert(*instr_idx, local);
+        }
+    }
+    shared
+}
+
 /// Returns: (should_include, do_fuel_before)
 /// - support_opcode: whether this opcode should be included in the generated function.
 /// - do_fuel_before: whether we should compute the fuel implications at this location
 ///   (before emitting this opcode).
-fn calc_op_cost(is_in_slice: bool, at_func_end: bool, op: &Operator, state: &mut CodeGenState) -> bool {
+fn calc_op_cost(instr_idx: usize, is_dropped: bool, is_in_slice: bool, at_func_end: bool, op: &Operator, cost_model: &dyn OpCostModel, state: &mut CodeGenState) -> bool {
     // compute and increment the cost to calculate for this block
-    state.add_cost(op_cost(op));
+    // (computations that are only dropped are free if we're not charging for them)
+    state.add_cost(instr_idx, if is_dropped { 0 } else { cost_model.cost(op) });
 
-    let is_cf = is_branching_op(op) || matches!(op,
+    let is_cf = is_branching_op(op) || is_tail_call(op) || matches!(op,
         Operator::If {..} |
         // block
         Operator::Else | Operator::End |
         // control opcodes
-        Operator::Return
+        Operator::Return | Operator::Throw {..} | Operator::ThrowRef
     );
 
     if (is_cf && is_in_slice) || at_func_end {
@@ -156,65 +704,158 @@ fn calc_op_cost(is_in_slice: bool, at_func_end: bool, op: &Operator, state: &mut
     }
 }
 
-fn op_cost(_op: &Operator) -> u64 {
-    // TODO: assumes 1 for now
-    1
+fn gen_fuel_comp(fuel: &LocalID, ty: &CompType, cost: u64, state: &CodeGenState, func: &mut FunctionBuilder) {
+    match ty {
+        // the coarse metering only charges a fixed cost (see `gen_const_func`)
+        CompType::Exact | CompType::Epoch | CompType::BackEdge => gen_fuel_comp_exact(fuel, cost, state, func),
+        CompType::Approx => gen_fuel_comp_approx(fuel, cost, state, func)
+    }
 }
 
-fn gen_fuel_comp(fuel: &LocalID, ty: &CompType, state: &mut CodeGenState, func: &mut FunctionBuilder) {
-    match ty {
-        CompType::Exact => gen_fuel_comp_exact(fuel, state, func),
-        CompType::Approx => gen_fuel_comp_approx(fuel, state, func),
+fn gen_fuel_comp_exact(fuel: &LocalID, cost: u64, state: &CodeGenState, func: &mut FunctionBuilder) {
+    if cost > 0 {
+        func.local_get(*fuel);
+        func.i64_const(fuel_const(cost));
+        charge(state, func);
+        func.local_set(*fuel);
+        gen_refill(fuel, state, func);
+    }
+}
+
+fn gen_dyn_cost(fuel: &LocalID, per_elem: u64, req: &ReqState, len_ty: DataType, state: &CodeGenState, func: &mut FunctionBuilder) {
+    if per_elem > 0 {
+        func.local_get(*fuel);
+        handle_reqs(Some(req), func);
+        if len_ty == DataType::I32 {
+            func.i64_extend_i32u();
+        }
+        func.i64_const(fuel_const(per_elem.saturating_mul(state.cost_multiplier)));
+        func.i64_mul();
+        charge(state, func);
+        func.local_set(*fuel);
+        gen_refill(fuel, state, func);
     }
 }
 
-fn gen_fuel_comp_exact(fuel: &LocalID, state: &mut CodeGenState, func: &mut FunctionBuilder) {
-    if state.curr_cost > 0 {
+/// If the charge exhausted the fuel (it's below 0), call the out-of-fuel hook (see `OUT_OF_FUEL_FUNC`)
+/// and add the fuel it returns
+fn gen_refill(fuel: &LocalID, state: &CodeGenState, func: &mut FunctionBuilder) {
+    if let Some(hook) = state.out_of_fuel {
+        func.local_get(*fuel);
+        func.i64_const(0);
+        func.i64_lt_signed();
+        func.if_stmt(BlockType::Empty);
         func.local_get(*fuel);
-        func.i64_const(state.curr_cost as i64);
+        func.call(hook);
         func.i64_add();
         func.local_set(*fuel);
+        func.end();
     }
 }
 
-fn gen_fuel_comp_approx(_fuel: &LocalID, _state: &mut CodeGenState, _func: &mut FunctionBuilder) {
-    // TODO
-    todo!()
+/// Applies the cost on top of the stack to the fuel below it: counts up from 0
+/// or down from the initial fuel (see `CodeGenState::init_fuel`)
+fn charge(state: &CodeGenState, func: &mut FunctionBuilder) {
+    if state.init_fuel.is_some() {
+        func.i64_sub();
+    } else {
+        func.i64_add();
+    }
 }
 
+fn gen_fuel_comp_approx(fuel: &LocalID, cost: u64, state: &CodeGenState, func: &mut FunctionBuilder) {
+    // conservatively scale the cost by how deep the slice is nested in loops (a multiple of the
+    // charge granularity stays one)
+    gen_fuel_comp_exact(fuel, cost.saturating_mul(state.cost_multiplier), state, func);
+}
+
+pub(crate) mod dispatch;
 pub(crate) mod max;
 pub(crate) mod min;
 
+#[derive(Default, Serialize, Deserialize)]
 pub struct CodeGenResult {
     /// The instr_idx and the cost calculation to insert at that location!
-    pub cost_maps: Vec<HashMap<usize, u64>>,
+    pub cost_maps: Vec<BTreeMap<usize, u64>>,
+    /// The instr_idx of each fuel point and the instructions (and their costs) it accumulates
+    pub charge_maps: Vec<BTreeMap<usize, Vec<(usize, u64)>>>,
+    /// The fid of the function at each index of `cost_maps` and `charge_maps`
+    pub fids: Vec<u32>,
     /// We can generate 1->many functions per original function
-    pub func_map: HashMap<u32, Vec<GeneratedFunc>>
+    pub func_map: BTreeMap<u32, Vec<GeneratedFunc>>
+}
+impl CodeGenResult {
+    /// The static cost of running the instructions `from_idx..to_idx` of function `fid`, e.g. for
+    /// tooling that wants a quick answer without executing anything. This is only known if the range
+    /// is straight-line code: all of its instructions are charged at the same fuel point, so only
+    /// its last instruction can branch. Otherwise (or if the function isn't known) returns None.
+    pub fn static_cost_between(&self, fid: u32, from_idx: usize, to_idx: usize) -> Option<u64> {
+        let func_idx = self.fids.iter().position(|f| *f == fid)?;
+        if from_idx >= to_idx {
+            return (from_idx == to_idx).then_some(0);
+        }
+        let charged = self.charge_maps[func_idx].values().find(|charged| charged.iter().any(|(idx, _)| *idx == from_idx))?;
+        let mut cost = 0;
+        for instr_idx in from_idx..to_idx {
+            // a missing instruction is charged at another fuel point (or skipped over, e.g. a nested loop)
+            let (_, instr_cost) = charged.iter().find(|(idx, _)| *idx == instr_idx)?;
+            cost += instr_cost;
+        }
+        Some(cost)
+    }
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct GeneratedFunc {
     pub fid: u32,
     pub fname: String,
 
     // Maps from the type of state that we're requesting
-    // to a HashMap from instr_idx -> stack values we need at that instr
-    pub req_state: HashMap<StateType, HashMap<usize, ReqState>>,
+    // to a map from instr_idx -> stack values we need at that instr
+    pub req_state: BTreeMap<StateType, BTreeMap<usize, ReqState>>,
+    // The table that each requested call_indirect (instr_idx) calls through
+    pub call_indirect_tables: BTreeMap<usize, u32>,
+    // The global (gid) that each requested global.get (instr_idx) reads and where it's defined
+    pub global_sources: BTreeMap<usize, (u32, GlobalSource)>,
+    // The op that the analysis couldn't model, the function only returns the worst-case cost
+    pub fallback: Option<String>,
+    // The types of the function's params (the requested state, in order of gen_param_id)
+    #[serde(with = "data_types")]
+    pub params: Vec<DataType>,
+    // The checkpoints (see `checkpoint_id`) of the fuel points in this function, in order
+    pub checkpoints: Vec<u64>,
+    // The source-level name of the state that each param (gen_param_id) requests, e.g. `param "len"`,
+    // when the module has debug names (see `names::name_params`)
+    #[serde(default)]
+    pub param_names: BTreeMap<u32, String>,
+    // The ID that the `fuel` export calls this function through (see `dispatch.rs`)
+    pub dispatch_id: Option<u32>
 }
 impl From<CodeGenState> for GeneratedFunc {
     fn from(value: CodeGenState) -> Self {
-        let mut req_state = HashMap::new();
+        let mut req_state = BTreeMap::new();
         req_state.insert(StateType::Param, value.for_params);
         req_state.insert(StateType::Global, value.for_globals);
         req_state.insert(StateType::Load, value.for_loads);
         req_state.insert(StateType::Call, value.for_calls);
         req_state.insert(StateType::CallIndirect, value.for_call_indirects);
+        req_state.insert(StateType::CallRef, value.for_call_refs);
+        req_state.insert(StateType::TableGet, value.for_table_gets);
+        req_state.insert(StateType::FieldLoad, value.for_field_loads);
         req_state.insert(StateType::Taken, value.for_taken);
+        req_state.insert(StateType::Length, value.for_lengths);
 
         Self {
             fid: value.fid,
             fname: value.fname,
-            req_state
+            req_state,
+            call_indirect_tables: value.call_indirect_tables,
+            global_sources: value.global_sources,
+            fallback: value.fallback,
+            params: value.params,
+            checkpoints: value.checkpoints,
+            param_names: BTreeMap::new(),
+            dispatch_id: None
         }
     }
 }
@@ -227,81 +868,180 @@ pub(crate) struct CodeGenState {
 
     // Maps from dependency index -> generated local ID for each
     // of the types of program state the slice can depend on.
-    pub(crate) for_params: HashMap<usize, ReqState>,
-    pub(crate) for_globals: HashMap<usize, ReqState>,
-    pub(crate) for_loads: HashMap<usize, ReqState>,
-    pub(crate) for_calls: HashMap<usize, ReqState>,
-    pub(crate) for_call_indirects: HashMap<usize, ReqState>,
+    pub(crate) for_params: BTreeMap<usize, ReqState>,
+    pub(crate) for_globals: BTreeMap<usize, ReqState>,
+    pub(crate) for_loads: BTreeMap<usize, ReqState>,
+    pub(crate) for_calls: BTreeMap<usize, ReqState>,
+    pub(crate) for_call_indirects: BTreeMap<usize, ReqState>,
+    pub(crate) call_indirect_tables: BTreeMap<usize, u32>,
+    pub(crate) for_call_refs: BTreeMap<usize, ReqState>,
+    pub(crate) for_table_gets: BTreeMap<usize, ReqState>,
+    pub(crate) for_field_loads: BTreeMap<usize, ReqState>,
+    pub(crate) global_sources: BTreeMap<usize, (u32, GlobalSource)>,
+    // The reads of the globals whose value is known statically, replayed as constants
+    pub(crate) const_globals: BTreeMap<usize, ConstValue>,
 
-    pub(crate) for_taken: HashMap<usize, ReqState>,
+    pub(crate) for_taken: BTreeMap<usize, ReqState>,
+    // Both max and min need the length operand of the bulk ops to compute their cost
+    pub(crate) for_lengths: BTreeMap<usize, ReqState>,
+    // The replayed constants that are read from a shared local (see `share_consts`)
+    pub(crate) shared_consts: BTreeMap<usize, LocalID>,
+    // The handler that each `throw` (instr_idx) is caught by in the body (see `throw_targets`)
+    pub(crate) throw_targets: BTreeMap<usize, ThrowTarget>,
+    // The copies of the pure callees whose calls are replayed: original FID -> FID of the copy
+    pub(crate) replayed: BTreeMap<u32, FunctionID>,
+    pub(crate) fallback: Option<String>,
+    pub(crate) params: Vec<DataType>,
+    // The fuel budget that the generated function counts down from, it then returns the
+    // remaining fuel (negative if the budget is exceeded) rather than the fuel consumed
+    pub(crate) init_fuel: Option<u64>,
+    // The imported hook that's called when a charge exhausts the (initial) fuel, see `OUT_OF_FUEL_FUNC`
+    pub(crate) out_of_fuel: Option<FunctionID>,
+    // What the costs are scaled by, only the approx computation scales them (by the loop depth,
+    // see `AnalysisConfig::loop_depth_multiplier`)
+    pub(crate) cost_multiplier: u64,
+    // Every charge is rounded up to a multiple of this, as some metering schemes require
+    pub(crate) granularity: Option<u64>,
+    // The checkpoints of the fuel points that were generated so far
+    pub(crate) checkpoints: Vec<u64>,
 
     // Used to track the current cost of the basic block
     // Once we reach a branching opcode, we need to gen the
     // cost computation before branching!
     // 1. generate computation
     // 2. curr_cost = 0
-    curr_cost: u64
+    curr_cost: u64,
+    // The instructions (and their costs) that contributed to `curr_cost`
+    curr_instrs: Vec<(usize, u64)>
 }
 impl CodeGenState {
     fn new_max(slice: &Slice) -> (Self, Vec<DataType>) {
         let mut used_params = Vec::new();
-
+        let state = Self::requesting(slice, |_| true, &mut used_params);
+        (state, used_params)
+    }
+    fn new_min(slice: &Slice) -> (Self, Vec<DataType>) {
+        let mut used_params = Vec::new();
+        let for_taken = process_needed_state(&slice.taken, &mut used_params);
+        // only requests the state of the conditions that the min slice still replays (none with
+        // the branch-outcome substitution, see `ReductionPass::BranchOutcomes`)
+        let state = Self::requesting(slice, |instr_idx| slice.min_slice.contains(&instr_idx), &mut used_params);
+        (Self {
+            for_taken,
+            ..state
+        }, used_params)
+    }
+    /// Request the state read by the `replayed` instructions of the slice (and the bulk op lengths)
+    fn requesting(slice: &Slice, replayed: impl Fn(usize) -> bool, used_params: &mut Vec<DataType>) -> Self {
         let for_params = process_needed_state(&slice.params.iter()
-            .map(|((_, index), value)| (*index, value.clone()))
-            .collect(), &mut used_params);
-        let for_globals = process_needed_state(&slice.globals.iter()
-            .map(|((_, index), value)| (*index, value.clone()))
-            .collect(), &mut used_params);
-        let for_loads = process_needed_state(&slice.loads, &mut used_params);
-        let for_calls = process_needed_call(&slice.calls, &mut used_params);
-        let for_call_indirects = process_needed_call(&slice.call_indirects, &mut used_params);
-
-        fn process_needed_call(needed_state: &HashMap<(usize, usize), DataType>, used_params: &mut Vec<DataType>) -> HashMap<usize, ReqState> {
-            let mut res = HashMap::default();
-            for ((opidx, arg), dt) in needed_state.iter() {
+            .filter(|((_, index), _)| replayed(*index))
+            .map(|((_, index), value)| (*index, *value))
+            .collect(), used_params);
+        let mut for_globals = process_needed_state(&slice.globals.iter()
+            .filter(|((_, index), _)| replayed(*index))
+            .map(|((_, index), value)| (*index, *value))
+            .collect(), used_params);
+        let mut global_sources: BTreeMap<usize, (u32, GlobalSource)> = slice.globals.keys()
+            .filter(|(_, index)| replayed(*index))
+            .map(|(gid, index)| (*index, (*gid, slice.global_sources[gid].clone())))
+            .collect();
+        for (alias, first) in slice.global_aliases.iter().filter(|(alias, first)| replayed(**alias) && replayed(**first)) {
+            // reads the same value as the first read, reuse its param
+            let gen_param_id = for_globals[first].req_state[0].gen_param_id();
+            for_globals.insert(*alias, ReqState {
+                req_state: vec![ StackVal::Res { num: 0, gen_param_id }],
+                memory: None
+            });
+            global_sources.insert(*alias, global_sources[first].clone());
+        }
+        let for_loads = process_needed_state(&slice.loads.iter()
+            .filter(|((_, index), _)| replayed(*index))
+            .map(|(key, value)| (*key, *value))
+            .collect(), used_params)
+            .into_iter()
+            .map(|((memory, index), req)| (index, ReqState { memory: Some(memory), ..req }))
+            .collect();
+        let for_calls = process_needed_call(&slice.calls, &replayed, used_params);
+        let for_call_indirects = process_needed_call(&slice.call_indirects, &replayed, used_params);
+        let for_call_refs = process_needed_call(&slice.call_refs, &replayed, used_params);
+        let for_table_gets = process_needed_state(&slice.table_gets.iter()
+            .filter(|(index, _)| replayed(**index))
+            .map(|(index, value)| (*index, *value))
+            .collect(), used_params);
+        let for_field_loads = process_needed_state(&slice.field_loads.iter()
+            .filter(|(index, _)| replayed(**index))
+            .map(|(index, value)| (*index, *value))
+            .collect(), used_params);
+        let for_lengths = process_needed_state(&slice.lengths, used_params);
+
+        fn process_needed_call(needed_state: &BTreeMap<(usize, usize), DataType>, replayed: impl Fn(usize) -> bool, used_params: &mut Vec<DataType>) -> BTreeMap<usize, ReqState> {
+            let mut res = BTreeMap::default();
+            for ((opidx, arg), dt) in needed_state.iter().filter(|((opidx, _), _)| replayed(*opidx)) {
                 res.insert(*opidx, ReqState {
-                    req_state: vec![ StackVal::Res { num: *arg, gen_param_id: used_params.len() as u32 }]
+                    req_state: vec![ StackVal::Res { num: *arg, gen_param_id: used_params.len() as u32 }],
+                    memory: None
                 });
                 used_params.push(*dt);
             }
             res
         }
 
-        (Self {
+        Self {
             for_params,
             for_globals,
             for_loads,
             for_calls,
             for_call_indirects,
+            call_indirect_tables: slice.call_indirect_tables.iter()
+                .filter(|(index, _)| replayed(**index))
+                .map(|(index, table)| (*index, *table))
+                .collect(),
+            for_call_refs,
+            for_table_gets,
+            for_field_loads,
+            global_sources,
+            const_globals: slice.const_globals.iter()
+                .filter(|(index, _)| replayed(**index))
+                .map(|(index, value)| (*index, *value))
+                .collect(),
+            for_lengths,
             ..Self::default()
-        }, used_params)
-    }
-    fn new_min(slice: &Slice) -> (Self, Vec<DataType>) {
-        let mut used_params = Vec::new();
-        let for_taken = process_needed_state(&slice.taken, &mut used_params);
-        (Self {
-            for_taken,
-            ..Self::default()
-        }, used_params)
+        }
     }
     // ----- COST
-    fn add_cost(&mut self, cost: u64) {
+    fn add_cost(&mut self, instr_idx: usize, cost: u64) {
         self.curr_cost += cost;
+        self.curr_instrs.push((instr_idx, cost));
     }
-    fn reset_cost(&mut self) {
-        self.curr_cost = 0;
+    /// The cost charged at a fuel point and the instructions it's charged for, then resets them.
+    /// The cost is rounded up to the charge granularity here (and only here), the rounding is
+    /// charged with the last instruction so that the instructions still add up to the cost.
+    fn take_charge(&mut self) -> (u64, Vec<(usize, u64)>) {
+        if let Some(granularity) = self.granularity.filter(|granularity| *granularity > 0) {
+            let rounded = self.curr_cost.div_ceil(granularity).saturating_mul(granularity);
+            if let Some((_, cost)) = self.curr_instrs.last_mut() {
+                *cost += rounded - self.curr_cost;
+            }
+            self.curr_cost = rounded;
+        }
+        (std::mem::take(&mut self.curr_cost), std::mem::take(&mut self.curr_instrs))
     }
 }
 
-#[derive(Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum StateType {
     Param,
     Global,
     Load,
     Call,
     CallIndirect,
-    Taken
+    CallRef,
+    TableGet,
+    FieldLoad,
+    Taken,
+    Length
 }
+#[derive(Serialize, Deserialize)]
 pub enum StackVal {
     Arg { num: usize, gen_param_id: u32 },
     Res { num: usize, gen_param_id: u32 },
@@ -322,7 +1062,13 @@ impl Display for StackVal {
         }
     }
 }
-pub struct ReqState { pub req_state: Vec<StackVal> }
+#[derive(Serialize, Deserialize)]
+pub struct ReqState {
+    pub req_state: Vec<StackVal>,
+    /// The memory that a requested load reads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u32>
+}
 impl Display for ReqState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut reqs = String::new();
@@ -334,14 +1080,12 @@ impl Display for ReqState {
     }
 }
 
-pub(crate) fn process_needed_state<T: Clone + Eq + Hash + Ord>(needed_state: &HashMap<T, DataType>, used_params: &mut Vec<DataType>) -> HashMap<T, ReqState> {
-    let mut res = HashMap::default();
-    let mut sorted: Vec<&T> = needed_state.keys().collect();
-    sorted.sort();
-    for key in sorted.iter() {
-        let dt = needed_state.get(*key).unwrap();
-        res.insert((*key).clone(), ReqState {
-            req_state: vec![ StackVal::Res { num: 0, gen_param_id: used_params.len() as u32 }]
+pub(crate) fn process_needed_state<T: Clone + Ord>(needed_state: &BTreeMap<T, DataType>, used_params: &mut Vec<DataType>) -> BTreeMap<T, ReqState> {
+    let mut res = BTreeMap::default();
+    for (key, dt) in needed_state.iter() {
+        res.insert(key.clone(), ReqState {
+            req_state: vec![ StackVal::Res { num: 0, gen_param_id: used_params.len() as u32 }],
+            memory: None
         });
         used_params.push(*dt);
     }
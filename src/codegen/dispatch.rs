@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use wirm::{DataType, Module, Opcode};
+use wirm::ir::function::FunctionBuilder;
+use wirm::ir::id::{FunctionID, LocalID};
+use wirm::ir::types::BlockType;
+use wirm::wasmparser::{MemArg, MemoryType};
+use crate::codegen::GeneratedFunc;
+
+/// The name of the single entry point: `fuel(dispatch_id: i32, state_ptr: i32) -> i64`
+pub const DISPATCH_EXPORT: &str = "fuel";
+/// The memory that the host writes the requested state to before calling the dispatcher
+pub const DISPATCH_MEMORY_EXPORT: &str = "memory";
+/// Each requested state value takes a slot of this many bytes in the buffer
+pub const STATE_SLOT_SIZE: u64 = 8;
+
+/// Emit the `fuel` export, it switches over the dispatch IDs (assigned here, in the order of the
+/// generated functions) and calls the (non-exported) generated function with the requested state.
+/// Buffer ABI: the value of gen_param_id `i` is stored (little-endian) at `state_ptr + 8 * i` of
+/// the exported memory. A dispatch ID that doesn't map to a function traps, as does a function
+/// requesting state that can't be stored in the buffer (e.g. a reference).
+pub(crate) fn gen_dispatch(func_map: &mut BTreeMap<u32, Vec<GeneratedFunc>>, gen_wasm: &mut Module) {
+    let memory = gen_wasm.add_local_memory(MemoryType {
+        memory64: false,
+        shared: false,
+        initial: 1,
+        maximum: None,
+        page_size_log2: None
+    });
+    gen_wasm.exports.add_export_mem(DISPATCH_MEMORY_EXPORT.to_string(), *memory);
+
+    let mut dispatcher = FunctionBuilder::new(&[DataType::I32, DataType::I32], &[DataType::I64]);
+    let (dispatch_id, state_ptr) = (LocalID(0), LocalID(1));
+    for (id, generated) in func_map.values_mut().flatten().enumerate() {
+        generated.dispatch_id = Some(id as u32);
+
+        dispatcher.local_get(dispatch_id);
+        dispatcher.i32_const(id as i32);
+        dispatcher.i32_eq();
+        dispatcher.if_stmt(BlockType::Empty);
+        if gen_load_state(&generated.params, &state_ptr, &mut dispatcher) {
+            dispatcher.call(FunctionID(generated.fid));
+            dispatcher.return_stmt();
+        } else {
+            dispatcher.unreachable();
+        }
+        dispatcher.end();
+    }
+    // not a dispatch ID
+    dispatcher.unreachable();
+
+    let fid = dispatcher.finish_module(gen_wasm);
+    gen_wasm.exports.add_export_func(DISPATCH_EXPORT.to_string(), *fid);
+}
+
+/// Push the params of a generated function from the state buffer, returns false if a param
+/// can't be stored in the buffer.
+fn gen_load_state(params: &[DataType], state_ptr: &LocalID, func: &mut FunctionBuilder) -> bool {
+    let Some(aligns) = params.iter().map(|ty| match ty {
+        DataType::I32 | DataType::F32 => Some(2),
+        DataType::I64 | DataType::F64 => Some(3),
+        _ => None
+    }).collect::<Option<Vec<u8>>>() else {
+        return false;
+    };
+    for (i, (ty, align)) in params.iter().zip(aligns).enumerate() {
+        let memarg = |align: u8| MemArg {
+            align,
+            max_align: align,
+            offset: i as u64 * STATE_SLOT_SIZE,
+            memory: 0
+        };
+        func.local_get(*state_ptr);
+        match ty {
+            DataType::I32 => func.i32_load(memarg(align)),
+            DataType::I64 => func.i64_load(memarg(align)),
+            DataType::F32 => func.f32_load(memarg(align)),
+            _ => func.f64_load(memarg(align))
+        };
+    }
+    true
+}
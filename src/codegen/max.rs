@@ -1,15 +1,30 @@
 use wirm::{Module, Opcode};
 use wirm::ir::function::FunctionBuilder;
-use wirm::ir::id::LocalID;
+use wirm::ir::id::{FunctionID, LocalID};
 use wirm::opcode::Inject;
 use wirm::wasmparser::Operator;
 use crate::analyze::FuncState;
-use crate::codegen::{codegen, handle_reqs, CodeGenResult, CodeGenState};
-use crate::run::CompType;
+use crate::error::AnalysisError;
+use crate::codegen::{codegen, gen_br_on_ref, gen_for_func, gen_throw, handle_reqs, CodeGenResult, CodeGenState, FuncGen, SharedGen};
+use crate::globals::ConstValue;
+use crate::run::AnalysisConfig;
 use crate::slice::{Slice, SliceResult};
+use crate::utils::is_tail_call;
 
-pub fn codegen_max<'a, 'b>(ty: &CompType, slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> CodeGenResult where 'a : 'b {
-    codegen(ty, slices, CodeGenState::new_max, in_max_slice, gen_op, funcs, wasm, gen_wasm)
+pub fn codegen_max<'a, 'b>(config: &AnalysisConfig, slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> Result<CodeGenResult, AnalysisError> where 'a : 'b {
+    codegen(config, slices, CodeGenState::new_max, in_max_slice, gen_op, funcs, wasm, gen_wasm)
+}
+
+/// `codegen_max` of a single function, see `codegen::gen_for_func`
+pub(crate) fn codegen_max_func<'a, 'b>(config: &AnalysisConfig, func_slices: &SliceResult, shared: &SharedGen, wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> Result<FuncGen, AnalysisError> where 'a : 'b {
+    gen_for_func(config, func_slices, CodeGenState::new_max, in_max_slice, gen_op, shared, wasm, gen_wasm)
+}
+
+fn replayed_callee(op: &Operator, gen_state: &CodeGenState) -> Option<FunctionID> {
+    let Operator::Call { function_index } = op else {
+        return None;
+    };
+    gen_state.replayed.get(function_index).copied()
 }
 
 fn in_max_slice(instr_idx: usize, slice: &Slice) -> bool {
@@ -17,12 +32,39 @@ fn in_max_slice(instr_idx: usize, slice: &Slice) -> bool {
 }
 
 // Translate instructions into `local.get` on parameter representing that state! (if necessary)
-fn gen_op<'a, 'b>(opidx: usize, op: &Operator<'a>, fuel: &LocalID, gen_state: &CodeGenState, func: &mut FunctionBuilder<'b>) where 'a : 'b {
-    if handle_reqs(gen_state.for_params.get(&opidx), func) {
-    } else if handle_reqs(gen_state.for_globals.get(&opidx), func) {
-    } else if handle_reqs(gen_state.for_loads.get(&opidx), func) {
-    } else if handle_reqs(gen_state.for_calls.get(&opidx), func) {
-    } else if handle_reqs(gen_state.for_call_indirects.get(&opidx), func) {
+pub(crate) fn gen_op<'a, 'b>(opidx: usize, op: &Operator<'a>, fuel: &LocalID, gen_state: &CodeGenState, func: &mut FunctionBuilder<'b>) where 'a : 'b {
+    let requested = handle_reqs(gen_state.for_params.get(&opidx), func)
+        || handle_reqs(gen_state.for_globals.get(&opidx), func)
+        || handle_reqs(gen_state.for_loads.get(&opidx), func)
+        || handle_reqs(gen_state.for_table_gets.get(&opidx), func)
+        || handle_reqs(gen_state.for_field_loads.get(&opidx), func)
+        || handle_reqs(gen_state.for_calls.get(&opidx), func)
+        || handle_reqs(gen_state.for_call_indirects.get(&opidx), func)
+        || handle_reqs(gen_state.for_call_refs.get(&opidx), func);
+    if requested {
+        // the op's result is read from the requested state
+    } else if let Some(value) = gen_state.const_globals.get(&opidx) {
+        // the global's value is known statically
+        match value {
+            ConstValue::I32(v) => func.i32_const(*v),
+            ConstValue::I64(v) => func.i64_const(*v),
+            ConstValue::F32(v) => func.f32_const(*v),
+            ConstValue::F64(v) => func.f64_const(*v)
+        };
+    } else if let Some(copy) = replayed_callee(op, gen_state) {
+        // a replayed call to a pure function, its arguments were replayed
+        func.call(copy);
+    } else if let Operator::TryTable { try_table } = op {
+        // nothing throws in the generated code, the handlers are reached through `gen_throw`
+        func.inject(Operator::Block { blockty: try_table.ty });
+    } else if let Operator::Throw { .. } | Operator::ThrowRef = op {
+        gen_throw(gen_state.throw_targets.get(&opidx), fuel, func);
+    } else if let Operator::BrOnNull { .. } | Operator::BrOnNonNull { .. } | Operator::BrOnCast { .. } | Operator::BrOnCastFail { .. } = op {
+        gen_br_on_ref(opidx, op, gen_state, func);
+    } else if is_tail_call(op) {
+        // the callee is charged by its own fuel function, the fuel of this frame is returned
+        func.local_get(*fuel);
+        func.return_stmt();
     } else {
         if let Operator::Return = op {
             func.local_get(*fuel);
@@ -1,15 +1,20 @@
-use wirm::{Module, Opcode};
+use wirm::Module;
 use wirm::ir::function::FunctionBuilder;
 use wirm::ir::id::LocalID;
-use wirm::opcode::Inject;
 use wirm::wasmparser::Operator;
 use crate::analyze::FuncState;
-use crate::codegen::{codegen, handle_reqs, CodeGenResult, CodeGenState};
-use crate::run::CompType;
+use crate::error::AnalysisError;
+use crate::codegen::{codegen, gen_for_func, handle_reqs, max, CodeGenResult, CodeGenState, FuncGen, SharedGen};
+use crate::run::AnalysisConfig;
 use crate::slice::{Slice, SliceResult};
 
-pub fn codegen_min<'a, 'b>(ty: &CompType, slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> CodeGenResult where 'a : 'b {
-    codegen(ty, slices, CodeGenState::new_min, in_min_slice, gen_op, funcs, wasm, gen_wasm)
+pub fn codegen_min<'a, 'b>(config: &AnalysisConfig, slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> Result<CodeGenResult, AnalysisError> where 'a : 'b {
+    codegen(config, slices, CodeGenState::new_min, in_min_slice, gen_op, funcs, wasm, gen_wasm)
+}
+
+/// `codegen_min` of a single function, see `codegen::gen_for_func`
+pub(crate) fn codegen_min_func<'a, 'b>(config: &AnalysisConfig, func_slices: &SliceResult, shared: &SharedGen, wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> Result<FuncGen, AnalysisError> where 'a : 'b {
+    gen_for_func(config, func_slices, CodeGenState::new_min, in_min_slice, gen_op, shared, wasm, gen_wasm)
 }
 
 fn in_min_slice(instr_idx: usize, slice: &Slice) -> bool {
@@ -18,11 +23,7 @@ fn in_min_slice(instr_idx: usize, slice: &Slice) -> bool {
 
 // Translate instructions into `local.get` on parameter representing that state! (if necessary)
 fn gen_op<'a, 'b>(opidx: usize, op: &Operator<'a>, fuel: &LocalID, gen_state: &CodeGenState, func: &mut FunctionBuilder<'b>) where 'a : 'b {
-    if handle_reqs(gen_state.for_taken.get(&opidx), func) {
-    }
-
-    if let Operator::Return = op {
-        func.local_get(*fuel);
-    }
-    func.inject(op.clone());
+    handle_reqs(gen_state.for_taken.get(&opidx), func);
+    // (only the conditions that the min slice still replays request state, see `CodeGenState::new_min`)
+    max::gen_op(opidx, op, fuel, gen_state, func);
 }
@@ -0,0 +1,92 @@
+//! The colors of the reports: termcolor's with the `color` feature, otherwise (e.g. for
+//! `wasm32-unknown-unknown`) a stand-in with the same API that writes the plain text.
+
+#[cfg(feature = "color")]
+pub use termcolor::{Color, ColorSpec, NoColor, WriteColor};
+#[cfg(not(feature = "color"))]
+pub use plain::{Color, ColorSpec, NoColor, WriteColor};
+
+#[cfg(not(feature = "color"))]
+mod plain {
+    use std::io::{self, Write};
+
+    /// The colors that the reports use
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Color {
+        Blue,
+        Cyan,
+        Green,
+        Magenta,
+        Red,
+        White,
+        Yellow,
+        Rgb(u8, u8, u8)
+    }
+
+    /// How a piece of a report would be colored, it's only recorded
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct ColorSpec {
+        fg: Option<Color>,
+        bold: bool,
+        italic: bool
+    }
+    impl ColorSpec {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn set_fg(&mut self, color: Option<Color>) -> &mut Self {
+            self.fg = color;
+            self
+        }
+
+        pub fn set_bold(&mut self, bold: bool) -> &mut Self {
+            self.bold = bold;
+            self
+        }
+
+        pub fn set_italic(&mut self, italic: bool) -> &mut Self {
+            self.italic = italic;
+            self
+        }
+    }
+
+    /// A writer that the reports can color
+    pub trait WriteColor: Write {
+        fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()>;
+    }
+    impl<T: WriteColor + ?Sized> WriteColor for &mut T {
+        fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+            (**self).set_color(spec)
+        }
+    }
+
+    /// Ignores the colors
+    #[derive(Debug)]
+    pub struct NoColor<W: Write> {
+        out: W
+    }
+    impl<W: Write> NoColor<W> {
+        pub fn new(out: W) -> Self {
+            Self { out }
+        }
+
+        pub fn into_inner(self) -> W {
+            self.out
+        }
+    }
+    impl<W: Write> Write for NoColor<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.out.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.out.flush()
+        }
+    }
+    impl<W: Write> WriteColor for NoColor<W> {
+        fn set_color(&mut self, _spec: &ColorSpec) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
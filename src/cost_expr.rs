@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use wirm::wasmparser::Operator;
+
+/// A symbolic cost, shared by the static reports: the formulas in the summary and the JSON report,
+/// the `explain` output and the composition of the costs of the callees (see `compose`).
+/// E.g. `7 + max(2, 3) + 5*n1_12 + f2` where `n1_12` is how many times the body of the loop at
+/// instr_idx 12 of function 1 runs (per entry into the loop) and `f2` is the cost of a call to function 2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CostExpr {
+    Const(u64),
+    Sum(Vec<CostExpr>),
+    /// The worst of the alternatives, e.g. the arms of an `if`
+    Max(Vec<CostExpr>),
+    /// The cost of a single iteration of the `body` of the loop at `instr_idx` of function `fid`, times its trip count
+    Loop { fid: u32, instr_idx: usize, body: Box<CostExpr> },
+    /// The cost of a call to function `fid`, a placeholder until it's composed with the callee's formula
+    Call { fid: u32 }
+}
+
+/// The constructs of the body that get their own subexpression
+enum Frame {
+    Loop(usize),
+    /// The terms of the `then` arm once the `else` is reached
+    If(Option<Vec<CostExpr>>)
+}
+
+impl CostExpr {
+    /// The formula of the `body` of function `fid`, from the cost charged for each instruction (`charges`
+    /// maps from a fuel point to the instructions it charges for, see `CodeGenResult::charge_maps`).
+    /// The `loop` and its `end` run once per entry into the loop, they're charged outside of it.
+    /// The arms of an `if` are alternatives, any other branch only skips costs (so it's an upper bound).
+    pub(crate) fn of_func(fid: u32, body: &[Operator], charges: &BTreeMap<usize, Vec<(usize, u64)>>) -> Self {
+        Self::paths_of_func(fid, body, charges).simplify()
+    }
+
+    /// `of_func` before it's simplified: every alternative is kept (e.g. both arms of an `if` that
+    /// cost constants), to tell the cost of each path apart
+    pub(crate) fn paths_of_func(fid: u32, body: &[Operator], charges: &BTreeMap<usize, Vec<(usize, u64)>>) -> Self {
+        let mut costs: BTreeMap<usize, u64> = BTreeMap::new();
+        for (instr_idx, cost) in charges.values().flatten() {
+            *costs.entry(*instr_idx).or_default() += cost;
+        }
+
+        // the terms of the function and of each open loop and `if`
+        let mut func_terms: Vec<CostExpr> = vec![];
+        let mut frames: Vec<(Frame, Vec<CostExpr>)> = vec![];
+        // whether each of the open blocks has a frame
+        let mut blocks: Vec<bool> = vec![];
+        for (instr_idx, op) in body.iter().enumerate() {
+            let cost = CostExpr::Const(costs.get(&instr_idx).copied().unwrap_or_default());
+            match op {
+                Operator::End if blocks.last() == Some(&true) => {
+                    blocks.pop();
+                    // each of the blocks with a frame pushed one
+                    let Some((frame, terms)) = frames.pop() else { continue };
+                    let expr = match frame {
+                        Frame::Loop(loop_idx) => CostExpr::Loop { fid, instr_idx: loop_idx, body: Box::new(CostExpr::Sum(terms)) },
+                        Frame::If(Some(then)) => CostExpr::Max(vec![CostExpr::Sum(then), CostExpr::Sum(terms)]),
+                        // the `then` arm runs or nothing does
+                        Frame::If(None) => CostExpr::Sum(terms)
+                    };
+                    let outer = frames.last_mut().map_or(&mut func_terms, |(_, terms)| terms);
+                    outer.push(expr);
+                    outer.push(cost);
+                }
+                Operator::Else => match frames.last_mut() {
+                    // the `else` ends the `then` arm
+                    Some((Frame::If(then), terms)) => {
+                        terms.push(cost);
+                        *then = Some(std::mem::take(terms));
+                    }
+                    Some((_, terms)) => terms.push(cost),
+                    None => func_terms.push(cost)
+                },
+                _ => {
+                    let terms = frames.last_mut().map_or(&mut func_terms, |(_, terms)| terms);
+                    terms.push(cost);
+                    match op {
+                        Operator::Call { function_index } | Operator::ReturnCall { function_index } => {
+                            terms.push(CostExpr::Call { fid: *function_index });
+                        }
+                        Operator::Loop { .. } => {
+                            blocks.push(true);
+                            frames.push((Frame::Loop(instr_idx), vec![]));
+                        }
+                        Operator::If { .. } => {
+                            blocks.push(true);
+                            frames.push((Frame::If(None), vec![]));
+                        }
+                        Operator::Block { .. } | Operator::TryTable { .. } | Operator::Try { .. } => blocks.push(false),
+                        // ends a `try` without an `end`
+                        Operator::End | Operator::Delegate { .. } => { blocks.pop(); }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        CostExpr::Sum(func_terms)
+    }
+
+    /// Flatten the sums and maxes, fold their constants and drop the terms that cost nothing
+    pub fn simplify(self) -> Self {
+        match self {
+            CostExpr::Const(_) | CostExpr::Call { .. } => self,
+            CostExpr::Loop { fid, instr_idx, body } => match body.simplify() {
+                CostExpr::Const(0) => CostExpr::Const(0),
+                body => CostExpr::Loop { fid, instr_idx, body: Box::new(body) }
+            },
+            CostExpr::Sum(terms) => {
+                let mut constant = 0;
+                let mut rest = vec![];
+                for term in terms.into_iter().map(CostExpr::simplify) {
+                    let inner = match term {
+                        CostExpr::Sum(inner) => inner,
+                        term => vec![term]
+                    };
+                    for term in inner {
+                        match term {
+                            CostExpr::Const(cost) => constant += cost,
+                            term => rest.push(term)
+                        }
+                    }
+                }
+                if constant > 0 || rest.is_empty() {
+                    rest.insert(0, CostExpr::Const(constant));
+                }
+                if rest.len() == 1 { rest.pop().unwrap() } else { CostExpr::Sum(rest) }
+            }
+            CostExpr::Max(alternatives) => {
+                let mut constant = 0;
+                let mut rest: Vec<CostExpr> = vec![];
+                for alternative in alternatives.into_iter().map(CostExpr::simplify) {
+                    let inner = match alternative {
+                        CostExpr::Max(inner) => inner,
+                        alternative => vec![alternative]
+                    };
+                    for alternative in inner {
+                        match alternative {
+                            CostExpr::Const(cost) => constant = constant.max(cost),
+                            alternative if !rest.contains(&alternative) => rest.push(alternative),
+                            _ => {}
+                        }
+                    }
+                }
+                // the costs aren't negative, `max(0, x)` is `x`
+                if constant > 0 || rest.is_empty() {
+                    rest.insert(0, CostExpr::Const(constant));
+                }
+                if rest.len() == 1 { rest.pop().unwrap() } else { CostExpr::Max(rest) }
+            }
+        }
+    }
+
+    /// Replace the call placeholders with the formulas of the callees (`formulas` maps from fid ->
+    /// the function's own formula). The calls to the functions without a formula (e.g. imported)
+    /// and the recursive calls are left as placeholders.
+    pub fn compose(&self, formulas: &BTreeMap<u32, CostExpr>) -> CostExpr {
+        self.compose_in(formulas, &mut vec![]).simplify()
+    }
+
+    fn compose_in(&self, formulas: &BTreeMap<u32, CostExpr>, callers: &mut Vec<u32>) -> CostExpr {
+        match self {
+            CostExpr::Const(_) => self.clone(),
+            CostExpr::Sum(terms) => CostExpr::Sum(terms.iter().map(|term| term.compose_in(formulas, callers)).collect()),
+            CostExpr::Max(alternatives) => CostExpr::Max(alternatives.iter().map(|alternative| alternative.compose_in(formulas, callers)).collect()),
+            CostExpr::Loop { fid, instr_idx, body } => CostExpr::Loop { fid: *fid, instr_idx: *instr_idx, body: Box::new(body.compose_in(formulas, callers)) },
+            CostExpr::Call { fid } => match formulas.get(fid) {
+                Some(callee) if !callers.contains(fid) => {
+                    callers.push(*fid);
+                    let composed = callee.compose_in(formulas, callers);
+                    callers.pop();
+                    composed
+                }
+                _ => self.clone()
+            }
+        }
+    }
+
+    /// Estimate the cost with the given trip counts (`trip_count(fid, instr_idx)` of a loop),
+    /// `None` if a trip count is unknown or a call is still a placeholder.
+    pub fn eval(&self, trip_count: &impl Fn(u32, usize) -> Option<u64>) -> Option<u64> {
+        match self {
+            CostExpr::Const(cost) => Some(*cost),
+            CostExpr::Sum(terms) => terms.iter().try_fold(0u64, |total, term| Some(total.saturating_add(term.eval(trip_count)?))),
+            CostExpr::Max(alternatives) => alternatives.iter().try_fold(0u64, |worst, alternative| Some(worst.max(alternative.eval(trip_count)?))),
+            CostExpr::Loop { fid, instr_idx, body } => Some(trip_count(*fid, *instr_idx)?.saturating_mul(body.eval(trip_count)?)),
+            CostExpr::Call { .. } => None
+        }
+    }
+
+    /// The (fid, instr_idx) of every loop in the expression (the trip counts that it depends on)
+    pub fn loops(&self) -> Vec<(u32, usize)> {
+        match self {
+            CostExpr::Const(_) | CostExpr::Call { .. } => vec![],
+            CostExpr::Sum(terms) | CostExpr::Max(terms) => terms.iter().flat_map(CostExpr::loops).collect(),
+            CostExpr::Loop { fid, instr_idx, body } => [vec![(*fid, *instr_idx)], body.loops()].concat()
+        }
+    }
+}
+impl Display for CostExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let join = |terms: &[CostExpr], sep: &str| terms.iter().map(|term| term.to_string()).collect::<Vec<_>>().join(sep);
+        match self {
+            CostExpr::Const(cost) => write!(f, "{cost}"),
+            CostExpr::Sum(terms) => write!(f, "{}", join(terms, " + ")),
+            CostExpr::Max(alternatives) => write!(f, "max({})", join(alternatives, ", ")),
+            CostExpr::Loop { fid, instr_idx, body } => match body.as_ref() {
+                CostExpr::Sum(_) => write!(f, "({body})*n{fid}_{instr_idx}"),
+                _ => write!(f, "{body}*n{fid}_{instr_idx}")
+            },
+            CostExpr::Call { fid } => write!(f, "f{fid}")
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use wirm::wasmparser::{for_each_operator, Operator};
+use crate::stats::classify;
+use crate::utils::dyn_cost_per_elem;
+pub use crate::stats::InstrClass;
+
+macro_rules! op_names {
+    ($(@$proposal:ident $op:ident $({ $($payload:tt)* })? => $visit:ident ($($ann:tt)*))*) => {
+        &[$(stringify!($op)),*]
+    }
+}
+/// The name of every op (as in wasmparser's `Operator`, e.g. `I32Add`)
+const OP_NAMES: &[&str] = for_each_operator!(op_names);
+const CLASSES: [InstrClass; 6] = [InstrClass::Arithmetic, InstrClass::Memory, InstrClass::Control,
+    InstrClass::Call, InstrClass::Variable, InstrClass::Other];
+
+/// The fuel that the generated functions charge for each op of the original program, pass one to
+/// `AnalysisConfig::cost_model` (`CostModel` by default). The built-in models are `Uniform`,
+/// `ClassWeights` and `CostModel` (a table loaded from a file).
+pub trait OpCostModel: std::fmt::Debug {
+    fn cost(&self, op: &Operator) -> u64;
+    /// The fuel charged per element (or byte) of the bulk ops whose cost depends on their length,
+    /// on top of their `cost`. TABLE_ELEM_COST and MEMORY_BYTE_COST by default (see `dyn_cost_per_elem`).
+    fn cost_per_elem(&self, op: &Operator) -> u64 {
+        dyn_cost_per_elem(op).unwrap_or_default()
+    }
+}
+
+/// Every op costs the same
+#[derive(Debug, Clone, Copy)]
+pub struct Uniform(pub u64);
+impl OpCostModel for Uniform {
+    fn cost(&self, _op: &Operator) -> u64 {
+        self.0
+    }
+}
+
+/// An op is charged the weight of its class (see `stats::InstrClass`), else the default
+#[derive(Debug, Clone)]
+pub struct ClassWeights {
+    pub default: u64,
+    pub classes: BTreeMap<InstrClass, u64>
+}
+impl ClassWeights {
+    pub fn new(default: u64) -> Self {
+        Self { default, classes: BTreeMap::new() }
+    }
+
+    pub fn weight(mut self, class: InstrClass, weight: u64) -> Self {
+        self.classes.insert(class, weight);
+        self
+    }
+}
+impl OpCostModel for ClassWeights {
+    fn cost(&self, op: &Operator) -> u64 {
+        self.classes.get(&classify(op)).copied().unwrap_or(self.default)
+    }
+}
+
+/// The fuel charged per op, e.g. loaded from a JSON gas schedule:
+/// `{ "default": 1, "classes": { "memory": 3 }, "ops": { "I64DivU": 20 } }`
+/// An op is charged its own weight, else the weight of its class (see `stats::InstrClass`), else the default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CostModel {
+    #[serde(default = "default_weight")]
+    pub default: u64,
+    /// class name -> weight
+    #[serde(default)]
+    pub classes: BTreeMap<String, u64>,
+    /// op name -> weight
+    #[serde(default)]
+    pub ops: BTreeMap<String, u64>
+}
+fn default_weight() -> u64 { 1 }
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            default: default_weight(),
+            classes: BTreeMap::new(),
+            ops: BTreeMap::new()
+        }
+    }
+}
+impl CostModel {
+    /// Load (and validate) the cost model at `path`.
+    #[cfg(feature = "fs")]
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).with_context(|| format!("Failed to read the cost model {path}"))?;
+        Self::parse(&contents, path)
+    }
+
+    /// Parse (and validate) a cost model that wasn't read from a file, e.g. in the browser
+    pub fn from_json(contents: &str) -> anyhow::Result<Self> {
+        Self::parse(contents, "JSON")
+    }
+
+    /// - `name`: where the cost model comes from, for the errors
+    fn parse(contents: &str, name: &str) -> anyhow::Result<Self> {
+        let model: Self = serde_json::from_str(contents).with_context(|| format!("Malformed cost model {name}"))?;
+        model.validate()?;
+        Ok(model)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for class in self.classes.keys() {
+            if !CLASSES.iter().any(|known| known.to_string() == *class) {
+                bail!("Unknown instruction class in the cost model: {class} (expected one of: {})",
+                      CLASSES.map(|known| known.to_string()).join(", "));
+            }
+        }
+        for op in self.ops.keys() {
+            if !OP_NAMES.contains(&op.as_str()) {
+                bail!("Unknown op in the cost model: {op} (ops are named as in wasmparser, e.g. I32Add)");
+            }
+        }
+        Ok(())
+    }
+}
+impl OpCostModel for CostModel {
+    fn cost(&self, op: &Operator) -> u64 {
+        if !self.ops.is_empty() {
+            let debug = format!("{op:?}");
+            let name = debug.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default();
+            if let Some(weight) = self.ops.get(name) {
+                return *weight;
+            }
+        }
+        self.classes.get(&classify(op).to_string()).copied().unwrap_or(self.default)
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::{BTreeSet, HashMap};
+use anyhow::bail;
+use wirm::wasmparser::{ElementItems, ExternalKind, Operator, Parser, Payload, TypeRef};
+
+/// The local functions that are analyzed with `--entry`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryClosure {
+    pub fids: BTreeSet<u32>,
+    /// How many local functions were left out
+    pub num_excluded: usize
+}
+
+/// The local functions that an execution of the exported function `export` can run: the
+/// closure of its direct calls. If one of them calls indirectly (or through a reference),
+/// every function that is in a table or referenced with `ref.func` is included (and followed).
+/// Calls to the host aren't followed, the host reentering the module through another export
+/// is out of scope (as when metering a single handler).
+pub fn reachable_from(wasm_bytes: &[u8], export: &str) -> anyhow::Result<EntryClosure> {
+    let mut num_imported = 0;
+    let mut entry = None;
+    // the functions whose address is taken, the targets of the indirect calls
+    let mut address_taken = BTreeSet::new();
+    // fid -> (the functions it calls directly, whether it calls indirectly)
+    let mut calls: HashMap<u32, (BTreeSet<u32>, bool)> = HashMap::new();
+    let mut num_bodies = 0;
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::ImportSection(imports) => {
+                for import in imports {
+                    if matches!(import?.ty, TypeRef::Func(_)) {
+                        num_imported += 1;
+                    }
+                }
+            }
+            Payload::ExportSection(exports) => {
+                for exp in exports {
+                    let exp = exp?;
+                    if exp.name == export && matches!(exp.kind, ExternalKind::Func) {
+                        entry = Some(exp.index);
+                    }
+                }
+            }
+            Payload::ElementSection(elements) => {
+                for element in elements {
+                    match element?.items {
+                        ElementItems::Functions(funcs) => {
+                            for fid in funcs {
+                                address_taken.insert(fid?);
+                            }
+                        }
+                        ElementItems::Expressions(_, exprs) => {
+                            for expr in exprs {
+                                let mut reader = expr?.get_operators_reader();
+                                while !reader.eof() {
+                                    if let Operator::RefFunc { function_index } = reader.read()? {
+                                        address_taken.insert(function_index);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let fid = num_imported + num_bodies;
+                num_bodies += 1;
+                let (callees, calls_indirect) = calls.entry(fid).or_default();
+                let mut reader = body.get_operators_reader()?;
+                while !reader.eof() {
+                    match reader.read()? {
+                        Operator::Call { function_index } | Operator::ReturnCall { function_index } => {
+                            callees.insert(function_index);
+                        }
+                        Operator::CallIndirect { .. } | Operator::ReturnCallIndirect { .. } |
+                        Operator::CallRef { .. } | Operator::ReturnCallRef { .. } => *calls_indirect = true,
+                        Operator::RefFunc { function_index } => {
+                            address_taken.insert(function_index);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let Some(entry) = entry else {
+        bail!("The module has no exported function \"{export}\"");
+    };
+
+    let mut reachable = BTreeSet::new();
+    let mut worklist = vec![entry];
+    let mut followed_indirect = false;
+    while let Some(fid) = worklist.pop() {
+        // imported functions have no body
+        let Some((callees, calls_indirect)) = calls.get(&fid) else {
+            continue;
+        };
+        if !reachable.insert(fid) {
+            continue;
+        }
+        worklist.extend(callees.iter().copied());
+        if *calls_indirect && !followed_indirect {
+            followed_indirect = true;
+            worklist.extend(address_taken.iter().copied());
+        }
+    }
+    Ok(EntryClosure {
+        num_excluded: num_bodies as usize - reachable.len(),
+        fids: reachable
+    })
+}
@@ -0,0 +1,143 @@
+use std::fmt::{Display, Formatter};
+
+const UNSUPPORTED_OP: &str = "op not supported";
+
+/// The analysis failed on this module, either it is invalid or uses something we don't support.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnalysisError {
+    MissingFuncType { tid: u32 },
+    /// The instruction is not in the function body
+    MissingInstr { fid: u32, instr_idx: usize },
+    /// The call does not have the result that was used
+    MissingCallResult { fid: u32, instr_idx: usize, result_idx: usize },
+    MissingParam { fid: u32, lid: u32 },
+    /// The function is imported (or not in the module), it has no body
+    NotLocalFunc { fid: u32 },
+    /// The module doesn't define the table that an instruction reads
+    MissingTable { table_index: u32 },
+    /// The module doesn't define the struct or array type that an instruction uses
+    MissingGcType { tid: u32 },
+    UnsupportedOp { op: String },
+    /// The instruction popped more values than there were on the stack
+    StackUnderflow { fid: u32, instr_idx: usize },
+    /// A block's `end` does not match the stack state at the start of the block
+    UnbalancedControl { fid: u32, instr_idx: usize },
+    /// The function finished with unexpected values on the stack
+    LeftoverStack { fid: u32, height: usize },
+    /// The module couldn't be parsed
+    InvalidModule { reason: String },
+    /// The module uses a Wasm proposal that isn't enabled (see `--features`)
+    DisabledFeature { reason: String },
+    /// The analysis panicked (see `repro::catch_failure`)
+    Panicked { msg: String },
+    /// The analysis only approximates the fuel of the instruction, rejected with `--strict` (see `strict`)
+    Approximated { fid: u32, instr_idx: usize, reason: String }
+}
+impl Display for AnalysisError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalysisError::MissingFuncType { tid } => write!(f, "Should have found a function type for type #{tid}!"),
+            AnalysisError::MissingInstr { fid, instr_idx } => write!(f, "function #{fid} has no instruction @{instr_idx}"),
+            AnalysisError::MissingCallResult { fid, instr_idx, result_idx } => write!(f, "function #{fid}: the call @{instr_idx} has no result #{result_idx}"),
+            AnalysisError::MissingParam { fid, lid } => write!(f, "function #{fid} has no param #{lid}"),
+            AnalysisError::NotLocalFunc { fid } => write!(f, "function #{fid} is not a local function of the module"),
+            AnalysisError::MissingTable { table_index } => write!(f, "Should have found table #{table_index}!"),
+            AnalysisError::MissingGcType { tid } => write!(f, "Should have found a struct or array type for type #{tid}!"),
+            AnalysisError::UnsupportedOp { op } => write!(f, "{UNSUPPORTED_OP}: {op}"),
+            AnalysisError::StackUnderflow { fid, instr_idx } => write!(f, "function #{fid}: issue when popping @{instr_idx}, your Wasm module may be invalid"),
+            AnalysisError::UnbalancedControl { fid, instr_idx } => write!(f, "function #{fid}: the block ending @{instr_idx} left the stack unbalanced, your Wasm module may be invalid"),
+            AnalysisError::LeftoverStack { fid, height } => write!(f, "function #{fid} still had {height} stack values leftover"),
+            AnalysisError::InvalidModule { reason } => write!(f, "Failed to parse module: {reason}"),
+            AnalysisError::DisabledFeature { reason } => write!(f, "The module is rejected with the enabled Wasm features: {reason}"),
+            AnalysisError::Panicked { msg } => write!(f, "The analysis panicked: {msg}"),
+            AnalysisError::Approximated { fid, instr_idx, reason } => write!(f, "function #{fid} @{instr_idx} is approximated, rejected in strict mode: {reason}")
+        }
+    }
+}
+impl std::error::Error for AnalysisError {}
+impl AnalysisError {
+    /// The function that the analysis failed in (if it's known)
+    pub fn fid(&self) -> Option<u32> {
+        match self {
+            AnalysisError::MissingInstr { fid, .. } | AnalysisError::MissingCallResult { fid, .. } |
+            AnalysisError::MissingParam { fid, .. } | AnalysisError::NotLocalFunc { fid } | AnalysisError::StackUnderflow { fid, .. } |
+            AnalysisError::UnbalancedControl { fid, .. } | AnalysisError::LeftoverStack { fid, .. } |
+            AnalysisError::Approximated { fid, .. } => Some(*fid),
+            AnalysisError::MissingFuncType { .. } | AnalysisError::MissingTable { .. } | AnalysisError::MissingGcType { .. } |
+            AnalysisError::UnsupportedOp { .. } |
+            AnalysisError::InvalidModule { .. } | AnalysisError::DisabledFeature { .. } | AnalysisError::Panicked { .. } => None
+        }
+    }
+
+    /// The instruction that the analysis failed at (if it's known)
+    pub fn instr_idx(&self) -> Option<usize> {
+        match self {
+            AnalysisError::MissingInstr { instr_idx, .. } | AnalysisError::MissingCallResult { instr_idx, .. } |
+            AnalysisError::StackUnderflow { instr_idx, .. } | AnalysisError::UnbalancedControl { instr_idx, .. } |
+            AnalysisError::Approximated { instr_idx, .. } => Some(*instr_idx),
+            _ => None
+        }
+    }
+}
+
+/// What kind of failure ended the binary, so that scripts wrapping it can react to each
+/// (clap uses the exit code 2 for bad arguments).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Anything else, e.g. a file that couldn't be read
+    Other,
+    /// The module couldn't be parsed
+    Parse,
+    /// The module uses an op or a Wasm proposal that isn't supported
+    Unsupported,
+    /// The analysis itself failed (a bug, or an invalid module that did parse)
+    Internal
+}
+impl FailureKind {
+    pub fn of(err: &anyhow::Error) -> Self {
+        if err.chain().any(|cause| cause.is::<wirm::wasmparser::BinaryReaderError>()) {
+            return Self::Parse;
+        }
+        match err.chain().find_map(|cause| cause.downcast_ref::<AnalysisError>()) {
+            None => Self::Other,
+            Some(AnalysisError::InvalidModule { .. }) => Self::Parse,
+            Some(AnalysisError::UnsupportedOp { .. } | AnalysisError::DisabledFeature { .. } | AnalysisError::Approximated { .. }) => Self::Unsupported,
+            // by default, `fail!` panics with the error's message
+            Some(AnalysisError::Panicked { msg }) if msg.starts_with(UNSUPPORTED_OP) => Self::Unsupported,
+            Some(_) => Self::Internal
+        }
+    }
+
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::Parse => 3,
+            Self::Unsupported => 4,
+            Self::Internal => 5
+        }
+    }
+}
+impl Display for FailureKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Other => write!(f, "error"),
+            Self::Parse => write!(f, "parse failure"),
+            Self::Unsupported => write!(f, "unsupported"),
+            Self::Internal => write!(f, "internal analysis error")
+        }
+    }
+}
+
+/// Fail the analysis with an `AnalysisError`.
+/// By default, this panics (so we get a backtrace). With the `no-panic` feature, the error is returned
+/// from the enclosing function so that a bad module cannot take down the embedding service.
+macro_rules! fail {
+    ($err:expr) => {{
+        let err: $crate::error::AnalysisError = $err;
+        #[cfg(feature = "no-panic")]
+        return Err(err);
+        #[cfg(not(feature = "no-panic"))]
+        panic!("{err}");
+    }};
+}
+pub(crate) use fail;
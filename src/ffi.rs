@@ -0,0 +1,214 @@
+//! The C API (behind the `ffi` feature), for runtimes that aren't written in Rust, see
+//! `include/whamm_fuel.h`.
+//!
+//! A host analyzes a module with `whamm_fuel_analyze` (or `whamm_fuel_analyze_with` to pass a
+//! `WhammFuelConfig`), reads the generated modules and the JSON report out of the returned handle,
+//! then releases it with `whamm_fuel_free`. The buffers that the handle hands out live as long as
+//! the handle.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::ptr;
+use crate::repro::catch_failure;
+use anyhow::bail;
+use crate::run::{run_analysis, AnalysisConfig, AnalysisOutput, CompType};
+
+/// `whamm_fuel_wasm` returns the max variant
+pub const WHAMM_FUEL_MAX: u32 = 0;
+/// `whamm_fuel_wasm` returns the min variant
+pub const WHAMM_FUEL_MIN: u32 = 1;
+
+/// `WhammFuelConfig::comp_type`, see `CompType`
+pub const WHAMM_FUEL_EXACT: u32 = 0;
+pub const WHAMM_FUEL_APPROX: u32 = 1;
+pub const WHAMM_FUEL_EPOCH: u32 = 2;
+pub const WHAMM_FUEL_BACK_EDGE: u32 = 3;
+
+/// `whamm_fuel_wasm` succeeded
+pub const WHAMM_FUEL_OK: i32 = 0;
+/// A pointer that must be set is NULL
+pub const WHAMM_FUEL_ERR_NULL: i32 = -1;
+/// The variant is neither `WHAMM_FUEL_MAX` nor `WHAMM_FUEL_MIN`
+pub const WHAMM_FUEL_ERR_VARIANT: i32 = -2;
+
+thread_local! {
+    /// Why the last call on this thread failed
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// The options of the analysis that the C API exposes (a subset of `AnalysisConfig`), start from
+/// `whamm_fuel_default_config`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct WhammFuelConfig {
+    /// `WHAMM_FUEL_EXACT`, `WHAMM_FUEL_APPROX`, `WHAMM_FUEL_EPOCH` or `WHAMM_FUEL_BACK_EDGE`
+    pub comp_type: u32,
+    /// The budget the generated functions count down from, negative to count up from 0 instead
+    pub init_fuel: i64,
+    /// See `AnalysisConfig::on_out_of_fuel`, needs an `init_fuel`
+    pub on_out_of_fuel: bool,
+    /// Round the cost of every charge up to a multiple of it, 0 or 1 to charge the exact cost
+    pub granularity: u64,
+    /// See `AnalysisConfig::export_static_costs`
+    pub export_static_costs: bool,
+    /// See `AnalysisConfig::keep_going`
+    pub keep_going: bool,
+    /// See `AnalysisConfig::strict`
+    pub strict: bool
+}
+impl WhammFuelConfig {
+    fn to_config(self) -> anyhow::Result<AnalysisConfig> {
+        let comp_type = match self.comp_type {
+            WHAMM_FUEL_EXACT => CompType::Exact,
+            WHAMM_FUEL_APPROX => CompType::Approx,
+            WHAMM_FUEL_EPOCH => CompType::Epoch,
+            WHAMM_FUEL_BACK_EDGE => CompType::BackEdge,
+            other => bail!("Unknown comp type: {other}")
+        };
+        Ok(AnalysisConfig {
+            comp_type,
+            init_fuel: u64::try_from(self.init_fuel).ok(),
+            on_out_of_fuel: self.on_out_of_fuel,
+            granularity: (self.granularity > 1).then_some(self.granularity),
+            export_static_costs: self.export_static_costs,
+            keep_going: self.keep_going,
+            strict: self.strict,
+            ..Default::default()
+        })
+    }
+}
+
+/// The results of `whamm_fuel_analyze`, opaque to C
+pub struct WhammFuelOutput {
+    output: AnalysisOutput,
+    /// The JSON report, NUL-terminated
+    report: CString
+}
+
+/// The options of the default `AnalysisConfig`
+#[no_mangle]
+pub extern "C" fn whamm_fuel_default_config() -> WhammFuelConfig {
+    let config = AnalysisConfig::default();
+    let comp_type = match config.comp_type {
+        CompType::Exact => WHAMM_FUEL_EXACT,
+        CompType::Approx => WHAMM_FUEL_APPROX,
+        CompType::Epoch => WHAMM_FUEL_EPOCH,
+        CompType::BackEdge => WHAMM_FUEL_BACK_EDGE
+    };
+    WhammFuelConfig {
+        comp_type,
+        init_fuel: config.init_fuel.map_or(-1, |init_fuel| init_fuel as i64),
+        on_out_of_fuel: config.on_out_of_fuel,
+        granularity: config.granularity.unwrap_or_default(),
+        export_static_costs: config.export_static_costs,
+        keep_going: config.keep_going,
+        strict: config.strict
+    }
+}
+
+/// Analyze the module in `wasm[..len]` with the default config. Returns NULL if the analysis
+/// fails, the reason is then available from `whamm_fuel_last_error`.
+///
+/// # Safety
+/// `wasm` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn whamm_fuel_analyze(wasm: *const u8, len: usize) -> *mut WhammFuelOutput {
+    whamm_fuel_analyze_with(wasm, len, ptr::null())
+}
+
+/// Analyze the module in `wasm[..len]` with the `config` (the default config if it's NULL).
+/// Returns NULL if the analysis fails, the reason is then available from `whamm_fuel_last_error`.
+///
+/// # Safety
+/// `wasm` must point to `len` readable bytes, `config` must be NULL or point to a `WhammFuelConfig`.
+#[no_mangle]
+pub unsafe extern "C" fn whamm_fuel_analyze_with(wasm: *const u8, len: usize, config: *const WhammFuelConfig) -> *mut WhammFuelOutput {
+    if wasm.is_null() {
+        set_last_error("The module is NULL".to_string());
+        return ptr::null_mut();
+    }
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    let bytes = std::slice::from_raw_parts(wasm, len);
+    let config = config.as_ref().copied().unwrap_or_else(|| whamm_fuel_default_config());
+    let result = catch_failure(|| {
+        let output = run_analysis(bytes, &config.to_config()?)?;
+        // the JSON encoding doesn't contain NULs
+        let report = CString::new(serde_json::to_string(&output.report)?)?;
+        Ok(WhammFuelOutput { output, report })
+    });
+    match result {
+        Ok(output) => Box::into_raw(Box::new(output)),
+        Err(err) => {
+            set_last_error(format!("{err:#}"));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// The generated module of a variant (`WHAMM_FUEL_MAX` or `WHAMM_FUEL_MIN`): sets `*data` and
+/// `*len` to its bytes. Returns `WHAMM_FUEL_OK`, or the error code (and sets `whamm_fuel_last_error`)
+/// if a pointer is NULL or the variant is unknown.
+///
+/// # Safety
+/// `output` must be NULL or come from `whamm_fuel_analyze` (and not be freed yet), `data` and `len`
+/// must be NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn whamm_fuel_wasm(output: *const WhammFuelOutput, variant: u32, data: *mut *const u8, len: *mut usize) -> i32 {
+    if output.is_null() || data.is_null() || len.is_null() {
+        set_last_error("The output, data or len is NULL".to_string());
+        return WHAMM_FUEL_ERR_NULL;
+    }
+    let output = &(*output).output;
+    let bytes = match variant {
+        WHAMM_FUEL_MAX => &output.max_wasm,
+        WHAMM_FUEL_MIN => &output.min_wasm,
+        _ => {
+            set_last_error(format!("Unknown variant: {variant}"));
+            return WHAMM_FUEL_ERR_VARIANT;
+        }
+    };
+    *data = bytes.as_ptr();
+    *len = bytes.len();
+    WHAMM_FUEL_OK
+}
+
+/// The JSON report (as written by `--report-json`), NUL-terminated. Returns NULL (and sets
+/// `whamm_fuel_last_error`) if `output` is NULL.
+///
+/// # Safety
+/// `output` must be NULL or come from `whamm_fuel_analyze` (and not be freed yet).
+#[no_mangle]
+pub unsafe extern "C" fn whamm_fuel_report(output: *const WhammFuelOutput) -> *const c_char {
+    if output.is_null() {
+        set_last_error("The output is NULL".to_string());
+        return ptr::null();
+    }
+    (*output).report.as_ptr()
+}
+
+/// Release the results, the buffers they handed out can't be used anymore
+///
+/// # Safety
+/// `output` must come from `whamm_fuel_analyze` (or be NULL), and is only freed once.
+#[no_mangle]
+pub unsafe extern "C" fn whamm_fuel_free(output: *mut WhammFuelOutput) {
+    if !output.is_null() {
+        drop(Box::from_raw(output));
+    }
+}
+
+/// Why the last call on this thread failed, NULL if the last analysis didn't (and nothing failed
+/// since). The message lives until the next analysis or failure on this thread.
+#[no_mangle]
+pub extern "C" fn whamm_fuel_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |msg| msg.as_ptr()))
+}
+
+fn set_last_error(msg: String) {
+    // an error message with a NUL is cut there
+    let msg = CString::new(msg).unwrap_or_else(|err| {
+        let end = err.nul_position();
+        CString::new(&err.into_vec()[..end]).unwrap()
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(msg));
+}
@@ -0,0 +1,151 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use wirm::ir::id::{FunctionID, GlobalID};
+use wirm::ir::module::module_globals::{GlobalKind, ImportedGlobal, LocalGlobal};
+use wirm::ir::types::{InitInstr, Value};
+use wirm::Module;
+use wirm::wasmparser::Operator;
+use crate::purity::{func_effects, Effects};
+use crate::utils::local_funcs;
+
+/// The static writes of the globals and the call graph of the module, used to find the
+/// globals that keep their value for a whole function execution (see `is_stable_in`).
+/// All the reads of such a global in a function depend on the same def, so they can share
+/// a single requested state rather than requesting it per read.
+/// Every local function is scanned (not only the analyzed ones), a callee that isn't analyzed
+/// can still write a global while the caller runs.
+#[derive(Debug, Default)]
+pub(crate) struct GlobalDefs {
+    /// gid -> the (fid, instr_idx) of each `global.set` of the global, and whether it's inside a loop
+    writes: HashMap<u32, Vec<(u32, usize, bool)>>,
+    /// fid -> the local functions that it calls directly
+    callees: HashMap<u32, HashSet<u32>>,
+    /// The effects of each local function, including the effects of its callees (see `purity::func_effects`)
+    effects: BTreeMap<u32, Effects>
+}
+impl GlobalDefs {
+    pub(crate) fn new(wasm: &Module) -> Self {
+        let mut defs = Self { effects: func_effects(wasm), ..Default::default() };
+        for (fid, local) in local_funcs(wasm) {
+            let body = local.body.instructions.get_ops();
+            // whether each of the open blocks is a `loop`
+            let mut blocks: Vec<bool> = Vec::new();
+            for (instr_idx, op) in body.iter().enumerate() {
+                match op {
+                    Operator::Block { .. } | Operator::If { .. } | Operator::TryTable { .. } => blocks.push(false),
+                    Operator::Loop { .. } => blocks.push(true),
+                    Operator::End => { blocks.pop(); }
+                    Operator::GlobalSet { global_index } => {
+                        let in_loop = blocks.iter().any(|is_loop| *is_loop);
+                        defs.writes.entry(*global_index).or_default().push((fid, instr_idx, in_loop));
+                    }
+                    Operator::Call { function_index } | Operator::ReturnCall { function_index }
+                        if wasm.functions.is_local(FunctionID(*function_index)) => {
+                        defs.callees.entry(fid).or_default().insert(*function_index);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        defs
+    }
+
+    /// The (fid, instr_idx) of the global's only `global.set`, if it's written exactly once
+    /// in the module (and not inside a loop).
+    pub(crate) fn single_writer(&self, gid: u32) -> Option<(u32, usize)> {
+        match self.writes.get(&gid).map(|writes| writes.as_slice()) {
+            Some([(fid, instr_idx, false)]) => Some((*fid, *instr_idx)),
+            _ => None
+        }
+    }
+
+    /// Whether the global keeps its value during any execution of function `fid`: it's immutable,
+    /// neither `fid` nor its callees write globals (see `purity::Effects`), or it's never written /
+    /// only has a single writer (see `single_writer`) and `fid` can't reach that write (through its calls).
+    pub(crate) fn is_stable_in(&self, gid: u32, fid: u32, wasm: &Module) -> bool {
+        let (GlobalKind::Local(LocalGlobal { ty, .. }) |
+            GlobalKind::Import(ImportedGlobal { ty, .. })) = wasm.globals.get_kind(GlobalID(gid));
+        if !ty.mutable {
+            return true;
+        }
+        if ty.shared {
+            // another thread can write it at any time
+            return false;
+        }
+        if !self.calls_unknown(fid) && !self.effects.get(&fid).is_some_and(|effects| effects.writes_globals) {
+            // neither the function nor its callees write any global
+            return true;
+        }
+        let writer = self.single_writer(gid).map(|(writer, _)| writer);
+        if self.writes.contains_key(&gid) && writer.is_none() {
+            return false;
+        }
+        !self.reaches(fid, writer)
+    }
+
+    /// Whether the function (or one of its callees) calls something we can't see: an indirect call,
+    /// or a call to the host (which may reenter the module and write anything)
+    fn calls_unknown(&self, fid: u32) -> bool {
+        self.effects.get(&fid).is_none_or(|effects| effects.calls_imports || effects.calls_indirect)
+    }
+
+    /// Whether a call from `from` can reach the `to` function (`None` only looks for unknown calls).
+    /// Unknown calls conservatively reach everything.
+    fn reaches(&self, from: u32, to: Option<u32>) -> bool {
+        let mut visited = HashSet::new();
+        let mut worklist = VecDeque::from([from]);
+        while let Some(fid) = worklist.pop_front() {
+            if !visited.insert(fid) {
+                continue;
+            }
+            if Some(fid) == to || self.calls_unknown(fid) {
+                return true;
+            }
+            worklist.extend(self.callees.get(&fid).into_iter().flatten());
+        }
+        false
+    }
+}
+
+/// The value of an immutable global whose initializer is a constant expression (see `const_value`)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ConstValue {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64)
+}
+impl Display for ConstValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::I32(v) => write!(f, "i32.const {v}"),
+            Self::I64(v) => write!(f, "i64.const {v}"),
+            Self::F32(v) => write!(f, "f32.const {v}"),
+            Self::F64(v) => write!(f, "f64.const {v}")
+        }
+    }
+}
+
+/// The value of global `gid` if it's known statically: a local immutable global initialized with a
+/// constant or the value of another such global. An imported global is set by the host, as is any
+/// initializer reading one. (The extended-const arithmetic isn't supported: wirm rejects those
+/// initializers when parsing the module.)
+pub(crate) fn const_value(gid: u32, wasm: &Module) -> Option<ConstValue> {
+    let GlobalKind::Local(LocalGlobal { ty, init_expr, .. }) = wasm.globals.get_kind(GlobalID(gid)) else {
+        return None;
+    };
+    if ty.mutable {
+        return None;
+    }
+    match init_expr.instructions() {
+        [InitInstr::Value(Value::I32(v))] => Some(ConstValue::I32(*v)),
+        [InitInstr::Value(Value::I64(v))] => Some(ConstValue::I64(*v)),
+        [InitInstr::Value(Value::F32(v))] => Some(ConstValue::F32(*v)),
+        [InitInstr::Value(Value::F64(v))] => Some(ConstValue::F64(*v)),
+        // an initializer can only read the globals defined before it, so this terminates
+        [InitInstr::Global(other)] => const_value(**other, wasm),
+        // e.g. a reference, it isn't a number that the generated code can replay
+        _ => None
+    }
+}
@@ -0,0 +1,133 @@
+//! A static HTML page to browse the analysis results of several modules at once (e.g. every module
+//! of a release), so that they can be published as a single artifact: one table of functions per
+//! module and a search over the function names and costs across all of them.
+
+use crate::names::DebugNames;
+use crate::sidecar::JsonReport;
+
+/// The results of one analyzed module, as listed on the index page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleSummary {
+    /// How the module is called on the page (e.g. its file name)
+    pub name: String,
+    pub functions: Vec<FuncSummary>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncSummary {
+    pub fid: u32,
+    /// The debug (or export) name of the function, if it has one
+    pub name: Option<String>,
+    /// The number of fuel points in the function
+    pub fuel_points: usize,
+    /// The fuel charged by all the fuel points of the function together
+    pub static_cost: u64,
+    /// The worst-case cost in terms of the trip counts of the loops (see `CostExpr`)
+    pub formula: String,
+    /// The number of values that the max variant's generated functions request
+    pub requested_state: usize,
+    /// Whether the analysis fell back to the worst-case cost of the function
+    pub fallback: bool
+}
+
+impl ModuleSummary {
+    /// Summarize the `report` of the module `name` (see `run::run_analysis`), the functions are
+    /// named from the module's `names`
+    pub(crate) fn new(name: &str, report: &JsonReport, names: &DebugNames) -> Self {
+        let functions = report.functions.iter().map(|func| {
+            let generated = report.fid_mapping.get("max").and_then(|mapping| mapping.get(&func.fid)).map(Vec::as_slice).unwrap_or_default();
+            FuncSummary {
+                fid: func.fid,
+                name: names.function(func.fid).map(str::to_string),
+                fuel_points: func.costs.len(),
+                static_cost: func.costs.values().sum(),
+                formula: report.formulas.get(&func.fid).cloned().unwrap_or_default(),
+                requested_state: generated.iter()
+                    .flat_map(|entry| entry.req_state.values().flat_map(|reqs| reqs.values()))
+                    .map(Vec::len)
+                    .sum(),
+                fallback: generated.iter().any(|entry| entry.fallback.is_some())
+            }
+        }).collect();
+        Self {
+            name: name.to_string(),
+            functions
+        }
+    }
+}
+
+/// Filters the rows of every table by the search (on the module and function names) and the
+/// minimum static cost, and hides the modules without a matching function
+const SCRIPT: &str = r#"
+function filterFuncs() {
+    const query = document.getElementById("search").value.toLowerCase();
+    const minCost = Number(document.getElementById("min-cost").value) || 0;
+    let shown = 0;
+    for (const module of document.querySelectorAll("section.module")) {
+        let matches = 0;
+        for (const row of module.querySelectorAll("tr.func")) {
+            const match = row.dataset.search.includes(query) && Number(row.dataset.cost) >= minCost;
+            row.hidden = !match;
+            matches += match ? 1 : 0;
+        }
+        module.hidden = matches === 0;
+        shown += matches;
+    }
+    document.getElementById("shown").textContent = shown;
+}
+"#;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }
+td.num { text-align: right; }
+tr.fallback { background: #fdd; }
+"#;
+
+/// Render the index page of the `modules`: a search box, then a table of the functions of each module
+pub fn render_index(modules: &[ModuleSummary]) -> String {
+    let num_funcs: usize = modules.iter().map(|module| module.functions.len()).sum();
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>whamm-fuel results</title>\n");
+    html += &format!("<style>{STYLE}</style>\n<script>{SCRIPT}</script>\n</head>\n<body>\n");
+    html += &format!("<h1>Fuel analysis of {} modules</h1>\n", modules.len());
+    html += &format!("<p><input id=\"search\" type=\"search\" placeholder=\"Module or function name\" oninput=\"filterFuncs()\"> \
+                     <label>Min. static cost <input id=\"min-cost\" type=\"number\" min=\"0\" oninput=\"filterFuncs()\"></label> \
+                     <span id=\"shown\">{num_funcs}</span> of {num_funcs} functions</p>\n");
+    html += "<ul>\n";
+    for (idx, module) in modules.iter().enumerate() {
+        html += &format!("<li><a href=\"#module{idx}\">{}</a> ({} functions)</li>\n", escape(&module.name), module.functions.len());
+    }
+    html += "</ul>\n";
+    for (idx, module) in modules.iter().enumerate() {
+        html += &format!("<section class=\"module\" id=\"module{idx}\">\n<h2>{}</h2>\n", escape(&module.name));
+        html += "<table>\n<tr><th>FID</th><th>Function</th><th>Fuel points</th><th>Static cost</th><th>Worst case</th><th>Requested state</th></tr>\n";
+        for func in module.functions.iter() {
+            let name = func.name.as_deref().unwrap_or_default();
+            let search = format!("{} {name} #{}", module.name, func.fid).to_lowercase();
+            html += &format!("<tr class=\"func{}\" data-search=\"{}\" data-cost=\"{}\"><td class=\"num\">{}</td><td>{}</td><td class=\"num\">{}</td>\
+                             <td class=\"num\">{}</td><td>{}</td><td class=\"num\">{}</td></tr>\n",
+                             if func.fallback { " fallback" } else { "" }, escape(&search), func.static_cost, func.fid, escape(name),
+                             func.fuel_points, func.static_cost, escape(&func.formula), func.requested_state);
+        }
+        html += "</table>\n</section>\n";
+    }
+    html += "</body>\n</html>\n";
+    html
+}
+
+/// Escape the text for the content or a (quoted) attribute of an element
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c)
+        }
+    }
+    escaped
+}
@@ -1,6 +1,97 @@
+//! Statically computes the fuel (instruction cost) of WebAssembly functions and generates the
+//! functions that compute it at runtime, from only the state that influences the control flow.
+//!
+//! `run::do_analysis` runs the whole pipeline, as the CLI does. To embed the steps:
+//! 1. [`analyze`]: the taint analysis of each local function
+//! 2. [`slice_program`]: slice each function backward from its control flow
+//! 3. [`save_structure`] then [`reduce_slice`]: keep the block structure, compute the min slices
+//!    ([`reduce_with`] picks the [`ReductionPass`]es)
+//! 4. [`codegen`]: generate the fuel computations of a [`Variant`] into a new module
+//!
+//! The functions (and slices) are identified by their ID in the original module and the
+//! instructions by their index in the function's body.
+//!
+//! The tools that only need the taint facts can stop at [`analyze_taint`]: the [`Origin`]s of
+//! the inputs of each instruction ([`InstrInfo`]) of each function ([`FuncState`]).
+//!
+//! For very large modules, [`stream::stream_analysis`] runs the steps one function at a time and
+//! yields the results of each function as it's done, rather than of every function at once.
+
 pub mod run;
 mod utils;
+pub mod error;
 mod analyze;
 mod slice;
+mod serial;
+mod globals;
 mod reduce;
+mod purity;
+mod replay;
 mod codegen;
+pub mod cost_model;
+mod verify;
+mod strict;
+mod stats;
+mod names;
+pub mod patch;
+pub mod preserve;
+mod cert;
+pub mod color;
+pub mod reporter;
+pub mod sidecar;
+pub mod analyzer;
+pub mod size;
+pub mod repro;
+pub mod entry;
+pub mod cost_expr;
+pub mod baseline;
+pub mod strategies;
+pub mod stream;
+pub mod pdg;
+pub mod state_layout;
+pub mod meter;
+pub mod artifact;
+#[cfg(feature = "fs")]
+pub mod watch;
+pub mod html;
+#[cfg(feature = "audit")]
+pub mod audit;
+#[cfg(feature = "bmc")]
+pub mod bmc;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+/// The version of wirm that the API takes modules of
+pub use wirm;
+pub use crate::analyze::{analyze, analyze_taint, analyze_with, AnalysisVisitor, FuncState, InstrInfo, OpKind, Origin};
+pub use crate::globals::ConstValue;
+pub use crate::purity::{func_effects, Effects};
+pub use crate::pdg::Pdg;
+pub use crate::slice::{save_structure, slice_program, GlobalSetSinks, ImportCallSinks, MemoryGrowSinks, SinkDetector, Slice, SliceCriterion, SliceResult};
+pub use crate::reduce::{parse_reductions, reduce_slice, reduce_with, ReductionPass, ReductionStats};
+pub use crate::codegen::{CodeGenResult, GeneratedFunc, StateType};
+pub use crate::cost_model::OpCostModel;
+pub use crate::verify::{verify_fuel_points, FuelPointViolation, ViolationKind};
+pub use crate::error::AnalysisError;
+pub use crate::run::{AnalysisConfig, CompType};
+
+/// Which slices the generated functions replay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// The max slices: everything that influences the control flow, the generated functions
+    /// request the params, globals, loads and call results it depends on
+    Max,
+    /// The min slices: only the branches, the generated functions request whether they're taken
+    Min
+}
+
+/// Generate a fuel computation for each slice into `gen_wasm` (exported as `<comp type><fid><spec name>`,
+/// e.g. `exact0_loop_at_3`). The `slices` must come from `slice_program`, `save_structure` and
+/// `reduce_slice` on the same `funcs` and `wasm`.
+pub fn codegen<'a, 'b>(config: &AnalysisConfig, variant: Variant, slices: &mut [SliceResult], funcs: &[FuncState],
+                       wasm: &wirm::Module<'a>, gen_wasm: &mut wirm::Module<'b>) -> Result<CodeGenResult, AnalysisError> where 'a: 'b {
+    match variant {
+        Variant::Max => codegen::max::codegen_max(config, slices, funcs, wasm, gen_wasm),
+        Variant::Min => codegen::min::codegen_min(config, slices, funcs, wasm, gen_wasm)
+    }
+}
@@ -1,16 +1,31 @@
-mod run;
-mod utils;
-mod analyze;
-mod slice;
-mod reduce;
-mod codegen;
-
-use anyhow::bail;
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::process::ExitCode;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand};
 use termcolor::{ColorChoice, StandardStream};
-use crate::run::do_analysis;
+use wirm::wasmparser::WasmFeatures;
+use whamm_fuel::cost_model::CostModel;
+use whamm_fuel::error::FailureKind;
+use whamm_fuel::patch::PatchFormat;
+use whamm_fuel::{parse_reductions, ReductionPass};
+use whamm_fuel::sidecar::sidecar_path;
+use whamm_fuel::repro::{catch_failure, minimize, minimize_cert_mismatch, minimized_name, write_repro_bundle, write_test_program};
+use whamm_fuel::size::OutputBudget;
+#[cfg(feature = "audit")]
+use whamm_fuel::audit::AuditConfig;
+#[cfg(feature = "audit")]
+use whamm_fuel::run::report_audit;
+#[cfg(feature = "bmc")]
+use whamm_fuel::run::report_bmc;
+use whamm_fuel::run::{check_instrumented, do_analysis, dump_taint, explain_fuel_point, output_paths, parse_features, report_artifact_deltas, report_cost_between, report_slices, report_stats, report_strategies, report_taint, read_certificate, verify_certificate, watch_analysis, write_certificate, write_html_index, write_metered, write_patch, AnalysisConfig, BlockWrapper, CompType, Emit, SliceCriterion, SliceVariants, Verbosity, STDIO_PATH};
 
-const OUTPUT_MAX: &str = "output-max.wasm";
-const OUTPUT_MIN: &str = "output-min.wasm";
+// WASI hosts usually capture stdout, don't emit escape codes there
+#[cfg(target_os = "wasi")]
+const COLOR: ColorChoice = ColorChoice::Never;
+#[cfg(not(target_os = "wasi"))]
+const COLOR: ColorChoice = ColorChoice::Always;
 
 /// Conservative static taint-slicing for WebAssembly.
 ///
@@ -28,16 +43,501 @@ const OUTPUT_MIN: &str = "output-min.wasm";
 /// once we see a store of a tainted value, we mark memory as tainted globally; loads are considered tainted if memory is tainted.
 ///
 /// Things to configure per domain:
-/// - The amount of initial fuel allotted to computation (configured with INIT_FUEL)
-/// - The fuel cost per opcode (see codegen::op_cost function)
-fn main() -> anyhow::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        bail!("Usage: whamm_fuel <file.wasm>");
-    }
-    let data = std::fs::read(&args[1])?;
+/// - The amount of initial fuel allotted to computation (configured with `--init-fuel`, otherwise the fuel consumed is returned)
+/// - The fuel cost per opcode (configured with `--cost-model`, 1 for every opcode otherwise, see cost_model::CostModel)
+/// - The granularity that every charge is rounded up to (configured with `--granularity`, charges aren't rounded otherwise)
+/// - The fuel cost per element of the bulk table/memory ops (configured with `OpCostModel::cost_per_elem`, TABLE_ELEM_COST and MEMORY_BYTE_COST by default)
+/// - Whether computations whose results are dropped are charged (configured with `AnalysisConfig::charge_dropped`, CHARGE_DROPPED by default)
+/// - Whether toolchain glue functions are skipped: not sliced or generated, only listed in the report (configured with SKIP_SHIMS)
+/// - How much the approx fuel computation scales the cost per enclosing loop (configured with `AnalysisConfig::loop_depth_multiplier`, LOOP_DEPTH_MULTIPLIER by default)
+/// - How often a replayed constant must occur to be shared through a local (configured with `AnalysisConfig::dedup_const_min_uses`, DEDUP_CONST_MIN_USES by default)
+///
+/// The exit code tells how it failed: 3 if the module couldn't be parsed, 4 if it uses something that
+/// isn't supported, 5 if the analysis itself failed and 1 otherwise (see `FailureKind`).
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let Err(err) = catch_failure(|| run(cli)) else {
+        return ExitCode::SUCCESS;
+    };
+    let kind = FailureKind::of(&err);
+    eprintln!("Error: {err:?}");
+    eprintln!("whamm_fuel failed: {kind} (exit code {})", kind.exit_code());
+    ExitCode::from(kind.exit_code())
+}
 
-    let stdout = StandardStream::stdout(ColorChoice::Always);
-    do_analysis(stdout, &data, OUTPUT_MAX, OUTPUT_MIN)?;
+fn run(cli: Cli) -> anyhow::Result<()> {
+    // Only the reports go to stdout, the progress of the commands that write files goes to stderr
+    let stdout = StandardStream::stdout(COLOR);
+    let stderr = StandardStream::stderr(COLOR);
+    match cli.command {
+        Command::Analyze { wasm, dump_taint: true } => {
+            let data = read_module(&wasm)?;
+            dump_taint(stdout, &data)?;
+        }
+        Command::Analyze { wasm, dump_taint: false } => {
+            let data = read_module(&wasm)?;
+            report_taint(stdout, &data)?;
+        }
+        Command::Slice { wasm } => {
+            let data = read_module(&wasm)?;
+            report_slices(stdout, &data)?;
+        }
+        Command::Codegen(args) => {
+            let CodegenArgs { wasm, output, output_max, output_min, comp_type, epoch, init_fuel, on_out_of_fuel, granularity, analysis, block_wrapper, slice_at, mutation_sink, trap_sinks, checkpoints, report_json, entry, baseline, keep_going, strict, replay_pure_calls, dispatch, export_static_costs, state_section, no_codegen, emit, slice, repro_bundle, watch, quiet, verbose, sidecar, max_output_size, warn_output_size, features } = *args;
+            let output_budget = match (max_output_size, warn_output_size) {
+                (Some(max_bytes), _) => Some(OutputBudget { max_bytes, fail: true }),
+                (None, Some(max_bytes)) => Some(OutputBudget { max_bytes, fail: false }),
+                (None, None) => None
+            };
+            let config = AnalysisConfig {
+                sidecar_path: sidecar.then(|| sidecar_path(&wasm)),
+                output_budget,
+                features,
+                comp_type,
+                epoch_funcs: epoch,
+                init_fuel,
+                on_out_of_fuel,
+                block_wrapper,
+                replay_pure_calls,
+                dispatch,
+                export_static_costs,
+                state_section,
+                no_codegen,
+                emit,
+                variants: slice,
+                verbosity: Verbosity::from_flags(quiet, verbose),
+                granularity,
+                slice_at,
+                mutation_sinks: mutation_sink.into_iter().collect(),
+                trap_sinks,
+                checkpoints,
+                report_json,
+                entry,
+                baseline,
+                keep_going,
+                strict,
+                ..analysis.config()
+            };
+            if config.sidecar_path.is_some() && wasm == Path::new(STDIO_PATH) {
+                bail!("The sidecar is stored next to the module, it can't be used when reading the module from stdin");
+            }
+            let (default_max_path, default_min_path) = output_paths(&output);
+            let out_max_path = output_max.map_or(default_max_path, |path| path.to_string_lossy().to_string());
+            let out_min_path = output_min.map_or(default_min_path, |path| path.to_string_lossy().to_string());
+            if let Some(watched) = watch {
+                if wasm == Path::new(STDIO_PATH) || out_max_path == STDIO_PATH || out_min_path == STDIO_PATH {
+                    bail!("--watch reads the module from its path on every change and writes the generated modules to files, it can't use stdin or stdout");
+                }
+                let watched = watched.unwrap_or_else(|| wasm.clone());
+                return watch_analysis(stdout, &wasm, &watched, &out_max_path, &out_min_path, &config);
+            }
+            let data = read_module(&wasm)?;
+            // the generated module takes stdout, the report then goes to stderr
+            let to_stdout = out_max_path == STDIO_PATH || out_min_path == STDIO_PATH;
+            let report = if to_stdout { stderr } else { stdout };
+            match &repro_bundle {
+                Some(path) => {
+                    let res = catch_failure(|| do_analysis(report, &data, &out_max_path, &out_min_path, &config));
+                    if let Err(err) = &res {
+                        write_repro_bundle(path, &data, &config, err)?;
+                        eprintln!("Wrote a repro bundle to {}", path.display());
+                    }
+                    res?;
+                }
+                None => do_analysis(report, &data, &out_max_path, &out_min_path, &config)?
+            }
+            if quiet {
+                return Ok(());
+            }
+            if out_max_path == STDIO_PATH && out_min_path == STDIO_PATH && !no_codegen {
+                eprintln!("Wrote the generated ({}) Wasm to stdout", if slice.max() { "max" } else { "min" });
+            } else if !no_codegen {
+                let written: Vec<String> = [(slice.max(), &out_max_path), (slice.min(), &out_min_path)].into_iter()
+                    .filter(|(generated, _)| *generated)
+                    .flat_map(|(_, path)| emit.paths(path))
+                    .collect();
+                let written: Vec<&str> = written.iter().map(|path| if path == STDIO_PATH { "stdout" } else { path }).collect();
+                eprintln!("Wrote generated Wasm to {}", written.join(", "));
+            }
+            if let Some(sidecar_path) = &config.sidecar_path {
+                eprintln!("Wrote analysis sidecar to {}", sidecar_path.display());
+            }
+            if let Some(report_path) = &config.report_json {
+                eprintln!("Wrote the JSON report to {}", report_path.display());
+            }
+        }
+        #[cfg(feature = "audit")]
+        Command::Audit { wasm, cases, seed, analysis } => {
+            let data = read_module(&wasm)?;
+            report_audit(stdout, &data, &AuditConfig { cases, seed }, &analysis.config())?;
+        }
+        #[cfg(feature = "bmc")]
+        Command::Bmc { wasm } => {
+            let data = read_module(&wasm)?;
+            report_bmc(stdout, &data)?;
+        }
+        Command::Minimize { wasm, out_dir, name, cert, analysis } => {
+            let data = read_module(&wasm)?;
+            let config = analysis.config();
+            let minimized = match cert {
+                Some(cert) => minimize_cert_mismatch(&data, &read_certificate(&cert)?, &config)?,
+                None => minimize(&data, &config)?
+            };
+            let name = name.unwrap_or_else(|| minimized_name(&wasm.file_stem().unwrap_or_default().to_string_lossy(), &minimized));
+            let written = write_test_program(&out_dir, &name, &minimized)?;
+            eprintln!("Wrote the minimized module to {}", written.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", "));
+        }
+        Command::Explain { wasm, fid, instr_idx, analysis } => {
+            let data = read_module(&wasm)?;
+            explain_fuel_point(stdout, &data, fid, instr_idx, &analysis.config())?;
+        }
+        Command::CostBetween { wasm, fid, from_idx, to_idx, analysis } => {
+            let data = read_module(&wasm)?;
+            report_cost_between(stdout, &data, fid, from_idx, to_idx, &analysis.config())?;
+        }
+        Command::Stats { wasm, analysis } => {
+            let data = read_module(&wasm)?;
+            report_stats(stdout, &data, &analysis.config())?;
+        }
+        Command::CompareStrategies { wasm, analysis } => {
+            let data = read_module(&wasm)?;
+            report_strategies(stdout, &data, analysis.config())?;
+        }
+        Command::Patch { wasm, out, format, analysis } => {
+            let data = read_module(&wasm)?;
+            write_patch(stderr, &data, &out, format, &analysis.config())?;
+        }
+        Command::Meter { wasm, out, init_fuel, on_out_of_fuel, analysis } => {
+            let data = read_module(&wasm)?;
+            let config = analysis.config().init_fuel(init_fuel).on_out_of_fuel(on_out_of_fuel);
+            write_metered(stderr, &data, &out, &config)?;
+        }
+        Command::CheckInstrumented { original, instrumented } => {
+            let original = read_module(&original)?;
+            let instrumented = read_module(&instrumented)?;
+            check_instrumented(stdout, &original, &instrumented)?;
+        }
+        Command::CompareArtifacts { old, new } => {
+            let old = read_module(&old)?;
+            let new = read_module(&new)?;
+            report_artifact_deltas(stdout, &old, &new)?;
+        }
+        Command::HtmlIndex { out, wasm } => {
+            let modules = wasm.iter()
+                .map(|path| Ok((path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().to_string(), read_module(path)?)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            write_html_index(stderr, &modules, &out)?;
+        }
+        Command::Cert { wasm, out, analysis } => {
+            let data = read_module(&wasm)?;
+            write_certificate(stderr, &data, &out, &analysis.config())?;
+        }
+        Command::VerifyCert { wasm, cert, analysis } => {
+            let data = read_module(&wasm)?;
+            verify_certificate(stdout, &data, &cert, &analysis.config())?;
+        }
+    }
     Ok(())
 }
+
+fn read_module(path: &Path) -> anyhow::Result<Vec<u8>> {
+    if path == Path::new(STDIO_PATH) {
+        let mut data = vec![];
+        std::io::stdin().read_to_end(&mut data).context("Failed to read the module from stdin")?;
+        return Ok(data);
+    }
+    std::fs::read(path).with_context(|| format!("Failed to read the module {}", path.display()))
+}
+
+#[derive(Parser)]
+#[command(name = "whamm_fuel", about = "Conservative static taint-slicing for WebAssembly")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Only run the taint analysis, reports the origins of the values that feed control flow
+    Analyze {
+        wasm: PathBuf,
+        /// Dump the kind and the input origins recorded for every instruction (not only the sinks)
+        #[arg(long)]
+        dump_taint: bool
+    },
+    /// Run the analysis and create the slices, without generating code
+    Slice {
+        wasm: PathBuf
+    },
+    /// Generate the fuel computations for the slices of each function
+    Codegen(Box<CodegenArgs>),
+    /// Run both generated variants on random inputs, reports functions where the min slice diverges from the max slice
+    #[cfg(feature = "audit")]
+    Audit {
+        wasm: PathBuf,
+        /// The number of random inputs each function's max slice is run on
+        #[arg(long, default_value_t = 256)]
+        cases: usize,
+        /// Seed for the random inputs
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    },
+    /// Check the exact fuel of the small loop-free functions against a simulation of the original function on every input from a bounded domain
+    #[cfg(feature = "bmc")]
+    Bmc {
+        wasm: PathBuf
+    },
+    /// Shrink a module that the analysis fails on to a minimal failing function, written as a test program
+    Minimize {
+        wasm: PathBuf,
+        /// Where to write `<name>.wasm` and `<name>.wat`
+        #[arg(long, value_name = "DIR", default_value = "tests/programs")]
+        out_dir: PathBuf,
+        /// The name of the test program (`<module>-min-<hash of the minimized module>` by default)
+        #[arg(long)]
+        name: Option<String>,
+        /// Minimize the mismatch of the module against this certificate (see `verify-cert`) instead of a failure of the analysis
+        #[arg(long, value_name = "PATH")]
+        cert: Option<String>,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    },
+    /// Explain which instructions were accumulated into the charge at a fuel point
+    Explain {
+        wasm: PathBuf,
+        fid: u32,
+        instr_idx: usize,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    },
+    /// The static cost of running the straight-line instructions `from_idx..to_idx` of a function
+    CostBetween {
+        wasm: PathBuf,
+        fid: u32,
+        from_idx: usize,
+        to_idx: usize,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    },
+    /// Break down the slices by instruction class
+    Stats {
+        wasm: PathBuf,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    },
+    /// Generate the fuel computations with each metering strategy (exact, approx, epoch, back-edge) and compare their
+    /// code size, charge sites, requested state and accuracy
+    CompareStrategies {
+        wasm: PathBuf,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    },
+    /// Emit the fuel points as a patch file for external instrumenters
+    Patch {
+        wasm: PathBuf,
+        out: String,
+        /// `offsets` (the patch file, keyed on the byte offsets) or `json` (a JSON description keyed
+        /// on the function names and instruction positions)
+        #[arg(long, value_name = "FORMAT", default_value_t = PatchFormat::default())]
+        format: PatchFormat,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    },
+    /// Meter the module in place: it counts the fuel down in the exported mutable i64 global `whamm_fuel`
+    /// and traps when a charge exhausts it
+    Meter {
+        wasm: PathBuf,
+        out: String,
+        /// The fuel budget the global starts at, at most `i64::MAX`
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(..=i64::MAX as u64))]
+        init_fuel: u64,
+        /// When a charge exhausts the fuel, call the imported `env.on_out_of_fuel() -> i64` and add the
+        /// fuel it returns to the global (e.g. after yielding), only trap if that wasn't enough
+        #[arg(long)]
+        on_out_of_fuel: bool,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    },
+    /// Check that a module that was metered in place (e.g. with the patch file) kept the imports (in order),
+    /// exports, start function and data segments of the original module
+    CheckInstrumented {
+        original: PathBuf,
+        instrumented: PathBuf
+    },
+    /// Compare a generated module to a previous one: matches the generated functions by their export
+    /// (the stable ID of their slice) and reports the signature changes, cost deltas and newly requested state
+    CompareArtifacts {
+        old: PathBuf,
+        new: PathBuf
+    },
+    /// Analyze several modules (e.g. the modules of a release) and write an HTML page to browse their
+    /// functions and costs, with a search across all of the modules
+    HtmlIndex {
+        out: String,
+        #[arg(required = true)]
+        wasm: Vec<PathBuf>
+    },
+    /// Emit a gas-cost certificate for audit trails
+    Cert {
+        wasm: PathBuf,
+        out: String,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    },
+    /// Check a module against a gas-cost certificate
+    VerifyCert {
+        wasm: PathBuf,
+        cert: String,
+        #[command(flatten)]
+        analysis: AnalysisArgs
+    }
+}
+
+/// The options of the analysis that the commands share
+#[derive(Args)]
+struct AnalysisArgs {
+    /// The passes that reduce the min slices, from the defaults (`branch-outcomes`), e.g.
+    /// `+constants` or `+structure`: `branch-outcomes`, `constants`, `structure`, `all` or `none`
+    #[arg(long, value_name = "LIST", default_value = "default", value_parser = parse_reductions)]
+    reductions: BTreeSet<ReductionPass>,
+    /// A JSON file with the fuel charged per op or per instruction class, e.g.
+    /// `{ "default": 1, "classes": { "memory": 3 }, "ops": { "I64DivU": 20 } }`
+    #[arg(long, value_name = "PATH", value_parser = CostModel::load)]
+    cost_model: Option<CostModel>
+}
+
+impl AnalysisArgs {
+    /// The default config, with these options
+    fn config(self) -> AnalysisConfig {
+        AnalysisConfig { reductions: self.reductions, ..AnalysisConfig::default().cost_model(self.cost_model.unwrap_or_default()) }
+    }
+}
+
+/// The arguments of `codegen` (boxed, they're much larger than those of the other commands)
+#[derive(Args)]
+struct CodegenArgs {
+    wasm: PathBuf,
+    /// Where to write the generated modules, `-max` and `-min` are appended to the file name.
+    /// `-` writes the generated module to stdout (and the report to stderr), with a single `--slice` variant
+    #[arg(short, long, value_name = "PATH", default_value = "output.wasm")]
+    output: PathBuf,
+    /// Where to write the generated max module (overrides the path derived from `--output`)
+    #[arg(long, value_name = "PATH")]
+    output_max: Option<PathBuf>,
+    /// Where to write the generated min module (overrides the path derived from `--output`)
+    #[arg(long, value_name = "PATH")]
+    output_min: Option<PathBuf>,
+    /// Write the generated modules as `wasm`, `wat` (next to the output, with a `.wat` extension) or `both`
+    #[arg(long, value_name = "FORMAT", default_value_t = Emit::default())]
+    emit: Emit,
+    /// Only generate (and write) the `max` or the `min` variant, or `both`
+    #[arg(long, value_name = "VARIANT", default_value_t = SliceVariants::default())]
+    slice: SliceVariants,
+    /// How the generated functions compute the fuel: `exact`, `approx`, `epoch` (only charges
+    /// static estimates on function entry and loop iterations, without requesting any state) or
+    /// `back-edge` (the same estimates, charged at the function exits and loop back edges)
+    #[arg(long, value_name = "TYPE", default_value_t = CompType::default())]
+    comp_type: CompType,
+    /// Use the `epoch` comp type for this function only (by FID, can be repeated)
+    #[arg(long, value_name = "FID")]
+    epoch: Vec<u32>,
+    /// The fuel budget the generated functions count down from (they return the remaining fuel), at most `i64::MAX`
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(..=i64::MAX as u64))]
+    init_fuel: Option<u64>,
+    /// When a charge exhausts the initial fuel, call the imported `env.on_out_of_fuel() -> i64` and add
+    /// the fuel it returns (e.g. after yielding), instead of going negative
+    #[arg(long, requires = "init_fuel")]
+    on_out_of_fuel: bool,
+    /// Round the cost of every charge up to a multiple of N, as some metering schemes require
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    granularity: Option<u64>,
+    #[command(flatten)]
+    analysis: AnalysisArgs,
+    /// How the generated functions wrap the replayed body: an `empty` block followed by the fuel,
+    /// or a `typed` block that yields the fuel (the branches to the function's label return it)
+    #[arg(long, value_name = "WRAPPER", default_value_t = BlockWrapper::default())]
+    block_wrapper: BlockWrapper,
+    /// Also slice backward from the values consumed by this instruction (repeatable), the name
+    /// of the generated function then ends with `_at_INSTR_IDX`
+    #[arg(long, value_name = "FID:INSTR_IDX")]
+    slice_at: Vec<SliceCriterion>,
+    /// Also slice the values written to this mutable global (repeatable), the generated functions
+    /// store what would be written in the exported global `mutation_global_GID`
+    #[arg(long, value_name = "GID")]
+    mutation_sink: Vec<u32>,
+    /// Also slice the operands of the divisions, remainders and float-to-int truncations that
+    /// depend on the state, as whether they trap decides the control flow
+    #[arg(long)]
+    trap_sinks: bool,
+    /// Label every fuel point in the generated code with its checkpoint ID (`fid << 32 | instr_idx`)
+    /// as `i64.const <id>; drop`, to join runtime traces with the static costs (see the sidecar)
+    #[arg(long)]
+    checkpoints: bool,
+    /// Also write the report as JSON to this path (the slices, cost maps and FID mapping)
+    #[arg(long, value_name = "PATH")]
+    report_json: Option<PathBuf>,
+    /// Only analyze the functions that the exported function can reach through its calls
+    /// (e.g. to meter a single handler), the number of excluded functions is reported
+    #[arg(long, value_name = "EXPORT")]
+    entry: Option<String>,
+    /// Compare the analysis to a report that was saved with `--report-json`, the changes of each
+    /// function (slice size, requested state and block costs) are reported
+    #[arg(long, value_name = "REPORT")]
+    baseline: Option<PathBuf>,
+    /// Skip the functions that the analysis fails on (e.g. an unsupported op) with a warning,
+    /// the other functions still get slices and generated code
+    #[arg(long)]
+    keep_going: bool,
+    /// Fail (with the function and instruction) instead of relying on a conservative approximation:
+    /// an unsupported op, an untracked value or a value loaded from memory that influences the control flow
+    #[arg(long)]
+    strict: bool,
+    /// Replay the calls to pure functions (that only compute from their params: no memory, globals
+    /// or calls) in the generated functions with a copy of the callee, instead of requesting their results
+    #[arg(long)]
+    replay_pure_calls: bool,
+    /// Only export a single `fuel(dispatch_id: i32, state_ptr: i32) -> i64` function, the requested
+    /// state of the generated function is read from the exported memory (8 bytes per value)
+    #[arg(long)]
+    dispatch: bool,
+    /// Also export the worst-case cost of each function (with its callees) without loops, indirect calls
+    /// or length-dependent ops as an immutable i64 global `max_static_cost_fn_<FID>`
+    #[arg(long, conflicts_with = "dispatch")]
+    export_static_costs: bool,
+    /// Embed the requested state of each generated function in the generated modules, as the custom
+    /// section `whamm-fuel.state` in a compact binary layout (for hosts without a JSON parser)
+    #[arg(long)]
+    state_section: bool,
+    /// Dry run: only report the slices, without generating (or writing) the fuel computations
+    #[arg(long, conflicts_with_all = ["sidecar", "max_output_size", "warn_output_size"])]
+    no_codegen: bool,
+    /// Only write the generated modules, without reporting anything
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Report the annotated instruction listing of the slices (`-vv`: and the taint of every instruction),
+    /// otherwise only a summary per function
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// If the analysis fails, write the failing function (the rest of the module is redacted),
+    /// the config and the error to this (tar) archive, e.g. to attach it to an issue
+    #[arg(long, value_name = "PATH")]
+    repro_bundle: Option<PathBuf>,
+    /// Keep running: analyze the module again every time that it (or this path, e.g. the directory
+    /// it's built into) changes, and only report what changed since the previous run
+    #[arg(long, value_name = "PATH", conflicts_with = "repro_bundle")]
+    watch: Option<Option<PathBuf>>,
+    /// Also store the analysis results next to the module
+    #[arg(long)]
+    sidecar: bool,
+    /// Fail if a generated module is larger than this
+    #[arg(long, value_name = "BYTES", conflicts_with = "warn_output_size")]
+    max_output_size: Option<usize>,
+    /// Only warn if a generated module is larger than this
+    #[arg(long, value_name = "BYTES")]
+    warn_output_size: Option<usize>,
+    /// Reject modules using Wasm proposals outside of this list, e.g. `wasm2,-simd,+tail-call`
+    #[arg(long, value_name = "LIST", value_parser = parse_features)]
+    features: Option<WasmFeatures>
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use anyhow::bail;
+use wirm::{DataType, Location, Module, Opcode};
+use wirm::ir::function::FunctionModifier;
+use wirm::ir::id::{FunctionID, GlobalID};
+use wirm::ir::types::{BlockType, InitExpr, InitInstr, Value};
+use wirm::module_builder::AddLocal;
+use wirm::opcode::Instrumenter;
+use crate::codegen::{CodeGenResult, OUT_OF_FUEL_FUNC, OUT_OF_FUEL_MODULE};
+use crate::run::AnalysisConfig;
+use crate::utils::{dyn_cost_per_elem, fuel_const, length_type, local_ops};
+
+/// The mutable i64 global that a module metered in place counts its remaining fuel down in, it's
+/// exported as `whamm_fuel` so that the host can read (and refill) it between calls
+pub const FUEL_GLOBAL: &str = "whamm_fuel";
+
+/// Meter `wasm` in place: adds the fuel global (starting at the `config`'s `init_fuel`) and charges
+/// the static cost of each fuel point in the `result` (of the max codegen on the same module) right
+/// before its instruction, as a patch file would. The bulk ops also charge their length times
+/// their cost per element. A charge that exhausts the fuel traps, or with `on_out_of_fuel` first
+/// calls the imported `env.on_out_of_fuel() -> i64` and adds the fuel it returns back to the global
+/// (it only traps if that wasn't enough).
+pub(crate) fn meter_in_place(wasm: &mut Module, result: &CodeGenResult, config: &AnalysisConfig) -> anyhow::Result<()> {
+    let Some(init_fuel) = config.init_fuel else {
+        bail!("Metering in place needs an initial fuel to count down from");
+    };
+    let Ok(init_fuel) = i64::try_from(init_fuel) else {
+        bail!("The initial fuel must fit the i64 global it's counted in (at most {})", i64::MAX);
+    };
+    if wasm.exports.get_by_name(FUEL_GLOBAL.to_string()).is_some() {
+        bail!("The module already exports `{FUEL_GLOBAL}`");
+    }
+
+    // the length type and cost per element of each bulk op, before the module is modified
+    let mut bulk_ops = vec![];
+    for fid in result.fids.iter() {
+        if !result.func_map.contains_key(fid) {
+            // the toolchain glue isn't metered
+            bulk_ops.push(BTreeMap::new());
+            continue;
+        }
+        let body = local_ops(*fid, wasm)?;
+        let lengths: BTreeMap<usize, (DataType, u64)> = body.iter().enumerate()
+            .filter(|(_, op)| dyn_cost_per_elem(op).is_some())
+            .map(|(instr_idx, op)| (instr_idx, (length_type(op, wasm), config.cost_model.cost_per_elem(op))))
+            .collect();
+        bulk_ops.push(lengths);
+    }
+
+    let out_of_fuel = config.on_out_of_fuel.then(|| {
+        let ty = wasm.types.add_func_type(&[], &[DataType::I64]);
+        wasm.add_import_func(OUT_OF_FUEL_MODULE.to_string(), OUT_OF_FUEL_FUNC.to_string(), ty).0
+    });
+    let fuel = wasm.add_global(InitExpr::new(vec![InitInstr::Value(Value::I64(init_fuel))]), DataType::I64, true, false);
+    wasm.exports.add_export_global(FUEL_GLOBAL.to_string(), *fuel);
+
+    for ((fid, cost_map), lengths) in result.fids.iter().zip(result.cost_maps.iter()).zip(bulk_ops.iter()) {
+        let Some(mut func) = wasm.functions.get_fn_modifier(FunctionID(*fid)) else {
+            continue;
+        };
+        for (instr_idx, cost) in cost_map.iter() {
+            if *cost == 0 {
+                continue;
+            }
+            func.before_at(Location::Module { func_idx: FunctionID(*fid), instr_idx: *instr_idx });
+            func.global_get(fuel);
+            func.i64_const(fuel_const(*cost));
+            charge_fuel(fuel, out_of_fuel, &mut func);
+        }
+        for (instr_idx, (ty, cost_per_elem)) in lengths.iter() {
+            // the length is on top of the stack right before the op
+            let len = func.add_local(*ty);
+            func.before_at(Location::Module { func_idx: FunctionID(*fid), instr_idx: *instr_idx });
+            func.local_set(len);
+            func.global_get(fuel);
+            func.local_get(len);
+            if *ty == DataType::I32 {
+                func.i64_extend_i32u();
+            }
+            func.i64_const(fuel_const(*cost_per_elem));
+            func.i64_mul();
+            charge_fuel(fuel, out_of_fuel, &mut func);
+            func.local_get(len);
+        }
+    }
+    Ok(())
+}
+
+/// Subtracts the cost on top of the stack from the fuel below it (read from the `fuel` global) and
+/// writes it back, then checks whether that exhausted the fuel
+fn charge_fuel(fuel: GlobalID, out_of_fuel: Option<FunctionID>, func: &mut FunctionModifier) {
+    func.i64_sub();
+    func.global_set(fuel);
+    exhausted(fuel, func);
+    if let Some(hook) = out_of_fuel {
+        // refill with what the host returns, then check again
+        func.global_get(fuel);
+        func.call(hook);
+        func.i64_add();
+        func.global_set(fuel);
+        exhausted(fuel, func);
+        func.unreachable();
+        func.end();
+    } else {
+        func.unreachable();
+    }
+    func.end();
+}
+
+/// Opens an `if` that runs when the `fuel` global went negative
+fn exhausted(fuel: GlobalID, func: &mut FunctionModifier) {
+    func.global_get(fuel);
+    func.i64_const(0);
+    func.i64_lt_signed();
+    func.if_stmt(BlockType::Empty);
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use wirm::Module;
+use wirm::wasmparser::{ExternalKind, KnownCustom, Name, Operator, Parser, Payload};
+use crate::codegen::{GeneratedFunc, StateType};
+use crate::error::AnalysisError;
+use crate::state_layout::append_custom_section;
+use crate::utils::{local_ops, write_leb128};
+
+/// The custom section with the names of the functions and locals
+const NAME_SECTION: &str = "name";
+
+/// The source-level names of the functions, params/locals and globals, when the module has debug info.
+/// Read from the `name` custom section (emitted along with DWARF by the usual toolchains),
+/// the DWARF `.debug_info` itself is not consulted yet.
+#[derive(Debug, Default)]
+pub struct DebugNames {
+    /// fid -> name (falls back to the export name)
+    functions: BTreeMap<u32, String>,
+    /// (fid, lid) -> name
+    locals: BTreeMap<(u32, u32), String>,
+    /// gid -> name
+    globals: BTreeMap<u32, String>
+}
+impl DebugNames {
+    /// Best-effort: a malformed name section just means we fall back to the indices.
+    pub fn parse(wasm_bytes: &[u8]) -> Self {
+        let mut names = Self::default();
+        for payload in Parser::new(0).parse_all(wasm_bytes) {
+            let reader = match payload {
+                Ok(Payload::ExportSection(exports)) => {
+                    for export in exports.into_iter().flatten() {
+                        if matches!(export.kind, ExternalKind::Func) {
+                            names.functions.entry(export.index).or_insert(export.name.to_string());
+                        }
+                    }
+                    continue;
+                }
+                Ok(Payload::CustomSection(reader)) => reader,
+                _ => continue
+            };
+            let KnownCustom::Name(name_reader) = reader.as_known() else {
+                continue;
+            };
+            for subsection in name_reader.into_iter().flatten() {
+                match subsection {
+                    Name::Function(funcs) => {
+                        for func in funcs.into_iter().flatten() {
+                            names.functions.insert(func.index, func.name.to_string());
+                        }
+                    }
+                    Name::Local(funcs) => {
+                        for func in funcs.into_iter().flatten() {
+                            for local in func.names.into_iter().flatten() {
+                                names.locals.insert((func.index, local.index), local.name.to_string());
+                            }
+                        }
+                    }
+                    Name::Global(globals) => {
+                        for global in globals.into_iter().flatten() {
+                            names.globals.insert(global.index, global.name.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        names
+    }
+
+    pub fn function(&self, fid: u32) -> Option<&str> {
+        self.functions.get(&fid).map(|name| name.as_str())
+    }
+
+    pub fn local(&self, fid: u32, lid: u32) -> Option<&str> {
+        self.locals.get(&(fid, lid)).map(|name| name.as_str())
+    }
+
+    pub fn global(&self, gid: u32) -> Option<&str> {
+        self.globals.get(&gid).map(|name| name.as_str())
+    }
+
+    /// The source-level name of the state that `op` (of function `fid`) reads, e.g. `param "len"`
+    /// or `global "heap_ptr"`. None if the module doesn't name it.
+    pub fn state_label(&self, fid: u32, op: &Operator) -> Option<String> {
+        match op {
+            Operator::LocalGet { local_index } => self.local(fid, *local_index).map(|name| format!("param \"{name}\"")),
+            Operator::GlobalGet { global_index } => self.global(*global_index).map(|name| format!("global \"{name}\"")),
+            _ => None
+        }
+    }
+}
+
+/// Name the params of the generated functions after the state they request (see `DebugNames::state_label`),
+/// the `func_map` maps from the fid of each original function to the functions generated for it.
+pub(crate) fn name_params(func_map: &mut BTreeMap<u32, Vec<GeneratedFunc>>, names: &DebugNames, wasm: &Module) -> Result<(), AnalysisError> {
+    for (fid, generated) in func_map.iter_mut() {
+        let body = local_ops(*fid, wasm)?;
+        for func in generated.iter_mut() {
+            for ty in [StateType::Param, StateType::Global] {
+                for (instr_idx, req) in func.req_state[&ty].iter() {
+                    let Some(label) = names.state_label(*fid, &body[*instr_idx]) else {
+                        continue;
+                    };
+                    for val in req.req_state.iter() {
+                        func.param_names.entry(val.gen_param_id()).or_insert_with(|| label.clone());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replace the `name` section of the generated module (`wasm_bytes`): the `generated` functions are named
+/// after their export, and their params after the state they request (`GeneratedFunc::param_names`,
+/// `param "len"` becomes `param_len`).
+pub(crate) fn with_name_section<'a>(wasm_bytes: &[u8], generated: impl Iterator<Item = &'a GeneratedFunc>) -> anyhow::Result<Vec<u8>> {
+    // the names are listed in order of the indices
+    let generated: BTreeMap<u32, &GeneratedFunc> = generated.map(|func| (func.fid, func)).collect();
+    let mut func_names = Vec::new();
+    write_leb128(&mut func_names, generated.len() as u32);
+    for (fid, func) in generated.iter() {
+        write_leb128(&mut func_names, *fid);
+        write_name(&mut func_names, &func.fname);
+    }
+    let mut local_names = Vec::new();
+    let named: Vec<_> = generated.iter().filter(|(_, func)| !func.param_names.is_empty()).collect();
+    write_leb128(&mut local_names, named.len() as u32);
+    for (fid, func) in named {
+        write_leb128(&mut local_names, *fid);
+        write_leb128(&mut local_names, func.param_names.len() as u32);
+        for (param, label) in func.param_names.iter() {
+            write_leb128(&mut local_names, *param);
+            write_name(&mut local_names, &label.replace('"', "").replace(' ', "_"));
+        }
+    }
+    let mut payload = Vec::new();
+    for (id, contents) in [(1, func_names), (2, local_names)] {
+        payload.push(id);
+        write_leb128(&mut payload, contents.len() as u32);
+        payload.extend(contents);
+    }
+
+    let mut named = without_custom_section(wasm_bytes, NAME_SECTION)?;
+    append_custom_section(&mut named, NAME_SECTION, &payload);
+    Ok(named)
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    write_leb128(bytes, name.len() as u32);
+    bytes.extend(name.as_bytes());
+}
+
+/// The module without its custom sections called `name`
+fn without_custom_section(wasm_bytes: &[u8], name: &str) -> anyhow::Result<Vec<u8>> {
+    let mut stripped = Vec::with_capacity(wasm_bytes.len());
+    // where the next section starts (its ID and size come before its contents)
+    let mut section_start = 0;
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        let payload = payload?;
+        if let Payload::Version { range, .. } = &payload {
+            stripped.extend_from_slice(&wasm_bytes[range.clone()]);
+            section_start = range.end;
+            continue;
+        }
+        let Some((_, range)) = payload.as_section() else {
+            continue;
+        };
+        if !matches!(&payload, Payload::CustomSection(reader) if reader.name() == name) {
+            stripped.extend_from_slice(&wasm_bytes[section_start..range.end]);
+        }
+        section_start = range.end;
+    }
+    Ok(stripped)
+}
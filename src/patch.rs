@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use anyhow::bail;
+use serde::Serialize;
+use wirm::wasmparser::{KnownCustom, Name, Parser, Payload, TypeRef};
+use crate::utils::fuel_const;
+
+/// A single fuel point for an external instrumenter: before executing the instruction
+/// at `byte_offset` (absolute in the original module) of function `fid`, subtract `delta`
+/// from the fuel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuelPatch {
+    pub fid: u32,
+    pub byte_offset: usize,
+    /// The position of the instruction in the function's body
+    pub instr_idx: usize,
+    pub delta: i64
+}
+impl Display for FuelPatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, {}, {}", self.fid, self.byte_offset, self.delta)
+    }
+}
+
+pub const PATCH_HEADER: &str = "# fid, byte_offset, i64 delta";
+
+/// Translate the fuel points in the `cost_maps` (fid, keyed on instr_idx) to patches keyed
+/// on the byte offset of the instruction in the original `wasm_bytes`.
+pub fn gen_patches<'a>(wasm_bytes: &[u8], cost_maps: impl IntoIterator<Item = (u32, &'a BTreeMap<usize, u64>)>) -> anyhow::Result<Vec<FuelPatch>> {
+    let offsets = instr_offsets(wasm_bytes)?;
+    let mut patches = vec![];
+    for (fid, cost_map) in cost_maps {
+        let Some(func_offsets) = offsets.get(&fid) else {
+            bail!("Function #{fid} has no body in the module");
+        };
+        for (instr_idx, cost) in cost_map.iter() {
+            if *cost == 0 {
+                continue;
+            }
+            let Some(byte_offset) = func_offsets.get(*instr_idx) else {
+                bail!("Function #{fid} has no instruction @{instr_idx}");
+            };
+            patches.push(FuelPatch {
+                fid,
+                byte_offset: *byte_offset,
+                instr_idx: *instr_idx,
+                delta: fuel_const(*cost)
+            });
+        }
+    }
+    Ok(patches)
+}
+
+/// Render the patches in the patch file format: a header comment followed by one
+/// `fid, byte_offset, delta` line per fuel point.
+pub fn patch_file(patches: &[FuelPatch]) -> String {
+    let mut s = format!("{PATCH_HEADER}\n");
+    for patch in patches.iter() {
+        s += &format!("{patch}\n");
+    }
+    s
+}
+
+/// How the fuel points are written for the external instrumenters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PatchFormat {
+    /// The patch file, keyed on the byte offsets (see `patch_file`)
+    #[default]
+    Offsets,
+    /// A JSON description keyed on the function names and instruction positions (see `json_description`)
+    Json
+}
+impl Display for PatchFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PatchFormat::Offsets => "offsets",
+                PatchFormat::Json => "json"
+            }
+        )
+    }
+}
+impl FromStr for PatchFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "offsets" => Ok(PatchFormat::Offsets),
+            "json" => Ok(PatchFormat::Json),
+            _ => Err(format!("Unknown patch format: {}", s))
+        }
+    }
+}
+
+/// A fuel point of the JSON description
+#[derive(Debug, Serialize)]
+struct JsonFuelPoint {
+    /// The function's name in the `name` section, otherwise its index
+    function: String,
+    /// The position of the instruction in the function's body, as it's encoded in the module
+    instr_idx: usize,
+    delta: i64
+}
+
+/// Render the patches as JSON, for the instrumenters that don't work on the byte offsets: the fuel
+/// points are keyed on the function's name (from the `name` section, otherwise its index) and on the
+/// position of the instruction in the function's body, as it's encoded in the module.
+pub fn json_description(wasm_bytes: &[u8], patches: &[FuelPatch]) -> anyhow::Result<String> {
+    let names = function_names(wasm_bytes);
+    let fuel_points: Vec<JsonFuelPoint> = patches.iter()
+        .map(|patch| JsonFuelPoint {
+            function: names.get(&patch.fid).cloned().unwrap_or_else(|| patch.fid.to_string()),
+            instr_idx: patch.instr_idx,
+            delta: patch.delta
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&serde_json::json!({ "fuel_points": fuel_points }))?)
+}
+
+/// The function names of the `name` section (a malformed section is skipped)
+fn function_names(wasm_bytes: &[u8]) -> BTreeMap<u32, String> {
+    let mut names = BTreeMap::new();
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        let Ok(Payload::CustomSection(reader)) = payload else {
+            continue;
+        };
+        let KnownCustom::Name(name_reader) = reader.as_known() else {
+            continue;
+        };
+        for subsection in name_reader.into_iter().flatten() {
+            if let Name::Function(funcs) = subsection {
+                for func in funcs.into_iter().flatten() {
+                    names.insert(func.index, func.name.to_string());
+                }
+            }
+        }
+    }
+    names
+}
+
+/// The byte offset of every instruction in each local function body, keyed on the fid of the function.
+fn instr_offsets(wasm_bytes: &[u8]) -> anyhow::Result<BTreeMap<u32, Vec<usize>>> {
+    let mut offsets = BTreeMap::new();
+    let mut fid = 0;
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::ImportSection(imports) => {
+                for import in imports {
+                    if matches!(import?.ty, TypeRef::Func(_)) {
+                        fid += 1;
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let mut func_offsets = vec![];
+                let mut reader = body.get_operators_reader()?;
+                while !reader.eof() {
+                    let (_, offset) = reader.read_with_offset()?;
+                    func_offsets.push(offset);
+                }
+                offsets.insert(fid, func_offsets);
+                fid += 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(offsets)
+}
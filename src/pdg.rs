@@ -0,0 +1,106 @@
+//! The program dependence graph (PDG) of a function: which instructions each instruction depends
+//! on, through the values it consumes (data) and through the `if` that decides whether it runs
+//! (control). The slicer follows its control dependences.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use wirm::Module;
+use wirm::wasmparser::Operator;
+use crate::analyze::{FuncState, Origin};
+use crate::error::AnalysisError;
+use crate::utils::local_ops;
+
+/// The dependences between the instructions of a local function (by their index in the body)
+#[derive(Debug, Clone, Default)]
+pub struct Pdg {
+    fid: u32,
+    /// instr_idx -> where each of its inputs comes from (from the taint analysis, see `InstrInfo`)
+    inputs: Vec<Vec<Origin>>,
+    /// instr_idx -> the instructions that consume one of its results
+    users: BTreeMap<usize, Vec<usize>>,
+    /// instr_idx -> the innermost `if` around it (the `else` and `end` of the `if` included),
+    /// if the innermost block around it is an `if`
+    control: BTreeMap<usize, usize>
+}
+impl Pdg {
+    /// The PDG of an analyzed function, its control dependences come from the block structure of its body
+    pub fn new(func: &FuncState, wasm: &Module) -> Result<Self, AnalysisError> {
+        let inputs: Vec<Vec<Origin>> = func.instrs().iter().map(|info| info.inputs().to_vec()).collect();
+        let mut users: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (instr_idx, origins) in inputs.iter().enumerate() {
+            for producer in origins.iter().filter_map(Origin::instr_idx) {
+                users.entry(producer).or_default().push(instr_idx);
+            }
+        }
+        let body = local_ops(func.fid(), wasm)?;
+        Ok(Self {
+            fid: func.fid(),
+            inputs,
+            users,
+            control: enclosing_ifs(body)
+        })
+    }
+
+    pub fn fid(&self) -> u32 {
+        self.fid
+    }
+
+    /// Where the inputs of the instruction come from (empty if the analysis didn't reach it)
+    pub fn inputs(&self, instr_idx: usize) -> &[Origin] {
+        self.inputs.get(instr_idx).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The instructions that produce the inputs of the instruction (data dependences)
+    pub fn data_deps(&self, instr_idx: usize) -> BTreeSet<usize> {
+        self.inputs(instr_idx).iter().filter_map(Origin::instr_idx).collect()
+    }
+
+    /// The instructions that consume a result of the instruction (the reverse of `data_deps`)
+    pub fn data_users(&self, instr_idx: usize) -> &[usize] {
+        self.users.get(&instr_idx).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The `if` whose condition decides whether the instruction runs (control dependence)
+    pub fn control_dep(&self, instr_idx: usize) -> Option<usize> {
+        self.control.get(&instr_idx).copied()
+    }
+
+    /// Every instruction that the instruction depends on, directly or not, through data or control
+    pub fn backward_closure(&self, instr_idx: usize) -> BTreeSet<usize> {
+        let mut closure = BTreeSet::new();
+        let mut worklist = VecDeque::from([instr_idx]);
+        while let Some(idx) = worklist.pop_front() {
+            let deps = self.data_deps(idx).into_iter().chain(self.control_dep(idx));
+            for dep in deps {
+                if closure.insert(dep) {
+                    worklist.push_back(dep);
+                }
+            }
+        }
+        closure
+    }
+
+    /// Whether `instr_idx` depends on `on`, directly or not (see `backward_closure`)
+    pub fn depends_on(&self, instr_idx: usize, on: usize) -> bool {
+        self.backward_closure(instr_idx).contains(&on)
+    }
+}
+
+/// Maps each instruction that's inside the arms of an `if` to the instr_idx of the innermost `if`
+/// around it (the `else` and `end` of the `if` included).
+fn enclosing_ifs(body: &[Operator]) -> BTreeMap<usize, usize> {
+    let mut ifs = BTreeMap::new();
+    // the open blocks, remembers the instr_idx of the ones that are an `if`
+    let mut blocks: Vec<Option<usize>> = Vec::new();
+    for (instr_idx, op) in body.iter().enumerate() {
+        if let Some(Some(if_idx)) = blocks.last() {
+            ifs.insert(instr_idx, *if_idx);
+        }
+        match op {
+            Operator::If { .. } => blocks.push(Some(instr_idx)),
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::TryTable { .. } => blocks.push(None),
+            Operator::End => { blocks.pop(); }
+            _ => {}
+        }
+    }
+    ifs
+}
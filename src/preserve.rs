@@ -0,0 +1,159 @@
+use wirm::wasmparser::{DataKind, ExternalKind, Operator, Parser, Payload, TypeRef};
+
+/// The parts of a module that metering it in place (e.g. applying the fuel points of `patch`)
+/// must leave alone: its imports (in order), exports, start function and data segments.
+#[derive(Debug, Default)]
+struct Skeleton {
+    /// (module, name, type) of each import, in order
+    imports: Vec<(String, String, TypeRef)>,
+    /// (name, kind, index) of each export
+    exports: Vec<(String, ExternalKind, u32)>,
+    start: Option<u32>,
+    data: Vec<DataSegment>,
+    /// The number of imports of each kind, see `kind_idx`
+    num_imported: [u32; 5]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DataSegment {
+    /// (memory, the operators of the offset expression), None if the segment is passive
+    active: Option<(u32, Vec<OffsetOp>)>,
+    bytes: Vec<u8>
+}
+
+/// An operator of the offset of a data segment, only the globals it reads can move
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OffsetOp {
+    GlobalGet(u32),
+    Other(String)
+}
+
+impl Skeleton {
+    fn new(wasm_bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut skeleton = Self::default();
+        for payload in Parser::new(0).parse_all(wasm_bytes) {
+            match payload? {
+                Payload::ImportSection(imports) => {
+                    for import in imports {
+                        let import = import?;
+                        skeleton.num_imported[type_idx(&import.ty)] += 1;
+                        skeleton.imports.push((import.module.to_string(), import.name.to_string(), import.ty));
+                    }
+                }
+                Payload::ExportSection(exports) => {
+                    for exp in exports {
+                        let exp = exp?;
+                        skeleton.exports.push((exp.name.to_string(), exp.kind, exp.index));
+                    }
+                }
+                Payload::StartSection { func, .. } => skeleton.start = Some(func),
+                Payload::DataSection(data) => {
+                    for segment in data {
+                        let segment = segment?;
+                        let active = match segment.kind {
+                            DataKind::Passive => None,
+                            DataKind::Active { memory_index, offset_expr } => {
+                                let mut ops = vec![];
+                                let mut reader = offset_expr.get_operators_reader();
+                                while !reader.eof() {
+                                    ops.push(match reader.read()? {
+                                        Operator::GlobalGet { global_index } => OffsetOp::GlobalGet(global_index),
+                                        op => OffsetOp::Other(format!("{op:?}"))
+                                    });
+                                }
+                                Some((memory_index, ops))
+                            }
+                        };
+                        skeleton.data.push(DataSegment { active, bytes: segment.data.to_vec() });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(skeleton)
+    }
+
+    /// Where the item at `idx` of the original module moved to in the `instrumented` one. The
+    /// imports that are added after the original ones shift the indices of the local items.
+    fn moved_to(&self, instrumented: &Skeleton, kind: ExternalKind, idx: u32) -> u32 {
+        let kind = kind_idx(kind);
+        if idx < self.num_imported[kind] {
+            idx
+        } else {
+            idx + instrumented.num_imported[kind].saturating_sub(self.num_imported[kind])
+        }
+    }
+}
+
+/// Check that the `instrumented` module only adds to the `original` one: its original imports
+/// come first (in the same order), and the exports, start function and data segments of the
+/// original module are still there, referring to the same items (the added imports shift the
+/// indices of the local items, which is accounted for). Imports, exports and data segments
+/// can be added, e.g. to import the fuel. Returns a description of each difference.
+pub fn check_preserved(original: &[u8], instrumented: &[u8]) -> anyhow::Result<Vec<String>> {
+    let was = Skeleton::new(original)?;
+    let now = Skeleton::new(instrumented)?;
+    let mut diffs = vec![];
+
+    for (i, import) in was.imports.iter().enumerate() {
+        match now.imports.get(i) {
+            Some(moved) if moved == import => {}
+            Some((module, name, ty)) => diffs.push(format!("import #{i}: was \"{}\".\"{}\" ({:?}), now \"{module}\".\"{name}\" ({ty:?})", import.0, import.1, import.2)),
+            None => diffs.push(format!("import #{i}: \"{}\".\"{}\" was removed", import.0, import.1))
+        }
+    }
+
+    for (name, kind, idx) in was.exports.iter() {
+        let expected = was.moved_to(&now, *kind, *idx);
+        match now.exports.iter().find(|(other, ..)| other == name) {
+            Some((_, moved_kind, moved_idx)) if moved_kind == kind && *moved_idx == expected => {}
+            Some((_, moved_kind, moved_idx)) => diffs.push(format!("export \"{name}\": was {kind:?} {idx} (now expected at {expected}), now {moved_kind:?} {moved_idx}")),
+            None => diffs.push(format!("export \"{name}\" was removed"))
+        }
+    }
+
+    let expected_start = was.start.map(|fid| was.moved_to(&now, ExternalKind::Func, fid));
+    if now.start != expected_start {
+        diffs.push(format!("start function: was {:?} (now expected at {expected_start:?}), now {:?}", was.start, now.start));
+    }
+
+    for (i, segment) in was.data.iter().enumerate() {
+        let expected = DataSegment {
+            active: segment.active.as_ref().map(|(memory, offset)| (
+                was.moved_to(&now, ExternalKind::Memory, *memory),
+                offset.iter().map(|op| match op {
+                    OffsetOp::GlobalGet(gid) => OffsetOp::GlobalGet(was.moved_to(&now, ExternalKind::Global, *gid)),
+                    op => op.clone()
+                }).collect()
+            )),
+            bytes: segment.bytes.clone()
+        };
+        match now.data.get(i) {
+            Some(moved) if *moved == expected => {}
+            Some(moved) if moved.bytes != expected.bytes => diffs.push(format!("data segment #{i}: its bytes changed")),
+            Some(moved) => diffs.push(format!("data segment #{i}: was placed at {:?}, now at {:?}", expected.active, moved.active)),
+            None => diffs.push(format!("data segment #{i} was removed"))
+        }
+    }
+    Ok(diffs)
+}
+
+fn kind_idx(kind: ExternalKind) -> usize {
+    match kind {
+        ExternalKind::Func => 0,
+        ExternalKind::Table => 1,
+        ExternalKind::Memory => 2,
+        ExternalKind::Global => 3,
+        ExternalKind::Tag => 4
+    }
+}
+
+fn type_idx(ty: &TypeRef) -> usize {
+    match ty {
+        TypeRef::Func(_) => 0,
+        TypeRef::Table(_) => 1,
+        TypeRef::Memory(_) => 2,
+        TypeRef::Global(_) => 3,
+        TypeRef::Tag(_) => 4
+    }
+}
@@ -0,0 +1,129 @@
+//! The side effects of the local functions: whether they touch memory, globals or tables, and what
+//! they call. A call to a pure function can be replayed instead of requested as state
+//! (`AnalysisConfig::replay_pure_calls`).
+
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Deserialize, Serialize};
+use wirm::ir::id::FunctionID;
+use wirm::Module;
+use wirm::wasmparser::Operator;
+use crate::stats::{classify, InstrClass};
+use crate::utils::local_funcs;
+
+/// What a function can do besides computing its results from its params. The effects of the
+/// (local) functions that it calls are included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Effects {
+    /// Loads, `memory.size` and the reads of the bulk memory ops
+    pub reads_memory: bool,
+    /// Stores, `memory.grow` and the writes of the bulk memory ops
+    pub writes_memory: bool,
+    pub reads_globals: bool,
+    pub writes_globals: bool,
+    pub calls_imports: bool,
+    /// Calls through a table or a reference, the callee can do anything
+    pub calls_indirect: bool,
+    /// Uses tables, references or other state that isn't tracked
+    pub other: bool,
+    /// Throws or catches exceptions, their tags belong to the module
+    #[serde(default)]
+    pub throws: bool,
+    /// Calls local functions (whose effects are merged in)
+    pub calls_local: bool
+}
+impl Effects {
+    /// The results only depend on the params, and the function doesn't change any state
+    pub fn is_pure(&self) -> bool {
+        !(self.reads_memory || self.writes_memory || self.reads_globals || self.writes_globals
+            || self.calls_imports || self.calls_indirect || self.other || self.throws)
+    }
+
+    /// The calls through the function are replayed on their own: it can't call anything
+    pub fn is_leaf_pure(&self) -> bool {
+        self.is_pure() && !self.calls_local
+    }
+
+    /// Include the effects of a callee
+    fn merge(&mut self, callee: &Effects) {
+        self.reads_memory |= callee.reads_memory;
+        self.writes_memory |= callee.writes_memory;
+        self.reads_globals |= callee.reads_globals;
+        self.writes_globals |= callee.writes_globals;
+        self.calls_imports |= callee.calls_imports;
+        self.calls_indirect |= callee.calls_indirect;
+        self.other |= callee.other;
+        self.throws |= callee.throws;
+    }
+}
+
+/// The effects of each local function (fid -> effects), propagated through the direct calls
+pub fn func_effects(wasm: &Module) -> BTreeMap<u32, Effects> {
+    let mut effects = BTreeMap::new();
+    let mut callees: BTreeMap<u32, BTreeSet<u32>> = BTreeMap::new();
+    for (fid, local) in local_funcs(wasm) {
+        let mut func = Effects::default();
+        for op in local.body.instructions.get_ops().iter() {
+            func.merge(&op_effects(op, wasm));
+            if let Operator::Call { function_index } | Operator::ReturnCall { function_index } = op {
+                if wasm.functions.is_local(FunctionID(*function_index)) {
+                    func.calls_local = true;
+                    callees.entry(fid).or_default().insert(*function_index);
+                }
+            }
+        }
+        effects.insert(fid, func);
+    }
+
+    // the effects only grow, so this terminates (even with recursion)
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (fid, called) in callees.iter() {
+            let mut func = effects[fid];
+            for callee in called.iter() {
+                func.merge(&effects[callee]);
+            }
+            if func != effects[fid] {
+                effects.insert(*fid, func);
+                changed = true;
+            }
+        }
+    }
+    effects
+}
+
+/// The effects of a single op, a direct call to a local function has none of its own
+fn op_effects(op: &Operator, wasm: &Module) -> Effects {
+    let mut effects = Effects::default();
+    match op {
+        Operator::GlobalGet { .. } => effects.reads_globals = true,
+        Operator::GlobalSet { .. } => effects.writes_globals = true,
+        Operator::Call { function_index } | Operator::ReturnCall { function_index } => {
+            effects.calls_imports = !wasm.functions.is_local(FunctionID(*function_index));
+        }
+        Operator::CallIndirect { .. } | Operator::ReturnCallIndirect { .. }
+        | Operator::CallRef { .. } | Operator::ReturnCallRef { .. } => effects.calls_indirect = true,
+        // they're control flow, but the exception can leave the function (and the tags are the module's)
+        Operator::Throw { .. } | Operator::ThrowRef | Operator::TryTable { .. } => effects.throws = true,
+        Operator::LocalGet { .. } | Operator::LocalSet { .. } | Operator::LocalTee { .. }
+        | Operator::Drop | Operator::Select | Operator::TypedSelect { .. } | Operator::Nop => {}
+        op => {
+            let name = format!("{op:?}");
+            if name.contains("Atomic") {
+                // read-modify-writes, waits and notifies all synchronize through memory
+                effects.reads_memory = true;
+                effects.writes_memory = true;
+                return effects;
+            }
+            match classify(op) {
+                InstrClass::Arithmetic | InstrClass::Control => {}
+                InstrClass::Memory => {
+                    effects.reads_memory = name.contains("Load") || matches!(op, Operator::MemorySize { .. } | Operator::MemoryCopy { .. });
+                    effects.writes_memory = !name.contains("Load") && !matches!(op, Operator::MemorySize { .. });
+                }
+                InstrClass::Call | InstrClass::Variable | InstrClass::Other => effects.other = true
+            }
+        }
+    }
+    effects
+}
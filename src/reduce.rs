@@ -1,43 +1,215 @@
-use wirm::ir::id::FunctionID;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::{Display, Formatter};
+use std::ops::{AddAssign, Range};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
 use wirm::{DataType, Module};
 use wirm::wasmparser::Operator;
 use crate::analyze::FuncState;
-use crate::slice::SliceResult;
-use crate::utils::is_branching_op;
+use crate::error::AnalysisError;
+use crate::slice::{Slice, SliceResult};
+use crate::utils::{is_branching_op, is_tail_call, local_func, REDUCTIONS};
 
-pub(crate) fn reduce_slice(slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Module) {
+/// A pass of the pipeline that reduces the max slices to the min slices (see `reduce_with`).
+/// The passes run in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ReductionPass {
+    /// Branch-outcome substitution: whether each conditional branch is taken is requested as state,
+    /// so only the branches are replayed and the instructions computing their conditions are dropped.
+    /// Without it, the min slice replays the conditions (and requests their state) like the max slice.
+    BranchOutcomes,
+    /// Constant resolution: a conditional branch on a constant (the `i32.const` right before it, or
+    /// the read of a global whose value is known statically) replays the constant rather than
+    /// requesting whether it's taken.
+    /// Not in `REDUCTIONS`: it changes the min functions that the existing users generate.
+    Constants,
+    /// Structure pruning: drops what the slice's generated function never replays, the instructions
+    /// outside of the slice (e.g. the branches of a nested loop, which is its own slice).
+    /// Not in `REDUCTIONS`: it changes the state that the min functions request.
+    Structure
+}
+impl ReductionPass {
+    pub const ALL: [ReductionPass; 3] = [ReductionPass::BranchOutcomes, ReductionPass::Constants, ReductionPass::Structure];
+}
+impl Display for ReductionPass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ReductionPass::BranchOutcomes => "branch-outcomes",
+                ReductionPass::Constants => "constants",
+                ReductionPass::Structure => "structure"
+            }
+        )
+    }
+}
+impl FromStr for ReductionPass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "branch-outcomes" => Ok(ReductionPass::BranchOutcomes),
+            "constants" => Ok(ReductionPass::Constants),
+            "structure" => Ok(ReductionPass::Structure),
+            _ => Err(format!("Unknown reduction pass: {}", s))
+        }
+    }
+}
+
+/// What a reduction pass removed from a min slice
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReductionStats {
+    /// The instructions that the min slice no longer replays
+    pub instrs: usize,
+    /// The state that the min function no longer requests (the branch outcomes requested
+    /// by `BranchOutcomes` aren't subtracted)
+    pub requests: usize
+}
+impl AddAssign for ReductionStats {
+    fn add_assign(&mut self, other: Self) {
+        self.instrs += other.instrs;
+        self.requests += other.requests;
+    }
+}
+
+/// Parses a comma-separated list of the reduction passes to run, e.g. `+constants,+structure`:
+/// - `default`/`all`/`none`: start over from `REDUCTIONS`, every pass or no pass
+/// - `name` or `+name`: run the pass (named as in `ReductionPass`'s `Display`)
+/// - `-name`: skip the pass
+///
+/// Starts from `REDUCTIONS`.
+pub fn parse_reductions(spec: &str) -> anyhow::Result<BTreeSet<ReductionPass>> {
+    let mut passes: BTreeSet<ReductionPass> = REDUCTIONS.iter().copied().collect();
+    for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        match item {
+            "default" => passes = REDUCTIONS.iter().copied().collect(),
+            "all" => passes = ReductionPass::ALL.into_iter().collect(),
+            "none" => passes.clear(),
+            _ => {
+                let (enable, name) = match item.strip_prefix('-') {
+                    Some(name) => (false, name),
+                    None => (true, item.strip_prefix('+').unwrap_or(item))
+                };
+                let pass = ReductionPass::from_str(name).map_err(anyhow::Error::msg)?;
+                if enable {
+                    passes.insert(pass);
+                } else {
+                    passes.remove(&pass);
+                }
+            }
+        }
+    }
+    Ok(passes)
+}
+
+/// Compute the min slices with the default passes (`REDUCTIONS`): only the branches are replayed,
+/// whether each conditional branch is taken is requested as state instead. Must follow `save_structure`.
+pub fn reduce_slice(slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Module) -> Result<(), AnalysisError> {
+    reduce_with(slices, funcs, wasm, &REDUCTIONS.iter().copied().collect())
+}
+
+/// Compute the min slices with the `passes`: each slice starts from replaying its max slice (and
+/// every branch), then the passes reduce that in the order of `ReductionPass`. What each of them
+/// removed is recorded in the slice (see `Slice::reductions`). Must follow `save_structure`.
+pub fn reduce_with(slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Module, passes: &BTreeSet<ReductionPass>) -> Result<(), AnalysisError> {
     for (result, func) in slices.iter_mut().zip(funcs.iter()) {
+        if result.fallback.is_some() {
+            // nothing is replayed, so no branches are needed either
+            continue;
+        }
+        let lf = local_func(func.fid, wasm)?;
+        let body = lf.body.instructions.get_ops();
+        let ranges: Vec<Range<usize>> = result.slices.values().map(Slice::instr_range).collect();
+
         for (_instr_idx, slice) in result.slices.iter_mut() {
-            let lf = wasm.functions.unwrap_local(FunctionID(func.fid));
-            let body = &lf.body.instructions;
-
-            for (i, op) in body.get_ops().iter().enumerate() {
-                let in_support = slice.instrs_support.contains(&i);
-                let (in_min_slice, need_taken) = visit_op(op);
-                if in_min_slice && !in_support {
-                    slice.min_slice.insert(i);
-                }
-                if let Some(dt) = need_taken {
-                    slice.taken.insert(i, dt);
-                }
+            let branches = body.iter().enumerate().filter(|(_, op)| is_replayed_branch(op)).map(|(i, _)| i);
+            slice.min_slice = slice.max_slice.iter().copied().chain(branches)
+                .filter(|i| !slice.instrs_support.contains(i))
+                .collect();
+            slice.taken.clear();
+            slice.reductions.clear();
+
+            for pass in passes.iter() {
+                let stats = match pass {
+                    ReductionPass::BranchOutcomes => substitute_outcomes(slice, body),
+                    ReductionPass::Constants => resolve_constants(slice, body),
+                    ReductionPass::Structure => prune_structure(slice, &ranges)
+                };
+                slice.reductions.insert(*pass, stats);
             }
         }
     }
+    Ok(())
 }
 
-/// Returns: (should_include, do_fuel_before)
-/// - support_opcode: whether this opcode should be included in the generated function.
-/// - do_fuel_before: whether we should compute the fuel implications at this location
-///   (before emitting this opcode).
-/// Returns (in_min_slice, need_taken)
-fn visit_op(op: &Operator) -> (bool, Option<DataType>) {
-    // If this opcode is in the slice && it's a branching opcode, I want to know if the branch was taken
-    let in_min_slice = is_branching_op(op) || matches!(op, Operator::If {..} | Operator::Return);
-    let need_taken = if in_min_slice && is_branching_op(op) && !matches!(op, Operator::Br {..}) || matches!(op, Operator::If {..}) {
-        Some(DataType::I32)
-    } else {
-        None
-    };
+/// See `ReductionPass::BranchOutcomes`
+fn substitute_outcomes(slice: &mut Slice, body: &[Operator]) -> ReductionStats {
+    let dropped: HashSet<usize> = slice.min_slice.iter().copied()
+        .filter(|i| !is_replayed_branch(&body[*i]))
+        .collect();
+    slice.min_slice.retain(|i| !dropped.contains(i));
+    for (i, op) in body.iter().enumerate() {
+        if let Some(dt) = needs_taken(op) {
+            slice.taken.insert(i, dt);
+        }
+    }
+    ReductionStats {
+        instrs: dropped.len(),
+        requests: state_requests(slice, |i| dropped.contains(&i))
+    }
+}
+
+/// See `ReductionPass::Constants`
+fn resolve_constants(slice: &mut Slice, body: &[Operator]) -> ReductionStats {
+    let constant: Vec<usize> = slice.taken.keys().copied()
+        .filter(|i| *i > 0 && (matches!(body[*i - 1], Operator::I32Const {..}) || slice.const_globals.contains_key(&(*i - 1)))
+            && !slice.instrs_support.contains(&(*i - 1)))
+        .collect();
+    for i in constant.iter() {
+        slice.taken.remove(i);
+        slice.min_slice.insert(*i - 1);
+    }
+    ReductionStats {
+        instrs: 0,
+        requests: constant.len()
+    }
+}
+
+/// See `ReductionPass::Structure`, `ranges` are the instructions of every slice of the function
+fn prune_structure(slice: &mut Slice, ranges: &[Range<usize>]) -> ReductionStats {
+    let own = slice.instr_range();
+    let nested: Vec<&Range<usize>> = ranges.iter().filter(|range| **range != own && own.contains(&range.start)).collect();
+    let replayed = |i: &usize| own.contains(i) && !nested.iter().any(|range| range.contains(i));
+
+    let (num_instrs, num_taken) = (slice.min_slice.len(), slice.taken.len());
+    slice.min_slice.retain(replayed);
+    slice.taken.retain(|i, _| replayed(i));
+    ReductionStats {
+        instrs: num_instrs - slice.min_slice.len(),
+        requests: num_taken - slice.taken.len()
+    }
+}
+
+/// The min slice replays every branch (and `if`/`return`/`throw`/tail call)
+fn is_replayed_branch(op: &Operator) -> bool {
+    is_branching_op(op) || is_tail_call(op) || matches!(op, Operator::If {..} | Operator::Return | Operator::Throw {..} | Operator::ThrowRef)
+}
+
+/// The type of the requested `taken` state of a conditional branch (or `if`)
+fn needs_taken(op: &Operator) -> Option<DataType> {
+    let conditional = is_branching_op(op) && !matches!(op, Operator::Br {..}) || matches!(op, Operator::If {..});
+    conditional.then_some(DataType::I32)
+}
 
-    (in_min_slice, need_taken)
+/// The number of state values that the max slice requests for the instructions in `instrs`
+fn state_requests(slice: &Slice, instrs: impl Fn(usize) -> bool) -> usize {
+    slice.params.keys().filter(|(_, i)| instrs(*i)).count()
+        + slice.globals.keys().filter(|(_, i)| instrs(*i)).count()
+        + slice.loads.keys().filter(|(_, i)| instrs(*i)).count()
+        + slice.calls.keys().filter(|(i, _)| instrs(*i)).count()
+        + slice.call_indirects.keys().filter(|(i, _)| instrs(*i)).count()
+        + slice.call_refs.keys().filter(|(i, _)| instrs(*i)).count()
+        + slice.table_gets.keys().filter(|i| instrs(**i)).count()
+        + slice.field_loads.keys().filter(|i| instrs(**i)).count()
 }
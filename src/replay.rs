@@ -0,0 +1,133 @@
+//! Replaying the calls to pure functions (`AnalysisConfig::replay_pure_calls`): instead of requesting
+//! the result of such a call as state, the generated functions call a copy of the callee with the
+//! replayed arguments.
+
+use std::collections::{BTreeMap, BTreeSet};
+use wirm::ir::function::FunctionBuilder;
+use wirm::ir::id::FunctionID;
+use wirm::ir::module::module_functions::LocalFunction;
+use wirm::ir::module::module_types::Types;
+use wirm::{DataType, Module};
+use wirm::module_builder::AddLocal;
+use wirm::opcode::Inject;
+use wirm::wasmparser::{BlockType, Catch, HeapType, Operator, ValType};
+use crate::error::{fail, AnalysisError};
+use crate::purity::func_effects;
+use crate::slice::SliceResult;
+use crate::stats::{classify, InstrClass};
+use crate::utils::{local_func, local_funcs, local_ops};
+
+/// The local functions that only compute their results from their params: no memory, no globals,
+/// no tables, no calls and no exceptions (see `Effects::is_leaf_pure`), and that can be copied into
+/// the generated module (see `uncopyable`). A call to one of them can be replayed anywhere.
+pub fn replayable_funcs(wasm: &Module) -> BTreeSet<u32> {
+    let effects = func_effects(wasm);
+    local_funcs(wasm)
+        .filter(|(fid, lf)| effects[fid].is_leaf_pure() && uncopyable(*fid, lf, wasm).is_none())
+        .map(|(fid, _)| fid)
+        .collect()
+}
+
+/// Copy the replayed callees (the pure functions called from the max slices) into `gen_wasm`.
+/// Returns: the original FID -> the FID of its copy
+pub(crate) fn copy_replayed<'a, 'b>(slices: &[SliceResult], wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> Result<BTreeMap<u32, FunctionID>, AnalysisError> where 'a: 'b {
+    let replayable = replayable_funcs(wasm);
+    let mut called = BTreeSet::new();
+    for func_slices in slices.iter() {
+        let ops = local_ops(func_slices.fid, wasm)?;
+        for slice in func_slices.slices.values() {
+            for instr_idx in slice.max_slice.iter() {
+                if let Some(Operator::Call { function_index }) = ops.get(*instr_idx) {
+                    if replayable.contains(function_index) {
+                        called.insert(*function_index);
+                    }
+                }
+            }
+        }
+    }
+    called.into_iter().map(|fid| Ok((fid, copy_func(fid, wasm, gen_wasm)?))).collect()
+}
+
+fn copy_func<'a, 'b>(fid: u32, wasm: &Module<'a>, gen_wasm: &mut Module<'b>) -> Result<FunctionID, AnalysisError> where 'a: 'b {
+    let lf = local_func(fid, wasm)?;
+    let Some(Types::FuncType { params, results, .. }) = wasm.types.get(lf.ty_id) else {
+        fail!(AnalysisError::MissingFuncType { tid: *lf.ty_id });
+    };
+    if let Some(reason) = uncopyable(fid, lf, wasm) {
+        fail!(AnalysisError::UnsupportedOp { op: format!("{reason} (in the replayed function #{fid})") });
+    }
+    let mut func = FunctionBuilder::new(params, results);
+    for (count, ty) in lf.body.locals.iter() {
+        for _ in 0..*count {
+            func.add_local(*ty);
+        }
+    }
+    // the builder ends the function
+    let ops = lf.body.instructions.get_ops();
+    for op in ops.iter().take(ops.len().saturating_sub(1)) {
+        func.inject(op.clone());
+    }
+    Ok(func.finish_module(gen_wasm))
+}
+
+/// Why the function `fid` (`lf`) can't be copied as is into the generated module, None if it can:
+/// its signature, locals and ops don't refer to anything of the original module (see `module_index`).
+fn uncopyable(fid: u32, lf: &LocalFunction, wasm: &Module) -> Option<String> {
+    let Some(Types::FuncType { params, results, .. }) = wasm.types.get(lf.ty_id) else {
+        return Some(format!("function #{fid} has no function type"));
+    };
+    // the references to the module's own types
+    let defined = |ty: &DataType| matches!(ty, DataType::Module { .. } | DataType::RecGroup(_) | DataType::CoreTypeId(_));
+    if params.iter().chain(results.iter()).chain(lf.body.locals.iter().map(|(_, ty)| ty)).any(defined) {
+        return Some(format!("function #{fid} has a local of a reference to a defined type"));
+    }
+    lf.body.instructions.get_ops().iter()
+        .find_map(|op| module_index(op).map(|kind| format!("{op:?} (refers to a {kind} of the module)")))
+}
+
+/// The kind of module index (a tag, a type, a data or element segment...) that `op` refers to, None
+/// if it doesn't refer to any. `copy_func` doesn't remap them: they'd point to nothing (or to
+/// something else) in the generated module.
+fn module_index(op: &Operator) -> Option<&'static str> {
+    let typed_block = |blockty: &BlockType| matches!(blockty, BlockType::FuncType(_));
+    match op {
+        Operator::Block { blockty } | Operator::Loop { blockty } | Operator::If { blockty } => typed_block(blockty).then_some("type"),
+        Operator::TryTable { try_table } => {
+            if try_table.catches.iter().any(|catch| matches!(catch, Catch::One { .. } | Catch::OneRef { .. })) {
+                Some("tag")
+            } else {
+                typed_block(&try_table.ty).then_some("type")
+            }
+        }
+        Operator::Throw { .. } | Operator::Try { .. } | Operator::Catch { .. } | Operator::CatchAll
+        | Operator::Rethrow { .. } | Operator::Delegate { .. } => Some("tag"),
+        Operator::Call { .. } | Operator::ReturnCall { .. } | Operator::RefFunc { .. } => Some("function"),
+        Operator::CallIndirect { .. } | Operator::ReturnCallIndirect { .. } => Some("table"),
+        Operator::CallRef { .. } | Operator::ReturnCallRef { .. } => Some("type"),
+        Operator::GlobalGet { .. } | Operator::GlobalSet { .. } => Some("global"),
+        Operator::MemoryInit { .. } | Operator::DataDrop { .. }
+        | Operator::ArrayNewData { .. } | Operator::ArrayInitData { .. } => Some("data segment"),
+        Operator::TableInit { .. } | Operator::ElemDrop { .. }
+        | Operator::ArrayNewElem { .. } | Operator::ArrayInitElem { .. } => Some("element segment"),
+        Operator::RefNull { hty } | Operator::RefTestNonNull { hty } | Operator::RefTestNullable { hty }
+        | Operator::RefCastNonNull { hty } | Operator::RefCastNullable { hty } => matches!(hty, HeapType::Concrete(_)).then_some("type"),
+        Operator::BrOnCast { from_ref_type, to_ref_type, .. } | Operator::BrOnCastFail { from_ref_type, to_ref_type, .. } => {
+            (from_ref_type.type_index().is_some() || to_ref_type.type_index().is_some()).then_some("type")
+        }
+        Operator::TypedSelect { ty } => matches!(ty, ValType::Ref(ref_ty) if ref_ty.type_index().is_some()).then_some("type"),
+        Operator::ArrayLen => None,
+        op => {
+            // the GC, table and memory ops are too many to list, go off of the opcode's name
+            let name = format!("{op:?}");
+            if name.starts_with("Struct") || name.starts_with("Array") {
+                Some("type")
+            } else if name.starts_with("Table") {
+                Some("table")
+            } else if matches!(classify(op), InstrClass::Memory) {
+                Some("memory")
+            } else {
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Rendering the reports (e.g. the slice listings, the FID mappings and the cost deltas): the report
+//! only says what to write and the role of each highlighted piece, a `Reporter` decides what that looks like.
+
+use std::io::{self, Write};
+use crate::color::{Color, ColorSpec, WriteColor};
+
+/// The role of a highlighted piece of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// An instruction in the min slice
+    Min,
+    /// An instruction in the max slice, or the state that influences the control flow
+    Tainted,
+    /// An instruction that keeps the structure of the slice (e.g. the `end` of a block)
+    Support,
+    /// The fuel charged at a fuel point (or a change of it), and the warnings and failures
+    Cost,
+    /// A generated function
+    Fid
+}
+
+/// Renders a report, the plain text is written through `io::Write`
+pub trait Reporter: Write {
+    fn styled(&mut self, s: &str, style: Style) -> io::Result<()>;
+}
+
+/// Colors the highlighted text, for a terminal
+#[derive(Debug)]
+pub struct ColorReporter<W: WriteColor> {
+    out: W
+}
+impl<W: WriteColor> ColorReporter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+impl<W: WriteColor> Write for ColorReporter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+impl<W: WriteColor> Reporter for ColorReporter<W> {
+    fn styled(&mut self, s: &str, style: Style) -> io::Result<()> {
+        let (color, italics) = match style {
+            Style::Min => (Color::Yellow, false),
+            Style::Tainted => (Color::Green, false),
+            Style::Support => (Color::Blue, false),
+            Style::Cost => (Color::Red, false),
+            Style::Fid => (Color::Magenta, true)
+        };
+        self.out.set_color(ColorSpec::new().set_bold(true).set_italic(italics).set_fg(Some(color)))?;
+        write!(self.out, "{s}")?;
+        self.out.set_color(&ColorSpec::default())
+    }
+}
+
+/// Writes the highlighted text as is, e.g. to a file
+#[derive(Debug)]
+pub struct PlainReporter<W: Write> {
+    out: W
+}
+impl<W: Write> PlainReporter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+impl<W: Write> Write for PlainReporter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+impl<W: Write> Reporter for PlainReporter<W> {
+    fn styled(&mut self, s: &str, _style: Style) -> io::Result<()> {
+        write!(self.out, "{s}")
+    }
+}
+
+/// Drops the report, to run headless
+#[derive(Debug, Default)]
+pub struct NullReporter;
+impl Write for NullReporter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl Reporter for NullReporter {
+    fn styled(&mut self, _s: &str, _style: Style) -> io::Result<()> {
+        Ok(())
+    }
+}
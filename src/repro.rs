@@ -0,0 +1,340 @@
+use std::any::Any;
+use std::ops::Range;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+use anyhow::bail;
+#[cfg(feature = "fs")]
+use anyhow::Context;
+use wirm::wasmparser::{BinaryReader, Export, FromReader, FunctionBody, Import, ImportSectionReader, Parser, Payload, TypeRef};
+#[cfg(feature = "fs")]
+use crate::cert::{sha256, FuelCertificate};
+use crate::error::AnalysisError;
+#[cfg(feature = "fs")]
+use crate::run::gen_certificate;
+use crate::run::{parse_module, run_analysis, AnalysisConfig};
+use crate::utils::write_leb128;
+
+/// The files of a repro bundle (a tar archive that can be attached to an issue as is)
+pub const REPRO_MODULE: &str = "module.wasm";
+pub const REPRO_CONFIG: &str = "config.txt";
+pub const REPRO_ERROR: &str = "error.txt";
+pub const REPRO_MINIMIZED: &str = "minimized.wasm";
+
+const IMPORT_SECTION: u8 = 2;
+const EXPORT_SECTION: u8 = 7;
+const CODE_SECTION: u8 = 10;
+const DATA_SECTION: u8 = 11;
+/// The body of a stubbed function: no locals, `unreachable`, `end`
+const STUB_BODY: [u8; 3] = [0x00, 0x00, 0x0b];
+
+/// Write what's needed to reproduce a failed analysis to the archive `path`:
+/// - `module.wasm`: the module, redacted down to the function that the analysis failed in (see `redact_module`)
+/// - `config.txt`: the configuration of the analysis
+/// - `error.txt`: the error, with where the analysis failed
+/// - `minimized.wasm`: the module minimized with the same configuration (see `minimize`), if the
+///   failure reproduces in memory
+#[cfg(feature = "fs")]
+pub fn write_repro_bundle(path: &Path, wasm_bytes: &[u8], config: &AnalysisConfig, err: &anyhow::Error) -> anyhow::Result<()> {
+    let fid = err.downcast_ref::<AnalysisError>().and_then(AnalysisError::fid);
+    let module = redact_module(wasm_bytes, fid)?;
+
+    let mut error = format!("{err:#}\n");
+    if fid.is_none() {
+        error.push_str("(the failing function is unknown, every function body was stubbed)\n");
+    }
+    let mut files = vec![(REPRO_MODULE, module), (REPRO_CONFIG, format!("{config:#?}\n").into_bytes()), (REPRO_ERROR, error.into_bytes())];
+    // (the original module is returned when not even the redacted one fails, that can't be shared)
+    if let Ok(minimized) = minimize(wasm_bytes, config).map_err(|_| ()).and_then(|m| if m == wasm_bytes { Err(()) } else { Ok(m) }) {
+        files.push((REPRO_MINIMIZED, minimized));
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, tar(&files)).with_context(|| format!("Failed to write the repro bundle {}", path.display()))
+}
+
+#[cfg(feature = "fs")]
+const TAR_BLOCK: usize = 512;
+
+/// A (ustar) tar archive of the `files`
+#[cfg(feature = "fs")]
+fn tar(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut archive = vec![];
+    for (name, contents) in files {
+        let mut header = [0u8; TAR_BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[108..116].copy_from_slice(b"0000000\0");
+        header[116..124].copy_from_slice(b"0000000\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+        header[136..148].copy_from_slice(b"00000000000\0");
+        header[156] = b'0';
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        // the checksum is computed with its own field as spaces
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+        header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(contents);
+        archive.resize(archive.len().div_ceil(TAR_BLOCK) * TAR_BLOCK, 0);
+    }
+    archive.resize(archive.len() + 2 * TAR_BLOCK, 0);
+    archive
+}
+
+/// Redact the module so that only the function `keep_fid` can be shared:
+/// - the body of every other function becomes `unreachable` (the function indices, types, imports
+///   and exports are unchanged, so the kept function still validates)
+/// - the contents of the data segments are zeroed
+/// - the import names become `env`.`import<i>` and the export names `export<i>` (by their position
+///   in the section)
+/// - the custom sections (names, producers, debug info) are dropped
+pub fn redact_module(wasm_bytes: &[u8], keep_fid: Option<u32>) -> anyhow::Result<Vec<u8>> {
+    rewrite_module(wasm_bytes, true, |fid, body| if keep_fid == Some(fid) {
+        body.to_vec()
+    } else {
+        STUB_BODY.to_vec()
+    })
+}
+
+/// Re-encode the module with the function bodies returned by `rewrite_body(fid, body)`, the
+/// data segments, import and export names and custom sections are `redact`ed as in `redact_module`.
+fn rewrite_module(wasm_bytes: &[u8], redact: bool, mut rewrite_body: impl FnMut(u32, &[u8]) -> Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let mut rewritten = Vec::with_capacity(wasm_bytes.len());
+    let mut num_imported_funcs = 0;
+    // the bodies follow the start of the code section as separate payloads,
+    // the section is written once they've all been read
+    let (mut code, mut num_bodies, mut bodies_left) = (vec![], 0, 0);
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::Version { range, .. } => rewritten.extend_from_slice(&wasm_bytes[range]),
+            Payload::CustomSection(_) if redact => {}
+            Payload::CodeSectionStart { count, .. } => {
+                write_leb128(&mut code, count);
+                bodies_left = count;
+                if count == 0 {
+                    write_section(&mut rewritten, CODE_SECTION, &code);
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let body = rewrite_body(num_imported_funcs + num_bodies, &wasm_bytes[body.range()]);
+                write_leb128(&mut code, body.len() as u32);
+                code.extend_from_slice(&body);
+                num_bodies += 1;
+                bodies_left -= 1;
+                if bodies_left == 0 {
+                    write_section(&mut rewritten, CODE_SECTION, &code);
+                }
+            }
+            Payload::DataSection(reader) if redact => {
+                let range = reader.range();
+                let mut contents = wasm_bytes[range.clone()].to_vec();
+                for data in reader {
+                    // the bytes of a segment come last
+                    let data = data?;
+                    contents[data.range.end - data.data.len() - range.start..data.range.end - range.start].fill(0);
+                }
+                write_section(&mut rewritten, DATA_SECTION, &contents);
+            }
+            Payload::ImportSection(reader) if redact => {
+                num_imported_funcs = count_imported_funcs(&reader)?;
+                let contents = rename_entries::<Import>(wasm_bytes, reader.range(), |i| vec!["env".to_string(), format!("import{i}")])?;
+                write_section(&mut rewritten, IMPORT_SECTION, &contents);
+            }
+            Payload::ExportSection(reader) if redact => {
+                let contents = rename_entries::<Export>(wasm_bytes, reader.range(), |i| vec![format!("export{i}")])?;
+                write_section(&mut rewritten, EXPORT_SECTION, &contents);
+            }
+            payload => {
+                if let Payload::ImportSection(reader) = &payload {
+                    num_imported_funcs = count_imported_funcs(reader)?;
+                }
+                if let Some((id, range)) = payload.as_section() {
+                    write_section(&mut rewritten, id, &wasm_bytes[range]);
+                }
+            }
+        }
+    }
+    Ok(rewritten)
+}
+
+fn count_imported_funcs(reader: &ImportSectionReader) -> anyhow::Result<u32> {
+    let mut num_imported_funcs = 0;
+    for import in reader.clone() {
+        if let TypeRef::Func(_) = import?.ty {
+            num_imported_funcs += 1;
+        }
+    }
+    Ok(num_imported_funcs)
+}
+
+/// Re-encode the entries (imports or exports) of the section at `range` with the names `names(i)`
+/// instead of the names they start with, the rest of each entry is copied as is
+fn rename_entries<'a, T: FromReader<'a>>(wasm_bytes: &'a [u8], range: Range<usize>, names: impl Fn(u32) -> Vec<String>) -> anyhow::Result<Vec<u8>> {
+    let mut reader = BinaryReader::new(&wasm_bytes[range.clone()], range.start);
+    let count = reader.read_var_u32()?;
+    let mut contents = vec![];
+    write_leb128(&mut contents, count);
+    for i in 0..count {
+        let start = reader.original_position();
+        reader.read::<T>()?;
+        let mut entry = BinaryReader::new(&wasm_bytes[start..reader.original_position()], start);
+        for name in names(i) {
+            entry.read_string()?;
+            write_leb128(&mut contents, name.len() as u32);
+            contents.extend_from_slice(name.as_bytes());
+        }
+        contents.extend_from_slice(&wasm_bytes[entry.original_position()..reader.original_position()]);
+    }
+    Ok(contents)
+}
+
+/// Shrink a module that the analysis fails on, while it keeps failing the same way (see `failure_signature`):
+/// 1. redact it down to a single function (the one in the error, otherwise the first one that still fails)
+/// 2. remove chunks of that function's instructions, halving the chunks until no single instruction can be removed
+///
+/// The panics of the candidates are caught (see `catch_failure`), the panic hook is left as is.
+pub fn minimize(wasm_bytes: &[u8], config: &AnalysisConfig) -> anyhow::Result<Vec<u8>> {
+    let Some(err) = run_pipeline(wasm_bytes, config) else {
+        bail!("The analysis doesn't fail on this module, there's nothing to minimize");
+    };
+    let signature = failure_signature(&err);
+    let known_fid = err.downcast_ref::<AnalysisError>().and_then(AnalysisError::fid);
+    shrink(wasm_bytes, known_fid, |candidate| run_pipeline(candidate, config).is_some_and(|err| failure_signature(&err) == signature))
+}
+
+/// Shrink a module whose certificate (see `cert`) doesn't match the one recomputed with `config`
+/// (as reported by `verify-cert`), while the worst case of the first mismatching function still
+/// differs from the certified one
+#[cfg(feature = "fs")]
+pub fn minimize_cert_mismatch(wasm_bytes: &[u8], certified: &FuelCertificate, config: &AnalysisConfig) -> anyhow::Result<Vec<u8>> {
+    let worst_case = |cert: &FuelCertificate, fid: u32| cert.functions.iter().find(|func| func.fid == fid).map(|func| func.worst_case);
+    let mismatch = |candidate: &[u8], fid: u32| catch_failure(|| gen_certificate(candidate, config)).is_ok_and(|cert| worst_case(&cert, fid) != worst_case(certified, fid));
+    let Some(fid) = certified.functions.iter().map(|func| func.fid).find(|fid| mismatch(wasm_bytes, *fid)) else {
+        bail!("The certificate matches the module, there's nothing to minimize");
+    };
+    shrink(wasm_bytes, Some(fid), |candidate| mismatch(candidate, fid))
+}
+
+/// The steps of `minimize`, while the candidates still `fail`
+fn shrink(wasm_bytes: &[u8], known_fid: Option<u32>, fails: impl Fn(&[u8]) -> bool) -> anyhow::Result<Vec<u8>> {
+    // 1. a single function
+    let num_funcs = parse_module(wasm_bytes)?.functions.iter().count() as u32;
+    let mut kept = None;
+    for fid in known_fid.into_iter().chain(0..num_funcs) {
+        let candidate = redact_module(wasm_bytes, Some(fid))?;
+        if fails(&candidate) {
+            kept = Some((fid, candidate));
+            break;
+        }
+    }
+    let Some((fid, mut module)) = kept else {
+        // the failure needs more than one function, only redact the rest
+        let candidate = rewrite_module(wasm_bytes, true, |_, body| body.to_vec())?;
+        return Ok(if fails(&candidate) { candidate } else { wasm_bytes.to_vec() });
+    };
+
+    // 2. the instructions of that function
+    let Some(mut body) = func_body(&module, fid)? else {
+        // an imported function, there's nothing left to remove
+        return Ok(module);
+    };
+    let mut ops = op_ranges(&body)?;
+    // never remove the final `end`
+    let mut chunk = ops.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start + 1 < ops.len() {
+            let end = (start + chunk).min(ops.len() - 1);
+            let removed = ops[start].start..ops[end - 1].end;
+            let mut candidate_body = body[..removed.start].to_vec();
+            candidate_body.extend_from_slice(&body[removed.end..]);
+            let candidate = rewrite_module(&module, false, |f, b| if f == fid { candidate_body.clone() } else { b.to_vec() })?;
+            if fails(&candidate) {
+                (module, body) = (candidate, candidate_body);
+                ops = op_ranges(&body)?;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+    Ok(module)
+}
+
+/// The default name of the minimized module of `<stem>.wasm`: `<stem>-min-<the first 8 hex digits of
+/// its SHA-256>`, after its contents so that minimizing several modules (or failures) doesn't overwrite
+/// the earlier ones
+#[cfg(feature = "fs")]
+pub fn minimized_name(stem: &str, minimized: &[u8]) -> String {
+    format!("{stem}-min-{}", &sha256(minimized)[..8])
+}
+
+/// Write the (minimized) module as a test program: `<dir>/<name>.wasm` and its text `<dir>/<name>.wat`,
+/// returns the paths that were written
+#[cfg(feature = "fs")]
+pub fn write_test_program(dir: &Path, name: &str, wasm_bytes: &[u8]) -> anyhow::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let wat = wasmprinter::print_bytes(wasm_bytes).context("Failed to print the minimized module")?;
+    let paths = vec![dir.join(format!("{name}.wasm")), dir.join(format!("{name}.wat"))];
+    for (path, contents) in paths.iter().zip([wasm_bytes, wat.as_bytes()]) {
+        std::fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(paths)
+}
+
+/// Run the whole pipeline on the module (in memory), returns how it failed (if it did)
+fn run_pipeline(wasm_bytes: &[u8], config: &AnalysisConfig) -> Option<anyhow::Error> {
+    catch_failure(|| run_analysis(wasm_bytes, config)).err()
+}
+
+/// How the analysis failed, without where (the functions and instructions move while minimizing)
+fn failure_signature(err: &anyhow::Error) -> String {
+    err.root_cause().to_string().chars().filter(|c| !c.is_ascii_digit()).collect()
+}
+
+/// The body (locals and instructions) of the function `fid`, if it's a local function
+fn func_body(wasm_bytes: &[u8], fid: u32) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut found = None;
+    rewrite_module(wasm_bytes, false, |f, body| {
+        if f == fid {
+            found = Some(body.to_vec());
+        }
+        body.to_vec()
+    })?;
+    Ok(found)
+}
+
+/// The byte range of each instruction in the body
+fn op_ranges(body: &[u8]) -> anyhow::Result<Vec<Range<usize>>> {
+    let mut reader = FunctionBody::new(BinaryReader::new(body, 0)).get_operators_reader()?;
+    let mut starts = vec![];
+    while !reader.eof() {
+        starts.push(reader.original_position());
+        reader.read()?;
+    }
+    Ok(starts.iter().enumerate().map(|(i, start)| *start..starts.get(i + 1).copied().unwrap_or(body.len())).collect())
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_leb128(out, contents.len() as u32);
+    out.extend_from_slice(contents);
+}
+
+/// Run `f`, turning a panic of the analysis into an error (with the panic message) so that a
+/// repro bundle can still be written. The fid is then unknown though.
+pub fn catch_failure<T>(f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|payload| Err(AnalysisError::Panicked { msg: panic_message(&payload) }.into()))
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "(no message)".to_string()
+    }
+}
@@ -1,25 +1,85 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
-use std::hash::Hash;
 use std::io;
 use std::iter::zip;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::Write;
 use std::str::FromStr;
-use termcolor::{Color, ColorSpec, WriteColor};
-use wirm::ir::id::FunctionID;
+use crate::color::{NoColor, WriteColor};
+use anyhow::bail;
+#[cfg(feature = "fs")]
+use anyhow::Context;
+use wirm::ir::id::{FunctionID, GlobalID};
+use wirm::ir::module::module_globals::{GlobalKind, ImportedGlobal, LocalGlobal};
+use wirm::ir::module::module_functions::FuncKind;
 use wirm::{DataType, Module};
-use crate::analyze::{analyze, FuncState};
-use crate::codegen::{CodeGenResult, GeneratedFunc, ReqState, StateType};
+use wirm::wasmparser::WasmFeatures;
+use crate::analyze::{analyze, analyze_except, FuncState, NoVisitor, OpKind};
+use crate::error::AnalysisError;
+use crate::globals::ConstValue;
+pub use crate::codegen::CodeGenResult;
+use crate::codegen::{GeneratedFunc, ReqState, StateType};
 use crate::codegen::max::codegen_max;
 use crate::codegen::min::codegen_min;
-use crate::reduce::reduce_slice;
-use crate::slice::{save_structure, slice_program, SliceResult};
-use crate::utils::{FUEL_COMPUTATION, SPACE_PER_TAB};
+use crate::cost_model::{CostModel, OpCostModel};
+use crate::reduce::{reduce_with, ReductionPass, ReductionStats};
+use crate::replay::replayable_funcs;
+use crate::slice::{detect_sinks, detect_trap_sinks, save_structure, slice_program_replaying, GlobalSetSinks, GlobalSource, SliceResult};
+pub use crate::slice::{SinkDetector, SliceCriterion};
+use crate::utils::{check_features, local_func, local_ops, CHARGE_DROPPED, DEDUP_CONST_MIN_USES, FUEL_COMPUTATION, LOOP_DEPTH_MULTIPLIER, REDUCTIONS, SPACE_PER_TAB};
+use crate::verify::{verify_fuel_points, FuelPointViolation, ViolationKind};
+use crate::meter::meter_in_place;
+#[cfg(feature = "fs")]
+use crate::patch::{gen_patches, json_description, patch_file, PatchFormat};
+use crate::preserve::check_preserved;
+use crate::artifact::{compare_artifacts, ArtifactDelta};
+#[cfg(feature = "fs")]
+use crate::cert::FuelCertificate;
+use crate::sidecar::{GeneratedHandles, JsonReport, Sidecar, SliceHandle};
+#[cfg(feature = "fs")]
+use crate::sidecar::{write_json_report, write_sidecar};
+use crate::state_layout::{append_custom_section, encode_state_layout, STATE_SECTION};
+use crate::stats::SliceStats;
+use crate::html::ModuleSummary;
+#[cfg(feature = "fs")]
+use crate::html::render_index;
+use crate::names::{name_params, with_name_section, DebugNames};
+use crate::reporter::{ColorReporter, Reporter, Style};
+use crate::size::OutputBudget;
+#[cfg(feature = "fs")]
+use crate::size::SizeReport;
+use crate::entry::reachable_from;
+use crate::cost_expr::CostExpr;
+#[cfg(feature = "fs")]
+use crate::baseline::{compare, load_json_report, FuncDelta};
+#[cfg(feature = "fs")]
+use crate::watch::{WatchEvent, Watcher, WATCH_INTERVAL};
+use crate::repro::catch_failure;
+use crate::strict::approximations;
+use crate::strategies::{StrategyReport, STRATEGIES};
+#[cfg(feature = "audit")]
+use crate::audit::{audit, AuditConfig, AuditOutcome, AuditTarget, FuncAudit};
+#[cfg(feature = "audit")]
+use wirm::wasmparser::Operator;
+#[cfg(feature = "bmc")]
+use crate::bmc::{model_check, BmcOutcome, BmcTarget, FuncBmc};
 
+#[derive(Debug, Clone, Copy)]
 pub enum CompType {
     Exact,
-    Approx
+    Approx,
+    /// Coarse metering: only charges a static estimate (the worst-case cost of the body, without
+    /// the nested loops) on entry into the function and into each iteration of its loops,
+    /// the generated functions don't request any state
+    Epoch,
+    /// Coarse metering as with `Epoch`, but the estimate is charged at the end of the function and of
+    /// each iteration of its loops (at the back edge), once the code it's charged for ran
+    BackEdge
+}
+impl Default for CompType {
+    fn default() -> Self {
+        FUEL_COMPUTATION
+    }
 }
 impl Display for CompType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -28,7 +88,9 @@ impl Display for CompType {
             "{}",
             match self {
                 CompType::Exact => "exact",
-                CompType::Approx => "approx"
+                CompType::Approx => "approx",
+                CompType::Epoch => "epoch",
+                CompType::BackEdge => "back-edge"
             }
         )
     }
@@ -40,96 +102,1306 @@ impl FromStr for CompType {
         match s {
             "exact" => Ok(CompType::Exact),
             "approx" => Ok(CompType::Approx),
+            "epoch" => Ok(CompType::Epoch),
+            "back-edge" => Ok(CompType::BackEdge),
             _ => Err(format!("Unknown comp type: {}", s))
         }
     }
 }
 
+/// The path that reads the module from stdin (as input) or writes the generated module to stdout (as output)
+pub const STDIO_PATH: &str = "-";
+
+/// How much of the analysis `do_analysis` reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Nothing, only the generated modules are written
+    Quiet,
+    /// A line per function, the fuel ID mapping and the checks (as the CLI without `-q` or `-v`)
+    #[default]
+    Summary,
+    /// The annotated instruction listing of every slice
+    Listing,
+    /// The listing, followed by the taint recorded for every instruction
+    Taint
+}
+impl Verbosity {
+    /// `-q` or the number of `-v`s on the command line (the summary without either)
+    pub fn from_flags(quiet: bool, verbose: u8) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Summary,
+            (false, 1) => Verbosity::Listing,
+            (false, _) => Verbosity::Taint
+        }
+    }
+}
+
+/// Which renderings of the generated modules `do_analysis` writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Emit {
+    #[default]
+    Wasm,
+    /// The text format, to review the generated functions
+    Wat,
+    Both
+}
+impl Emit {
+    /// The files that the generated module for `out_path` is written to (the WAT is written
+    /// next to it, with a `.wat` extension). Stdout only takes one rendering: the text for `wat`,
+    /// the binary otherwise.
+    pub fn paths(&self, out_path: &str) -> Vec<String> {
+        if out_path == STDIO_PATH {
+            return vec![STDIO_PATH.to_string()];
+        }
+        let wat_path = || Path::new(out_path).with_extension("wat").to_string_lossy().to_string();
+        match self {
+            Emit::Wasm => vec![out_path.to_string()],
+            Emit::Wat => vec![wat_path()],
+            Emit::Both => vec![out_path.to_string(), wat_path()]
+        }
+    }
+}
+impl Display for Emit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Emit::Wasm => "wasm",
+                Emit::Wat => "wat",
+                Emit::Both => "both"
+            }
+        )
+    }
+}
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wasm" => Ok(Emit::Wasm),
+            "wat" => Ok(Emit::Wat),
+            "both" => Ok(Emit::Both),
+            _ => Err(format!("Unknown output format: {}", s))
+        }
+    }
+}
+
+/// Which of the variants (see `Variant`) `do_analysis` generates and writes, skipping one halves
+/// the codegen and encoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SliceVariants {
+    Max,
+    Min,
+    #[default]
+    Both
+}
+impl SliceVariants {
+    pub fn max(&self) -> bool {
+        matches!(self, SliceVariants::Max | SliceVariants::Both)
+    }
+
+    pub fn min(&self) -> bool {
+        matches!(self, SliceVariants::Min | SliceVariants::Both)
+    }
+}
+impl Display for SliceVariants {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                SliceVariants::Max => "max",
+                SliceVariants::Min => "min",
+                SliceVariants::Both => "both"
+            }
+        )
+    }
+}
+impl FromStr for SliceVariants {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(SliceVariants::Max),
+            "min" => Ok(SliceVariants::Min),
+            "both" => Ok(SliceVariants::Both),
+            _ => Err(format!("Unknown slice variant: {}", s))
+        }
+    }
+}
+
+/// How the generated functions wrap the replayed body, so that a branch to the function's label
+/// (as in the original body) still returns the fuel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockWrapper {
+    /// An empty block that the branches exit, followed by a `local.get` of the fuel
+    #[default]
+    Empty,
+    /// A block that yields the fuel (`block (result i64)`), a branch to the function's label becomes
+    /// a `return` of the fuel (the original's results aren't carried). A function with another branch
+    /// to the function's label (e.g. a `br_table`, whose targets must all carry the same values)
+    /// falls back to the empty block.
+    Typed
+}
+impl Display for BlockWrapper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                BlockWrapper::Empty => "empty",
+                BlockWrapper::Typed => "typed"
+            }
+        )
+    }
+}
+impl FromStr for BlockWrapper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "empty" => Ok(BlockWrapper::Empty),
+            "typed" => Ok(BlockWrapper::Typed),
+            _ => Err(format!("Unknown block wrapper: {}", s))
+        }
+    }
+}
+
+/// The configuration of a run: the optional outputs and checks of `do_analysis` and what the lower-level
+/// passes (e.g. `codegen`) compute. Start from `AnalysisConfig::default()` and chain the setters, e.g.
+/// `AnalysisConfig::default().init_fuel(1000).comp_type(CompType::Approx)`.
+#[derive(Debug)]
+pub struct AnalysisConfig {
+    /// Also store the analysis results as JSON at this path (see `sidecar`)
+    pub sidecar_path: Option<PathBuf>,
+    /// Report the size of the generated modules and check them against this budget
+    pub output_budget: Option<OutputBudget>,
+    /// Only accept modules using these Wasm proposals (see `parse_features`),
+    /// otherwise anything that the parser can read is analyzed
+    pub features: Option<WasmFeatures>,
+    /// How the generated functions compute the fuel (`FUEL_COMPUTATION` by default)
+    pub comp_type: CompType,
+    /// The functions (by original FID) that use the `Epoch` comp type, whatever the `comp_type`
+    pub epoch_funcs: Vec<u32>,
+    /// The fuel budget that the generated functions start from, they then return the remaining
+    /// fuel (negative once it's exhausted). Otherwise they return the fuel consumed.
+    pub init_fuel: Option<u64>,
+    /// With `init_fuel`: instead of going negative, a charge that exhausts the fuel calls the imported
+    /// `env.on_out_of_fuel() -> i64` and adds the fuel it returns (see `codegen::OUT_OF_FUEL_FUNC`)
+    pub on_out_of_fuel: bool,
+    /// The fuel charged per op (1 for every op by default), see `cost_model::OpCostModel`
+    pub cost_model: Box<dyn OpCostModel>,
+    /// How the generated functions wrap the replayed body (see `BlockWrapper`)
+    pub block_wrapper: BlockWrapper,
+    /// Only export a single `fuel(dispatch_id, state_ptr)` function that reads the requested
+    /// state from memory and calls the generated function (see `codegen::dispatch`)
+    pub dispatch: bool,
+    /// Also export the worst-case cost of each function whose cost is fully static as an immutable global
+    /// (see `codegen::STATIC_COST_PREFIX`), the host can read the bound without calling anything
+    pub export_static_costs: bool,
+    /// Dry run: only report the slices, skips generating (and writing) the fuel computations
+    pub no_codegen: bool,
+    /// Write the generated modules as binary, text or both (see `Emit::paths`)
+    pub emit: Emit,
+    /// Only generate (and write) the max or the min variant, both by default
+    pub variants: SliceVariants,
+    /// How much of the analysis is reported
+    pub verbosity: Verbosity,
+    /// Round the cost of every charge up to a multiple of this (must be > 0), e.g. 8
+    pub granularity: Option<u64>,
+    /// Also slice backward from these instructions (not only from the control flow)
+    pub slice_at: Vec<SliceCriterion>,
+    /// Also slice backward from the sinks that these find (see `SinkDetector`)
+    pub sinks: Vec<Box<dyn SinkDetector>>,
+    /// Replay the calls to pure functions (see `replay::replayable_funcs`) in the generated functions,
+    /// instead of requesting their results as state
+    pub replay_pure_calls: bool,
+    /// Treat the writes to these (mutable, numeric) globals as sinks: the generated functions also
+    /// recompute the value that would be written, into an exported copy of the global (see
+    /// `codegen::MUTATION_PREFIX`). E.g. to monitor an invariant on a balance global.
+    pub mutation_sinks: BTreeSet<u32>,
+    /// Also slice the operands of the ops that may trap on them (divisions, remainders and float-to-int
+    /// truncations) when they depend on the state: the trap decides the control flow as a branch would
+    pub trap_sinks: bool,
+    /// Embed the state that each generated function requests in the generated modules, in the compact
+    /// binary layout of `state_layout` (the custom section `state_layout::STATE_SECTION`)
+    pub state_section: bool,
+    /// Label every fuel point in the generated code with its checkpoint ID (`i64.const <id>; drop`),
+    /// the IDs are always listed in the sidecar's manifest
+    pub checkpoints: bool,
+    /// Where to also write the report as JSON (the slices, cost maps and FID mapping)
+    pub report_json: Option<PathBuf>,
+    /// Only analyze the functions reachable from this exported function (see `entry::reachable_from`)
+    pub entry: Option<String>,
+    /// Compare the analysis to this report (saved with `report_json`), the changes are reported per function
+    pub baseline: Option<PathBuf>,
+    /// Skip the functions that the taint analysis fails on (with a warning) instead of failing,
+    /// the other functions are still sliced and get generated code (see `analyze_keep_going`)
+    pub keep_going: bool,
+    /// Fail instead of relying on a conservative approximation, e.g. the worst-case cost of a
+    /// function with an unsupported op (see `strict::approximations`)
+    pub strict: bool,
+    /// Only analyze these functions (by FID), with `entry` only the ones that are also reachable from it
+    pub only_funcs: Option<BTreeSet<u32>>,
+    /// Whether computations whose results are `drop`ped are charged (`CHARGE_DROPPED` by default)
+    pub charge_dropped: bool,
+    /// The approx fuel computation scales the cost of a slice by this for each loop around it
+    /// (`LOOP_DEPTH_MULTIPLIER` by default)
+    pub loop_depth_multiplier: u64,
+    /// Constants that the generated code replays at least this many times are computed once into a
+    /// shared local (`DEDUP_CONST_MIN_USES` by default, `usize::MAX` turns this off)
+    pub dedup_const_min_uses: usize,
+    /// The passes that reduce the max slices to the min slices (`REDUCTIONS` by default, see `ReductionPass`)
+    pub reductions: BTreeSet<ReductionPass>,
+    /// Runs on each generated module after codegen and before it's encoded, e.g. to add exports,
+    /// imports or custom sections that an embedder needs (`run_analysis` and `do_analysis` only)
+    pub post_process: Option<fn(&mut Module)>
+}
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        Self {
+            sidecar_path: None,
+            output_budget: None,
+            features: None,
+            comp_type: CompType::default(),
+            epoch_funcs: vec![],
+            init_fuel: None,
+            on_out_of_fuel: false,
+            cost_model: Box::new(CostModel::default()),
+            block_wrapper: BlockWrapper::default(),
+            dispatch: false,
+            export_static_costs: false,
+            no_codegen: false,
+            emit: Emit::default(),
+            variants: SliceVariants::default(),
+            verbosity: Verbosity::default(),
+            granularity: None,
+            slice_at: vec![],
+            sinks: vec![],
+            replay_pure_calls: false,
+            mutation_sinks: BTreeSet::new(),
+            trap_sinks: false,
+            state_section: false,
+            checkpoints: false,
+            report_json: None,
+            entry: None,
+            baseline: None,
+            keep_going: false,
+            strict: false,
+            only_funcs: None,
+            charge_dropped: CHARGE_DROPPED,
+            loop_depth_multiplier: LOOP_DEPTH_MULTIPLIER,
+            dedup_const_min_uses: DEDUP_CONST_MIN_USES,
+            reductions: REDUCTIONS.iter().copied().collect(),
+            post_process: None
+        }
+    }
+}
+impl AnalysisConfig {
+    /// How the generated function of `fid` computes the fuel
+    pub fn comp_type_of(&self, fid: u32) -> CompType {
+        if self.epoch_funcs.contains(&fid) { CompType::Epoch } else { self.comp_type }
+    }
+
+    pub fn comp_type(mut self, comp_type: CompType) -> Self {
+        self.comp_type = comp_type;
+        self
+    }
+
+    /// Use the `Epoch` comp type for `fid`, whatever the `comp_type`
+    pub fn epoch_func(mut self, fid: u32) -> Self {
+        self.epoch_funcs.push(fid);
+        self
+    }
+
+    pub fn init_fuel(mut self, init_fuel: u64) -> Self {
+        self.init_fuel = Some(init_fuel);
+        self
+    }
+
+    pub fn on_out_of_fuel(mut self, on_out_of_fuel: bool) -> Self {
+        self.on_out_of_fuel = on_out_of_fuel;
+        self
+    }
+
+    pub fn cost_model(mut self, cost_model: impl OpCostModel + 'static) -> Self {
+        self.cost_model = Box::new(cost_model);
+        self
+    }
+
+    pub fn block_wrapper(mut self, block_wrapper: BlockWrapper) -> Self {
+        self.block_wrapper = block_wrapper;
+        self
+    }
+
+    pub fn granularity(mut self, granularity: u64) -> Self {
+        self.granularity = Some(granularity);
+        self
+    }
+
+    /// Also slice backward from this instruction, on top of the control flow (the default sinks)
+    pub fn slice_at(mut self, criterion: SliceCriterion) -> Self {
+        self.slice_at.push(criterion);
+        self
+    }
+
+    pub fn sink(mut self, detector: impl SinkDetector + 'static) -> Self {
+        self.sinks.push(Box::new(detector));
+        self
+    }
+
+    pub fn replay_pure_calls(mut self, replay_pure_calls: bool) -> Self {
+        self.replay_pure_calls = replay_pure_calls;
+        self
+    }
+
+    /// Slice the values written to the global `gid` (see `mutation_sinks`)
+    pub fn mutation_sink(mut self, gid: u32) -> Self {
+        self.mutation_sinks.insert(gid);
+        self
+    }
+
+    /// Slice the operands of the ops that may trap on the state (see `trap_sinks`)
+    pub fn trap_sinks(mut self, trap_sinks: bool) -> Self {
+        self.trap_sinks = trap_sinks;
+        self
+    }
+
+    /// Only analyze the functions reachable from the exported function `export`
+    pub fn entry(mut self, export: &str) -> Self {
+        self.entry = Some(export.to_string());
+        self
+    }
+
+    /// Only analyze the function `fid` (and the others passed to this)
+    pub fn only_func(mut self, fid: u32) -> Self {
+        self.only_funcs.get_or_insert_with(BTreeSet::new).insert(fid);
+        self
+    }
+
+    pub fn charge_dropped(mut self, charge_dropped: bool) -> Self {
+        self.charge_dropped = charge_dropped;
+        self
+    }
+
+    pub fn loop_depth_multiplier(mut self, multiplier: u64) -> Self {
+        self.loop_depth_multiplier = multiplier;
+        self
+    }
+
+    pub fn dedup_const_min_uses(mut self, min_uses: usize) -> Self {
+        self.dedup_const_min_uses = min_uses;
+        self
+    }
+
+    /// Embed the requested state as a custom section (see `state_section`)
+    pub fn state_section(mut self, state_section: bool) -> Self {
+        self.state_section = state_section;
+        self
+    }
+
+    /// Post-process the generated modules before they're encoded (see `post_process`)
+    pub fn post_process(mut self, hook: fn(&mut Module)) -> Self {
+        self.post_process = Some(hook);
+        self
+    }
+
+    /// Run the reduction `pass` or not (see `reductions`)
+    pub fn reduction(mut self, pass: ReductionPass, enabled: bool) -> Self {
+        if enabled {
+            self.reductions.insert(pass);
+        } else {
+            self.reductions.remove(&pass);
+        }
+        self
+    }
+
+    pub fn variants(mut self, variants: SliceVariants) -> Self {
+        self.variants = variants;
+        self
+    }
+
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The functions to analyze: the ones reachable from the `entry` (`from_entry`) that are also in `only_funcs`
+    pub(crate) fn restrict(&self, from_entry: Option<BTreeSet<u32>>) -> Option<BTreeSet<u32>> {
+        match (from_entry, &self.only_funcs) {
+            (Some(from_entry), Some(only)) => Some(from_entry.intersection(only).copied().collect()),
+            (from_entry, only) => from_entry.or_else(|| only.clone())
+        }
+    }
+}
+
+/// Parses a comma-separated list of the Wasm proposals that the parser accepts, e.g. `wasm2,-simd,+tail-call`:
+/// - `default`/`wasm1`/`wasm2`/`wasm3`: start over from that feature set (the parser's defaults otherwise)
+/// - `name` or `+name`: accept modules using the proposal (named as in wasmparser, e.g. `reference-types`)
+/// - `-name`: reject modules using the proposal
+pub fn parse_features(spec: &str) -> anyhow::Result<WasmFeatures> {
+    let mut features = WasmFeatures::default();
+    for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        match item {
+            "default" => features = WasmFeatures::default(),
+            "wasm1" => features = WasmFeatures::WASM1,
+            "wasm2" => features = WasmFeatures::WASM2,
+            "wasm3" => features = WasmFeatures::WASM3,
+            _ => {
+                let (enable, name) = match item.strip_prefix('-') {
+                    Some(name) => (false, name),
+                    None => (true, item.strip_prefix('+').unwrap_or(item))
+                };
+                let Some(feature) = WasmFeatures::from_name(&name.to_uppercase().replace('-', "_")) else {
+                    bail!("Unknown Wasm feature: {name}");
+                };
+                features.set(feature, enable);
+            }
+        }
+    }
+    Ok(features)
+}
+
+/// Where the generated modules for `output` are written: `out/foo.wasm` -> (`out/foo-max.wasm`, `out/foo-min.wasm`)
+/// `-` writes the generated module to stdout, only a single variant can be generated then.
+pub fn output_paths(output: &Path) -> (String, String) {
+    if output == Path::new(STDIO_PATH) {
+        return (STDIO_PATH.to_string(), STDIO_PATH.to_string());
+    }
+    let stem = output.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = output.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or("wasm".to_string());
+    let path = |sty: &str| output.with_file_name(format!("{stem}-{sty}.{ext}")).to_string_lossy().to_string();
+    (path("max"), path("min"))
+}
+
 /// Compute backward slice of values that feed control-flow ops inside a function body.
 /// - `num_params`: number of parameters (so we can mark `local.get` of param indices as Param).
-pub fn do_analysis<W: WriteColor>(mut out: W, wasm_bytes: &[u8], out_max_path: &str, out_min_path: &str) -> anyhow::Result<()> {
-    // Read app Wasm into Wirm module
-    let mut wasm = Module::parse(wasm_bytes, false, true).unwrap();
+/// - `config`: the optional outputs and checks of the analysis (see `AnalysisConfig`).
+#[cfg(feature = "fs")]
+pub fn do_analysis<W: WriteColor>(out: W, wasm_bytes: &[u8], out_max_path: &str, out_min_path: &str, config: &AnalysisConfig) -> anyhow::Result<()> {
+    if config.verbosity == Verbosity::Quiet {
+        // nothing is reported, only the generated modules (and the sidecar) are written
+        gen_fuel_modules(NoColor::new(io::sink()), wasm_bytes, out_max_path, out_min_path, config)?;
+        return Ok(());
+    }
+    gen_fuel_modules(out, wasm_bytes, out_max_path, out_min_path, config)?;
+    Ok(())
+}
 
-    let func_taints = analyze(&mut wasm);
+/// Run the pipeline on the module at `wasm_path` as `do_analysis` does, then again every time that
+/// `watched` changes (see `watch::Watcher`), only reporting what changed since the previous run.
+/// Runs until it's interrupted.
+#[cfg(feature = "fs")]
+pub fn watch_analysis<W: WriteColor>(mut out: W, wasm_path: &Path, watched: &Path, out_max_path: &str, out_min_path: &str, config: &AnalysisConfig) -> anyhow::Result<()> {
+    let mut watcher = Watcher::new(wasm_path, watched, out_max_path, out_min_path, config);
+    writeln!(out, "Watching {} for changes (Ctrl-C to stop)", watched.display())?;
+    loop {
+        if let Some(event) = watcher.poll() {
+            flush_watch_event(&mut ColorReporter::new(&mut out), &wasm_path.display().to_string(), &event)?;
+        }
+        std::thread::sleep(WATCH_INTERVAL);
+    }
+}
 
-    // create the slices
-    let mut slices = slice_program(&func_taints, &wasm);
-    save_structure(&mut slices, &func_taints, &wasm);
-    reduce_slice(&mut slices, &func_taints, &wasm);
+/// The results of `run_analysis`: what `do_analysis` would write to disk, kept in memory.
+#[derive(Debug)]
+pub struct AnalysisOutput {
+    /// The encoded max variant (empty with `no_codegen`, or if `variants` skips it)
+    pub max_wasm: Vec<u8>,
+    /// The encoded min variant (empty with `no_codegen`, or if `variants` skips it)
+    pub min_wasm: Vec<u8>,
+    /// What `--report-json` writes
+    pub report: JsonReport,
+    /// What `--sidecar` writes
+    pub sidecar: Sidecar,
+    /// The functions skipped with `keep_going`, and why
+    pub skipped: Vec<(u32, String)>
+}
+
+impl AnalysisOutput {
+    /// The `ordinal`-th slice of function `fid`: 0 is the function's own slice, then its loops in
+    /// the order of the body
+    pub fn slice(&self, fid: u32, ordinal: usize) -> Option<SliceHandle<'_>> {
+        self.report.slice(fid, ordinal)
+    }
+
+    /// The functions generated for function `fid` in the max and min modules
+    pub fn generated_for(&self, fid: u32) -> Option<GeneratedHandles<'_>> {
+        self.report.generated_for(fid)
+    }
+}
+
+/// `do_analysis` without touching the filesystem: the generated modules are returned encoded,
+/// along with the reports. The output paths, `emit` and the output budget of the `config` don't apply.
+pub fn run_analysis(wasm_bytes: &[u8], config: &AnalysisConfig) -> anyhow::Result<AnalysisOutput> {
+    gen_outputs(NoColor::new(io::sink()), wasm_bytes, config)
+}
+
+/// Runs the pipeline, reports on it to `out` and writes the outputs, returns the JSON report
+#[cfg(feature = "fs")]
+pub(crate) fn gen_fuel_modules<W: WriteColor>(mut out: W, wasm_bytes: &[u8], out_max_path: &str, out_min_path: &str, config: &AnalysisConfig) -> anyhow::Result<JsonReport> {
+    if !config.no_codegen && config.variants.max() && config.variants.min() && out_max_path == STDIO_PATH && out_min_path == STDIO_PATH {
+        bail!("Stdout only takes a single generated module, pick one with `--slice max` or `--slice min`, or write the other one to a file");
+    }
+    let AnalysisOutput { max_wasm: bytes_max, min_wasm: bytes_min, report, sidecar, .. } = gen_outputs(&mut out, wasm_bytes, config)?;
+    if config.no_codegen {
+        flush_json_report(&mut ColorReporter::new(&mut out), &report, config)?;
+        return Ok(report);
+    }
+
+    if let Some(budget) = &config.output_budget {
+        // Check the generated modules against the budget before writing them out
+        let over_max = config.variants.max() && flush_size_report(&mut ColorReporter::new(&mut out), "max", &SizeReport::new(&bytes_max)?, budget)?;
+        let over_min = config.variants.min() && flush_size_report(&mut ColorReporter::new(&mut out), "min", &SizeReport::new(&bytes_min)?, budget)?;
+        if budget.fail && (over_max || over_min) {
+            bail!("The generated Wasm exceeds the output size budget of {} bytes", budget.max_bytes);
+        }
+    }
+
+    // Write the generated wasm to the output file
+    if config.variants.max() {
+        write_bytes(&bytes_max, out_max_path, config.emit)?;
+    }
+    if config.variants.min() {
+        write_bytes(&bytes_min, out_min_path, config.emit)?;
+    }
+
+    if let Some(sidecar_path) = &config.sidecar_path {
+        write_sidecar(sidecar_path, &sidecar)?;
+    }
+    flush_json_report(&mut ColorReporter::new(&mut out), &report, config)?;
+    Ok(report)
+}
+
+/// Runs the pipeline and reports on it to `out`, but doesn't write anything
+fn gen_outputs<W: WriteColor>(mut out: W, wasm_bytes: &[u8], config: &AnalysisConfig) -> anyhow::Result<AnalysisOutput> {
+    if config.on_out_of_fuel && config.init_fuel.is_none() {
+        bail!("The out-of-fuel hook needs an initial fuel to count down from");
+    }
+    if config.init_fuel.is_some_and(|init_fuel| i64::try_from(init_fuel).is_err()) {
+        bail!("The initial fuel must fit the i64 that the generated functions count with (at most {})", i64::MAX);
+    }
+    if let Some(features) = config.features {
+        check_features(wasm_bytes, features)?;
+    }
+    // Read app Wasm into Wirm module
+    let mut wasm = parse_module(wasm_bytes)?;
+    let only = match &config.entry {
+        Some(export) => {
+            let closure = reachable_from(wasm_bytes, export)?;
+            writeln!(out, "Analyzing the {} functions reachable from \"{export}\" ({} excluded)", closure.fids.len(), closure.num_excluded)?;
+            Some(closure.fids)
+        }
+        None => None
+    };
+    let only = config.restrict(only);
+    let (func_taints, skipped) = analyze_funcs(&mut wasm, config.keep_going)?;
+    flush_skipped(&mut ColorReporter::new(&mut out), &skipped)?;
+    let (func_taints, mut slices) = slice_funcs(&mut wasm, func_taints, &config.slice_at, &config.sinks, &config.mutation_sinks, config.trap_sinks, config.replay_pure_calls, &config.reductions, only.as_ref())?;
+    if config.strict {
+        reject_approximations(&func_taints, &wasm)?;
+    }
+    if config.no_codegen {
+        // dry run: only report the slices (without their fuel points)
+        let no_costs = vec![BTreeMap::new(); func_taints.len()];
+        flush_report(&mut ColorReporter::new(&mut out), config.verbosity, &slices, &func_taints, &no_costs, &[], &wasm)?;
+        return Ok(AnalysisOutput {
+            max_wasm: vec![],
+            min_wasm: vec![],
+            report: JsonReport::new(&slices, &func_taints, &no_costs, &[], &BTreeMap::new(), &BTreeMap::new()),
+            sidecar: Sidecar::new(&slices, &no_costs, &BTreeMap::new(), &BTreeMap::new()),
+            skipped: describe_skipped(&skipped)
+        });
+    }
 
     // MAX: generate code for the slices (leave placeholders for the cost calculation)
     let mut gen_wasm_max = Module::default();
-    let CodeGenResult { cost_maps, func_map: func_map_max } = codegen_max(&FUEL_COMPUTATION, &mut slices, &func_taints, &wasm, &mut gen_wasm_max);
+    let mut max = if config.variants.max() {
+        codegen_max(config, &mut slices, &func_taints, &wasm, &mut gen_wasm_max).map_err(|err| in_phase("code generation", err, &wasm))?
+    } else {
+        CodeGenResult::default()
+    };
 
     // MIN: generate code for the slices (leave placeholders for the cost calculation)
     let mut gen_wasm_min = Module::default();
-    let CodeGenResult { func_map: func_map_min, .. } = codegen_min(&FUEL_COMPUTATION, &mut slices, &func_taints, &wasm, &mut gen_wasm_min);
+    let mut min = if config.variants.min() {
+        codegen_min(config, &mut slices, &func_taints, &wasm, &mut gen_wasm_min).map_err(|err| in_phase("code generation", err, &wasm))?
+    } else {
+        CodeGenResult::default()
+    };
+
+    let names = DebugNames::parse(wasm_bytes);
+    name_params(&mut max.func_map, &names, &wasm)?;
+    name_params(&mut min.func_map, &names, &wasm)?;
 
     // Flush state
     // cost maps are the same between max/min
-    flush_slices(&mut out, wasm.globals.len(), &slices, &func_taints, &cost_maps, &wasm)?;
+    let CodeGenResult { cost_maps, charge_maps, .. } = if config.variants.max() { &max } else { &min };
+    let formulas = cost_formulas(charge_maps, &func_taints, &wasm)?;
+    flush_report(&mut ColorReporter::new(&mut out), config.verbosity, &slices, &func_taints, cost_maps, &formulas, &wasm)?;
+
+    if config.variants.max() {
+        flush_fid_mapping(&mut ColorReporter::new(&mut out), "max", &max.func_map, &names, &wasm)?;
+    }
+    if config.variants == SliceVariants::Both {
+        writeln!(out)?;
+    }
+    if config.variants.min() {
+        flush_fid_mapping(&mut ColorReporter::new(&mut out), "min", &min.func_map, &names, &wasm)?;
+    }
 
-    flush_fid_mapping(&mut out, "max", &func_map_max)?;
-    writeln!(out)?;
-    flush_fid_mapping(&mut out, "min", &func_map_min)?;
+    // Check that the placement of the fuel points is sound, the epoch metering charges ahead
+    // of the control flow on purpose (but still charges every instruction once)
+    let checked = |violations: Vec<FuelPointViolation>| -> Vec<FuelPointViolation> {
+        violations.into_iter()
+            .filter(|violation| !(matches!(violation.kind, ViolationKind::Straddle) && matches!(config.comp_type_of(violation.fid), CompType::Epoch | CompType::BackEdge)))
+            .collect()
+    };
+    // (a variant that isn't generated has no charges to check)
+    flush_violations(&mut ColorReporter::new(&mut out), "max", &checked(verify_fuel_points(&func_taints, &max.charge_maps, &wasm)?))?;
+    flush_violations(&mut ColorReporter::new(&mut out), "min", &checked(verify_fuel_points(&func_taints, &min.charge_maps, &wasm)?))?;
 
-    // Write the generated wasm to the output file
-    write_bytes(&mut out, &gen_wasm_max.encode(), out_max_path)?;
-    write_bytes(&mut out, &gen_wasm_min.encode(), out_min_path)?;
+    let encode = |gen_wasm: &mut Module, func_map: &BTreeMap<u32, Vec<GeneratedFunc>>| -> anyhow::Result<Vec<u8>> {
+        if let Some(hook) = config.post_process {
+            hook(gen_wasm);
+        }
+        let mut bytes = with_name_section(&gen_wasm.encode(), func_map.values().flatten())?;
+        if config.state_section {
+            append_custom_section(&mut bytes, STATE_SECTION, &encode_state_layout(func_map.values().flatten())?);
+        }
+        Ok(bytes)
+    };
+    Ok(AnalysisOutput {
+        max_wasm: if config.variants.max() { encode(&mut gen_wasm_max, &max.func_map)? } else { vec![] },
+        min_wasm: if config.variants.min() { encode(&mut gen_wasm_min, &min.func_map)? } else { vec![] },
+        report: JsonReport::new(&slices, &func_taints, cost_maps, &formulas, &max.func_map, &min.func_map),
+        sidecar: Sidecar::new(&slices, cost_maps, &max.func_map, &min.func_map),
+        skipped: describe_skipped(&skipped)
+    })
+}
+
+fn describe_skipped(skipped: &SkippedFuncs) -> Vec<(u32, String)> {
+    skipped.iter().map(|(fid, err)| (*fid, format!("{err:#}"))).collect()
+}
+
+/// Explain the charge at a fuel point: lists exactly which instructions' costs
+/// were accumulated into the charge at `instr_idx` of function `fid` (sliced and charged with the `config`).
+pub fn explain_fuel_point<W: WriteColor>(mut out: W, wasm_bytes: &[u8], fid: u32, instr_idx: usize, config: &AnalysisConfig) -> anyhow::Result<()> {
+    let (wasm, func_taints, mut slices) = slices_with(wasm_bytes, config)?;
+
+    let mut gen_wasm = Module::default();
+    let CodeGenResult { charge_maps, .. } = codegen_max(config, &mut slices, &func_taints, &wasm, &mut gen_wasm).map_err(|err| in_phase("code generation", err, &wasm))?;
+
+    let Some(func_idx) = func_taints.iter().position(|func| func.fid == fid) else {
+        bail!("Function #{fid} is not a local function");
+    };
+    let Some(charged) = charge_maps[func_idx].get(&instr_idx) else {
+        bail!("There is no fuel point at function #{fid} @{instr_idx}");
+    };
+    flush_fuel_point(&mut ColorReporter::new(&mut out), fid, instr_idx, charged, &wasm)?;
+
+    let formulas: BTreeMap<u32, CostExpr> = zip(func_taints.iter().map(|func| func.fid), cost_formulas(&charge_maps, &func_taints, &wasm)?).collect();
+    let formula = &formulas[&fid];
+    writeln!(out, "\ncost of function #{fid}: {formula}")?;
+    let composed = formula.compose(&formulas);
+    if composed != *formula {
+        writeln!(out, "with its callees: {composed}")?;
+    }
+    Ok(())
+}
+
+/// Run the analysis and the (max) codegen without writing anything, to query the static costs
+/// of the module (see `CodeGenResult::static_cost_between`).
+pub fn static_costs(wasm_bytes: &[u8], config: &AnalysisConfig) -> anyhow::Result<CodeGenResult> {
+    let (wasm, func_taints, mut slices) = slices_with(wasm_bytes, config)?;
+    let mut gen_wasm = Module::default();
+    codegen_max(config, &mut slices, &func_taints, &wasm, &mut gen_wasm).map_err(|err| in_phase("code generation", err, &wasm))
+}
+
+/// Run the analysis and create the slices of the functions that the `config` picks (with its
+/// criteria and sinks), without reporting anything
+fn slices_with<'a>(wasm_bytes: &'a [u8], config: &AnalysisConfig) -> anyhow::Result<(Module<'a>, Vec<FuncState>, Vec<SliceResult>)> {
+    let mut wasm = parse_module(wasm_bytes)?;
+    let only = config.entry.as_ref().map(|export| reachable_from(wasm_bytes, export)).transpose()?;
+    let only = config.restrict(only.map(|closure| closure.fids));
+    let (func_taints, _) = analyze_funcs(&mut wasm, config.keep_going)?;
+    let (func_taints, slices) = slice_funcs(&mut wasm, func_taints, &config.slice_at, &config.sinks, &config.mutation_sinks, config.trap_sinks, config.replay_pure_calls, &config.reductions, only.as_ref())?;
+    if config.strict {
+        reject_approximations(&func_taints, &wasm)?;
+    }
+    Ok((wasm, func_taints, slices))
+}
+
+/// Report the static cost of the straight-line instructions `from_idx..to_idx` of function `fid`.
+pub fn report_cost_between<W: Write>(mut out: W, wasm_bytes: &[u8], fid: u32, from_idx: usize, to_idx: usize, config: &AnalysisConfig) -> anyhow::Result<()> {
+    let Some(cost) = static_costs(wasm_bytes, config)?.static_cost_between(fid, from_idx, to_idx) else {
+        bail!("Function #{fid} @{from_idx}..{to_idx} is not straight-line code (or not a local function)");
+    };
+    writeln!(out, "{cost}")?;
+    Ok(())
+}
+
+/// Write the fuel points as a patch file (`fid, byte_offset, delta` per line) for external
+/// binary-rewriting tools that only want to use this crate as the analysis engine, or in the
+/// JSON `format` (see `json_description`). The functions are sliced and charged with the `config`.
+#[cfg(feature = "fs")]
+pub fn write_patch<W: Write>(mut out: W, wasm_bytes: &[u8], out_path: &str, format: PatchFormat, config: &AnalysisConfig) -> anyhow::Result<()> {
+    let (wasm, func_taints, mut slices) = slices_with(wasm_bytes, config)?;
+
+    let mut gen_wasm = Module::default();
+    let CodeGenResult { cost_maps, .. } = codegen_max(config, &mut slices, &func_taints, &wasm, &mut gen_wasm).map_err(|err| in_phase("code generation", err, &wasm))?;
+
+    let patches = gen_patches(wasm_bytes, zip(func_taints.iter().map(|func| func.fid), &cost_maps))?;
+    try_path(&out_path.to_string())?;
+    let contents = match format {
+        PatchFormat::Offsets => patch_file(&patches),
+        PatchFormat::Json => json_description(wasm_bytes, &patches)?
+    };
+    std::fs::write(out_path, contents).with_context(|| format!("Failed to write the patch file {out_path}"))?;
+    writeln!(out, "Wrote {} fuel points to {}", patches.len(), out_path)?;
+    Ok(())
+}
+
+/// Meter the module in place (see `meter::meter_in_place`): the instrumented module counts the
+/// `config`'s `init_fuel` down in the exported `whamm_fuel` global, with the `config`'s cost model,
+/// and traps (or calls the out-of-fuel hook) when a charge exhausts it.
+pub fn meter_module(wasm_bytes: &[u8], config: &AnalysisConfig) -> anyhow::Result<Vec<u8>> {
+    let (wasm, func_taints, mut slices) = slices_with(wasm_bytes, config)?;
+    let mut gen_wasm = Module::default();
+    let result = codegen_max(config, &mut slices, &func_taints, &wasm, &mut gen_wasm).map_err(|err| in_phase("code generation", err, &wasm))?;
+
+    // the analysis took the module mutably, instrument a fresh copy
+    let mut metered = parse_module(wasm_bytes)?;
+    meter_in_place(&mut metered, &result, config)?;
+    Ok(metered.encode())
+}
+
+/// Meter the module in place (see `meter_module`) and write the instrumented module to `out_path`.
+#[cfg(feature = "fs")]
+pub fn write_metered<W: Write>(mut out: W, wasm_bytes: &[u8], out_path: &str, config: &AnalysisConfig) -> anyhow::Result<()> {
+    let metered = meter_module(wasm_bytes, config)?;
+    try_path(&out_path.to_string())?;
+    std::fs::write(out_path, metered).with_context(|| format!("Failed to write the metered module {out_path}"))?;
+    writeln!(out, "Wrote the metered module to {out_path}")?;
+    Ok(())
+}
+
+/// Analyze the module `name` (with the default config) and summarize its functions for the index page
+/// of `html::render_index`
+pub fn summarize_module(name: &str, wasm_bytes: &[u8]) -> anyhow::Result<ModuleSummary> {
+    let output = run_analysis(wasm_bytes, &AnalysisConfig::default())?;
+    Ok(ModuleSummary::new(name, &output.report, &DebugNames::parse(wasm_bytes)))
+}
+
+/// Analyze each of the `modules` (name, bytes), e.g. the modules of a release, and write the page to
+/// browse all of their functions and costs to `out_path` (see `html::render_index`)
+#[cfg(feature = "fs")]
+pub fn write_html_index<W: Write>(mut out: W, modules: &[(String, Vec<u8>)], out_path: &str) -> anyhow::Result<()> {
+    let summaries = modules.iter()
+        .map(|(name, wasm_bytes)| summarize_module(name, wasm_bytes).with_context(|| format!("Failed to analyze the module {name}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    try_path(&out_path.to_string())?;
+    std::fs::write(out_path, render_index(&summaries)).with_context(|| format!("Failed to write the index page {out_path}"))?;
+    let num_funcs: usize = summaries.iter().map(|summary| summary.functions.len()).sum();
+    writeln!(out, "Wrote {} functions of {} modules to {}", num_funcs, summaries.len(), out_path)?;
+    Ok(())
+}
+
+/// Check that metering the `original` module in place (e.g. applying its patch file) kept its
+/// imports, exports, start function and data segments (see `preserve::check_preserved`).
+/// Fails if the `instrumented` module changed them.
+pub fn check_instrumented<W: Write>(mut out: W, original: &[u8], instrumented: &[u8]) -> anyhow::Result<()> {
+    let diffs = check_preserved(original, instrumented)?;
+    if !diffs.is_empty() {
+        for diff in diffs.iter() {
+            writeln!(out, "{diff}")?;
+        }
+        bail!("The instrumented module changed the original module ({} differences)", diffs.len());
+    }
+    writeln!(out, "The instrumented module preserves the imports, exports, start function and data segments of the original module")?;
+    Ok(())
+}
+
+/// Compare the generated module `new` to a previous one (`old`), see `artifact::compare_artifacts`:
+/// reports the generated functions whose signature, costs or requested state changed.
+pub fn report_artifact_deltas<W: WriteColor>(mut out: W, old: &[u8], new: &[u8]) -> anyhow::Result<()> {
+    let (deltas, num_funcs) = compare_artifacts(old, new)?;
+    flush_artifact_deltas(&mut ColorReporter::new(&mut out), &deltas, num_funcs)?;
+    Ok(())
+}
+
+/// Write a gas-cost certificate (JSON) for the module to `out_path`, sliced and charged with the `config`.
+#[cfg(feature = "fs")]
+pub fn write_certificate<W: Write>(mut out: W, wasm_bytes: &[u8], out_path: &str, config: &AnalysisConfig) -> anyhow::Result<()> {
+    let cert = gen_certificate(wasm_bytes, config)?;
+    try_path(&out_path.to_string())?;
+    std::fs::write(out_path, serde_json::to_string_pretty(&cert)?).with_context(|| format!("Failed to write the certificate {out_path}"))?;
+    writeln!(out, "Wrote certificate for {} functions to {}", cert.functions.len(), out_path)?;
+    Ok(())
+}
+
+/// Recompute the certificate for the module with the `config` (the one it was written with) and
+/// compare it against the one at `cert_path`.
+#[cfg(feature = "fs")]
+pub fn verify_certificate<W: Write>(mut out: W, wasm_bytes: &[u8], cert_path: &str, config: &AnalysisConfig) -> anyhow::Result<()> {
+    let certified = read_certificate(cert_path)?;
+    let mismatches = certified.diff(&gen_certificate(wasm_bytes, config)?);
+    if !mismatches.is_empty() {
+        for mismatch in mismatches.iter() {
+            writeln!(out, "{mismatch}")?;
+        }
+        bail!("Certificate {cert_path} does not match the module ({} mismatches)", mismatches.len());
+    }
+    writeln!(out, "Certificate {cert_path} matches the module")?;
+    Ok(())
+}
+
+#[cfg(feature = "fs")]
+pub fn read_certificate(cert_path: &str) -> anyhow::Result<FuelCertificate> {
+    let certified = std::fs::read_to_string(cert_path).with_context(|| format!("Failed to read the certificate {cert_path}"))?;
+    serde_json::from_str(&certified).with_context(|| format!("Malformed certificate {cert_path}"))
+}
+
+#[cfg(feature = "fs")]
+pub(crate) fn gen_certificate(wasm_bytes: &[u8], config: &AnalysisConfig) -> anyhow::Result<FuelCertificate> {
+    let (wasm, func_taints, mut slices) = slices_with(wasm_bytes, config)?;
+
+    let mut gen_wasm = Module::default();
+    let CodeGenResult { cost_maps, .. } = codegen_max(config, &mut slices, &func_taints, &wasm, &mut gen_wasm).map_err(|err| in_phase("code generation", err, &wasm))?;
+    Ok(FuelCertificate::new(wasm_bytes, &func_taints, &cost_maps, config, &wasm)?)
+}
+
+/// Report the breakdown of each slice by instruction class, and the share of the cost
+/// that each class contributes (sliced and charged with the `config`).
+pub fn report_stats<W: WriteColor>(mut out: W, wasm_bytes: &[u8], config: &AnalysisConfig) -> anyhow::Result<()> {
+    let (wasm, func_taints, mut slices) = slices_with(wasm_bytes, config)?;
+
+    let mut gen_wasm = Module::default();
+    let CodeGenResult { charge_maps, .. } = codegen_max(config, &mut slices, &func_taints, &wasm, &mut gen_wasm).map_err(|err| in_phase("code generation", err, &wasm))?;
+    flush_stats(&mut ColorReporter::new(&mut out), &slices, &charge_maps, &wasm)?;
     Ok(())
 }
 
-fn write_bytes<W: Write>(mut out: W, bytes: &[u8], out_path: &str) -> anyhow::Result<()> {
-    writeln!(out, "\n====================")?;
-    writeln!(out, "==== FLUSH WASM ====")?;
+/// Generate the fuel computations of the module with each metering strategy (see `strategies::STRATEGIES`),
+/// to compare what they cost and how accurate they are. Everything but the strategy comes from the `config`
+/// (e.g. its cost model), each strategy meters every function (the `epoch_funcs` don't apply).
+pub fn compare_strategies(wasm_bytes: &[u8], mut config: AnalysisConfig) -> anyhow::Result<Vec<StrategyReport>> {
+    let (wasm, func_taints, mut slices) = slices_with(wasm_bytes, &config)?;
+
+    config.epoch_funcs.clear();
+    let mut exact_formulas = vec![];
+    let mut reports = vec![];
+    for comp_type in STRATEGIES {
+        config.comp_type = comp_type;
+        let mut gen_wasm = Module::default();
+        let CodeGenResult { cost_maps, charge_maps, func_map, .. } = codegen_max(&config, &mut slices, &func_taints, &wasm, &mut gen_wasm).map_err(|err| in_phase("code generation", err, &wasm))?;
+        if matches!(comp_type, CompType::Exact) {
+            // the accuracy is measured against the exact charges, of each path
+            exact_formulas = zip(&func_taints, &charge_maps).map(|(func, charges)| {
+                Ok(CostExpr::paths_of_func(func.fid, local_ops(func.fid, &wasm)?, charges))
+            }).collect::<Result<_, AnalysisError>>()?;
+        }
+        reports.push(StrategyReport::new(comp_type, gen_wasm.encode().len(), &cost_maps, &func_map, &exact_formulas, config.loop_depth_multiplier));
+    }
+    Ok(reports)
+}
+
+/// Report the code size, charge sites, requested state and accuracy of each metering strategy.
+pub fn report_strategies<W: WriteColor>(out: W, wasm_bytes: &[u8], config: AnalysisConfig) -> anyhow::Result<()> {
+    let reports = compare_strategies(wasm_bytes, config)?;
+    let mut out = ColorReporter::new(out);
     writeln!(out, "====================")?;
+    writeln!(out, "==== STRATEGIES ====")?;
+    writeln!(out, "====================")?;
+    writeln!(out, "strategy\tsize (overhead)\tcharge sites\tstate\tmax overcharge")?;
+    for report in reports.iter() {
+        let overhead = report.code_size as f64 * 100.0 / wasm_bytes.len().max(1) as f64;
+        write!(out, "{:<8}\t{} B ({overhead:.1}%)\t{}\t\t{}\t", report.comp_type, report.code_size, report.charge_sites, report.requested_state)?;
+        out.styled(&format!(">>{}\n", report.max_overcharge), Style::Cost)?;
+    }
+    writeln!(out, "\nsize: the generated module (overhead: relative to the original module), state: the values the host passes in")?;
+    writeln!(out, "max overcharge: the most that a pass through the bodies (each loop body once) is overcharged, compared to exact")?;
+    Ok(())
+}
 
-    try_path(&out_path.to_string());
-    if let Err(e) = std::fs::write(out_path, bytes) {
-        unreachable!(
-            "Failed to dump instrumented wasm to {} from error: {}",
-            &out_path.to_string(), e
-        )
+/// Differential audit of the reductions: check that the min slice of each function can compute
+/// every cost that its max slice computes (see `audit::audit`). Fails if they diverge.
+/// The slices are picked, reduced and charged with the `analysis` config (e.g. its reductions and cost model).
+#[cfg(feature = "audit")]
+pub fn report_audit<W: WriteColor>(mut out: W, wasm_bytes: &[u8], config: &AuditConfig, analysis: &AnalysisConfig) -> anyhow::Result<()> {
+    let (wasm, func_taints, mut slices) = slices_with(wasm_bytes, analysis)?;
+
+    let mut gen_wasm_max = Module::default();
+    let CodeGenResult { func_map: func_map_max, .. } = codegen_max(analysis, &mut slices, &func_taints, &wasm, &mut gen_wasm_max).map_err(|err| in_phase("code generation", err, &wasm))?;
+    let mut gen_wasm_min = Module::default();
+    let CodeGenResult { func_map: func_map_min, .. } = codegen_min(analysis, &mut slices, &func_taints, &wasm, &mut gen_wasm_min).map_err(|err| in_phase("code generation", err, &wasm))?;
+
+    let mut targets = Vec::new();
+    for (fid, generated) in func_map_max.iter() {
+        let body = local_ops(*fid, &wasm)?;
+        for max in generated.iter() {
+            let Some(min) = func_map_min.get(fid).and_then(|min| min.iter().find(|min| min.fname == max.fname)) else {
+                continue;
+            };
+            let mut decisions = BTreeMap::new();
+            for (instr_idx, req) in min.req_state.get(&StateType::Taken).unwrap().iter() {
+                let choices = match &body[*instr_idx] {
+                    Operator::BrTable { targets } => targets.len() as u64 + 1,
+                    _ => 2
+                };
+                for val in req.req_state.iter() {
+                    decisions.insert(val.gen_param_id(), choices);
+                }
+            }
+            let lengths = max.req_state.get(&StateType::Length).unwrap().iter()
+                .filter_map(|(instr_idx, req)| Some((
+                    req.req_state.first()?.gen_param_id(),
+                    min.req_state.get(&StateType::Length).unwrap().get(instr_idx)?.req_state.first()?.gen_param_id()
+                )))
+                .collect();
+            targets.push(AuditTarget { fname: max.fname.clone(), decisions, lengths });
+        }
+    }
+
+    let results = audit(&gen_wasm_max.encode(), &gen_wasm_min.encode(), &targets, config)?;
+    flush_audit(&mut ColorReporter::new(&mut out), &results)?;
+    let diverging = results.iter().filter(|res| res.is_confirmed_divergence()).count();
+    if diverging > 0 {
+        bail!("The min slices of {diverging} functions diverge from their max slices");
+    }
+    Ok(())
+}
+
+/// Bounded model check of the exact fuel: run the exact-mode generated function of each small
+/// loop-free function on every input from a bounded domain, against a simulation of the original
+/// function (see `bmc::model_check`). Fails if they disagree.
+#[cfg(feature = "bmc")]
+pub fn report_bmc<W: WriteColor>(mut out: W, wasm_bytes: &[u8]) -> anyhow::Result<()> {
+    let mut wasm = parse_module(wasm_bytes)?;
+    let (func_taints, mut slices) = gen_slices(&mut wasm, &[])?;
+
+    // the dropped computations are charged, as the original function runs them
+    let config = AnalysisConfig::default().comp_type(CompType::Exact).charge_dropped(true);
+    let mut gen_wasm = Module::default();
+    let CodeGenResult { func_map, .. } = codegen_max(&config, &mut slices, &func_taints, &wasm, &mut gen_wasm).map_err(|err| in_phase("code generation", err, &wasm))?;
+
+    let mut targets = Vec::new();
+    let mut results = Vec::new();
+    for (fid, generated) in func_map.iter() {
+        let body = local_ops(*fid, &wasm)?;
+        for func in generated.iter() {
+            match BmcTarget::new(func, body) {
+                Ok(target) => targets.push(target),
+                Err(reason) => results.push(FuncBmc { fname: func.fname.clone(), outcome: BmcOutcome::Skipped { reason } })
+            }
+        }
+    }
+
+    results.extend(model_check(&gen_wasm.encode(), &targets, config.cost_model.as_ref())?);
+    results.sort_by(|a, b| a.fname.cmp(&b.fname));
+    flush_bmc(&mut ColorReporter::new(&mut out), &results)?;
+    let mismatches = results.iter().filter(|res| matches!(res.outcome, BmcOutcome::Mismatch { .. } | BmcOutcome::Trapped { .. })).count();
+    if mismatches > 0 {
+        bail!("The exact fuel of {mismatches} functions doesn't match the cost of their paths (or traps)");
+    }
+    Ok(())
+}
+
+/// Only run the taint analysis: report the origins of the values consumed by each control instruction.
+pub fn report_taint<W: WriteColor>(mut out: W, wasm_bytes: &[u8]) -> anyhow::Result<()> {
+    let mut wasm = parse_module(wasm_bytes)?;
+    let func_taints = analyze(&mut wasm).map_err(|err| in_phase("the taint analysis", err, &wasm))?;
+    flush_taint(&mut ColorReporter::new(&mut out), &func_taints, &wasm)?;
+    Ok(())
+}
+
+/// Developer-facing: dump what the taint analysis recorded for every instruction (its kind and
+/// the origins of its inputs), to catch regressions at the analysis layer.
+pub fn dump_taint<W: WriteColor>(mut out: W, wasm_bytes: &[u8]) -> anyhow::Result<()> {
+    let mut wasm = parse_module(wasm_bytes)?;
+    let func_taints = analyze(&mut wasm).map_err(|err| in_phase("the taint analysis", err, &wasm))?;
+    flush_taint_dump(&mut ColorReporter::new(&mut out), &func_taints, &wasm)?;
+    Ok(())
+}
+
+/// Only create the slices: report them without generating code (so without their fuel points).
+pub fn report_slices<W: WriteColor>(out: W, wasm_bytes: &[u8]) -> anyhow::Result<()> {
+    render_slices(&mut ColorReporter::new(out), wasm_bytes)
+}
+
+/// `report_slices`, rendered by the `reporter` instead of to a terminal
+pub fn render_slices(reporter: &mut dyn Reporter, wasm_bytes: &[u8]) -> anyhow::Result<()> {
+    let mut wasm = parse_module(wasm_bytes)?;
+    let (func_taints, slices) = gen_slices(&mut wasm, &[])?;
+    let no_costs = vec![BTreeMap::new(); func_taints.len()];
+    flush_slices(reporter, wasm.globals.len(), &slices, &func_taints, &no_costs, &wasm)?;
+    Ok(())
+}
+
+/// Run the analysis and create the (reduced) slices for each function.
+pub(crate) fn gen_slices(wasm: &mut Module, criteria: &[SliceCriterion]) -> anyhow::Result<(Vec<FuncState>, Vec<SliceResult>)> {
+    let (func_taints, _) = analyze_funcs(wasm, false)?;
+    slice_funcs(wasm, func_taints, criteria, &[], &BTreeSet::new(), false, false, &REDUCTIONS.iter().copied().collect(), None)
+}
+
+/// The functions that the analysis skipped with `keep_going`: (fid, why)
+type SkippedFuncs = Vec<(u32, anyhow::Error)>;
+
+/// Run the taint analysis, with `keep_going` the functions it fails on are skipped (see `analyze_keep_going`)
+fn analyze_funcs(wasm: &mut Module, keep_going: bool) -> anyhow::Result<(Vec<FuncState>, SkippedFuncs)> {
+    if keep_going {
+        return Ok(analyze_keep_going(wasm));
+    }
+    let func_taints = analyze(wasm).map_err(|err| in_phase("the taint analysis", err, wasm))?;
+    Ok((func_taints, vec![]))
+}
+
+/// Run the taint analysis without failing on a single function: if it fails on the module, each
+/// local function is analyzed on its own and the ones it fails on (or panics on, e.g. an op that
+/// `stack_effects` doesn't support) are skipped. Returns the analyzed functions and the skipped
+/// ones (fid, why).
+fn analyze_keep_going(wasm: &mut Module) -> (Vec<FuncState>, SkippedFuncs) {
+    if let Ok(func_taints) = catch_failure(|| Ok(analyze(wasm)?)) {
+        return (func_taints, vec![]);
+    }
+    let local_fids: Vec<FunctionID> = (0..wasm.functions.iter().count() as u32).map(FunctionID)
+        .filter(|fid| wasm.functions.is_local(*fid))
+        .collect();
+    let mut func_taints = vec![];
+    let mut skipped = vec![];
+    for fid in local_fids.iter() {
+        let others: Vec<FunctionID> = local_fids.iter().filter(|other| *other != fid).copied().collect();
+        match catch_failure(|| analyze_except(wasm, &others, &mut NoVisitor).map_err(|err| in_phase("the taint analysis", err, wasm))) {
+            Ok(func) => func_taints.extend(func),
+            Err(err) => skipped.push((**fid, err))
+        }
+    }
+    (func_taints, skipped)
+}
+
+/// Fail on the first approximation that the fuel of the functions relies on (see `strict::approximations`)
+pub(crate) fn reject_approximations(func_taints: &[FuncState], wasm: &Module) -> anyhow::Result<()> {
+    let mut found = approximations(func_taints);
+    if found.is_empty() {
+        return Ok(());
+    }
+    let num_found = found.len();
+    Err(in_phase("the strict check", found.swap_remove(0), wasm).context(format!("Strict mode: the fuel of {num_found} instructions is approximated, the first one is:")))
+}
+
+/// Create the (reduced) slices of the analyzed functions, `only` the given functions are sliced (when set).
+/// The functions are sliced from the `criteria` and the `sinks` that the detectors find, besides their control flow
+/// (and from the ops that may trap on the state with `trap_sinks`).
+/// With `replay_pure_calls`, the calls to pure functions are replayed rather than requested.
+/// The min slices are computed by the `reductions` passes.
+#[allow(clippy::too_many_arguments)]
+fn slice_funcs(wasm: &mut Module, mut func_taints: Vec<FuncState>, criteria: &[SliceCriterion], sinks: &[Box<dyn SinkDetector>], mutation_sinks: &BTreeSet<u32>,
+               trap_sinks: bool, replay_pure_calls: bool, reductions: &BTreeSet<ReductionPass>, only: Option<&BTreeSet<u32>>) -> anyhow::Result<(Vec<FuncState>, Vec<SliceResult>)> {
+    if let Some(only) = only {
+        func_taints.retain(|func| only.contains(&func.fid));
+    }
+    for gid in mutation_sinks.iter() {
+        check_mutation_sink(*gid, wasm)?;
+    }
+    let criteria = slice_criteria(&func_taints, criteria, sinks, mutation_sinks, trap_sinks, wasm)?;
+
+    // create the slices
+    let replayed = if replay_pure_calls { replayable_funcs(wasm) } else { BTreeSet::new() };
+    let mut slices = slice_program_replaying(&func_taints, &criteria, &replayed, wasm).map_err(|err| in_phase("slicing", err, wasm))?;
+    save_structure(&mut slices, &func_taints, wasm).map_err(|err| in_phase("slicing", err, wasm))?;
+    reduce_with(&mut slices, &func_taints, wasm, reductions).map_err(|err| in_phase("slicing", err, wasm))?;
+    Ok((func_taints, slices))
+}
+
+/// What to slice the `func_taints` at besides their control flow: the `criteria`, the sinks that the
+/// detectors find, the writes to the `mutation_sinks` and with `trap_sinks`, the ops that may trap on
+/// the state (see `detect_trap_sinks`). Fails on a criterion outside of the functions.
+pub(crate) fn slice_criteria(func_taints: &[FuncState], criteria: &[SliceCriterion], sinks: &[Box<dyn SinkDetector>], mutation_sinks: &BTreeSet<u32>,
+                             trap_sinks: bool, wasm: &Module) -> anyhow::Result<Vec<SliceCriterion>> {
+    let mut criteria = criteria.to_vec();
+    let mutations: Vec<Box<dyn SinkDetector>> = if mutation_sinks.is_empty() {
+        vec![]
     } else {
-        writeln!(out, "Wrote generated Wasm to {}", out_path)?;
+        vec![Box::new(GlobalSetSinks { globals: mutation_sinks.clone() })]
+    };
+    let traps = if trap_sinks { detect_trap_sinks(func_taints, wasm)? } else { vec![] };
+    let sinks = detect_sinks(sinks, func_taints, wasm)?.into_iter().chain(detect_sinks(&mutations, func_taints, wasm)?).chain(traps);
+    for sink in sinks {
+        if !criteria.contains(&sink) {
+            criteria.push(sink);
+        }
+    }
+    for criterion in criteria.iter() {
+        if !func_taints.iter().any(|func| func.fid == criterion.fid) {
+            bail!("Can't slice at {criterion}: function #{} is not a local function (or excluded by the entry, or skipped)", criterion.fid);
+        }
+        if criterion.instr_idx >= local_func(criterion.fid, wasm)?.body.instructions.len() {
+            bail!("Can't slice at {criterion}: function #{} has no instruction @{}", criterion.fid, criterion.instr_idx);
+        }
+    }
+    Ok(criteria)
+}
+
+/// The copy of a mutation sink in the generated modules has to be able to hold the written values
+pub(crate) fn check_mutation_sink(gid: u32, wasm: &Module) -> anyhow::Result<()> {
+    if gid as usize >= wasm.globals.len() {
+        bail!("Can't slice the writes to global #{gid}: there's no such global");
+    }
+    let (GlobalKind::Local(LocalGlobal { ty, .. }) |
+        GlobalKind::Import(ImportedGlobal { ty, .. })) = wasm.globals.get_kind(GlobalID(gid));
+    if !ty.mutable {
+        bail!("Can't slice the writes to global #{gid}: it's immutable");
+    }
+    if !matches!(DataType::from(ty.content_type), DataType::I32 | DataType::I64 | DataType::F32 | DataType::F64) {
+        bail!("Can't slice the writes to global #{gid}: only numeric globals are supported");
+    }
+    Ok(())
+}
+
+pub(crate) fn parse_module(wasm_bytes: &[u8]) -> anyhow::Result<Module<'_>> {
+    match Module::parse(wasm_bytes, false, true) {
+        Ok(wasm) => Ok(wasm),
+        Err(errs) => Err(AnalysisError::InvalidModule { reason: format!("{errs:?}") }.into())
+    }
+}
+
+/// Attach where the analysis failed: the phase, the function (id and name), the instruction
+/// index and its operator. This way a failure can be reported without sharing the module.
+pub(crate) fn in_phase(phase: &str, err: AnalysisError, wasm: &Module) -> anyhow::Error {
+    let mut location = String::new();
+    if let Some(fid) = err.fid() {
+        location.push_str(&format!(" in function #{fid}"));
+        // (the function of a `NotLocalFunc` may not be in the module)
+        let func = wasm.functions.get_fn_by_id(FunctionID(fid));
+        if let Some(name) = func.and_then(|_| wasm.functions.get_name(FunctionID(fid)).as_ref()) {
+            location.push_str(&format!(" \"{name}\""));
+        }
+        if let Some(instr_idx) = err.instr_idx() {
+            location.push_str(&format!(" @{instr_idx}"));
+            if let Some(FuncKind::Local(lf)) = func.map(|func| func.kind()) {
+                if let Some(op) = lf.body.instructions.get_ops().get(instr_idx) {
+                    location.push_str(&format!(" ({op:?})"));
+                }
+            }
+        }
+    }
+    anyhow::Error::new(err).context(format!("Failed during {phase}{location}"))
+}
+
+/// Returns whether the generated module exceeds the budget.
+#[cfg(feature = "fs")]
+fn flush_size_report(out: &mut dyn Reporter, sty: &str, report: &SizeReport, budget: &OutputBudget) -> io::Result<bool> {
+    writeln!(out, "\n============================")?;
+    writeln!(out, "==== OUTPUT SIZE ({sty}) ====")?;
+    writeln!(out, "============================")?;
+    for (fname, size) in report.funcs.iter() {
+        writeln!(out, "{}{fname}: {size} bytes", tab(1))?;
+    }
+    writeln!(out, "total: {} bytes (budget: {} bytes)", report.total, budget.max_bytes)?;
+
+    let exceeds = report.exceeds(budget);
+    if exceeds {
+        let level = if budget.fail { "ERROR" } else { "WARNING" };
+        let s = format!("{level}: exceeds the output size budget by {} bytes\n", report.total - budget.max_bytes);
+        out.styled(&s, Style::Cost)?;
+    }
+    Ok(exceeds)
+}
+
+#[cfg(feature = "fs")]
+fn write_bytes(bytes: &[u8], out_path: &str, emit: Emit) -> anyhow::Result<()> {
+    if out_path == STDIO_PATH {
+        let mut stdout = io::stdout().lock();
+        if emit == Emit::Wat {
+            stdout.write_all(wasmprinter::print_bytes(bytes).context("Failed to print the generated Wasm as WAT")?.as_bytes())?;
+        } else {
+            stdout.write_all(bytes)?;
+        }
+        stdout.flush().context("Failed to write the generated Wasm to stdout")?;
+        return Ok(());
+    }
+    for path in emit.paths(out_path) {
+        try_path(&path)?;
+        let res = if path == out_path {
+            std::fs::write(&path, bytes)
+        } else {
+            std::fs::write(&path, wasmprinter::print_bytes(bytes).context("Failed to print the generated Wasm as WAT")?)
+        };
+        if let Err(e) = res {
+            bail!("Failed to dump instrumented wasm to {} from error: {}", path, e);
+        }
     }
     Ok(())
 }
 
-pub(crate) fn try_path(path: &String) {
-    if !PathBuf::from(path).exists() {
-        std::fs::create_dir_all(PathBuf::from(path).parent().unwrap()).unwrap();
+#[cfg(feature = "fs")]
+pub(crate) fn try_path(path: &String) -> anyhow::Result<()> {
+    if let Some(parent) = PathBuf::from(path).parent().filter(|_| !PathBuf::from(path).exists()) {
+        std::fs::create_dir_all(parent).with_context(|| format!("Failed to create the directory of {path}"))?;
     }
+    Ok(())
 }
 
 // ===========================
 // = Terminal Printing Logic =
 // ===========================
 
-fn flush_fid_mapping<W: WriteColor>(mut out: W, sty: &str, fid_map: &HashMap<u32, Vec<GeneratedFunc>>) -> io::Result<()> {
+fn flush_fid_mapping(out: &mut dyn Reporter, sty: &str, fid_map: &BTreeMap<u32, Vec<GeneratedFunc>>, names: &DebugNames, wasm: &Module) -> anyhow::Result<()> {
     writeln!(out, "===========================")?;
     writeln!(out, "==== FID MAPPING ({sty}) ====")?;
     writeln!(out, "===========================")?;
-    let mut sorted: Vec<&u32> = fid_map.keys().collect();
-    sorted.sort();
-    for fid in sorted.iter() {
+    // the memory of a load is only worth telling apart with several
+    let multi_memory = wasm.memories.iter().count() > 1;
+    for (fid, generated) in fid_map.iter() {
+        let body = local_ops(*fid, wasm)?;
         for GeneratedFunc {
             fid: new_fid,
             fname,
-            req_state
-        } in fid_map.get(*fid).unwrap().iter() {
+            req_state,
+            call_indirect_tables,
+            global_sources,
+            fallback,
+            dispatch_id,
+            ..
+        } in generated.iter() {
             let mut tabs = 0;
             write!(out, "{fid} -> ")?;
-            print_fid(&mut out, &format!("{new_fid}:{fname}"));
+            out.styled(&format!("{new_fid}:{fname}"), Style::Fid)?;
+            if let Some(id) = dispatch_id {
+                writeln!(out, "\t(dispatch id: {id})")?;
+            }
+            if let Some(op) = fallback {
+                writeln!(out, "\t(fallback, unsupported op: {op})")?;
+            }
 
             tabs += 1;
-            print_params_for_state_req(&mut out, tabs, "LOCAL.GET (for a param)", req_state.get(&StateType::Param).unwrap())?;
-            print_params_for_state_req(&mut out, tabs, "GLOBAL.GET", req_state.get(&StateType::Global).unwrap())?;
-            print_params_for_state_req(&mut out, tabs, "LOADS", req_state.get(&StateType::Load).unwrap())?;
-            print_call_params_for_state_req(&mut out, tabs, "CALLS", req_state.get(&StateType::Call).unwrap())?;
-            print_call_params_for_state_req(&mut out, tabs, "CALL_INDIRECTS", req_state.get(&StateType::CallIndirect).unwrap())?;
-            print_params_for_state_req(&mut out, tabs, "TAKEN (for a branch)", req_state.get(&StateType::Taken).unwrap())?;
+            print_params_for_state_req(out, tabs, "LOCAL.GET (for a param)", req_state.get(&StateType::Param).unwrap(),
+                                       |instr_idx| names.state_label(*fid, &body[*instr_idx]))?;
+            print_params_for_state_req(out, tabs, "GLOBAL.GET", req_state.get(&StateType::Global).unwrap(),
+                                       |instr_idx| global_label(names.state_label(*fid, &body[*instr_idx]),
+                                                                global_sources.get(instr_idx).map(|(_, source)| source)))?;
+            let loads = req_state.get(&StateType::Load).unwrap();
+            print_params_for_state_req(out, tabs, "LOADS", loads,
+                                       |instr_idx| loads[instr_idx].memory.filter(|_| multi_memory).map(|memory| format!("(memory {memory})")))?;
+            print_call_params_for_state_req(out, tabs, "CALLS", req_state.get(&StateType::Call).unwrap(), None)?;
+            print_call_params_for_state_req(out, tabs, "CALL_INDIRECTS", req_state.get(&StateType::CallIndirect).unwrap(), Some(call_indirect_tables))?;
+            print_call_params_for_state_req(out, tabs, "CALL_REFS", req_state.get(&StateType::CallRef).unwrap(), None)?;
+            print_params_for_state_req(out, tabs, "TABLE_GETS", req_state.get(&StateType::TableGet).unwrap(), |_| None)?;
+            print_params_for_state_req(out, tabs, "FIELD_LOADS", req_state.get(&StateType::FieldLoad).unwrap(), |_| None)?;
+            print_params_for_state_req(out, tabs, "TAKEN (for a branch)", req_state.get(&StateType::Taken).unwrap(), |_| None)?;
+            print_params_for_state_req(out, tabs, "LENGTH (for a bulk op)", req_state.get(&StateType::Length).unwrap(), |_| None)?;
 
             writeln!(out, )?;
         }
@@ -138,20 +1410,254 @@ fn flush_fid_mapping<W: WriteColor>(mut out: W, sty: &str, fid_map: &HashMap<u32
     Ok(())
 }
 
-fn print_params_for_state_req<T: Debug + Ord + Hash, W: WriteColor>(mut out: W, tabs: i32, name: &str, map: &HashMap<T, ReqState>) -> io::Result<()> {
+fn flush_fuel_point(out: &mut dyn Reporter, fid: u32, fuel_point: usize, charged: &[(usize, u64)], wasm: &Module) -> anyhow::Result<()> {
+    let body = &local_func(fid, wasm)?.body.instructions;
+    let total: u64 = charged.iter().map(|(_, cost)| cost).sum();
+
+    writeln!(out, "==== FUEL POINT ====")?;
+    write!(out, "function #{fid} @{fuel_point}: ")?;
+    out.styled(&format!(">>{total}\n"), Style::Cost)?;
+    let tabs = 1;
+    for (instr_idx, cost) in charged.iter() {
+        writeln!(out, "{}{instr_idx}\t+{cost}\t{:?}", tab(tabs), body.get_ops().get(*instr_idx).unwrap())?;
+    }
+    Ok(())
+}
+
+fn flush_taint(out: &mut dyn Reporter, funcs: &[FuncState], wasm: &Module) -> anyhow::Result<()> {
+    writeln!(out, "\n===============")?;
+    writeln!(out, "==== TAINT ====")?;
+    writeln!(out, "===============")?;
+    for func in funcs.iter() {
+        let body = local_ops(func.fid, wasm)?;
+        writeln!(out, "function #{}:", func.fid)?;
+        for (instr_idx, info) in func.instrs.iter().enumerate() {
+            if let OpKind::Control = info.kind {
+                writeln!(out, "{}{instr_idx}\t{:?}", tab(1), body[instr_idx])?;
+                for inp in info.inputs.iter() {
+                    writeln!(out, "{}<- {inp:?}", tab(2))?;
+                }
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn flush_taint_dump(out: &mut dyn Reporter, funcs: &[FuncState], wasm: &Module) -> anyhow::Result<()> {
+    writeln!(out, "====================")?;
+    writeln!(out, "==== TAINT DUMP ====")?;
+    writeln!(out, "====================")?;
+    for func in funcs.iter() {
+        let body = local_ops(func.fid, wasm)?;
+        writeln!(out, "function #{}:", func.fid)?;
+        for (instr_idx, info) in func.instrs.iter().enumerate() {
+            writeln!(out, "{}{instr_idx}\t{:?}\t{:?}", tab(1), info.kind, body[instr_idx])?;
+            for inp in info.inputs.iter() {
+                writeln!(out, "{}<- {inp:?}", tab(2))?;
+            }
+        }
+        if let Some(op) = &func.fallback {
+            writeln!(out, "{}(not modeled from here on, unsupported op: {op})", tab(1))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "audit")]
+fn flush_audit(out: &mut dyn Reporter, results: &[FuncAudit]) -> io::Result<()> {
+    writeln!(out, "===============")?;
+    writeln!(out, "==== AUDIT ====")?;
+    writeln!(out, "===============")?;
+    for res in results.iter() {
+        if let AuditOutcome::Diverges { .. } = res.outcome {
+            out.styled(&format!("{res}\n"), Style::Cost)?;
+        } else {
+            writeln!(out, "{res}")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "bmc")]
+fn flush_bmc(out: &mut dyn Reporter, results: &[FuncBmc]) -> io::Result<()> {
+    writeln!(out, "=============================")?;
+    writeln!(out, "==== BOUNDED MODEL CHECK ====")?;
+    writeln!(out, "=============================")?;
+    for res in results.iter() {
+        if let BmcOutcome::Mismatch { .. } | BmcOutcome::Trapped { .. } = res.outcome {
+            out.styled(&format!("{res}\n"), Style::Cost)?;
+        } else {
+            writeln!(out, "{res}")?;
+        }
+    }
+    Ok(())
+}
+
+fn flush_stats(out: &mut dyn Reporter, slices: &[SliceResult], charge_maps: &[BTreeMap<usize, Vec<(usize, u64)>>], wasm: &Module) -> anyhow::Result<()> {
+    writeln!(out, "=====================")?;
+    writeln!(out, "==== SLICE STATS ====")?;
+    writeln!(out, "=====================")?;
+    for (result, charge_map) in zip(slices, charge_maps) {
+        let body = local_ops(result.fid, wasm)?;
+        for slice in result.slices.values() {
+            let stats = SliceStats::new(slice, charge_map, body);
+            write!(out, "function #{}{} ({} instructions in slice): ", result.fid, slice.spec_name, slice.max_slice.len())?;
+            out.styled(&format!(">>{}\n", stats.total_cost()), Style::Cost)?;
+
+            let tabs = 1;
+            writeln!(out, "{}class\t\tinstrs\tcost\tshare", tab(tabs))?;
+            let mut classes: Vec<_> = stats.counts.keys().chain(stats.costs.keys()).collect();
+            classes.sort();
+            classes.dedup();
+            for class in classes {
+                writeln!(out, "{}{class:<10}\t{}\t{}\t{:.1}%", tab(tabs),
+                         stats.counts.get(class).unwrap_or(&0),
+                         stats.costs.get(class).unwrap_or(&0),
+                         stats.cost_share(class))?;
+            }
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the report as JSON and compare it to the baseline (if requested)
+#[cfg(feature = "fs")]
+fn flush_json_report(out: &mut dyn Reporter, report: &JsonReport, config: &AnalysisConfig) -> anyhow::Result<()> {
+    if let Some(report_path) = &config.report_json {
+        write_json_report(report_path, report)?;
+    }
+    if let Some(baseline_path) = &config.baseline {
+        let baseline = load_json_report(baseline_path)?;
+        flush_baseline_deltas(out, &baseline_path.display().to_string(), &compare(&baseline, report), report.functions.len())?;
+    }
+    Ok(())
+}
+
+/// How the slice size, requested state and total cost of a function changed, None if it's in neither analysis
+#[cfg(feature = "fs")]
+fn describe_func_delta(func_delta: &FuncDelta) -> Option<String> {
+    fn delta(was: u64, now: u64) -> String {
+        if was == now {
+            format!("{now}")
+        } else {
+            format!("{was} -> {now} ({:+})", now as i128 - was as i128)
+        }
+    }
+    let fid = func_delta.fid;
+    match (&func_delta.was, &func_delta.now) {
+        (Some(was), Some(now)) => Some(format!("function #{fid}: slice {}, state {}, fuel {}", delta(was.slice_size as u64, now.slice_size as u64),
+                                               delta(was.req_state as u64, now.req_state as u64), delta(was.total_cost(), now.total_cost()))),
+        (None, Some(now)) => Some(format!("function #{fid}: new (slice {}, state {}, fuel {})", now.slice_size, now.req_state, now.total_cost())),
+        (Some(_), None) => Some(format!("function #{fid}: removed")),
+        (None, None) => None
+    }
+}
+
+#[cfg(feature = "fs")]
+fn flush_baseline_deltas(out: &mut dyn Reporter, baseline: &str, deltas: &[FuncDelta], num_funcs: usize) -> io::Result<()> {
+    writeln!(out, "\n=========================")?;
+    writeln!(out, "==== BASELINE DELTAS ====")?;
+    writeln!(out, "=========================")?;
+    writeln!(out, "(compared to {baseline})")?;
+    for func_delta in deltas.iter() {
+        let Some(description) = describe_func_delta(func_delta) else {
+            continue;
+        };
+        writeln!(out, "{description}")?;
+        for (instr_idx, was, now) in func_delta.changed_costs() {
+            let cost = |cost: Option<u64>| cost.map(|cost| cost.to_string()).unwrap_or("-".to_string());
+            out.styled(&format!("{}@{instr_idx}: {} -> {}\n", tab(1), cost(was), cost(now)), Style::Cost)?;
+        }
+    }
+    let num_changed = deltas.iter().filter(|func_delta| func_delta.now.is_some()).count();
+    writeln!(out, "{} functions unchanged", num_funcs - num_changed)
+}
+
+/// One line per run of `watch_analysis`, then one per function that changed since the previous run
+#[cfg(feature = "fs")]
+fn flush_watch_event(out: &mut dyn Reporter, module: &str, event: &WatchEvent) -> io::Result<()> {
+    match event {
+        WatchEvent::Analyzed { first: true, num_funcs, .. } => writeln!(out, "{module}: analyzed {num_funcs} functions"),
+        WatchEvent::Analyzed { deltas, num_funcs, .. } if deltas.is_empty() => writeln!(out, "{module}: changed, but none of its {num_funcs} functions' fuel accounting did"),
+        WatchEvent::Analyzed { deltas, num_funcs, .. } => {
+            writeln!(out, "{module}: {} of {num_funcs} functions changed", deltas.len())?;
+            for description in deltas.iter().filter_map(describe_func_delta) {
+                writeln!(out, "{}{description}", tab(1))?;
+            }
+            Ok(())
+        }
+        WatchEvent::Failed(err) => out.styled(&format!("{module}: {err:#}\n"), Style::Cost)
+    }
+}
+
+fn flush_artifact_deltas(out: &mut dyn Reporter, deltas: &[ArtifactDelta], num_funcs: usize) -> io::Result<()> {
+    writeln!(out, "=========================")?;
+    writeln!(out, "==== ARTIFACT DELTAS ====")?;
+    writeln!(out, "=========================")?;
+    for delta in deltas.iter() {
+        let name = &delta.name;
+        match (&delta.was, &delta.now) {
+            (Some(was), Some(now)) => {
+                if delta.signature_changed() {
+                    writeln!(out, "{name}: signature {} -> {}", was.signature(), now.signature())?;
+                } else {
+                    writeln!(out, "{name}: {}", now.signature())?;
+                }
+                for (site, was, now) in delta.changed_costs() {
+                    let cost = |cost: Option<u64>| cost.map(|cost| cost.to_string()).unwrap_or("-".to_string());
+                    out.styled(&format!("{}{site}: {} -> {}\n", tab(1), cost(was), cost(now)), Style::Cost)?;
+                }
+                for record in delta.new_state() {
+                    writeln!(out, "{}requests {record}", tab(1))?;
+                }
+            }
+            (None, Some(now)) => writeln!(out, "{name}: new {}", now.signature())?,
+            (Some(_), None) => writeln!(out, "{name}: removed")?,
+            (None, None) => {}
+        }
+    }
+    let num_changed = deltas.iter().filter(|delta| delta.now.is_some()).count();
+    writeln!(out, "{} generated functions unchanged", num_funcs - num_changed)
+}
+
+fn flush_skipped(out: &mut dyn Reporter, skipped: &[(u32, anyhow::Error)]) -> io::Result<()> {
+    for (fid, err) in skipped.iter() {
+        out.styled(&format!("WARNING: skipped function #{fid}, no slices or code are generated for it: {err:#}\n"), Style::Cost)?;
+    }
+    Ok(())
+}
+
+fn flush_violations(out: &mut dyn Reporter, sty: &str, violations: &[FuelPointViolation]) -> io::Result<()> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "\n=====================================")?;
+    writeln!(out, "==== FUEL POINT VIOLATIONS ({sty}) ====")?;
+    writeln!(out, "=====================================")?;
+    for violation in violations.iter() {
+        out.styled(&format!("{violation}\n"), Style::Cost)?;
+    }
+    Ok(())
+}
+
+/// - `debug_name`: the source-level name of the requested state (if the module has debug info)
+fn print_params_for_state_req<T: Debug>(out: &mut dyn Reporter, tabs: i32, name: &str, map: &BTreeMap<T, ReqState>, debug_name: impl Fn(&T) -> Option<String>) -> io::Result<()> {
     if !map.is_empty() {
         writeln!(out, )?;
         writeln!(out, "{}---- Requested {name}:", tab(tabs))?;
-        let mut sorted: Vec<&T> = map.keys().collect();
-        sorted.sort();
-        for orig in sorted.iter() {
-            let reqs = map.get(*orig).unwrap().req_state.first().unwrap();
-            writeln!(out, "{}{:?} is @param{}", tab(tabs), orig, reqs.gen_param_id())?;
+        for (orig, reqs) in map.iter() {
+            let reqs = reqs.req_state.first().unwrap();
+            let debug_name = debug_name(orig).map(|name| format!(" {name}")).unwrap_or_default();
+            writeln!(out, "{}{:?}{debug_name} is @param{}", tab(tabs), orig, reqs.gen_param_id())?;
         }
     }
     Ok(())
 }
-fn print_call_params_for_state_req<W: WriteColor>(mut out: W, tabs: i32, name: &str, map: &HashMap<usize, ReqState>) -> io::Result<()> {
+/// - `tables`: the table that each (indirect) call goes through
+fn print_call_params_for_state_req(out: &mut dyn Reporter, tabs: i32, name: &str, map: &BTreeMap<usize, ReqState>, tables: Option<&BTreeMap<usize, u32>>) -> io::Result<()> {
     if !map.is_empty() {
         writeln!(out, )?;
         writeln!(out, "{}---- Requested {name}:", tab(tabs))?;
@@ -161,38 +1667,106 @@ fn print_call_params_for_state_req<W: WriteColor>(mut out: W, tabs: i32, name: &
             //     let comma = if i == 0 { "" } else { "," };
             //     reqs.push_str(&format!("{comma}{r}"));
             // }
-            writeln!(out, "{}{orig}: {reqs}", tab(tabs))?;
+            let table = tables.and_then(|tables| tables.get(orig)).map(|t| format!(" (table {t})")).unwrap_or_default();
+            writeln!(out, "{}{orig}{table}: {reqs}", tab(tabs))?;
         }
     }
     Ok(())
 }
 
-fn flush_slices<W: WriteColor>(mut out: W, num_globals: usize, slices: &Vec<SliceResult>, funcs: &Vec<FuncState>, cost_maps: &Vec<HashMap<usize, u64>>, wasm: &Module) -> io::Result<()> {
+/// The symbolic cost of each function (see `CostExpr`), from what its fuel points charge (`charge_maps`)
+fn cost_formulas(charge_maps: &[BTreeMap<usize, Vec<(usize, u64)>>], funcs: &[FuncState], wasm: &Module) -> Result<Vec<CostExpr>, AnalysisError> {
+    zip(funcs, charge_maps).map(|(func, charges)| {
+        Ok(CostExpr::of_func(func.fid, local_ops(func.fid, wasm)?, charges))
+    }).collect()
+}
+
+/// The slices at the level of detail of `verbosity`
+/// - `formulas`: the symbolic cost of each function, empty if the costs weren't computed
+fn flush_report(out: &mut dyn Reporter, verbosity: Verbosity, slices: &Vec<SliceResult>, funcs: &Vec<FuncState>, cost_maps: &Vec<BTreeMap<usize, u64>>,
+                               formulas: &[CostExpr], wasm: &Module) -> anyhow::Result<()> {
+    match verbosity {
+        Verbosity::Quiet => Ok(()),
+        Verbosity::Summary => Ok(flush_slice_summary(out, slices, cost_maps, formulas)?),
+        Verbosity::Listing => flush_slices(out, wasm.globals.len(), slices, funcs, cost_maps, wasm),
+        Verbosity::Taint => {
+            flush_slices(out, wasm.globals.len(), slices, funcs, cost_maps, wasm)?;
+            flush_taint_dump(out, funcs, wasm)
+        }
+    }
+}
+
+fn flush_slice_summary(out: &mut dyn Reporter, slices: &[SliceResult], cost_maps: &[BTreeMap<usize, u64>], formulas: &[CostExpr]) -> io::Result<()> {
     writeln!(out, "\n================")?;
     writeln!(out, "==== SLICES ====")?;
     writeln!(out, "================")?;
-    for (result, (func, cost_map)) in zip(slices, zip(funcs, cost_maps)) {
-        let mut sorted: Vec<&usize> = result.slices.keys().collect();
-        sorted.sort();
-        for instr_index in sorted.iter() {
-            let slice = &result.slices[*instr_index];
+    for (i, (result, cost_map)) in zip(slices, cost_maps).enumerate() {
+        if let Some(name) = &result.shim {
+            writeln!(out, "function #{} (skipped toolchain shim: {name})", result.fid)?;
+        } else if let Some(op) = &result.fallback {
+            writeln!(out, "function #{} (fallback for unsupported {op}: worst-case cost of the body)", result.fid)?;
+        } else {
+            let in_slice: usize = result.slices.values().map(|slice| slice.max_slice.len()).sum();
+            let cost = formulas.get(i).map(|formula| format!(", cost {formula}")).unwrap_or_default();
+            writeln!(out, "function #{} ({} slices, {in_slice} instructions in slice, {} fuel points{cost})", result.fid, result.slices.len(), cost_map.len())?;
+        }
+    }
+    // what each reduction pass removed from the min slices, over all of them
+    let mut totals: BTreeMap<ReductionPass, ReductionStats> = BTreeMap::new();
+    for slice in slices.iter().flat_map(|result| result.slices.values()) {
+        for (pass, stats) in slice.reductions.iter() {
+            *totals.entry(*pass).or_default() += *stats;
+        }
+    }
+    if !totals.is_empty() {
+        let passes: Vec<String> = totals.iter()
+            .map(|(pass, stats)| format!("{pass} ({} instructions, {} requests removed)", stats.instrs, stats.requests))
+            .collect();
+        writeln!(out, "min slice reductions: {}", passes.join(", "))?;
+    }
+    writeln!(out)
+}
 
+fn flush_slices(out: &mut dyn Reporter, num_globals: usize, slices: &Vec<SliceResult>, funcs: &Vec<FuncState>, cost_maps: &Vec<BTreeMap<usize, u64>>, wasm: &Module) -> anyhow::Result<()> {
+    writeln!(out, "\n================")?;
+    writeln!(out, "==== SLICES ====")?;
+    writeln!(out, "================")?;
+    for (result, (func, cost_map)) in zip(slices, zip(funcs, cost_maps)) {
+        if let Some(name) = &result.shim {
+            writeln!(out, "function #{} (skipped toolchain shim: {name})\n", result.fid)?;
+            continue;
+        }
+        if let Some(op) = &result.fallback {
+            writeln!(out, "function #{} (fallback for unsupported {op}: worst-case cost of the body)\n", result.fid)?;
+            continue;
+        }
+        for slice in result.slices.values() {
             writeln!(out, "function #{} ({} instructions in slice):", result.fid, slice.max_slice.len())?;
-            let body = &wasm.functions.unwrap_local(FunctionID(func.fid)).body.instructions;
+            let body = &local_func(func.fid, wasm)?.body.instructions;
             let mut tabs = 0;
-            print_state_taint(&mut out, &slice.params, result.total_params, "params", &mut tabs)?;
-            print_state_taint(&mut out, &slice.globals, num_globals, "global", &mut tabs)?;
-            print_instr_taint(&mut out, &slice.params
+            // the locals read in a loop are listed after the params
+            let num_locals = slice.params.keys().map(|(lid, _)| *lid as usize + 1).max().unwrap_or(0);
+            print_state_taint(out, &slice.params, result.total_params.max(num_locals), "params", &mut tabs)?;
+            print_state_taint(out, &slice.globals, num_globals, "global", &mut tabs)?;
+            print_imported_globals(out, &slice.global_sources, &mut tabs)?;
+            print_const_globals(out, &slice.const_globals, &mut tabs)?;
+            print_instr_taint(out, &slice.params
                 .iter()
-                .map(|((_, index), value)| (*index, value.clone()))
+                .map(|((_, index), value)| (*index, *value))
                 .collect(), "local.get", &mut tabs)?;
-            print_instr_taint(&mut out, &slice.globals
+            print_instr_taint(out, &slice.globals
                 .iter()
-                .map(|((_, index), value)| (*index, value.clone()))
+                .map(|((_, index), value)| (*index, *value))
                 .collect(), "global.get", &mut tabs)?;
-            print_instr_taint(&mut out, &slice.loads, "load", &mut tabs)?;
-            print_call_taint(&mut out, &slice.calls, "calls", &mut tabs)?;
-            print_call_taint(&mut out, &slice.call_indirects, "call_indirects", &mut tabs)?;
+            print_instr_taint(out, &slice.loads
+                .iter()
+                .map(|((_, index), value)| (*index, *value))
+                .collect(), "load", &mut tabs)?;
+            print_call_taint(out, &slice.calls, None, "calls", &mut tabs)?;
+            print_call_taint(out, &slice.call_indirects, Some(&slice.call_indirect_tables), "call_indirects", &mut tabs)?;
+            print_call_taint(out, &slice.call_refs, None, "call_refs", &mut tabs)?;
+            print_instr_taint(out, &slice.table_gets, "table.get", &mut tabs)?;
+            print_instr_taint(out, &slice.field_loads, "struct/array read", &mut tabs)?;
 
 
             tabs += 1;
@@ -205,18 +1779,17 @@ fn flush_slices<W: WriteColor>(mut out: W, num_globals: usize, slices: &Vec<Slic
                 let in_support = slice.instrs_support.contains(&i);
 
                 if let Some(cost) = cost {
-                    let s = format!("{}\t! >>{cost}\n", tab(tabs));
-                    print_cost(&mut out, &s);
+                    out.styled(&format!("{}\t! >>{cost}\n", tab(tabs)), Style::Cost)?;
                 }
 
                 let mark = if in_min_slice { "-" } else if in_max_slice { "+" } else if in_support { "~" } else { " " };
                 let s = format!("{}{}\t{} {:?}\n", tab(tabs), i, mark, body.get_ops().get(i).unwrap());
                 if in_min_slice {
-                    print_min(&mut out, &s);
+                    out.styled(&s, Style::Min)?;
                 } else if in_max_slice {
-                    print_tainted(&mut out, &s);
+                    out.styled(&s, Style::Tainted)?;
                 } else if in_support {
-                    print_support(&mut out, &s);
+                    out.styled(&s, Style::Support)?;
                 } else {
                     write!(out, "{s}")?;
                 }
@@ -226,7 +1799,7 @@ fn flush_slices<W: WriteColor>(mut out: W, num_globals: usize, slices: &Vec<Slic
     }
     Ok(())
 }
-fn print_state_taint<W: WriteColor>(mut out: W, taint: &HashMap<(u32, usize), DataType>, out_of: usize, ty: &str, tabs: &mut i32) -> io::Result<()> {
+fn print_state_taint(out: &mut dyn Reporter, taint: &BTreeMap<(u32, usize), DataType>, out_of: usize, ty: &str, tabs: &mut i32) -> io::Result<()> {
     *tabs += 1;
     if !taint.is_empty() {
         writeln!(out, "{}the {ty} taint:", tab(*tabs))?;
@@ -241,7 +1814,7 @@ fn print_state_taint<W: WriteColor>(mut out: W, taint: &HashMap<(u32, usize), Da
             let tainted = keys_u32.contains(&(i as u32));
             let s = format!(" {}{i},", if tainted { "*" } else { " " });
             if tainted {
-                print_tainted(&mut out, &s);
+                out.styled(&s, Style::Tainted)?;
             } else {
                 write!(out, "{s}")?;
             }
@@ -251,32 +1824,64 @@ fn print_state_taint<W: WriteColor>(mut out: W, taint: &HashMap<(u32, usize), Da
     *tabs -= 1;
     Ok(())
 }
-fn print_instr_taint<W: WriteColor>(mut out: W, instrs: &HashMap<usize, DataType>, ty: &str, tabs: &mut i32) -> io::Result<()> {
+/// Imported globals are host-controlled, list where they come from
+fn print_imported_globals(out: &mut dyn Reporter, sources: &BTreeMap<u32, GlobalSource>, tabs: &mut i32) -> io::Result<()> {
+    *tabs += 1;
+    let imported: Vec<_> = sources.iter().filter(|(_, source)| **source != GlobalSource::Local).collect();
+    if !imported.is_empty() {
+        writeln!(out, "{}the imported globals:", tab(*tabs))?;
+        for (gid, source) in imported {
+            writeln!(out, "{}{gid} {source}", tab(*tabs + 1))?;
+        }
+    }
+    *tabs -= 1;
+    Ok(())
+}
+/// The reads of the globals whose value is known statically, they're replayed rather than requested
+fn print_const_globals(out: &mut dyn Reporter, values: &BTreeMap<usize, ConstValue>, tabs: &mut i32) -> io::Result<()> {
+    *tabs += 1;
+    if !values.is_empty() {
+        writeln!(out, "{}the constant globals:", tab(*tabs))?;
+        for (instr_idx, value) in values.iter() {
+            writeln!(out, "{}@{instr_idx} {value}", tab(*tabs + 1))?;
+        }
+    }
+    *tabs -= 1;
+    Ok(())
+}
+/// The label of a requested global in the FID mapping: its name and, if it's imported, where from
+fn global_label(name: Option<String>, source: Option<&GlobalSource>) -> Option<String> {
+    match source {
+        Some(source @ GlobalSource::Import { .. }) => Some(match name {
+            Some(name) => format!("{name} ({source})"),
+            None => format!("({source})")
+        }),
+        _ => name
+    }
+}
+fn print_instr_taint(out: &mut dyn Reporter, instrs: &BTreeMap<usize, DataType>, ty: &str, tabs: &mut i32) -> io::Result<()> {
     *tabs += 1;
     if !instrs.is_empty() {
         writeln!(out, "{}the {ty} instrs influencing CF:", tab(*tabs))?;
         write!(out, "{}", tab(*tabs))?;
 
-        let mut sorted: Vec<&usize> = instrs.keys().collect();
-        sorted.sort();
-        for instr in sorted.iter() {
-            print_tainted(&mut out, &format!(" *{},", **instr));
+        for instr in instrs.keys() {
+            out.styled(&format!(" *{},", *instr), Style::Tainted)?;
         }
         writeln!(out, )?;
     }
     *tabs -= 1;
     Ok(())
 }
-fn print_call_taint<W: WriteColor>(mut out: W, calls: &HashMap<(usize, usize), DataType>, ty: &str, tabs: &mut i32) -> io::Result<()> {
+fn print_call_taint(out: &mut dyn Reporter, calls: &BTreeMap<(usize, usize), DataType>, tables: Option<&BTreeMap<usize, u32>>, ty: &str, tabs: &mut i32) -> io::Result<()> {
     *tabs += 1;
     if !calls.is_empty() {
         writeln!(out, "{}the {ty} instrs influencing CF:", tab(*tabs))?;
         write!(out, "{}", tab(*tabs))?;
 
-        let mut sorted: Vec<&(usize, usize)> = calls.keys().collect();
-        sorted.sort();
-        for (instr, res) in sorted.iter() {
-            print_tainted(&mut out, &format!(" *(@{}, res{}),", *instr, *res));
+        for (instr, res) in calls.keys() {
+            let table = tables.and_then(|tables| tables.get(instr)).map(|t| format!(", table{t}")).unwrap_or_default();
+            out.styled(&format!(" *(@{}, res{}{table}),", *instr, *res), Style::Tainted)?;
         }
         writeln!(out, )?;
     }
@@ -284,70 +1889,6 @@ fn print_call_taint<W: WriteColor>(mut out: W, calls: &HashMap<(usize, usize), D
     Ok(())
 }
 
-const WRITE_ERR: &str = "Uh oh, something went wrong while printing to terminal";
-
-fn print_min<W: WriteColor>(out: W, s: &str) {
-    print_color(out, s, yellow);
-}
-fn print_tainted<W: WriteColor>(out: W, s: &str) {
-    print_color(out, s, green);
-}
-fn print_support<W: WriteColor>(out: W, s: &str) {
-    print_color(out, s, blue);
-}
-fn print_cost<W: WriteColor>(out: W, s: &str) {
-    print_color(out, s, red);
-}
-fn print_fid<W: WriteColor>(out: W, s: &str) {
-    print_color(out, s, magenta_italics);
-}
-fn print_color<W: WriteColor>(out: W, s: &str, color: fn(W, bool, &str)) {
-    color(out, true, s);
-}
-pub fn color<W: WriteColor>(mut out: W, s: &str, bold: bool, italics: bool, c: Color) {
-    out
-        .set_color(
-            ColorSpec::new()
-                .set_bold(bold)
-                .set_italic(italics)
-                .set_fg(Some(c)),
-        )
-        .expect(WRITE_ERR);
-    write!(out, "{}", s).expect(WRITE_ERR);
-    out.set_color(&ColorSpec::default()).expect("TODO: panic message");
-}
-pub fn blue<W: WriteColor>(out: W, bold: bool, s: &str) {
-    color(out, s, bold, false, Color::Blue)
-}
-#[allow(dead_code)]
-pub fn cyan<W: WriteColor>(out: W, bold: bool, s: &str) {
-    color(out, s, bold, false, Color::Cyan)
-}
-pub fn green<W: WriteColor>(out: W, bold: bool, s: &str) {
-    color(out, s, bold, false, Color::Green)
-}
-#[allow(dead_code)]
-pub fn magenta<W: WriteColor>(out: W, bold: bool, s: &str) {
-    color(out, s, bold, false, Color::Magenta)
-}
-pub fn magenta_italics<W: WriteColor>(out: W, bold: bool, s: &str) {
-    color(out, s, bold, true, Color::Magenta)
-}
-pub fn red<W: WriteColor>(out: W, bold: bool, s: &str) {
-    color(out, s, bold, false, Color::Red)
-}
-#[allow(dead_code)]
-pub fn white<W: WriteColor>(out: W, bold: bool, s: &str) {
-    color(out, s, bold, false, Color::Rgb(193, 193, 193))
-}
-#[allow(dead_code)]
-pub fn grey_italics<W: WriteColor>(out: W, bold: bool, s: &str) {
-    color(out, s, bold, true, Color::White)
-}
-#[allow(dead_code)]
-pub fn yellow<W: WriteColor>(out: W, bold: bool, s: &str) {
-    color(out, s, bold, false, Color::Yellow)
-}
 pub fn tab(tab: i32) -> String {
     " ".repeat(SPACE_PER_TAB * tab as usize)
 }
@@ -0,0 +1,94 @@
+//! serde helpers (`#[serde(with = "...")]`) for the fields of the analysis results that JSON can't
+//! encode as is: wirm's `DataType`, maps with tuple keys and the (unordered) instruction sets.
+
+use std::collections::{BTreeMap, HashSet};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+use wirm::DataType;
+
+/// The name of a value type, e.g. `I32`
+fn type_name(ty: &DataType) -> String {
+    format!("{ty:?}")
+}
+
+/// Only the value types can be requested as state
+fn parse_type<E: Error>(name: &str) -> Result<DataType, E> {
+    Ok(match name {
+        "I32" => DataType::I32,
+        "I64" => DataType::I64,
+        "F32" => DataType::F32,
+        "F64" => DataType::F64,
+        "V128" => DataType::V128,
+        "FuncRef" => DataType::FuncRef,
+        "ExternRef" => DataType::ExternRef,
+        _ => return Err(E::custom(format!("Unknown value type: {name}")))
+    })
+}
+
+/// A `Vec<DataType>` as the names of the types
+pub(crate) mod data_types {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(types: &[DataType], serializer: S) -> Result<S::Ok, S::Error> {
+        types.iter().map(type_name).collect::<Vec<_>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<DataType>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?.iter().map(|name| parse_type(name)).collect()
+    }
+}
+
+/// A `BTreeMap<K, DataType>` as a list of (key, type name) pairs, the keys can be tuples
+pub(crate) mod type_map {
+    use super::*;
+
+    pub fn serialize<K: Serialize, S: Serializer>(map: &BTreeMap<K, DataType>, serializer: S) -> Result<S::Ok, S::Error> {
+        map.iter().map(|(key, ty)| (key, type_name(ty))).collect::<Vec<_>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, K: Deserialize<'de> + Ord, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<K, DataType>, D::Error> {
+        Vec::<(K, String)>::deserialize(deserializer)?.into_iter().map(|(key, name)| Ok((key, parse_type(&name)?))).collect()
+    }
+}
+
+/// The loads of a slice as `type_map`, keyed by (memory, instr_idx). A key that's only the instr_idx
+/// (encoded before the memory was tracked) reads memory 0.
+pub(crate) mod load_map {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum LoadKey {
+        InMemory(u32, usize),
+        Memory0(usize)
+    }
+
+    pub fn serialize<S: Serializer>(map: &BTreeMap<(u32, usize), DataType>, serializer: S) -> Result<S::Ok, S::Error> {
+        type_map::serialize(map, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<(u32, usize), DataType>, D::Error> {
+        Vec::<(LoadKey, String)>::deserialize(deserializer)?.into_iter().map(|(key, name)| {
+            let key = match key {
+                LoadKey::InMemory(memory, instr_idx) => (memory, instr_idx),
+                LoadKey::Memory0(instr_idx) => (0, instr_idx)
+            };
+            Ok((key, parse_type(&name)?))
+        }).collect()
+    }
+}
+
+/// A `HashSet<usize>` of instructions as a sorted list, so the encoding is stable (and can be diffed)
+pub(crate) mod sorted_set {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(set: &HashSet<usize>, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut idxs: Vec<usize> = set.iter().copied().collect();
+        idxs.sort();
+        idxs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashSet<usize>, D::Error> {
+        Ok(Vec::<usize>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
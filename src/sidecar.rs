@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "fs")]
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use crate::cert::TOOL_NAME;
+use crate::analyze::FuncState;
+use crate::codegen::{GeneratedFunc, StateType};
+use crate::cost_expr::CostExpr;
+use crate::purity::Effects;
+use crate::reduce::ReductionStats;
+use crate::slice::{GlobalSource, Slice, SliceResult};
+
+pub const SIDECAR_EXT: &str = "fuel.json";
+
+/// The analysis results for a module, stored next to it (`<module>.fuel.json`) so that
+/// other tools can consume them without rerunning the analysis.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sidecar {
+    pub tool: String,
+    pub version: String,
+    pub functions: Vec<FuncSidecar>,
+    /// The generated functions, maps from `max`/`min` to the functions generated for each fid
+    pub manifest: BTreeMap<String, Vec<ManifestEntry>>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuncSidecar {
+    pub fid: u32,
+    /// Maps from instr_idx -> slice (the function's own slice is at 0, loop slices at loop start+1)
+    pub slices: BTreeMap<usize, SliceSidecar>,
+    /// Maps from instr_idx -> the fuel charged at that fuel point
+    pub costs: BTreeMap<usize, u64>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SliceSidecar {
+    pub start_instr_idx: usize,
+    pub end_instr_idx: usize,
+    pub spec_name: String,
+    pub max_slice: Vec<usize>,
+    pub min_slice: Vec<usize>,
+    pub instrs_support: Vec<usize>,
+    /// Maps from the instr_idx of a global.get in the slice -> the global it reads
+    #[serde(default)]
+    pub globals: BTreeMap<usize, GlobalSidecar>,
+    /// Maps from the name of a reduction pass that ran -> what it removed from the min slice
+    #[serde(default)]
+    pub reductions: BTreeMap<String, ReductionStats>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GlobalSidecar {
+    pub gid: u32,
+    /// The (module, name) that the global is imported from, `None` if it's defined in the module.
+    /// Imported globals are host-controlled, their value has to be harvested from the host.
+    pub import: Option<(String, String)>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub fid: u32,
+    pub fname: String,
+    /// Maps from the type of requested state -> instr_idx -> the stack values needed there
+    pub req_state: BTreeMap<String, BTreeMap<usize, Vec<String>>>,
+    /// Maps from the instr_idx of a requested call_indirect -> the table it calls through
+    pub call_indirect_tables: BTreeMap<usize, u32>,
+    /// Maps from the instr_idx of a requested load -> the memory it reads
+    #[serde(default)]
+    pub load_memories: BTreeMap<usize, u32>,
+    /// Maps from the instr_idx of a requested global.get -> the global it reads
+    #[serde(default)]
+    pub globals: BTreeMap<usize, GlobalSidecar>,
+    /// Set to the op that the analysis couldn't model if this is a fallback, which only
+    /// returns the worst-case cost of the function's body
+    pub fallback: Option<String>,
+    /// The checkpoint IDs of the fuel points that the function charges (`fid << 32 | instr_idx`,
+    /// see `checkpoint_id`), the costs of the fuel points are in `FuncSidecar::costs`
+    #[serde(default)]
+    pub checkpoints: Vec<u64>,
+    /// Maps from a param of the function (its index) -> the source-level name of the state it requests,
+    /// e.g. `param "len"` or `global "heap_ptr"` (only when the module has debug names)
+    #[serde(default)]
+    pub param_names: BTreeMap<u32, String>
+}
+
+/// The machine-readable version of the report printed by `codegen` (`--report-json`), so that
+/// other tools don't need to scrape the terminal output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonReport {
+    pub tool: String,
+    pub version: String,
+    /// The slices and cost maps of each function (the costs are empty with `--no-codegen`)
+    pub functions: Vec<FuncSidecar>,
+    /// Maps from `max`/`min` -> the fid of the original function -> the functions generated for it
+    pub fid_mapping: BTreeMap<String, BTreeMap<u32, Vec<ManifestEntry>>>,
+    /// Maps from fid -> the worst-case cost of the function in terms of the trip counts of its loops,
+    /// e.g. `7 + 5*n1_12` (see `CostExpr`, empty with `--no-codegen`)
+    #[serde(default)]
+    pub formulas: BTreeMap<u32, String>,
+    /// Maps from fid -> the side effects of the function (see `func_effects`)
+    #[serde(default)]
+    pub effects: BTreeMap<u32, Effects>
+}
+
+/// A slice addressed by its function and its ordinal in the function: 0 is the function's own slice,
+/// then its loops in the order of the body (see `JsonReport::slice`)
+#[derive(Debug, Clone, Copy)]
+pub struct SliceHandle<'a> {
+    pub fid: u32,
+    pub ordinal: usize,
+    slice: &'a SliceSidecar
+}
+impl<'a> SliceHandle<'a> {
+    /// The suffix of the names of the functions generated for the slice
+    pub fn spec_name(&self) -> &'a str {
+        &self.slice.spec_name
+    }
+
+    /// The instructions of the original function that the slice covers
+    pub fn instr_range(&self) -> Range<usize> {
+        self.slice.start_instr_idx..self.slice.end_instr_idx
+    }
+
+    pub fn max_slice(&self) -> &'a [usize] {
+        &self.slice.max_slice
+    }
+
+    pub fn min_slice(&self) -> &'a [usize] {
+        &self.slice.min_slice
+    }
+
+    /// What each reduction pass removed from the min slice, by the name of the pass
+    pub fn reductions(&self) -> &'a BTreeMap<String, ReductionStats> {
+        &self.slice.reductions
+    }
+
+    /// Everything the report has on the slice
+    pub fn details(&self) -> &'a SliceSidecar {
+        self.slice
+    }
+}
+
+/// The functions generated for an original function, in each variant (see `JsonReport::generated_for`)
+#[derive(Debug, Clone, Copy)]
+pub struct GeneratedHandles<'a> {
+    pub max: &'a [ManifestEntry],
+    pub min: &'a [ManifestEntry]
+}
+
+impl Sidecar {
+    pub fn new(slices: &[SliceResult], cost_maps: &[BTreeMap<usize, u64>],
+               func_map_max: &BTreeMap<u32, Vec<GeneratedFunc>>, func_map_min: &BTreeMap<u32, Vec<GeneratedFunc>>) -> Self {
+        let mut manifest = BTreeMap::new();
+        manifest.insert("max".to_string(), func_map_max.values().flatten().map(ManifestEntry::from).collect());
+        manifest.insert("min".to_string(), func_map_min.values().flatten().map(ManifestEntry::from).collect());
+        Self {
+            tool: TOOL_NAME.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            functions: func_sidecars(slices, cost_maps),
+            manifest
+        }
+    }
+}
+impl JsonReport {
+    pub fn new(slices: &[SliceResult], funcs: &[FuncState], cost_maps: &[BTreeMap<usize, u64>], formulas: &[CostExpr],
+               func_map_max: &BTreeMap<u32, Vec<GeneratedFunc>>, func_map_min: &BTreeMap<u32, Vec<GeneratedFunc>>) -> Self {
+        let mapping = |func_map: &BTreeMap<u32, Vec<GeneratedFunc>>| func_map.iter()
+            .map(|(fid, funcs)| (*fid, funcs.iter().map(ManifestEntry::from).collect()))
+            .collect();
+        let mut fid_mapping = BTreeMap::new();
+        fid_mapping.insert("max".to_string(), mapping(func_map_max));
+        fid_mapping.insert("min".to_string(), mapping(func_map_min));
+        Self {
+            tool: TOOL_NAME.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            functions: func_sidecars(slices, cost_maps),
+            fid_mapping,
+            formulas: slices.iter().zip(formulas).map(|(result, formula)| (result.fid, formula.to_string())).collect(),
+            effects: funcs.iter().map(|func| (func.fid, func.effects)).collect()
+        }
+    }
+}
+impl JsonReport {
+    /// The slices of function `fid`, by ordinal
+    pub fn slices(&self, fid: u32) -> impl Iterator<Item = SliceHandle<'_>> {
+        self.functions.iter()
+            .filter(move |func| func.fid == fid)
+            .flat_map(|func| func.slices.values().enumerate().map(move |(ordinal, slice)| SliceHandle { fid: func.fid, ordinal, slice }))
+    }
+
+    /// The `ordinal`-th slice of function `fid`, None if the function wasn't analyzed or has fewer slices
+    pub fn slice(&self, fid: u32, ordinal: usize) -> Option<SliceHandle<'_>> {
+        self.slices(fid).nth(ordinal)
+    }
+
+    /// The functions generated for function `fid`, None if it has none (e.g. with `--no-codegen`)
+    pub fn generated_for(&self, fid: u32) -> Option<GeneratedHandles<'_>> {
+        let generated = |variant: &str| self.fid_mapping.get(variant).and_then(|mapping| mapping.get(&fid)).map(Vec::as_slice);
+        Some(GeneratedHandles {
+            max: generated("max")?,
+            min: generated("min")?
+        })
+    }
+}
+
+impl From<&Slice> for SliceSidecar {
+    fn from(slice: &Slice) -> Self {
+        Self {
+            start_instr_idx: slice.start_instr_idx,
+            end_instr_idx: slice.end_instr_idx,
+            spec_name: slice.spec_name.clone(),
+            max_slice: sorted(slice.max_slice.iter()),
+            min_slice: sorted(slice.min_slice.iter()),
+            instrs_support: sorted(slice.instrs_support.iter()),
+            globals: slice.globals.keys()
+                .map(|(gid, instr_idx)| (*instr_idx, GlobalSidecar::new(*gid, &slice.global_sources[gid])))
+                .collect(),
+            reductions: slice.reductions.iter().map(|(pass, stats)| (pass.to_string(), *stats)).collect()
+        }
+    }
+}
+
+impl From<&GeneratedFunc> for ManifestEntry {
+    fn from(func: &GeneratedFunc) -> Self {
+        let req_state = func.req_state.iter().map(|(ty, reqs)| {
+            (format!("{ty:?}"), reqs.iter().map(|(idx, req)| {
+                (*idx, req.req_state.iter().map(|val| val.to_string()).collect())
+            }).collect())
+        }).collect();
+        Self {
+            fid: func.fid,
+            fname: func.fname.clone(),
+            req_state,
+            call_indirect_tables: func.call_indirect_tables.clone(),
+            load_memories: func.req_state.get(&StateType::Load).into_iter().flatten()
+                .filter_map(|(instr_idx, req)| Some((*instr_idx, req.memory?)))
+                .collect(),
+            globals: func.global_sources.iter()
+                .map(|(instr_idx, (gid, source))| (*instr_idx, GlobalSidecar::new(*gid, source)))
+                .collect(),
+            fallback: func.fallback.clone(),
+            checkpoints: func.checkpoints.clone(),
+            param_names: func.param_names.clone()
+        }
+    }
+}
+
+impl GlobalSidecar {
+    fn new(gid: u32, source: &GlobalSource) -> Self {
+        let import = match source {
+            GlobalSource::Local => None,
+            GlobalSource::Import { module, name } => Some((module.clone(), name.clone()))
+        };
+        Self { gid, import }
+    }
+}
+
+fn func_sidecars(slices: &[SliceResult], cost_maps: &[BTreeMap<usize, u64>]) -> Vec<FuncSidecar> {
+    slices.iter().zip(cost_maps.iter()).map(|(result, costs)| FuncSidecar {
+        fid: result.fid,
+        slices: result.slices.iter().map(|(idx, slice)| (*idx, SliceSidecar::from(slice))).collect(),
+        costs: costs.clone()
+    }).collect()
+}
+
+fn sorted<'a>(idxs: impl Iterator<Item = &'a usize>) -> Vec<usize> {
+    let mut idxs: Vec<usize> = idxs.copied().collect();
+    idxs.sort();
+    idxs
+}
+
+/// The stable ID of the fuel point at `instr_idx` of the original function `fid`: `fid << 32 | instr_idx`.
+/// Runtime traces from other instrumentation can be joined with the static costs on it.
+pub fn checkpoint_id(fid: u32, instr_idx: usize) -> u64 {
+    ((fid as u64) << 32) | instr_idx as u64
+}
+
+/// Where the sidecar for the module at `module_path` lives: `foo.wasm` -> `foo.fuel.json`
+pub fn sidecar_path(module_path: &Path) -> PathBuf {
+    module_path.with_extension(SIDECAR_EXT)
+}
+
+#[cfg(feature = "fs")]
+pub fn write_sidecar(path: &Path, sidecar: &Sidecar) -> anyhow::Result<()> {
+    std::fs::write(path, serde_json::to_string_pretty(sidecar)?).with_context(|| format!("Failed to write the sidecar {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(feature = "fs")]
+pub fn write_json_report(path: &Path, report: &JsonReport) -> anyhow::Result<()> {
+    std::fs::write(path, serde_json::to_string_pretty(report)?).with_context(|| format!("Failed to write the JSON report {}", path.display()))?;
+    Ok(())
+}
+
+/// Load the analysis results that were stored next to the module at `module_path`.
+#[cfg(feature = "fs")]
+pub fn load_sidecar(module_path: &Path) -> anyhow::Result<Sidecar> {
+    let path = sidecar_path(module_path);
+    let data = std::fs::read_to_string(&path).with_context(|| format!("Failed to read the sidecar {}", path.display()))?;
+    serde_json::from_str(&data).with_context(|| format!("Malformed sidecar {}", path.display()))
+}
@@ -0,0 +1,62 @@
+use wirm::wasmparser::{ExternalKind, Parser, Payload, TypeRef};
+
+/// The size budget of a generated module.
+#[derive(Debug, Clone, Copy)]
+pub struct OutputBudget {
+    pub max_bytes: usize,
+    /// Whether exceeding the budget fails the analysis (otherwise it's only reported)
+    pub fail: bool
+}
+
+/// Attributes the bytes of an encoded (generated) module to each of its functions.
+#[derive(Debug, Default)]
+pub struct SizeReport {
+    pub total: usize,
+    /// (export name, size of the function's body in bytes)
+    pub funcs: Vec<(String, usize)>
+}
+impl SizeReport {
+    pub fn new(wasm_bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut num_imported = 0;
+        let mut names = Vec::new();
+        let mut bodies = Vec::new();
+        for payload in Parser::new(0).parse_all(wasm_bytes) {
+            match payload? {
+                Payload::ImportSection(imports) => {
+                    for import in imports {
+                        if matches!(import?.ty, TypeRef::Func(_)) {
+                            num_imported += 1;
+                        }
+                    }
+                }
+                Payload::ExportSection(exports) => {
+                    for export in exports {
+                        let export = export?;
+                        if matches!(export.kind, ExternalKind::Func) {
+                            names.push((export.index, export.name.to_string()));
+                        }
+                    }
+                }
+                Payload::CodeSectionEntry(body) => bodies.push(body.range().len()),
+                _ => {}
+            }
+        }
+
+        let funcs = bodies.into_iter().enumerate().map(|(i, size)| {
+            let fid = num_imported + i as u32;
+            let name = names.iter()
+                .find(|(index, _)| *index == fid)
+                .map(|(_, name)| name.clone())
+                .unwrap_or_else(|| format!("#{fid}"));
+            (name, size)
+        }).collect();
+        Ok(Self {
+            total: wasm_bytes.len(),
+            funcs
+        })
+    }
+
+    pub fn exceeds(&self, budget: &OutputBudget) -> bool {
+        self.total > budget.max_bytes
+    }
+}
@@ -1,21 +1,42 @@
-use std::collections::{HashMap, HashSet, VecDeque};
-use wirm::ir::id::{FunctionID, GlobalID, TypeID};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use wirm::ir::id::{FunctionID, GlobalID, MemoryID, TypeID};
 use wirm::ir::module::module_types::Types;
 use wirm::{DataType, Module};
 use wirm::ir::module::module_globals::{GlobalKind, ImportedGlobal, LocalGlobal};
-use wirm::wasmparser::Operator;
+use wirm::wasmparser::{Operator, TypeRef};
 use crate::analyze::{FuncState, InstrInfo, OpKind, Origin};
-use crate::utils::{find_subsection_end, is_branching_op, is_loop};
+use crate::error::{fail, AnalysisError};
+use crate::globals::{const_value, ConstValue, GlobalDefs};
+use crate::pdg::Pdg;
+use crate::reduce::{ReductionPass, ReductionStats};
+use crate::serial::{load_map, sorted_set, type_map};
+use crate::utils::{dyn_cost_per_elem, field_type, find_subsection_end, has_side_effects, is_branching_op, is_loop, is_tail_call, length_type, local_func, local_ops, shim_name, table_elem_type, SKIP_SHIMS};
 
 /// Result of the slice analysis.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct SliceResult {
     pub(crate) fid: u32,
     pub(crate) total_params: usize,
     /// Maps from instr_idx -> Slice
     /// There can be 1->many slices for a function
     /// due to how we're handling `loop` blocks!
-    pub(crate) slices: HashMap<usize, Slice>,
+    pub(crate) slices: BTreeMap<usize, Slice>,
+    /// The instructions that only compute values that are eventually `drop`ped
+    /// (including the `drop` itself).
+    #[serde(with = "sorted_set")]
+    pub(crate) dropped: HashSet<usize>,
+    /// The op that the analysis can't model, if set there's a single slice that only
+    /// charges the worst-case cost of the whole body (see `codegen::gen_const_func`).
+    pub(crate) fallback: Option<String>,
+    /// The user-specified instructions to slice from (see `SliceCriterion`)
+    pub(crate) criteria: BTreeSet<usize>,
+    /// The name of the function if it's toolchain glue that's skipped (see `utils::SKIP_SHIMS`),
+    /// it then has no slices and no code is generated for it.
+    #[serde(default)]
+    pub(crate) shim: Option<String>,
 }
 impl SliceResult {
     fn new(fid: u32, total_params: usize) -> Self {
@@ -26,87 +47,453 @@ impl SliceResult {
     fn add_slice(&mut self, instr_idx: usize, slice: Slice) {
         self.slices.insert(instr_idx, slice);
     }
+
+    /// The ID of the sliced function
+    pub fn fid(&self) -> u32 {
+        self.fid
+    }
+
+    /// The slices of the function, keyed by their first instruction: the whole body (at 0)
+    /// and each loop's body (a generated function is emitted per slice)
+    pub fn slices(&self) -> &BTreeMap<usize, Slice> {
+        &self.slices
+    }
+
+    /// The op that the analysis can't model, see `fallback`
+    pub fn fallback(&self) -> Option<&str> {
+        self.fallback.as_deref()
+    }
 }
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Slice {
     pub(crate) start_instr_idx: usize,  // (inclusive)
     pub(crate) end_instr_idx: usize,    // (exclusive)
     pub(crate) spec_name: String,
+    /// the number of loops around the slice's instructions (the nested loops are their own slices)
+    pub(crate) loop_depth: u32,
     /// all instruction indices that are in the MAXIMAL backward slice (influencing control).
+    #[serde(with = "sorted_set")]
     pub(crate) max_slice: HashSet<usize>,
     /// all instruction indices that are in the MINIMAL backward slice (influencing control).
+    #[serde(with = "sorted_set")]
     pub(crate) min_slice: HashSet<usize>,
     /// all instruction indices that are included for support purposes (block structure)
+    #[serde(with = "sorted_set")]
     pub(crate) instrs_support: HashSet<usize>,
     /// local.get instruction indices that tie back to a
     /// function parameter that influence control
     /// remembers the parameter type as well.
-    pub(crate) params: HashMap<(u32, usize), DataType>,         // (local_id, instr_idx) -> datatype
+    #[serde(with = "type_map")]
+    pub(crate) params: BTreeMap<(u32, usize), DataType>,         // (local_id, instr_idx) -> datatype
     /// global.get instruction indices that influence control
     /// remembers the parameter type as well.
-    pub(crate) globals: HashMap<(u32, usize), DataType>,        // (local_id, instr_idx) -> datatype
+    #[serde(with = "type_map")]
+    pub(crate) globals: BTreeMap<(u32, usize), DataType>,        // (local_id, instr_idx) -> datatype
+    /// global.get instruction indices of immutable globals whose value is known statically (see
+    /// `globals::const_value`), the generated functions replay the value rather than requesting it
+    pub(crate) const_globals: BTreeMap<usize, ConstValue>,       // instr_idx -> value
+    /// where each global in `globals` is defined (local or imported)
+    pub(crate) global_sources: BTreeMap<u32, GlobalSource>,   // gid -> source
+    /// global.get instruction indices that read the same def as a global.get in `globals`,
+    /// they share its requested state (see `GlobalDefs::is_stable_in`).
+    pub(crate) global_aliases: BTreeMap<usize, usize>,           // instr_idx -> instr_idx in `globals`
     /// load instruction indices that influence control
     /// remembers the value's type as well.
-    pub(crate) loads: HashMap<usize, DataType>,
+    #[serde(with = "load_map")]
+    pub(crate) loads: BTreeMap<(u32, usize), DataType>,          // (memory, instr_idx) -> datatype
     /// call instruction indices that influence control
     /// AND the actually-used result of that call
     /// remembers the value's type as well.
-    pub(crate) calls: HashMap<(usize, usize), DataType>,
+    #[serde(with = "type_map")]
+    pub(crate) calls: BTreeMap<(usize, usize), DataType>,
     /// call_indirect instruction indices that influence control
     /// AND the actually-used result of that call
     /// remembers the value's type as well.
-    pub(crate) call_indirects: HashMap<(usize, usize), DataType>,
+    #[serde(with = "type_map")]
+    pub(crate) call_indirects: BTreeMap<(usize, usize), DataType>,
+    /// the table that each call_indirect above calls through
+    pub(crate) call_indirect_tables: BTreeMap<usize, u32>,            // instr_idx -> table_index
+    /// call_ref instruction indices that influence control
+    /// AND the actually-used result of that call
+    /// remembers the value's type as well.
+    #[serde(with = "type_map")]
+    pub(crate) call_refs: BTreeMap<(usize, usize), DataType>,
+    /// table.get instruction indices that influence control
+    /// remembers the reference's type as well.
+    #[serde(with = "type_map")]
+    pub(crate) table_gets: BTreeMap<usize, DataType>,
+    /// struct/array read instruction indices that influence control
+    /// remembers the field's type as well.
+    #[serde(with = "type_map")]
+    pub(crate) field_loads: BTreeMap<usize, DataType>,
+
+    /// bulk op instruction indices whose cost depends on their length operand
+    /// (the length is requested as state), remembers the length's type as well.
+    #[serde(with = "type_map")]
+    pub(crate) lengths: BTreeMap<usize, DataType>,
 
     /// This is for the minimum slice, stores the needed `taken` state
-    pub(crate) taken: HashMap<usize, DataType>,
+    #[serde(with = "type_map")]
+    pub(crate) taken: BTreeMap<usize, DataType>,
+
+    /// The `end`s of the blocks whose results the slice traces, and the instructions producing them
+    /// (in each arm). The other blocks are generated without their results, nothing computes them.
+    #[serde(default)]
+    pub(crate) merged_results: BTreeMap<usize, Vec<usize>>,             // end instr_idx -> producers
+
+    /// The user-specified criteria that aren't otherwise in the slice and the instructions producing
+    /// the values they consume. The criterion isn't replayed, its inputs are computed and dropped.
+    pub(crate) criteria: BTreeMap<usize, Vec<usize>>,
+
+    /// What each pass of the reduction pipeline removed from the min slice (see `reduce_with`)
+    pub(crate) reductions: BTreeMap<ReductionPass, ReductionStats>,
 }
 
-pub fn slice_program(func_taints: &[FuncState], wasm: &Module) -> Vec<SliceResult> {
-    let mut results = Vec::new();
-    for taint in func_taints.iter() {
-        let lf = wasm.functions.unwrap_local(FunctionID(taint.fid));
-        let Some(Types::FuncType { params , ..}) = wasm.types.get(lf.ty_id) else {
-            panic!("Should have found a function type!");
+impl Slice {
+    /// The instructions of the slice, `start..end` (the nested loops are their own slices)
+    pub fn instr_range(&self) -> std::ops::Range<usize> {
+        self.start_instr_idx..self.end_instr_idx
+    }
+
+    /// The suffix of the generated function's name, e.g. `_loop_at_3`
+    pub fn spec_name(&self) -> &str {
+        &self.spec_name
+    }
+
+    pub fn loop_depth(&self) -> u32 {
+        self.loop_depth
+    }
+
+    /// The instructions that influence the control flow (replayed by the max variant)
+    pub fn max_slice(&self) -> &HashSet<usize> {
+        &self.max_slice
+    }
+
+    /// The instructions that the min variant replays (the branches), it requests whether they're taken
+    pub fn min_slice(&self) -> &HashSet<usize> {
+        &self.min_slice
+    }
+
+    /// The reads of the globals whose value is known statically, instr_idx -> the value
+    pub fn const_globals(&self) -> &BTreeMap<usize, ConstValue> {
+        &self.const_globals
+    }
+
+    /// What each reduction pass that ran removed from the min slice, in the order they ran
+    pub fn reductions(&self) -> &BTreeMap<ReductionPass, ReductionStats> {
+        &self.reductions
+    }
+}
+
+/// Where a global is defined. An imported global is host-controlled state, its value has to be
+/// harvested from the host (and it can change across host calls) rather than from the module.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GlobalSource {
+    Local,
+    Import { module: String, name: String }
+}
+impl GlobalSource {
+    fn new(gid: u32, wasm: &Module) -> Self {
+        match wasm.globals.get_kind(GlobalID(gid)) {
+            GlobalKind::Local(_) => Self::Local,
+            GlobalKind::Import(ImportedGlobal { import_id, .. }) => {
+                let import = wasm.imports.get(*import_id);
+                Self::Import { module: import.module.to_string(), name: import.name.to_string() }
+            }
+        }
+    }
+}
+impl Display for GlobalSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Local => write!(f, "local"),
+            Self::Import { module, name } => write!(f, "imported from \"{module}\".\"{name}\"")
+        }
+    }
+}
+
+/// A user-specified slicing criterion (`--slice-at FID:INSTR_IDX`): the values consumed by the
+/// instruction are sliced backward, as for the control-flow sinks that are detected automatically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SliceCriterion {
+    pub fid: u32,
+    pub instr_idx: usize
+}
+impl Display for SliceCriterion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.fid, self.instr_idx)
+    }
+}
+impl FromStr for SliceCriterion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s.split_once(':').and_then(|(fid, instr_idx)| Some(SliceCriterion {
+            fid: fid.trim().parse().ok()?,
+            instr_idx: instr_idx.trim().parse().ok()?
+        }));
+        parsed.ok_or(format!("Expected a slicing criterion as FID:INSTR_IDX, got: {}", s))
+    }
+}
+
+/// Decides which ops are slicing sinks besides the control flow (that the fuel always depends on),
+/// e.g. `memory.grow` or the calls to some imports: the values they consume are sliced backward, as
+/// for a `SliceCriterion`. This way the slices (and the requested state) follow a domain-specific
+/// policy, see `AnalysisConfig::sink`.
+pub trait SinkDetector: Debug {
+    fn is_sink(&self, fid: u32, instr_idx: usize, op: &Operator, wasm: &Module) -> bool;
+}
+
+/// The `memory.grow`s: slices the number of pages that they request
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryGrowSinks;
+impl SinkDetector for MemoryGrowSinks {
+    fn is_sink(&self, _fid: u32, _instr_idx: usize, op: &Operator, _wasm: &Module) -> bool {
+        matches!(op, Operator::MemoryGrow { .. })
+    }
+}
+
+/// The calls to these imported functions (by module and name): slices their arguments
+#[derive(Debug, Clone)]
+pub struct ImportCallSinks {
+    pub imports: BTreeSet<(String, String)>
+}
+impl SinkDetector for ImportCallSinks {
+    fn is_sink(&self, _fid: u32, _instr_idx: usize, op: &Operator, wasm: &Module) -> bool {
+        let Operator::Call { function_index } = op else {
+            return false;
         };
-        let mut result = SliceResult::new(taint.fid, taint.total_params);
-        slice(&mut result, taint.fid, "".to_string(), 0, &taint.instrs, params, wasm);
-        results.push(result);
+        wasm.imports.iter()
+            .filter(|import| matches!(import.ty, TypeRef::Func(_)))
+            .nth(*function_index as usize)
+            .is_some_and(|import| self.imports.contains(&(import.module.to_string(), import.name.to_string())))
+    }
+}
+
+/// The writes to these globals (e.g. a balance or gas global): slices the written values, see
+/// `AnalysisConfig::mutation_sink`
+#[derive(Debug, Clone)]
+pub struct GlobalSetSinks {
+    pub globals: BTreeSet<u32>
+}
+impl SinkDetector for GlobalSetSinks {
+    fn is_sink(&self, _fid: u32, _instr_idx: usize, op: &Operator, _wasm: &Module) -> bool {
+        matches!(op, Operator::GlobalSet { global_index } if self.globals.contains(global_index))
+    }
+}
+
+/// The criteria at the sinks that the `detectors` find in the analyzed functions (the control flow
+/// is already sliced, it's skipped)
+pub(crate) fn detect_sinks(detectors: &[Box<dyn SinkDetector>], func_taints: &[FuncState], wasm: &Module) -> Result<Vec<SliceCriterion>, AnalysisError> {
+    let mut criteria = vec![];
+    if detectors.is_empty() {
+        return Ok(criteria);
+    }
+    for taint in func_taints.iter().filter(|taint| taint.fallback.is_none()) {
+        let ops = local_ops(taint.fid, wasm)?;
+        for (instr_idx, (info, op)) in taint.instrs.iter().zip(ops.iter()).enumerate() {
+            if !matches!(info.kind, OpKind::Control) && detectors.iter().any(|detector| detector.is_sink(taint.fid, instr_idx, op, wasm)) {
+                criteria.push(SliceCriterion { fid: taint.fid, instr_idx });
+            }
+        }
+    }
+    Ok(criteria)
+}
+
+/// Whether the op traps on some values of its operands: the integer divisions and remainders (by
+/// zero, or `MIN / -1`) and the float-to-int truncations (NaN or out of range, the saturating ones don't)
+fn may_trap(op: &Operator) -> bool {
+    matches!(op,
+        Operator::I32DivS | Operator::I32DivU | Operator::I32RemS | Operator::I32RemU |
+        Operator::I64DivS | Operator::I64DivU | Operator::I64RemS | Operator::I64RemU |
+        Operator::I32TruncF32S | Operator::I32TruncF32U | Operator::I32TruncF64S | Operator::I32TruncF64U |
+        Operator::I64TruncF32S | Operator::I64TruncF32U | Operator::I64TruncF64S | Operator::I64TruncF64U)
+}
+
+/// The criteria at the ops that may trap (see `may_trap`) on operands that depend on the state of
+/// the function (a param, global, load, call...), as whether they trap decides the control flow.
+/// The operands that are only computed from constants can't trap differently from run to run, and
+/// a value that the analysis doesn't track counts as depending on the state.
+pub(crate) fn detect_trap_sinks(func_taints: &[FuncState], wasm: &Module) -> Result<Vec<SliceCriterion>, AnalysisError> {
+    let mut criteria = vec![];
+    for taint in func_taints.iter().filter(|taint| taint.fallback.is_none()) {
+        let ops = local_ops(taint.fid, wasm)?;
+        // instr_idx -> whether its results depend on the state, producers come before their consumers
+        let mut on_state: Vec<bool> = Vec::with_capacity(taint.instrs.len());
+        for (instr_idx, (info, op)) in taint.instrs.iter().zip(ops.iter()).enumerate() {
+            let tainted = info.inputs.iter().any(|origin| match origin {
+                Origin::Instr { instr_idx } => on_state.get(*instr_idx).copied().unwrap_or(true),
+                _ => true
+            });
+            if tainted && may_trap(op) {
+                criteria.push(SliceCriterion { fid: taint.fid, instr_idx });
+            }
+            on_state.push(tainted);
+        }
+    }
+    Ok(criteria)
+}
+
+/// Slice every analyzed function backward from its control flow (and from the `criteria`):
+/// one `SliceResult` per `FuncState`, in the same order.
+pub fn slice_program(func_taints: &[FuncState], criteria: &[SliceCriterion], wasm: &Module) -> Result<Vec<SliceResult>, AnalysisError> {
+    slice_program_replaying(func_taints, criteria, &BTreeSet::new(), wasm)
+}
+
+/// `slice_program`, but the calls to the functions in `replayed` (pure, see `replay::replayable_funcs`)
+/// are replayed: their arguments are sliced instead of requesting their results.
+pub(crate) fn slice_program_replaying(func_taints: &[FuncState], criteria: &[SliceCriterion], replayed: &BTreeSet<u32>, wasm: &Module) -> Result<Vec<SliceResult>, AnalysisError> {
+    let global_defs = GlobalDefs::new(wasm);
+    func_taints.iter()
+        .map(|taint| slice_func(taint, criteria, replayed, &global_defs, wasm))
+        .collect()
+}
+
+/// Slice a single function, `global_defs` are of the whole module
+pub(crate) fn slice_func(taint: &FuncState, criteria: &[SliceCriterion], replayed: &BTreeSet<u32>, global_defs: &GlobalDefs, wasm: &Module) -> Result<SliceResult, AnalysisError> {
+    let lf = local_func(taint.fid, wasm)?;
+    let Some(Types::FuncType { params , ..}) = wasm.types.get(lf.ty_id) else {
+        fail!(AnalysisError::MissingFuncType { tid: *lf.ty_id });
+    };
+    let mut result = SliceResult::new(taint.fid, taint.total_params);
+    result.criteria = criteria.iter().filter(|criterion| criterion.fid == taint.fid).map(|criterion| criterion.instr_idx).collect();
+    if SKIP_SHIMS {
+        if let Some(name) = shim_name(taint.fid, wasm) {
+            result.shim = Some(name);
+            return Ok(result);
+        }
+    }
+    if let Some(op) = &taint.fallback {
+        result.fallback = Some(op.clone());
+        result.add_slice(0, Slice {
+            start_instr_idx: 0,
+            end_instr_idx: lf.body.instructions.len(),
+            ..Default::default()
+        });
+        return Ok(result);
+    }
+    // a local can be requested like a param (see `Origin::Param`)
+    let local_types: Vec<DataType> = params.iter().copied()
+        .chain(lf.body.locals.iter().flat_map(|(count, ty)| std::iter::repeat_n(*ty, *count as usize)))
+        .collect();
+    slice(&mut result, taint.fid, "".to_string(), 0, &taint.instrs, &Pdg::new(taint, wasm)?, &local_types, replayed, wasm)?;
+    share_stable_globals(&mut result, global_defs, wasm);
+    result.dropped = dropped_computations(&taint.instrs, lf.body.instructions.get_ops());
+    Ok(result)
+}
+
+/// The reads of a global that keeps its value during the function's execution all depend on the
+/// same def, only request the state for the first read and alias the others to it.
+fn share_stable_globals(result: &mut SliceResult, global_defs: &GlobalDefs, wasm: &Module) {
+    for slice in result.slices.values_mut() {
+        // gid -> the first read of the global
+        let mut first_reads: HashMap<u32, usize> = HashMap::new();
+        let mut aliases = Vec::new();
+        for (gid, instr_idx) in slice.globals.keys() {
+            if !global_defs.is_stable_in(*gid, result.fid, wasm) {
+                continue;
+            }
+            match first_reads.get(gid) {
+                Some(first) => aliases.push(((*gid, *instr_idx), *first)),
+                None => { first_reads.insert(*gid, *instr_idx); }
+            }
+        }
+        for (key, first) in aliases {
+            slice.globals.remove(&key);
+            slice.global_aliases.insert(key.1, first);
+        }
     }
-    results
 }
 
-fn slice(result: &mut SliceResult, fid: u32, spec_name: String, true_start: usize, instrs_info: &[InstrInfo], func_params: &[DataType], wasm: &Module) {
-    let op_at = |instr_idx: usize| -> &Operator {
-        let lf = wasm.functions.unwrap_local(FunctionID(fid));
-        lf.body.instructions.get_ops().get(instr_idx).unwrap()
+/// Find the instructions whose results only flow into a `drop`.
+fn dropped_computations(instrs_info: &[InstrInfo], body: &[Operator]) -> HashSet<usize> {
+    // instr_idx -> the instructions that consume its result
+    let mut consumers: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, info) in instrs_info.iter().enumerate() {
+        for inp in info.inputs.iter() {
+            if let Origin::Instr { instr_idx } | Origin::Load { instr_idx, .. } | Origin::TableGet { instr_idx, .. }
+                | Origin::FieldLoad { instr_idx } = inp {
+                consumers.entry(*instr_idx).or_default().push(i);
+            }
+        }
+    }
+
+    // consumers always come after their producers, so visit in reverse
+    let mut dropped = HashSet::new();
+    for i in (0..instrs_info.len()).rev() {
+        let op = &body[i];
+        if matches!(op, Operator::Drop) {
+            dropped.insert(i);
+        } else if !has_side_effects(op) {
+            if let Some(uses) = consumers.get(&i) {
+                if uses.iter().all(|u| dropped.contains(u)) {
+                    dropped.insert(i);
+                }
+            }
+        }
+    }
+    dropped
+}
+
+#[allow(clippy::too_many_arguments)]
+fn slice(result: &mut SliceResult, fid: u32, spec_name: String, true_start: usize, instrs_info: &[InstrInfo], pdg: &Pdg, func_params: &[DataType], replayed: &BTreeSet<u32>, wasm: &Module) -> Result<(), AnalysisError> {
+    let op_at = |instr_idx: usize| -> Result<&Operator, AnalysisError> {
+        let lf = local_func(fid, wasm)?;
+        let Some(op) = lf.body.instructions.get_ops().get(instr_idx) else {
+            fail!(AnalysisError::MissingInstr { fid, instr_idx });
+        };
+        Ok(op)
+    };
+    // the `instrs_info` are of this slice's instructions, from `true_start`
+    let inputs_of = |instr_idx: usize| -> Vec<Origin> {
+        instr_idx.checked_sub(true_start).and_then(|i| instrs_info.get(i)).map(|info| info.inputs.clone()).unwrap_or_default()
     };
     // Start from control instructions' inputs
     let mut worklist: VecDeque<Origin> = VecDeque::new();
     let mut included_instrs: HashSet<usize> = HashSet::new();
+    // the `end`s of the blocks whose (merged) results were traced, they aren't part of the slice
+    let mut merged_results: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
     // TODO -- track this as included instruction results! Not as the value at the end of a function!
-    let mut included_params: HashMap<(u32, usize), DataType> = HashMap::new();
-    let mut included_globals: HashMap<(u32, usize), DataType> = HashMap::new();
-    let mut included_loads: HashMap<usize, DataType> = HashMap::new();
-    let mut included_calls: HashMap<(usize, usize), DataType> = HashMap::new(); // the call_idx AND the result_idx used
-    let mut included_call_indirects: HashMap<(usize, usize), DataType> = HashMap::new();
+    let mut included_params: BTreeMap<(u32, usize), DataType> = BTreeMap::new();
+    let mut included_globals: BTreeMap<(u32, usize), DataType> = BTreeMap::new();
+    let mut included_global_sources: BTreeMap<u32, GlobalSource> = BTreeMap::new();
+    let mut included_const_globals: BTreeMap<usize, ConstValue> = BTreeMap::new();
+    let mut included_loads: BTreeMap<(u32, usize), DataType> = BTreeMap::new();
+    let mut included_calls: BTreeMap<(usize, usize), DataType> = BTreeMap::new(); // the call_idx AND the result_idx used
+    let mut included_call_indirects: BTreeMap<(usize, usize), DataType> = BTreeMap::new();
+    let mut included_call_indirect_tables: BTreeMap<usize, u32> = BTreeMap::new();
+    let mut included_call_refs: BTreeMap<(usize, usize), DataType> = BTreeMap::new();
+    let mut included_table_gets: BTreeMap<usize, DataType> = BTreeMap::new();
+    let mut included_field_loads: BTreeMap<usize, DataType> = BTreeMap::new();
+    let mut included_lengths: BTreeMap<usize, DataType> = BTreeMap::new();
+    let mut criteria: Vec<usize> = Vec::new();
 
     let mut i = 0;
     while i < instrs_info.len() {
         let true_instr_idx = true_start + i;
         let info = &instrs_info[i];
 
-        if is_loop(true_instr_idx, op_at(true_instr_idx)).is_some() {
-            let lf = wasm.functions.unwrap_local(FunctionID(fid));
+        if result.criteria.contains(&true_instr_idx) {
+            // a user-specified criterion, slice from its inputs
+            worklist.extend(info.inputs.iter().cloned());
+            criteria.push(true_instr_idx);
+        }
+
+        if is_loop(true_instr_idx, op_at(true_instr_idx)?).is_some() {
+            let lf = local_func(fid, wasm)?;
             let body = lf.body.instructions.get_ops();
-            let end = find_subsection_end(&body[i+1..]); // exclusive end index within body[i+1..]
+            let end = find_subsection_end(&body[true_instr_idx+1..]); // exclusive end index within body[true_instr_idx+1..]
             let sub_sec = &instrs_info[i+1..i+1+end];
 
             // Recurse on the subsection
             let spec_name = format!("_loop_at_{true_instr_idx}");
-            slice(result, fid, spec_name, true_instr_idx + 1, sub_sec, func_params, wasm);
+            slice(result, fid, spec_name, true_instr_idx + 1, sub_sec, pdg, func_params, replayed, wasm)?;
 
             // Move i past the subsection so we don't reprocess it (skip special opcode and its END)
             i += end + 1;
+        } else if dyn_cost_per_elem(op_at(true_instr_idx)?).is_some() {
+            // the cost depends on the runtime length, request it
+            included_lengths.insert(true_instr_idx, length_type(op_at(true_instr_idx)?, wasm));
         } else if let OpKind::Control = info.kind {
             // any input to this control op is a starting point of the backward slice
             for inp in &info.inputs {
@@ -118,123 +505,251 @@ fn slice(result: &mut SliceResult, fid: u32, spec_name: String, true_start: usiz
         i += 1;
     }
 
+    // The innermost `if` around each instruction of this slice (the ones around the slice are the
+    // parent slice's)
+    let slice_range = true_start..true_start + instrs_info.len();
+
     // Trace origins backwards
-    while let Some(origin) = worklist.pop_front() {
-        match origin {
-            Origin::Instr {instr_idx} => {
-                // if this instruction already included, skip
-                if !included_instrs.insert(instr_idx) {
-                    continue;
+    loop {
+        while let Some(origin) = worklist.pop_front() {
+            match origin {
+                Origin::Instr {instr_idx} => {
+                    if matches!(op_at(instr_idx)?, Operator::End) {
+                        // the results of an `if`, trace what computes them in both arms (the
+                        // structure around them is kept by `save_structure`)
+                        if merged_results.contains_key(&instr_idx) {
+                            continue;
+                        }
+                        merged_results.insert(instr_idx, inputs_of(instr_idx).iter().filter_map(Origin::instr_idx).collect());
+                    } else if !included_instrs.insert(instr_idx) {
+                        // if this instruction already included, skip
+                        continue;
+                    }
+                    // push its inputs to the worklist
+                    for inp in inputs_of(instr_idx) {
+                        worklist.push_back(inp);
+                    }
                 }
-                // push its inputs to the worklist
-                for inp in instrs_info.get(instr_idx).map(|i| i.inputs.clone()).unwrap_or_default() {
-                    worklist.push_back(inp);
+
+                Origin::Load {instr_idx, memory} => {
+                    let load_ty = match op_at(instr_idx)? {
+                        Operator::I32Load { .. }
+                        | Operator::I32Load8S { .. }
+                        | Operator::I32Load8U { .. }
+                        | Operator::I32Load16S { .. }
+                        | Operator::I32Load16U { .. } => DataType::I32,
+                        Operator::I64Load { .. }
+                        | Operator::I64Load8S { .. }
+                        | Operator::I64Load8U { .. }
+                        | Operator::I64Load16S { .. }
+                        | Operator::I64Load16U { .. }
+                        | Operator::I64Load32S { .. }
+                        | Operator::I64Load32U { .. } => DataType::I64,
+                        Operator::F32Load { .. } => DataType::F32,
+                        Operator::F64Load { .. } => DataType::F64,
+                        Operator::MemorySize { mem } | Operator::MemoryGrow { mem } => {
+                            let memory64 = wasm.memories.get_mem_by_id(MemoryID(*mem)).is_some_and(|memory| memory.ty.memory64);
+                            if memory64 { DataType::I64 } else { DataType::I32 }
+                        }
+                        op => fail!(AnalysisError::UnsupportedOp { op: format!("{op:?}") })
+                    };
+
+                    // Mark the load itself as influencing control
+                    if included_loads.insert((memory, instr_idx), load_ty).is_some() {
+                        continue;
+                    }
+
+                    // also include the load instruction index in the instr set
+                    included_instrs.insert(instr_idx);
                 }
-            }
 
-            Origin::Load {instr_idx} => {
-                let load_ty = match op_at(instr_idx) {
-                    Operator::I32Load { .. }
-                    | Operator::I32Load8S { .. }
-                    | Operator::I32Load8U { .. }
-                    | Operator::I32Load16S { .. }
-                    | Operator::I32Load16U { .. } => DataType::I32,
-                    Operator::I64Load { .. }
-                    | Operator::I64Load8S { .. }
-                    | Operator::I64Load8U { .. }
-                    | Operator::I64Load16S { .. }
-                    | Operator::I64Load16U { .. }
-                    | Operator::I64Load32S { .. }
-                    | Operator::I64Load32U { .. } => DataType::I64,
-                    Operator::F32Load { .. } => DataType::F32,
-                    Operator::F64Load { .. } => DataType::F64,
-                    op => panic!("Load opcode not supported: {op:?}")
-                };
-
-                // Mark the load itself as influencing control
-                if included_loads.insert(instr_idx, load_ty).is_some() {
-                    continue;
+                Origin::Call {instr_idx, result_idx} => {
+                    if matches!(op_at(instr_idx)?, Operator::Call { function_index } if replayed.contains(function_index)) {
+                        // the call is replayed, slice its arguments
+                        if included_instrs.insert(instr_idx) {
+                            for inp in inputs_of(instr_idx) {
+                                worklist.push_back(inp);
+                            }
+                        }
+                        continue;
+                    }
+                    let call_arg_ty = match op_at(instr_idx)? {
+                        Operator::Call { function_index } => {
+                            let tid = wasm.functions.get_type_id(FunctionID(*function_index));
+                            let Some(Types::FuncType { results, ..}) = wasm.types.get(tid) else {
+                                fail!(AnalysisError::MissingFuncType { tid: *tid });
+                            };
+                            let Some(ty) = results.get(result_idx) else {
+                                fail!(AnalysisError::MissingCallResult { fid, instr_idx, result_idx });
+                            };
+                            *ty
+                        },
+                        op => fail!(AnalysisError::UnsupportedOp { op: format!("{op:?}") })
+                    };
+
+                    // Mark the call itself as influencing control
+                    if included_calls.insert((instr_idx, result_idx), call_arg_ty).is_some() {
+                        continue;
+                    }
+                    // also include the call instruction index in the instr set
+                    included_instrs.insert(instr_idx);
                 }
 
-                // also include the load instruction index in the instr set
-                included_instrs.insert(instr_idx);
-            }
+                Origin::CallIndirect {instr_idx, result_idx, table_index} => {
+                    let call_arg_ty = match op_at(instr_idx)? {
+                        Operator::CallIndirect { type_index, .. } => {
+                            let Some(Types::FuncType { results, ..}) = wasm.types.get(TypeID(*type_index)) else {
+                                fail!(AnalysisError::MissingFuncType { tid: *type_index });
+                            };
+                            let Some(ty) = results.get(result_idx) else {
+                                fail!(AnalysisError::MissingCallResult { fid, instr_idx, result_idx });
+                            };
+                            *ty
+                        },
+                        op => fail!(AnalysisError::UnsupportedOp { op: format!("{op:?}") })
+                    };
 
-            Origin::Call {instr_idx, result_idx} => {
-                let call_arg_ty = match op_at(instr_idx) {
-                    Operator::Call { function_index } => {
-                        let Some(Types::FuncType { results, ..}) = wasm.types.get(wasm.functions.get_type_id(FunctionID(*function_index))) else {
-                            panic!("Should have found a function type!");
-                        };
-                        *results.get(result_idx).unwrap()
-                    },
-                    op => panic!("Call opcode not supported: {op:?}")
-                };
-
-                // Mark the call itself as influencing control
-                if included_calls.insert((instr_idx, result_idx), call_arg_ty).is_some() {
-                    continue;
+                    // Mark the call itself as influencing control
+                    if included_call_indirects.insert((instr_idx, result_idx), call_arg_ty).is_some() {
+                        continue;
+                    }
+                    included_call_indirect_tables.insert(instr_idx, table_index);
+                    // also include the call instruction index in the instr set
+                    included_instrs.insert(instr_idx);
                 }
-                // also include the call instruction index in the instr set
-                included_instrs.insert(instr_idx);
-            }
 
-            Origin::CallIndirect {instr_idx, result_idx} => {
-                let call_arg_ty = match op_at(instr_idx) {
-                    Operator::CallIndirect { type_index, .. } => {
-                        let Some(Types::FuncType { results, ..}) = wasm.types.get(TypeID(*type_index)) else {
-                            panic!("Should have found a function type!");
-                        };
-                        *results.get(result_idx).unwrap()
-                    },
-                    op => panic!("CallIndirect opcode not supported: {op:?}")
-                };
-
-                // Mark the call itself as influencing control
-                if included_call_indirects.insert((instr_idx, result_idx), call_arg_ty).is_some() {
-                    continue;
+                Origin::CallRef {instr_idx, result_idx} => {
+                    let call_arg_ty = match op_at(instr_idx)? {
+                        Operator::CallRef { type_index } => {
+                            let Some(Types::FuncType { results, ..}) = wasm.types.get(TypeID(*type_index)) else {
+                                fail!(AnalysisError::MissingFuncType { tid: *type_index });
+                            };
+                            let Some(ty) = results.get(result_idx) else {
+                                fail!(AnalysisError::MissingCallResult { fid, instr_idx, result_idx });
+                            };
+                            *ty
+                        },
+                        op => fail!(AnalysisError::UnsupportedOp { op: format!("{op:?}") })
+                    };
+
+                    // Mark the call itself as influencing control
+                    if included_call_refs.insert((instr_idx, result_idx), call_arg_ty).is_some() {
+                        continue;
+                    }
+                    // also include the call instruction index in the instr set
+                    included_instrs.insert(instr_idx);
                 }
-                // also include the call instruction index in the instr set
-                included_instrs.insert(instr_idx);
-            }
 
-            Origin::Global {gid, instr_idx} => {
-                let kind = wasm.globals.get_kind(GlobalID(gid));
-                let (GlobalKind::Local(LocalGlobal {ty, ..}) |
-                GlobalKind::Import(ImportedGlobal {ty, ..})) = kind;
-                let global_ty = DataType::from(ty.content_type);
+                Origin::TableGet {instr_idx, table_index} => {
+                    let Some(ref_ty) = table_elem_type(table_index, wasm) else {
+                        fail!(AnalysisError::MissingTable { table_index });
+                    };
 
-                included_globals.insert((gid, instr_idx), global_ty);
-                // also include the instruction index in the instr set
-                included_instrs.insert(instr_idx);
-            }
+                    // Mark the table.get itself as influencing control (the tables aren't tracked)
+                    if included_table_gets.insert(instr_idx, ref_ty).is_some() {
+                        continue;
+                    }
+                    included_instrs.insert(instr_idx);
+                }
+
+                Origin::FieldLoad {instr_idx} => {
+                    let field_ty = field_type(op_at(instr_idx)?, wasm)?;
+
+                    // Mark the read itself as influencing control (the heap isn't tracked)
+                    if included_field_loads.insert(instr_idx, field_ty).is_some() {
+                        continue;
+                    }
+                    included_instrs.insert(instr_idx);
+                }
+
+                Origin::Global {gid, instr_idx} => {
+                    if let Some(value) = const_value(gid, wasm) {
+                        // nothing to request, the value is replayed
+                        included_const_globals.insert(instr_idx, value);
+                        included_instrs.insert(instr_idx);
+                        continue;
+                    }
+                    let kind = wasm.globals.get_kind(GlobalID(gid));
+                    let (GlobalKind::Local(LocalGlobal {ty, ..}) |
+                    GlobalKind::Import(ImportedGlobal {ty, ..})) = kind;
+                    let global_ty = DataType::from(ty.content_type);
+
+                    included_globals.insert((gid, instr_idx), global_ty);
+                    included_global_sources.entry(gid).or_insert_with(|| GlobalSource::new(gid, wasm));
+                    // also include the instruction index in the instr set
+                    included_instrs.insert(instr_idx);
+                }
 
-            Origin::Param{lid, instr_idx} => {
-                let param_ty = *func_params.get(lid as usize).unwrap();
-                included_params.insert((lid, instr_idx), param_ty);
-                // also include the instruction index in the instr set
-                included_instrs.insert(instr_idx);
+                Origin::Param{lid, instr_idx} => {
+                    let Some(param_ty) = func_params.get(lid as usize).copied() else {
+                        fail!(AnalysisError::MissingParam { fid, lid });
+                    };
+                    included_params.insert((lid, instr_idx), param_ty);
+                    // also include the instruction index in the instr set
+                    included_instrs.insert(instr_idx);
+                }
+
+                Origin::Untracked => {}
             }
+        }
 
-            Origin::Untracked => {}
+        // An arm is only replayed under the same condition as in the original program, keep
+        // the `if` (and trace its condition) whenever either of its arms contributes to the slice.
+        let missing_ifs: HashSet<usize> = included_instrs.iter()
+            .filter(|instr_idx| slice_range.contains(instr_idx))
+            .filter_map(|instr_idx| pdg.control_dep(*instr_idx))
+            .filter(|if_idx| !included_instrs.contains(if_idx))
+            .collect();
+        if missing_ifs.is_empty() {
+            break;
+        }
+        for if_idx in missing_ifs {
+            included_instrs.insert(if_idx);
+            worklist.extend(pdg.inputs(if_idx).iter().cloned());
         }
     }
 
+    // A replayed value that the program drops (e.g. the value that a `br_if` carries out of its
+    // block when it isn't taken) is dropped by the replay too, or the stack wouldn't balance.
+    for (i, info) in instrs_info.iter().enumerate() {
+        if matches!(op_at(true_start + i)?, Operator::Drop)
+            && info.inputs.iter().filter_map(Origin::instr_idx).any(|instr_idx| included_instrs.contains(&instr_idx) || merged_results.contains_key(&instr_idx)) {
+            included_instrs.insert(true_start + i);
+        }
+    }
+
+    // The criteria are encoded in the name of the generated function, e.g. `exact0_at_12`
+    let spec_name = spec_name + &criteria.iter().map(|instr_idx| format!("_at_{instr_idx}")).collect::<String>();
+    let criteria = criteria.into_iter()
+        .filter(|instr_idx| !included_instrs.contains(instr_idx))
+        .map(|instr_idx| (instr_idx, instrs_info[instr_idx - true_start].inputs.iter().filter_map(Origin::instr_idx).collect()))
+        .collect();
+
     result.add_slice(
         true_start,
         Slice {
             start_instr_idx: true_start,
             end_instr_idx: true_start + instrs_info.len(),
             spec_name,
+            criteria,
             max_slice: included_instrs,
             params: included_params,
             globals: included_globals,
+            global_sources: included_global_sources,
+            const_globals: included_const_globals,
             loads: included_loads,
             calls: included_calls,
             call_indirects: included_call_indirects,
+            call_indirect_tables: included_call_indirect_tables,
+            call_refs: included_call_refs,
+            table_gets: included_table_gets,
+            field_loads: included_field_loads,
+            lengths: included_lengths,
+            merged_results,
             ..Default::default()
         }
     );
+    Ok(())
 }
 
 // ===================
@@ -249,6 +764,7 @@ struct IdentifyStructure {
     // EXIT block --> decrement block_depth; if block_depth == 0? block_has_instrs = false
     // KEEP op --> if block_depth > 0? block_has_instrs = true
     nested_blocks: Vec<usize>, // indices of the blocks we have seen thus far
+    nested_loops: Vec<bool>,   // whether each of the nested blocks is a `loop`
     block_support_instrs: HashSet<usize>,
     block_has_instrs: bool,
     // whether we need to save the innermost block for the sake of the slice
@@ -259,12 +775,14 @@ struct IdentifyStructure {
 impl IdentifyStructure {
     // ----- BLOCKS
     fn in_block(&self) -> bool { !self.nested_blocks.is_empty() }
-    fn block_enter(&mut self, instr_idx: usize) {
+    fn block_enter(&mut self, instr_idx: usize, is_loop: bool) {
         self.nested_blocks.push(instr_idx);
+        self.nested_loops.push(is_loop);
         self.save_block_for_slice.push(false);
     }
     fn block_exit(&mut self) -> (Option<usize>, Option<bool>) {
         let block_idx = self.nested_blocks.pop();
+        self.nested_loops.pop();
         let should_save = self.save_block_for_slice.pop();
         if self.nested_blocks.is_empty() {
             self.block_has_instrs = false;
@@ -272,12 +790,17 @@ impl IdentifyStructure {
         (block_idx, should_save)
     }
     fn save_block_for_slice(&mut self) {
-        let to_save = self.save_block_for_slice.last_mut().unwrap_or_else(|| { unreachable!()});
-        *to_save = true;
+        // always called right after entering the block
+        if let Some(to_save) = self.save_block_for_slice.last_mut() {
+            *to_save = true;
+        }
     }
     fn add_block_support(&mut self, instr_idx: usize) {
         self.block_support_instrs.insert(instr_idx);
     }
+    fn loop_depth(&self) -> u32 {
+        self.nested_loops.iter().filter(|is_loop| **is_loop).count() as u32
+    }
     fn use_block_support(&mut self) -> HashSet<usize> {
         let ret = self.block_support_instrs.to_owned();
         self.block_support_instrs.clear();
@@ -285,15 +808,24 @@ impl IdentifyStructure {
     }
 }
 
-pub fn save_structure(slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Module) {
+/// Add the blocks around the sliced instructions to the slices, so the generated functions
+/// keep the original control flow.
+pub fn save_structure(slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Module) -> Result<(), AnalysisError> {
     for (result, func) in slices.iter_mut().zip(funcs.iter()) {
+        if result.fallback.is_some() {
+            // no structure to keep, nothing is replayed
+            continue;
+        }
         for (_instr_idx, slice) in result.slices.iter_mut() {
-            let lf = wasm.functions.unwrap_local(FunctionID(func.fid));
+            let lf = local_func(func.fid, wasm)?;
 
             let body = &lf.body.instructions;
             let mut state = IdentifyStructure::default();     // one instance of state per function!
 
             for (i, op) in body.get_ops().iter().enumerate() {
+                if i == slice.start_instr_idx {
+                    slice.loop_depth = state.loop_depth();
+                }
                 let in_slice = slice.max_slice.contains(&i);
                 let support_ops = visit_op(op, i, i == body.len() - 1, in_slice, &mut state);
                 let mut to_add: HashSet<usize> = HashSet::default();
@@ -306,6 +838,7 @@ pub fn save_structure(slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Mo
             }
         }
     }
+    Ok(())
 }
 
 /// Returns: (should_include, do_fuel_before)
@@ -314,14 +847,14 @@ pub fn save_structure(slices: &mut [SliceResult], funcs: &[FuncState], wasm: &Mo
 ///   (before emitting this opcode).
 fn visit_op(op: &Operator, instr_idx: usize, at_func_end: bool, is_in_slice: bool, state: &mut IdentifyStructure) -> HashSet<usize> {
     // Test whether we need to save extra support opcodes
-    let is_cf = is_branching_op(op) || matches!(op,
+    let is_cf = is_branching_op(op) || is_tail_call(op) || matches!(op,
         // control opcodes
-        Operator::Return
+        Operator::Return | Operator::Throw {..} | Operator::ThrowRef
     );
-    let is_block = matches!(op, Operator::If {..} | Operator::Block {..} | Operator::Loop {..});
+    let is_block = matches!(op, Operator::If {..} | Operator::Block {..} | Operator::Loop {..} | Operator::TryTable {..});
     let should_include = if is_block {
         // This opcode creates block structure
-        state.block_enter(instr_idx);
+        state.block_enter(instr_idx, matches!(op, Operator::Loop {..}));
         if is_in_slice { state.save_block_for_slice(); }
         HashSet::default()
     } else if matches!(op, Operator::Else) {
@@ -0,0 +1,226 @@
+//! A compact binary encoding of the state that each generated function requests, for the hosts
+//! that can't parse the JSON sidecar (e.g. microcontroller-class runtimes). With
+//! `AnalysisConfig::state_section`, it's embedded in the generated modules as the custom section
+//! `STATE_SECTION`.
+//!
+//! All the integers are little-endian:
+//! - `version: u8` (`LAYOUT_VERSION`), `num_funcs: u32`
+//! - per generated function: `fid: u32` (in the generated module), `num_records: u32`, then its records
+//! - per record (`RECORD_SIZE` bytes, in order of the param position):
+//!   `kind: u8` (`StateType`, see `KINDS`), `type: u8` (the Wasm value type, e.g. `0x7f` for
+//!   `i32`), `stack_val: u8` (0 for a result, 1 for an argument), `num: u8` (which result/argument),
+//!   `instr_idx: u32` (the instruction in the original function), `memory: u32` (the memory that a
+//!   `Load` reads, 0 otherwise), `param: u32` (the param of the generated function that takes the value)
+
+use std::fmt::{Display, Formatter};
+use anyhow::bail;
+use wirm::DataType;
+use crate::codegen::{GeneratedFunc, StackVal, StateType};
+use crate::utils::write_leb128;
+
+/// The name of the custom section holding the encoding
+pub const STATE_SECTION: &str = "whamm-fuel.state";
+/// The version of the layout, the first byte of the encoding
+pub const LAYOUT_VERSION: u8 = 2;
+/// The size of an encoded `StateRecord`
+pub const RECORD_SIZE: usize = 16;
+
+/// A value that a generated function requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateRecord {
+    pub kind: StateType,
+    pub ty: DataType,
+    /// Whether it's an argument of the instruction (otherwise one of its results)
+    pub is_arg: bool,
+    /// Which result (or argument) of the instruction
+    pub num: u8,
+    pub instr_idx: u32,
+    /// The memory that a `Load` reads (0 for the other kinds)
+    pub memory: u32,
+    /// The position of the generated function's param that takes the value
+    pub param: u32
+}
+
+impl Display for StateRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let val = if self.is_arg { "arg" } else { "res" };
+        write!(f, "{:?} @{}: {val}{} ({:?})", self.kind, self.instr_idx, self.num, self.ty)
+    }
+}
+
+/// The requested state of a generated function, in order of its params
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncLayout {
+    pub fid: u32,
+    pub records: Vec<StateRecord>
+}
+impl TryFrom<&GeneratedFunc> for FuncLayout {
+    type Error = anyhow::Error;
+
+    /// Fails on a value that the layout can't represent (e.g. the 256th result of an instruction)
+    fn try_from(func: &GeneratedFunc) -> anyhow::Result<Self> {
+        let mut records: Vec<StateRecord> = func.req_state.iter()
+            .flat_map(|(kind, reqs)| reqs.iter().map(move |(instr_idx, req)| (kind, instr_idx, req)))
+            .flat_map(|(kind, instr_idx, req)| req.req_state.iter().map(move |val| {
+                let (is_arg, num) = match val {
+                    StackVal::Arg { num, .. } => (true, *num),
+                    StackVal::Res { num, .. } => (false, *num)
+                };
+                let param = val.gen_param_id();
+                let Ok(num) = u8::try_from(num) else {
+                    bail!("Can't encode the value #{num} of instruction @{instr_idx} in the state layout");
+                };
+                let Ok(instr_idx) = u32::try_from(*instr_idx) else {
+                    bail!("Can't encode the instruction index {instr_idx} in the state layout");
+                };
+                let Some(ty) = func.params.get(param as usize) else {
+                    bail!("Generated function #{} has no param #{param}", func.fid);
+                };
+                Ok(StateRecord { kind: *kind, ty: *ty, is_arg, num, instr_idx, memory: req.memory.unwrap_or_default(), param })
+            }))
+            .collect::<anyhow::Result<_>>()?;
+        records.sort_by_key(|record| (record.param, record.kind, record.instr_idx));
+        Ok(Self {
+            fid: func.fid,
+            records
+        })
+    }
+}
+
+/// Encode the requested state of the `funcs` (see the module docs for the layout), fails on a
+/// value that the layout can't represent instead of encoding something else
+pub fn encode_state_layout<'a>(funcs: impl IntoIterator<Item = &'a GeneratedFunc>) -> anyhow::Result<Vec<u8>> {
+    let layouts: Vec<FuncLayout> = funcs.into_iter().map(FuncLayout::try_from).collect::<anyhow::Result<_>>()?;
+    let mut bytes = vec![LAYOUT_VERSION];
+    bytes.extend(u32::try_from(layouts.len())?.to_le_bytes());
+    for layout in layouts.iter() {
+        bytes.extend(layout.fid.to_le_bytes());
+        bytes.extend(u32::try_from(layout.records.len())?.to_le_bytes());
+        for record in layout.records.iter() {
+            bytes.push(kind_code(record.kind)?);
+            bytes.push(type_code(record.ty)?);
+            bytes.push(u8::from(record.is_arg));
+            bytes.push(record.num);
+            bytes.extend(record.instr_idx.to_le_bytes());
+            bytes.extend(record.memory.to_le_bytes());
+            bytes.extend(record.param.to_le_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decode what `encode_state_layout` encoded
+pub fn decode_state_layout(bytes: &[u8]) -> anyhow::Result<Vec<FuncLayout>> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let version = reader.u8()?;
+    if version != LAYOUT_VERSION {
+        bail!("Unsupported state layout version {version} (expected {LAYOUT_VERSION})");
+    }
+    let num_funcs = reader.u32()?;
+    let mut layouts = Vec::new();
+    for _ in 0..num_funcs {
+        let fid = reader.u32()?;
+        let num_records = reader.u32()?;
+        let mut records = Vec::new();
+        for _ in 0..num_records {
+            records.push(StateRecord {
+                kind: kind_of(reader.u8()?)?,
+                ty: type_of(reader.u8()?)?,
+                is_arg: match reader.u8()? {
+                    0 => false,
+                    1 => true,
+                    val => bail!("Invalid stack value {val} in the state layout")
+                },
+                num: reader.u8()?,
+                instr_idx: reader.u32()?,
+                memory: reader.u32()?,
+                param: reader.u32()?
+            });
+        }
+        layouts.push(FuncLayout { fid, records });
+    }
+    if reader.pos != bytes.len() {
+        bail!("{} trailing bytes after the state layout", bytes.len() - reader.pos);
+    }
+    Ok(layouts)
+}
+
+/// Append the custom section `name` holding `payload` to the encoded module `wasm`
+pub(crate) fn append_custom_section(wasm: &mut Vec<u8>, name: &str, payload: &[u8]) {
+    let mut contents = Vec::new();
+    write_leb128(&mut contents, name.len() as u32);
+    contents.extend(name.as_bytes());
+    contents.extend(payload);
+    // the custom section's ID
+    wasm.push(0);
+    write_leb128(wasm, contents.len() as u32);
+    wasm.extend(contents);
+}
+
+/// The `kind` code of each `StateType` is its position here: the codes are part of the layout, so a
+/// new kind is appended (or `LAYOUT_VERSION` bumped)
+const KINDS: [StateType; 10] = [StateType::Param, StateType::Global, StateType::Load, StateType::Call,
+    StateType::CallIndirect, StateType::CallRef, StateType::Taken, StateType::Length, StateType::TableGet, StateType::FieldLoad];
+
+fn kind_code(kind: StateType) -> anyhow::Result<u8> {
+    match KINDS.iter().position(|k| *k == kind) {
+        Some(code) => Ok(code as u8),
+        None => bail!("The state kind {kind:?} has no code in the state layout")
+    }
+}
+
+fn kind_of(code: u8) -> anyhow::Result<StateType> {
+    match KINDS.get(code as usize) {
+        Some(kind) => Ok(*kind),
+        None => bail!("Unknown state kind {code} in the state layout")
+    }
+}
+
+/// The binary encoding of the value type in Wasm
+fn type_code(ty: DataType) -> anyhow::Result<u8> {
+    Ok(match ty {
+        DataType::I32 => 0x7f,
+        DataType::I64 => 0x7e,
+        DataType::F32 => 0x7d,
+        DataType::F64 => 0x7c,
+        DataType::V128 => 0x7b,
+        DataType::FuncRef => 0x70,
+        DataType::ExternRef => 0x6f,
+        _ => bail!("Can't encode the value type {ty:?} in the state layout")
+    })
+}
+
+fn type_of(code: u8) -> anyhow::Result<DataType> {
+    Ok(match code {
+        0x7f => DataType::I32,
+        0x7e => DataType::I64,
+        0x7d => DataType::F32,
+        0x7c => DataType::F64,
+        0x7b => DataType::V128,
+        0x70 => DataType::FuncRef,
+        0x6f => DataType::ExternRef,
+        _ => bail!("Unknown value type {code:#x} in the state layout")
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let Some(bytes) = self.bytes.get(self.pos..self.pos + N) else {
+            bail!("The state layout is truncated at byte {}", self.pos);
+        };
+        self.pos += N;
+        Ok(bytes.try_into()?)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take::<4>()?))
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use wirm::wasmparser::Operator;
+use crate::slice::Slice;
+use crate::utils::is_branching_op;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InstrClass {
+    Arithmetic,
+    Memory,
+    Control,
+    Call,
+    /// local/global access
+    Variable,
+    Other
+}
+impl Display for InstrClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                InstrClass::Arithmetic => "arithmetic",
+                InstrClass::Memory => "memory",
+                InstrClass::Control => "control",
+                InstrClass::Call => "call",
+                InstrClass::Variable => "variable",
+                InstrClass::Other => "other"
+            }
+        )
+    }
+}
+
+pub fn classify(op: &Operator) -> InstrClass {
+    match op {
+        Operator::Call {..} | Operator::CallIndirect {..} | Operator::CallRef {..} |
+        Operator::ReturnCall {..} | Operator::ReturnCallIndirect {..} | Operator::ReturnCallRef {..} => InstrClass::Call,
+        Operator::Block {..} | Operator::Loop {..} | Operator::If {..} | Operator::TryTable {..} | Operator::Else | Operator::End |
+        Operator::Return | Operator::Throw {..} | Operator::ThrowRef | Operator::Unreachable => InstrClass::Control,
+        op if is_branching_op(op) || matches!(op, Operator::Br {..}) => InstrClass::Control,
+        Operator::LocalGet {..} | Operator::LocalSet {..} | Operator::LocalTee {..} |
+        Operator::GlobalGet {..} | Operator::GlobalSet {..} => InstrClass::Variable,
+        _ => {
+            // there are too many numeric/memory opcodes to list, go off of the opcode's name
+            let name = format!("{op:?}");
+            if name.contains("Load") || name.contains("Store") || name.starts_with("Memory") || name.starts_with("Data") {
+                InstrClass::Memory
+            } else if ["I32", "I64", "F32", "F64"].iter().any(|prefix| name.starts_with(prefix)) {
+                InstrClass::Arithmetic
+            } else {
+                InstrClass::Other
+            }
+        }
+    }
+}
+
+/// The breakdown of a slice by instruction class.
+#[derive(Debug, Default)]
+pub struct SliceStats {
+    /// The number of instructions of each class in the (max) slice
+    pub counts: BTreeMap<InstrClass, usize>,
+    /// The cost charged for the instructions of each class in the slice's range
+    pub costs: BTreeMap<InstrClass, u64>
+}
+impl SliceStats {
+    pub fn new(slice: &Slice, charge_map: &BTreeMap<usize, Vec<(usize, u64)>>, body: &[Operator]) -> Self {
+        let mut stats = Self::default();
+        for instr_idx in slice.max_slice.iter() {
+            *stats.counts.entry(classify(&body[*instr_idx])).or_default() += 1;
+        }
+        for (instr_idx, cost) in charge_map.values().flatten() {
+            if (slice.start_instr_idx..slice.end_instr_idx).contains(instr_idx) {
+                *stats.costs.entry(classify(&body[*instr_idx])).or_default() += cost;
+            }
+        }
+        stats
+    }
+
+    pub fn total_cost(&self) -> u64 {
+        self.costs.values().sum()
+    }
+
+    /// The share of the total cost that the class contributes (as a percentage)
+    pub fn cost_share(&self, class: &InstrClass) -> f64 {
+        let total = self.total_cost();
+        if total == 0 {
+            return 0.0;
+        }
+        *self.costs.get(class).unwrap_or(&0) as f64 * 100.0 / total as f64
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use crate::codegen::GeneratedFunc;
+use crate::cost_expr::CostExpr;
+use crate::run::CompType;
+
+/// The metering strategies that `compare-strategies` generates the fuel computations with
+pub const STRATEGIES: [CompType; 4] = [CompType::Exact, CompType::Approx, CompType::Epoch, CompType::BackEdge];
+
+/// What metering a module with a strategy costs, and how much accuracy it gives up.
+#[derive(Debug, Clone)]
+pub struct StrategyReport {
+    pub comp_type: CompType,
+    /// The size of the generated (max) module, in bytes
+    pub code_size: usize,
+    /// The number of fuel points (charge sites) of the generated functions
+    pub charge_sites: usize,
+    /// The number of values the host has to pass to the generated functions (their params)
+    pub requested_state: usize,
+    /// The most that a single pass through the function bodies (each loop body once) can be
+    /// overcharged compared to what runs, see `max_overcharge`
+    pub max_overcharge: u64
+}
+impl StrategyReport {
+    pub fn new(comp_type: CompType, code_size: usize, cost_maps: &[BTreeMap<usize, u64>], func_map: &BTreeMap<u32, Vec<GeneratedFunc>>,
+               exact_formulas: &[CostExpr], loop_depth_multiplier: u64) -> Self {
+        Self {
+            comp_type,
+            code_size,
+            charge_sites: cost_maps.iter().map(BTreeMap::len).sum(),
+            requested_state: func_map.values().flatten().map(|func| func.params.len()).sum(),
+            max_overcharge: exact_formulas.iter().map(|formula| max_overcharge(comp_type, formula, loop_depth_multiplier)).sum()
+        }
+    }
+}
+
+/// The most that a single pass through the function (of the `exact` formula) can be overcharged:
+/// - exact: nothing, only what runs is charged
+/// - approx: the cost at loop depth d is scaled by `multiplier^d`, the surplus of the worst path
+/// - epoch and back-edge: the whole body is charged (on entry or at the back edge), so all that the
+///   cheapest path skips (the formula ignores the code skipped by a `br`, this is an estimate)
+fn max_overcharge(comp_type: CompType, exact: &CostExpr, multiplier: u64) -> u64 {
+    match comp_type {
+        CompType::Exact => 0,
+        CompType::Approx => single_pass(exact, 0, &|depth| multiplier.saturating_pow(depth).saturating_sub(1), Path::Worst),
+        CompType::Epoch | CompType::BackEdge => single_pass(exact, 0, &|_| 1, Path::All).saturating_sub(single_pass(exact, 0, &|_| 1, Path::Cheapest))
+    }
+}
+
+/// Which arms of the alternatives (e.g. of an `if`) a single pass goes through
+#[derive(Clone, Copy)]
+enum Path {
+    Worst,
+    Cheapest,
+    /// Every arm, as if they all ran
+    All
+}
+
+/// The cost of a single pass (each loop body once, the calls are free) with the cost at each loop
+/// `depth` scaled by `scale(depth)`.
+fn single_pass(expr: &CostExpr, depth: u32, scale: &impl Fn(u32) -> u64, path: Path) -> u64 {
+    match expr {
+        CostExpr::Const(cost) => cost.saturating_mul(scale(depth)),
+        CostExpr::Sum(terms) => terms.iter().map(|term| single_pass(term, depth, scale, path)).fold(0, u64::saturating_add),
+        CostExpr::Max(alternatives) => {
+            let costs = alternatives.iter().map(|alternative| single_pass(alternative, depth, scale, path));
+            match path {
+                Path::Worst => costs.max().unwrap_or_default(),
+                Path::Cheapest => costs.min().unwrap_or_default(),
+                Path::All => costs.fold(0, u64::saturating_add)
+            }
+        }
+        CostExpr::Loop { body, .. } => single_pass(body, depth + 1, scale, path),
+        CostExpr::Call { .. } => 0
+    }
+}
@@ -0,0 +1,132 @@
+//! Streaming the results of the pipeline one function at a time, for the modules that are too large
+//! to hold the taint, slices and generated functions of every function at once.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::slice;
+use anyhow::bail;
+use wirm::ir::id::FunctionID;
+use wirm::Module;
+use crate::analyze::{analyze_func, FuncState, NoVisitor};
+use crate::codegen::{export_static_cost, static_formula, FuncGen, GeneratedFunc, SharedGen};
+use crate::cost_expr::CostExpr;
+use crate::codegen::max::codegen_max_func;
+use crate::codegen::min::codegen_min_func;
+use crate::globals::GlobalDefs;
+use crate::purity::{func_effects, Effects};
+use crate::reduce::reduce_with;
+use crate::run::{check_mutation_sink, in_phase, reject_approximations, slice_criteria, AnalysisConfig};
+use crate::slice::{save_structure, slice_func, SliceCriterion, SliceResult};
+use crate::Variant;
+use crate::utils::local_ops;
+
+/// The results of a single function: its taint, its (reduced) slices and the functions generated for them
+pub type FuncResults = (FuncState, SliceResult, Vec<GeneratedFunc>);
+
+/// Runs the pipeline on each local function in turn (see `stream_analysis`), only the generated module
+/// grows as it goes. Take it back with `finish` once the functions are consumed.
+pub struct FuncStream<'a, 'c> {
+    config: &'c AnalysisConfig,
+    variant: Variant,
+    wasm: &'c Module<'a>,
+    gen_wasm: Module<'a>,
+    effects: BTreeMap<u32, Effects>,
+    global_defs: GlobalDefs,
+    shared: SharedGen,
+    // the formulas of the static costs of the functions streamed so far, see `codegen::export_static_cost`
+    static_formulas: BTreeMap<u32, CostExpr>,
+    fids: std::vec::IntoIter<u32>
+}
+
+/// Analyze, slice and generate the `variant` of the local functions of `wasm` one function at a time,
+/// rather than each step for every function (as `run::run_analysis` does). A function that fails
+/// is yielded as an error, the stream carries on with the next one (as with `keep_going`).
+///
+/// Everything that needs the results of the whole module before generating is rejected: the `entry`
+/// (use `only_funcs`), `dispatch` and `replay_pure_calls`.
+pub fn stream_analysis<'a, 'c>(wasm: &'c Module<'a>, config: &'c AnalysisConfig, variant: Variant) -> anyhow::Result<FuncStream<'a, 'c>> {
+    if config.on_out_of_fuel && config.init_fuel.is_none() {
+        bail!("The out-of-fuel hook needs an initial fuel to count down from");
+    }
+    if config.init_fuel.is_some_and(|init_fuel| i64::try_from(init_fuel).is_err()) {
+        bail!("The initial fuel must fit the i64 that the generated functions count with (at most {})", i64::MAX);
+    }
+    if config.entry.is_some() || config.dispatch || config.replay_pure_calls {
+        bail!("Can't stream the analysis with an entry, dispatch or replayed pure calls, they need every function first");
+    }
+    for gid in config.mutation_sinks.iter() {
+        check_mutation_sink(*gid, wasm)?;
+    }
+    let only = config.restrict(None);
+    let fids: Vec<u32> = (0..wasm.functions.iter().count() as u32)
+        .filter(|fid| wasm.functions.is_local(FunctionID(*fid)))
+        .filter(|fid| only.as_ref().is_none_or(|only| only.contains(fid)))
+        .collect();
+    if let Some(criterion) = config.slice_at.iter().find(|criterion| !fids.contains(&criterion.fid)) {
+        bail!("Can't slice at {criterion}: function #{} is not a local function (or excluded)", criterion.fid);
+    }
+
+    let mut gen_wasm = Module::default();
+    let shared = SharedGen::new(config, &[], wasm, &mut gen_wasm)?;
+    Ok(FuncStream {
+        config,
+        variant,
+        wasm,
+        gen_wasm,
+        effects: func_effects(wasm),
+        global_defs: GlobalDefs::new(wasm),
+        shared,
+        static_formulas: BTreeMap::new(),
+        fids: fids.into_iter()
+    })
+}
+
+impl<'a> FuncStream<'a, '_> {
+    /// The generated module, with the functions generated so far
+    pub fn finish(self) -> Module<'a> {
+        self.gen_wasm
+    }
+
+    fn next_func(&mut self, fid: u32) -> anyhow::Result<FuncResults> {
+        let (config, wasm) = (self.config, self.wasm);
+        let func = analyze_func(wasm, FunctionID(fid), &self.effects, &mut NoVisitor)
+            .map_err(|err| in_phase("the taint analysis", err, wasm))?;
+        if config.strict {
+            reject_approximations(slice::from_ref(&func), wasm)?;
+        }
+
+        let criteria: Vec<SliceCriterion> = config.slice_at.iter().filter(|criterion| criterion.fid == fid).copied().collect();
+        let criteria = slice_criteria(slice::from_ref(&func), &criteria, &config.sinks, &config.mutation_sinks, config.trap_sinks, wasm)?;
+        let mut result = slice_func(&func, &criteria, &BTreeSet::new(), &self.global_defs, wasm)
+            .map_err(|err| in_phase("slicing", err, wasm))?;
+        save_structure(slice::from_mut(&mut result), slice::from_ref(&func), wasm).map_err(|err| in_phase("slicing", err, wasm))?;
+        reduce_with(slice::from_mut(&mut result), slice::from_ref(&func), wasm, &config.reductions).map_err(|err| in_phase("slicing", err, wasm))?;
+
+        let body = local_ops(fid, wasm)?;
+        if result.shim.is_some() {
+            // toolchain glue isn't metered, nothing is generated for it
+            if let Some(formula) = config.export_static_costs.then(|| static_formula(fid, body, &BTreeMap::new())).flatten() {
+                self.static_formulas.insert(fid, formula);
+            }
+            return Ok((func, result, vec![]));
+        }
+        let FuncGen { charge_map, generated_funcs, .. } = match self.variant {
+            Variant::Max => codegen_max_func(config, &result, &self.shared, wasm, &mut self.gen_wasm),
+            Variant::Min => codegen_min_func(config, &result, &self.shared, wasm, &mut self.gen_wasm)
+        }.map_err(|err| in_phase("code generation", err, wasm))?;
+        if let Some(formula) = config.export_static_costs.then(|| static_formula(fid, body, &charge_map)).flatten() {
+            // only the callees streamed before the function are known
+            self.static_formulas.insert(fid, formula);
+            export_static_cost(fid, &self.static_formulas, &mut self.gen_wasm);
+        }
+        Ok((func, result, generated_funcs))
+    }
+}
+
+impl Iterator for FuncStream<'_, '_> {
+    type Item = anyhow::Result<FuncResults>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let fid = self.fids.next()?;
+        Some(self.next_func(fid))
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::BTreeSet;
+use crate::analyze::{FuncState, OpKind, Origin};
+use crate::error::AnalysisError;
+
+/// The conservative approximations that the fuel of the functions relies on, `--strict` rejects
+/// them (ordered by function and instruction):
+/// - an op that the analysis can't model: the function falls back to the worst-case cost of its body
+/// - a control-flow input that isn't tracked (e.g. a block result after an `unreachable`), it's not replayed
+/// - a control-flow input that is loaded from memory: the load is requested as state, memory isn't
+///   tracked through the stores
+/// - a control-flow input that is read from a table: likewise, the tables aren't tracked through `table.set`
+/// - a control-flow input that is read from a struct or an array: likewise, the heap isn't tracked
+///   through `struct.set`/`array.set`
+pub fn approximations(funcs: &[FuncState]) -> Vec<AnalysisError> {
+    let mut found = vec![];
+    for func in funcs.iter() {
+        let approximated = |instr_idx: usize, reason: &str| AnalysisError::Approximated { fid: func.fid, instr_idx, reason: reason.to_string() };
+        if let Some(op) = &func.fallback {
+            // `instrs` stops right before the op
+            found.push(approximated(func.instrs.len(), &format!("unsupported {op}, the function is charged the worst-case cost of its body")));
+            continue;
+        }
+
+        // trace the inputs of the control flow backward, as the slices do
+        let mut untracked: BTreeSet<usize> = BTreeSet::new();
+        let mut loads: BTreeSet<usize> = BTreeSet::new();
+        let mut table_gets: BTreeSet<usize> = BTreeSet::new();
+        let mut field_loads: BTreeSet<usize> = BTreeSet::new();
+        let mut visited: BTreeSet<usize> = BTreeSet::new();
+        let mut worklist: Vec<(usize, Origin)> = func.instrs.iter().enumerate()
+            .filter(|(_, info)| matches!(info.kind, OpKind::Control))
+            .flat_map(|(instr_idx, info)| info.inputs.iter().map(move |input| (instr_idx, input.clone())))
+            .collect();
+        while let Some((consumer, origin)) = worklist.pop() {
+            match origin {
+                Origin::Untracked => { untracked.insert(consumer); }
+                Origin::Load { instr_idx, .. } => { loads.insert(instr_idx); }
+                Origin::TableGet { instr_idx, .. } => { table_gets.insert(instr_idx); }
+                Origin::FieldLoad { instr_idx } => { field_loads.insert(instr_idx); }
+                Origin::Instr { instr_idx } if visited.insert(instr_idx) => {
+                    if let Some(info) = func.instrs.get(instr_idx) {
+                        worklist.extend(info.inputs.iter().map(|input| (instr_idx, input.clone())));
+                    }
+                }
+                _ => {}
+            }
+        }
+        found.extend(untracked.into_iter().map(|instr_idx| approximated(instr_idx, "an input that influences the control flow isn't tracked")));
+        found.extend(loads.into_iter().map(|instr_idx| approximated(instr_idx, "the loaded value influences the control flow, memory is only tracked coarsely (the value is requested as state)")));
+        found.extend(table_gets.into_iter().map(|instr_idx| approximated(instr_idx, "the reference read from a table influences the control flow, the tables aren't tracked (the value is requested as state)")));
+        found.extend(field_loads.into_iter().map(|instr_idx| approximated(instr_idx, "the field read from a struct or an array influences the control flow, the heap isn't tracked (the value is requested as state)")));
+    }
+    found.sort_by_key(|err| (err.fid(), err.instr_idx()));
+    found
+}
@@ -1,14 +1,89 @@
-use wirm::ir::id::{FunctionID, TypeID};
+use wirm::ir::id::{FunctionID, MemoryID, TableID, TypeID};
+use wirm::ir::module::module_functions::{FuncKind, LocalFunction};
 use wirm::ir::module::module_types::Types;
-use wirm::Module;
-use wirm::wasmparser::{BlockType, Operator};
+use wirm::{DataType, Module};
+use wirm::wasmparser::{BlockType, ExternalKind, Operator, ValType, Validator, WasmFeatures};
+use crate::error::{fail, AnalysisError};
+use crate::reduce::ReductionPass;
 use crate::run::CompType;
 
 pub(crate) const FUEL_COMPUTATION: CompType = CompType::Exact;
+/// Whether computations whose results are `drop`ped still contribute to fuel (the default of
+/// `AnalysisConfig::charge_dropped`).
+/// true: meter the execution cost (default)
+/// false: only meter "useful work"
+pub(crate) const CHARGE_DROPPED: bool = true;
 pub(crate) const SPACE_PER_TAB: usize = 4;
+/// The fuel charged per element for the bulk table ops (see `dyn_cost_per_elem`).
+pub(crate) const TABLE_ELEM_COST: u64 = 1;
+/// The fuel charged per byte for the bulk memory ops (see `dyn_cost_per_elem`).
+pub(crate) const MEMORY_BYTE_COST: u64 = 1;
+/// Constants that the generated code replays at least this many times are computed once into a
+/// shared local (see `codegen::share_consts`), `usize::MAX` turns this off. The default of
+/// `AnalysisConfig::dedup_const_min_uses`.
+pub(crate) const DEDUP_CONST_MIN_USES: usize = 3;
+/// The approx fuel computation scales the cost of a slice by this for each loop around it,
+/// a conservative stand-in for the unknown trip counts of the enclosing loops. The default of
+/// `AnalysisConfig::loop_depth_multiplier`.
+pub(crate) const LOOP_DEPTH_MULTIPLIER: u64 = 2;
+/// Whether toolchain glue functions (see `is_shim`) are skipped: they're neither sliced nor generated,
+/// only listed in the slice report.
+pub(crate) const SKIP_SHIMS: bool = true;
+/// The passes that reduce the max slices to the min slices (see `ReductionPass`), the default of
+/// `AnalysisConfig::reductions`.
+pub(crate) const REDUCTIONS: &[ReductionPass] = &[ReductionPass::BranchOutcomes];
 
+// wasm-bindgen's describe/externref shims and the wasi-libc/LLVM init functions
+const SHIM_PREFIXES: &[&str] = &["__wbindgen_", "__wbg_", "__externref_"];
+const SHIM_NAMES: &[&str] = &["__wasm_call_ctors", "_initialize", "__wasm_apply_data_relocs", "__wasm_init_memory", "__wasm_init_tls"];
 
 
+
+/// Whether the function is glue generated by the toolchain rather than user code.
+pub fn is_shim(name: &str) -> bool {
+    SHIM_NAMES.contains(&name) || SHIM_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// The name of function `fid` if it's toolchain glue (see `is_shim`): its debug name, otherwise its export name
+pub(crate) fn shim_name(fid: u32, wasm: &Module) -> Option<String> {
+    let name = wasm.functions.get_name(FunctionID(fid)).clone().or_else(|| {
+        wasm.exports.iter()
+            .find(|export| matches!(export.kind, ExternalKind::Func) && export.index == fid)
+            .map(|export| export.name.clone())
+    })?;
+    is_shim(&name).then_some(name)
+}
+
+/// The local function `fid`, fails if it's imported (or not in the module)
+pub(crate) fn local_func<'a, 'b>(fid: u32, wasm: &'b Module<'a>) -> Result<&'b LocalFunction<'a>, AnalysisError> {
+    match wasm.functions.get_fn_by_id(FunctionID(fid)).map(|func| func.kind()) {
+        Some(FuncKind::Local(func)) => Ok(func),
+        _ => fail!(AnalysisError::NotLocalFunc { fid })
+    }
+}
+
+/// The local functions of the module with their fid
+pub(crate) fn local_funcs<'a, 'b>(wasm: &'b Module<'a>) -> impl Iterator<Item = (u32, &'b LocalFunction<'a>)> {
+    wasm.functions.iter().enumerate().filter_map(|(fid, func)| match func.kind() {
+        FuncKind::Local(local) => Some((fid as u32, local.as_ref())),
+        FuncKind::Import(_) => None
+    })
+}
+
+/// The body of the local function `fid` (see `local_func`)
+pub(crate) fn local_ops<'a, 'b>(fid: u32, wasm: &'b Module<'a>) -> Result<&'b [Operator<'a>], AnalysisError> {
+    Ok(local_func(fid, wasm)?.body.instructions.get_ops())
+}
+
+/// Rejects a module that uses Wasm proposals outside of `features`, rather than
+/// (silently) analyzing it conservatively.
+pub fn check_features(wasm_bytes: &[u8], features: WasmFeatures) -> anyhow::Result<()> {
+    if let Err(e) = Validator::new_with_features(features).validate_all(wasm_bytes) {
+        return Err(AnalysisError::DisabledFeature { reason: e.to_string() }.into());
+    }
+    Ok(())
+}
+
 pub fn is_loop(instr_idx: usize, op: &Operator) -> Option<String> {
     if matches!(op, Operator::Loop {..}) {
         Some(format!("_loop_at_{instr_idx}"))
@@ -36,47 +111,159 @@ pub fn find_subsection_end(body: &[Operator]) -> usize {
     }
 
     fn is_block(op: &Operator) -> bool {
-        matches!(op, Operator::Block { .. } | Operator::Loop { .. } | Operator::If {..})
+        matches!(op, Operator::Block { .. } | Operator::Loop { .. } | Operator::If {..} | Operator::TryTable { .. })
     }
     i
 }
 
+// whether the op does more than compute its result value
+pub fn has_side_effects(op: &Operator) -> bool {
+    matches!(op, Operator::MemoryGrow {..} | Operator::MemoryInit {..} | Operator::MemoryCopy {..} |
+                 Operator::MemoryFill {..} | Operator::TableInit {..} | Operator::TableCopy {..} |
+                 Operator::TableGrow {..} | Operator::TableFill {..} | Operator::TableSet {..} |
+                 Operator::Call {..} | Operator::CallIndirect {..} | Operator::CallRef {..} |
+                 Operator::LocalTee {..} | Operator::GlobalSet {..})
+}
+
+/// The fuel to charge per element of the bulk ops whose cost depends on their length
+/// operand (always the top of the stack), None if the op only has a static cost.
+pub fn dyn_cost_per_elem(op: &Operator) -> Option<u64> {
+    match op {
+        Operator::TableGrow {..} | Operator::TableFill {..} |
+        Operator::TableCopy {..} | Operator::TableInit {..} => Some(TABLE_ELEM_COST),
+        Operator::MemoryFill {..} | Operator::MemoryCopy {..} | Operator::MemoryInit {..} => Some(MEMORY_BYTE_COST),
+        _ => None
+    }
+}
+
+/// Append `val` as an unsigned LEB128 (e.g. a length or an index in a section of the module)
+pub(crate) fn write_leb128(bytes: &mut Vec<u8>, mut val: u32) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// The `cost` as the `i64` that the fuel is computed in, clamped to `i64::MAX` (e.g. a saturated
+/// cost of a deep loop nest)
+pub(crate) fn fuel_const(cost: u64) -> i64 {
+    i64::try_from(cost).unwrap_or(i64::MAX)
+}
+
+/// The type of the length of a bulk op: the index type of the memory or table it writes (the
+/// narrower one for a copy), the length of a segment is always an `i32`
+pub fn length_type(op: &Operator, wasm: &Module) -> DataType {
+    let memory64 = |mem: u32| wasm.memories.get_mem_by_id(MemoryID(mem)).is_some_and(|memory| memory.ty.memory64);
+    let table64 = |table: u32| wasm.tables.get(TableID(table)).is_some_and(|table| table.table64);
+    let wide = match op {
+        Operator::MemoryFill { mem } => memory64(*mem),
+        Operator::MemoryCopy { dst_mem, src_mem } => memory64(*dst_mem) && memory64(*src_mem),
+        Operator::TableGrow { table } | Operator::TableFill { table } => table64(*table),
+        Operator::TableCopy { dst_table, src_table } => table64(*dst_table) && table64(*src_table),
+        _ => false
+    };
+    if wide { DataType::I64 } else { DataType::I32 }
+}
+
+/// The type of the references in the table `table_index` (what `table.get` reads), None if the
+/// module doesn't define it
+pub fn table_elem_type(table_index: u32, wasm: &Module) -> Option<DataType> {
+    wasm.tables.get(TableID(table_index)).map(|table| DataType::from(ValType::Ref(table.element_type)))
+}
+
+/// The type of the value that a struct or array read (`struct.get*`, `array.get*`, `array.len`) pushes,
+/// the packed fields are extended to an `i32`
+pub fn field_type(op: &Operator, wasm: &Module) -> Result<DataType, AnalysisError> {
+    let ty = match op {
+        Operator::StructGetS { .. } | Operator::StructGetU { .. } |
+        Operator::ArrayGetS { .. } | Operator::ArrayGetU { .. } | Operator::ArrayLen => DataType::I32,
+        Operator::StructGet { struct_type_index, field_index } => match wasm.types.get(TypeID(*struct_type_index)) {
+            Some(Types::StructType { fields, .. }) if (*field_index as usize) < fields.len() => fields[*field_index as usize],
+            _ => fail!(AnalysisError::MissingGcType { tid: *struct_type_index })
+        },
+        Operator::ArrayGet { array_type_index } => match wasm.types.get(TypeID(*array_type_index)) {
+            Some(Types::ArrayType { fields, .. }) => *fields,
+            _ => fail!(AnalysisError::MissingGcType { tid: *array_type_index })
+        },
+        op => fail!(AnalysisError::UnsupportedOp { op: format!("{op:?}") })
+    };
+    if let DataType::Module { .. } | DataType::RecGroup(_) | DataType::CoreTypeId(_) = ty {
+        // the generated module doesn't have the types of the original module to take the reference as a param
+        fail!(AnalysisError::UnsupportedOp { op: format!("{op:?} (of a reference to a defined type)") });
+    }
+    Ok(ty)
+}
+
 pub fn is_branching_op(op: &Operator) -> bool {
     matches!(op, Operator::Br {..} | Operator::BrIf{..} | Operator::BrTable{..} |
                  Operator::BrOnCast {..} | Operator::BrOnCastFail {..} |  Operator::BrOnNonNull {..} |
                  Operator::BrOnNull {..})
 }
 
+/// The tail calls (`return_call*`): they end the frame like a `return`, the callee's results are
+/// returned to the caller directly
+pub fn is_tail_call(op: &Operator) -> bool {
+    matches!(op, Operator::ReturnCall {..} | Operator::ReturnCallIndirect {..} | Operator::ReturnCallRef {..})
+}
+
 // Determine pops/pushes for instruction
 // returns (pops, pushes)
-pub fn stack_effects(op: &Operator, wasm: &Module) -> (usize, usize) {
-    return match op {
+// - `fid`: the function containing the instruction (for the results popped by `return`)
+pub fn stack_effects(op: &Operator, fid: FunctionID, wasm: &Module) -> Result<(usize, usize), AnalysisError> {
+    let Some(effects) = try_stack_effects(op, fid, wasm)? else {
+        fail!(AnalysisError::UnsupportedOp { op: format!("{op:?}") });
+    };
+    Ok(effects)
+}
+
+/// Same as `stack_effects`, but None if the analysis doesn't model the op.
+pub fn try_stack_effects(op: &Operator, fid: FunctionID, wasm: &Module) -> Result<Option<(usize, usize)>, AnalysisError> {
+    return Ok(Some(match op {
         Operator::If { blockty, .. } => {
             // NOTE: it doesn't actually add anything to the stack. It can just
             // pop values and return what's already on the stack...
-            block_effects(1, blockty, wasm)
+            block_effects(1, blockty, wasm)?
         },
         Operator::Block {blockty, ..} => {
             // NOTE: it doesn't actually add anything to the stack. It can just
             // pop values and return what's already on the stack...
-            block_effects(0, blockty, wasm)
+            block_effects(0, blockty, wasm)?
         },
+        Operator::TryTable { try_table } => block_effects(0, &try_table.ty, wasm)?,
+        Operator::ThrowRef => (1, 0),
         Operator::BrIf { .. } |
-        Operator::BrTable { .. } => (1, 0),
-        Operator::Call { function_index } |
-        Operator::ReturnCall { function_index } => {
+        Operator::BrTable { .. } |
+        Operator::BrOnNonNull { .. } => (1, 0),
+        // the reference is left on the stack when the branch isn't taken
+        Operator::BrOnNull { .. } |
+        Operator::BrOnCast { .. } |
+        Operator::BrOnCastFail { .. } => (1, 1),
+        Operator::Call { function_index } => {
             let tid = wasm.functions.get(FunctionID(*function_index)).get_type_id();
-            ty_effects(0, *tid, wasm)
+            ty_effects(0, *tid, wasm)?
         }
         Operator::CallIndirect { type_index, .. } |
+        Operator::CallRef { type_index } => ty_effects(1, *type_index, wasm)?,
+        // pops the arguments (and the table index or reference), the stack is polymorphic afterwards
+        Operator::ReturnCall { function_index } => {
+            let tid = wasm.functions.get(FunctionID(*function_index)).get_type_id();
+            (ty_effects(0, *tid, wasm)?.0, 0)
+        }
         Operator::ReturnCallIndirect { type_index, .. } |
-        Operator::CallRef { type_index } |
-        Operator::ReturnCallRef { type_index } => ty_effects(1, *type_index, wasm),
+        Operator::ReturnCallRef { type_index } => (ty_effects(1, *type_index, wasm)?.0, 0),
         Operator::LocalGet { .. } => (0, 1),
         Operator::LocalSet { .. } => (1, 0),
         Operator::LocalTee { .. } => (1, 1),
         Operator::GlobalGet { .. } => (0, 1),
         Operator::GlobalSet { .. } => (1, 0),
+        Operator::RefFunc { .. } | Operator::RefNull { .. } => (0, 1),
+        Operator::RefIsNull | Operator::RefAsNonNull => (1, 1),
+        Operator::RefEq => (2, 1),
         Operator::I32Const { .. } | Operator::I64Const { .. } | Operator::F32Const { .. } | Operator::F64Const { .. } => (0,1),
         Operator::I32Load { .. } | Operator::I64Load { .. } | Operator::F32Load { .. } | Operator::F64Load { .. } => (1,1),
         Operator::I32Store { .. } | Operator::I64Store { .. } | Operator::F32Store { .. } | Operator::F64Store { .. } => (2,0),
@@ -87,15 +274,20 @@ pub fn stack_effects(op: &Operator, wasm: &Module) -> (usize, usize) {
         Operator::Loop { blockty } => {
             match blockty {
                 BlockType::Empty => (0,0),
-                BlockType::FuncType(type_index) => ty_effects(0, *type_index, wasm),
+                BlockType::FuncType(type_index) => ty_effects(0, *type_index, wasm)?,
                 BlockType::Type(_) => (0, 1)
             }
         },
         Operator::Else => (0,0),
         Operator::End => (0,0),
         Operator::Br { .. } => (0,0),
-        Operator::Return => unreachable!(),
-        Operator::Select => (3, 1),
+        Operator::Return => {
+            // pops the current function's results, the stack is polymorphic afterwards
+            let tid = wasm.functions.get(fid).get_type_id();
+            let (_, results) = ty_effects(0, *tid, wasm)?;
+            (results, 0)
+        }
+        Operator::Select | Operator::TypedSelect { .. } => (3, 1),
         Operator::I32Load8S { .. } |
         Operator::I32Load8U { .. } |
         Operator::I32Load16S { .. } |
@@ -110,7 +302,7 @@ pub fn stack_effects(op: &Operator, wasm: &Module) -> (usize, usize) {
         Operator::I32Store16 { .. } |
         Operator::I64Store8 { .. } |
         Operator::I64Store16 { .. } |
-        Operator::I64Store32 { .. } => (1, 0),
+        Operator::I64Store32 { .. } => (2, 0),
         Operator::MemorySize { .. } => (0, 1),
         Operator::MemoryGrow { .. } => (1, 1),
         Operator::I32Eq |
@@ -253,24 +445,56 @@ pub fn stack_effects(op: &Operator, wasm: &Module) -> (usize, usize) {
         Operator::MemoryCopy { .. } |
         Operator::MemoryFill { .. } |
         Operator::TableInit { .. } |
-        Operator::TableCopy { .. } => (3, 1),
+        Operator::TableCopy { .. } |
+        Operator::TableFill { .. } => (3, 0),
+        Operator::TableGet { .. } => (1, 1),
+        Operator::TableSet { .. } => (2, 0),
+        Operator::TableSize { .. } => (0, 1),
+        Operator::TableGrow { .. } => (2, 1),
         Operator::DataDrop { .. } |
         Operator::ElemDrop { .. } => (0, 0),
-        _ => todo!("op not supported: {op:?}")
-    };
+        Operator::StructNew { struct_type_index } => match wasm.types.get(TypeID(*struct_type_index)) {
+            Some(Types::StructType { fields, .. }) => (fields.len(), 1),
+            _ => fail!(AnalysisError::MissingGcType { tid: *struct_type_index })
+        },
+        Operator::StructNewDefault { .. } => (0, 1),
+        Operator::StructGet { .. } |
+        Operator::StructGetS { .. } |
+        Operator::StructGetU { .. } => (1, 1),
+        Operator::StructSet { .. } => (2, 0),
+        Operator::ArrayNewFixed { array_size, .. } => (*array_size as usize, 1),
+        Operator::ArrayNewDefault { .. } |
+        Operator::ArrayLen => (1, 1),
+        Operator::ArrayNew { .. } |
+        Operator::ArrayNewData { .. } |
+        Operator::ArrayNewElem { .. } |
+        Operator::ArrayGet { .. } |
+        Operator::ArrayGetS { .. } |
+        Operator::ArrayGetU { .. } => (2, 1),
+        Operator::ArraySet { .. } => (3, 0),
+        Operator::ArrayFill { .. } |
+        Operator::ArrayInitData { .. } |
+        Operator::ArrayInitElem { .. } => (4, 0),
+        Operator::ArrayCopy { .. } => (5, 0),
+        Operator::RefCastNonNull { .. } |
+        Operator::RefCastNullable { .. } |
+        Operator::RefTestNonNull { .. } |
+        Operator::RefTestNullable { .. } => (1, 1),
+        _ => return Ok(None)
+    }));
 
-    fn block_effects(extra_pop: usize, blockty: &BlockType, wasm: &Module) -> (usize, usize) {
-        match blockty {
+    fn block_effects(extra_pop: usize, blockty: &BlockType, wasm: &Module) -> Result<(usize, usize), AnalysisError> {
+        Ok(match blockty {
             BlockType::Empty => (extra_pop, 0),
             BlockType::Type(_) => (extra_pop, 1),
-            BlockType::FuncType(tid) => ty_effects(extra_pop, *tid, wasm),
-        }
+            BlockType::FuncType(tid) => ty_effects(extra_pop, *tid, wasm)?,
+        })
     }
-    fn ty_effects(extra_pop: usize, tid: u32, wasm: &Module) -> (usize, usize) {
+    fn ty_effects(extra_pop: usize, tid: u32, wasm: &Module) -> Result<(usize, usize), AnalysisError> {
         if let Some(Types::FuncType { params , results, ..}) = wasm.types.get(TypeID(tid)) {
-            (params.len() + extra_pop, results.len())
+            Ok((params.len() + extra_pop, results.len()))
         } else {
-            panic!("Should have found a function type!");
+            fail!(AnalysisError::MissingFuncType { tid });
         }
     }
 }
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use wirm::Module;
+use wirm::wasmparser::Operator;
+use crate::analyze::FuncState;
+use crate::error::AnalysisError;
+use crate::utils::{is_branching_op, is_tail_call, local_ops, shim_name, SKIP_SHIMS};
+
+/// A fuel point whose charge is not dominated by the block it charges.
+#[derive(Debug)]
+pub struct FuelPointViolation {
+    pub fid: u32,
+    /// The instr_idx where the charge is emitted
+    pub fuel_point: usize,
+    /// The instr_idx that breaks the invariant
+    pub instr_idx: usize,
+    pub kind: ViolationKind
+}
+#[derive(Debug)]
+pub enum ViolationKind {
+    /// The charge accumulates the cost of instructions on both sides of a
+    /// control-flow op that is not emitted in the generated function.
+    Straddle,
+    /// The instruction's cost is accumulated into more than one charge.
+    DoubleCharge { other_fuel_point: usize },
+    /// The instruction's cost isn't accumulated into any charge (the `fuel_point` is the
+    /// instruction itself).
+    Uncharged
+}
+impl Display for FuelPointViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ViolationKind::Straddle => write!(f, "function #{}: charge @{} straddles the control flow at @{}", self.fid, self.fuel_point, self.instr_idx),
+            ViolationKind::DoubleCharge { other_fuel_point } => write!(f, "function #{}: @{} is charged by both @{} and @{}", self.fid, self.instr_idx, other_fuel_point, self.fuel_point),
+            ViolationKind::Uncharged => write!(f, "function #{}: @{} isn't charged by any fuel point", self.fid, self.instr_idx)
+        }
+    }
+}
+
+/// Checks that every charge in the `charge_maps` only covers a straight-line run of the
+/// original function (no charge can be skipped or double-counted via branches).
+/// This is the core soundness invariant of where `calc_op_cost` places fuel points.
+/// The charges of a function's slices (its body and each loop's body) must also partition the
+/// body: every instruction is charged exactly once.
+pub fn verify_fuel_points(funcs: &[FuncState], charge_maps: &[BTreeMap<usize, Vec<(usize, u64)>>], wasm: &Module) -> Result<Vec<FuelPointViolation>, AnalysisError> {
+    let mut violations = Vec::new();
+    for (func, charge_map) in funcs.iter().zip(charge_maps.iter()) {
+        if func.fallback.is_some() {
+            // charges the whole body at once on purpose (ignores control flow)
+            continue;
+        }
+        if SKIP_SHIMS && shim_name(func.fid, wasm).is_some() {
+            // isn't charged at all on purpose (see `SliceResult::shim`)
+            continue;
+        }
+        let body = local_ops(func.fid, wasm)?;
+
+        // instr_idx -> the fuel point that charged it
+        let mut charged_by: BTreeMap<usize, usize> = BTreeMap::new();
+        for (fuel_point, charged) in charge_map.iter() {
+            for (instr_idx, _) in charged.iter() {
+                if let Some(other) = charged_by.insert(*instr_idx, *fuel_point) {
+                    violations.push(FuelPointViolation {
+                        fid: func.fid,
+                        fuel_point: *fuel_point,
+                        instr_idx: *instr_idx,
+                        kind: ViolationKind::DoubleCharge { other_fuel_point: other }
+                    });
+                }
+                // The fuel point itself is charged before it executes, any other control flow
+                // means that the instructions after it may not run.
+                if *instr_idx != *fuel_point && changes_control(&body[*instr_idx]) {
+                    violations.push(FuelPointViolation {
+                        fid: func.fid,
+                        fuel_point: *fuel_point,
+                        instr_idx: *instr_idx,
+                        kind: ViolationKind::Straddle
+                    });
+                }
+            }
+        }
+        for instr_idx in (0..body.len()).filter(|instr_idx| !charged_by.contains_key(instr_idx)) {
+            violations.push(FuelPointViolation {
+                fid: func.fid,
+                fuel_point: instr_idx,
+                instr_idx,
+                kind: ViolationKind::Uncharged
+            });
+        }
+    }
+    Ok(violations)
+}
+
+fn changes_control(op: &Operator) -> bool {
+    is_branching_op(op) || is_tail_call(op) || matches!(op, Operator::Return | Operator::Throw {..} | Operator::ThrowRef | Operator::Else)
+}
@@ -0,0 +1,113 @@
+//! `codegen --watch`: re-runs the pipeline on a module every time that it (or e.g. the directory
+//! it's built into) changes, and reports what changed compared to the previous run rather than
+//! the whole report, to follow the fuel accounting while optimizing the module.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use anyhow::Context;
+use crate::baseline::{compare, FuncDelta};
+use crate::color::NoColor;
+use crate::run::{gen_fuel_modules, AnalysisConfig};
+use crate::sidecar::JsonReport;
+
+/// How often `run::watch_analysis` checks the watched path for changes
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The path, size and modification time of each file under the watched path (sorted by path)
+type Stamp = Vec<(PathBuf, u64, Option<SystemTime>)>;
+
+fn stamp(path: &Path) -> io::Result<Stamp> {
+    let mut stamp = vec![];
+    let metadata = std::fs::metadata(path)?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            stamp.extend(stamp_of(&entry?.path())?);
+        }
+        stamp.sort();
+    } else {
+        stamp.push((path.to_path_buf(), metadata.len(), metadata.modified().ok()));
+    }
+    Ok(stamp)
+}
+
+/// `stamp`, but a file that's removed in the meantime is skipped
+fn stamp_of(path: &Path) -> io::Result<Stamp> {
+    match stamp(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        res => res
+    }
+}
+
+/// The outcome of a run of the pipeline by a `Watcher`
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// The module was analyzed (and the generated modules written): the functions whose fuel accounting
+    /// changed since the previous run (see `baseline::compare`), empty on the first run
+    Analyzed {
+        first: bool,
+        deltas: Vec<FuncDelta>,
+        num_funcs: usize
+    },
+    /// The module couldn't be read or analyzed (e.g. it was half-written), the next change is waited for
+    Failed(anyhow::Error)
+}
+
+/// Re-runs the pipeline on the module at `wasm_path` when the `watched` path changes, see `poll`
+#[derive(Debug)]
+pub struct Watcher<'a> {
+    wasm_path: PathBuf,
+    watched: PathBuf,
+    out_max_path: String,
+    out_min_path: String,
+    config: &'a AnalysisConfig,
+    /// Of the watched path when it was last polled
+    stamp: Option<Stamp>,
+    /// The module and the report of the last run that succeeded
+    last: Option<(Vec<u8>, JsonReport)>
+}
+impl<'a> Watcher<'a> {
+    /// `watched` is the module itself or e.g. the directory it's built into, the generated modules
+    /// are written to the output paths on every run
+    pub fn new(wasm_path: &Path, watched: &Path, out_max_path: &str, out_min_path: &str, config: &'a AnalysisConfig) -> Self {
+        Self {
+            wasm_path: wasm_path.to_path_buf(),
+            watched: watched.to_path_buf(),
+            out_max_path: out_max_path.to_string(),
+            out_min_path: out_min_path.to_string(),
+            config,
+            stamp: None,
+            last: None
+        }
+    }
+
+    /// Check the watched path once: if it changed since the last poll (or this is the first one), the
+    /// pipeline is run again, unless the module is the same as on the last run (e.g. another file of
+    /// the watched directory changed). None if nothing ran.
+    pub fn poll(&mut self) -> Option<WatchEvent> {
+        // the path may be missing while the module is rebuilt
+        let stamp = stamp(&self.watched).ok()?;
+        if self.stamp.as_ref() == Some(&stamp) {
+            return None;
+        }
+        self.stamp = Some(stamp);
+        let wasm_bytes = match std::fs::read(&self.wasm_path).with_context(|| format!("Failed to read the module {}", self.wasm_path.display())) {
+            Ok(wasm_bytes) => wasm_bytes,
+            Err(err) => return Some(WatchEvent::Failed(err))
+        };
+        if self.last.as_ref().is_some_and(|(last_bytes, _)| *last_bytes == wasm_bytes) {
+            return None;
+        }
+        let report = match gen_fuel_modules(NoColor::new(io::sink()), &wasm_bytes, &self.out_max_path, &self.out_min_path, self.config) {
+            Ok(report) => report,
+            Err(err) => return Some(WatchEvent::Failed(err))
+        };
+        let (first, deltas) = match &self.last {
+            Some((_, last_report)) => (false, compare(last_report, &report)),
+            None => (true, vec![])
+        };
+        let num_funcs = report.functions.len();
+        self.last = Some((wasm_bytes, report));
+        Some(WatchEvent::Analyzed { first, deltas, num_funcs })
+    }
+}
@@ -0,0 +1,21 @@
+(module
+  ;; LLVM lowers an early exit with a value (e.g. `x != 0 ? x + 1 : y * 2`) to a `br_if` that carries
+  ;; the value out of the block, it stays on the stack (and is dropped) when the branch isn't taken
+  (func $early_exit (export "early_exit") (param i32 i32) (result i32)
+    block (result i32)  ;; label = @1
+      local.get 0
+      i32.const 1
+      i32.add
+      local.get 0
+      br_if 0 (;@1;)
+      drop
+      local.get 1
+      i32.const 2
+      i32.mul
+    end
+    if  ;; label = @1
+      nop
+    end
+    i32.const 0
+  )
+)
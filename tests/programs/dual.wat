@@ -0,0 +1,32 @@
+(module
+  (func $if_stmt (export "if_stmt") (param i32) (result i32)
+    local.get 0
+    i32.const 1
+    i32.eq
+    if
+      i32.const 1
+      return
+    else
+      i32.const 0
+      return
+    end
+    i32.const 0
+  )
+  (func $br_if (export "br_if") (param i32) (result i32)
+    (block $1
+        local.get 0
+        br_if $1
+        i32.const 3
+        return
+    )
+    i32.const 4
+  )
+  (table $t 10 funcref)
+  (func $table_fill (export "table_fill") (param i32 funcref) (result i32)
+    i32.const 0
+    local.get 1
+    local.get 0
+    table.fill $t
+    i32.const 0
+  )
+)
@@ -0,0 +1,11 @@
+(module
+  (type (;0;) (func))
+  (type (;1;) (func (param i32 i64)))
+  (func (;0;) (type 0))
+  (func (;1;) (type 1)
+    (local i32)
+  )
+  (func (;2;) (type 0)
+    nop
+  )
+)
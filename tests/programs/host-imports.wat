@@ -0,0 +1,13 @@
+(module
+  (import "env" "mem" (memory 1))
+  (import "env" "limit" (global $limit i32))
+  (func (export "check") (param i32) (result i32)
+    local.get 0
+    global.get $limit
+    i32.lt_s
+    if (result i32)
+      i32.const 0
+      i32.load
+    else
+      i32.const 1
+    end))
@@ -0,0 +1,29 @@
+(module
+  ;; only the else arm contributes to the slice
+  (func $else_only (export "else_only") (param i32 i32) (result i32)
+    local.get 0
+    if
+      nop
+    else
+      (block $b
+        local.get 1
+        br_if $b
+        nop
+      )
+    end
+    i32.const 0
+  )
+  ;; the else arm's own condition is nested under the outer `if`
+  (func $nested_else (export "nested_else") (param i32 i32) (result i32)
+    local.get 0
+    if
+    else
+      local.get 1
+      if
+        i32.const 7
+        return
+      end
+    end
+    i32.const 0
+  )
+)
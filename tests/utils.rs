@@ -5,15 +5,21 @@ use std::io::Write;
 use termcolor::{ColorSpec, WriteColor};
 use wasi_common::sync::{add_to_linker, WasiCtxBuilder};
 use wasi_common::WasiCtx;
-use wasmtime::{Engine, ExternType, FuncType, Instance, Linker, Module, Store, Val, ValType, V128};
-use whamm_fuel::run::{do_analysis, CompType};
+use std::collections::HashSet;
+use wasmtime::{Config, Engine, ExternType, FuncType, Global, GlobalType, Instance, Linker, Memory, MemoryType, Module, Mutability, Store, Val, ValType, V128};
+#[cfg(feature = "fs")]
+use whamm_fuel::run::do_analysis;
+use whamm_fuel::run::{AnalysisConfig, CompType, Verbosity};
 use whamm_fuel::run::CompType::{Approx, Exact};
+use whamm_fuel::{CodeGenResult, FuncState, SliceResult, Variant};
 
 const BASE_IN: &str = "tests/programs/";
 const BASE_OUT: &str = "output/tests/";
 const BASE_EXP: &str = "tests/programs/exp_out";
+const BASE_PROP_OUT: &str = "output/tests/prop/";
+const BASE_DUAL_OUT: &str = "output/tests/dual/";
 
-type FID = u32;
+type Fid = u32;
 enum SliceType {
     Max,
     Min
@@ -30,7 +36,8 @@ impl Display for SliceType {
 #[derive(Default)]
 pub(crate) struct Test {
     name: &'static str,
-    expected: HashMap<FID, TestCase>
+    expected: HashMap<Fid, TestCase>,
+    imports: HostImports
 }
 impl Test {
     pub(crate) fn new(name: &'static str
@@ -40,7 +47,7 @@ impl Test {
             ..Default::default()
         }
     }
-    pub(crate) fn add_base_case(&mut self, fid: FID, base_max: Exp, base_min: Exp) {
+    pub(crate) fn add_base_case(&mut self, fid: Fid, base_max: Exp, base_min: Exp) {
         self.expected.insert(fid, TestCase::new(Expected::new(
             base_max,
             HashMap::default()
@@ -49,7 +56,13 @@ impl Test {
             HashMap::default()
         )));
     }
-    pub(crate) fn add_case_with_loops(&mut self, fid: FID, base_max: Exp, loops_max: Vec<(LoopIdx, Exp)>,
+    pub(crate) fn add_memory_import(&mut self, module: &str, name: &str, min_pages: u32) {
+        self.imports.add_memory(module, name, min_pages);
+    }
+    pub(crate) fn add_global_import(&mut self, module: &str, name: &str, val: Val, mutable: bool) {
+        self.imports.add_global(module, name, val, mutable);
+    }
+    pub(crate) fn add_case_with_loops(&mut self, fid: Fid, base_max: Exp, loops_max: Vec<(LoopIdx, Exp)>,
                                       base_min: Exp, loops_min: Vec<(LoopIdx, Exp)>) {
         self.expected.insert(fid, TestCase::new(Expected::new(
             base_max,
@@ -61,28 +74,36 @@ impl Test {
     }
 }
 
+/// The memories and globals the host provides to the modules under test.
+/// Any memory/global import that isn't configured here is given a zeroed default.
+#[derive(Default)]
+pub(crate) struct HostImports {
+    /// (module, name) -> min pages
+    memories: HashMap<(String, String), u32>,
+    /// (module, name) -> (initial value, is mutable)
+    globals: HashMap<(String, String), (Val, bool)>
+}
+impl HostImports {
+    pub(crate) fn add_memory(&mut self, module: &str, name: &str, min_pages: u32) {
+        self.memories.insert((module.to_string(), name.to_string()), min_pages);
+    }
+    pub(crate) fn add_global(&mut self, module: &str, name: &str, val: Val, mutable: bool) {
+        self.globals.insert((module.to_string(), name.to_string()), (val, mutable));
+    }
+}
+
 type LoopIdx = usize;
 type Cost = i64;
 pub struct Exp {
     exact_on_true: Cost,
-    exact_on_false: Cost,
-    approx_on_true: Cost,
-    approx_on_false: Cost
+    exact_on_false: Cost
 }
 impl Exp {
     pub fn new_exact(
         exact_on_true: Cost,
         exact_on_false: Cost
     ) -> Self {
-        Self { exact_on_true, exact_on_false, approx_on_true: -1, approx_on_false: -1 }
-    }
-    pub fn new(
-        exact_on_true: Cost,
-        exact_on_false: Cost,
-        approx_on_true: Cost,
-        approx_on_false: Cost
-    ) -> Self {
-        Self { exact_on_true, exact_on_false, approx_on_true, approx_on_false }
+        Self { exact_on_true, exact_on_false }
     }
 }
 
@@ -109,12 +130,14 @@ impl TestCase {
     // }
 }
 
+#[cfg(feature = "fs")]
 pub fn run_test(test_case: Test) {
     if let Err(e) = run_test_internal(&test_case) {
         panic!("Failed to run test `{}`\nError: {}", test_case.name, e);
     }
 }
 
+#[cfg(feature = "fs")]
 fn run_test_internal(test: &Test) -> anyhow::Result<()> {
     let in_path = format!("{BASE_IN}{}.wasm", test.name);
     let out_max_path = format!("{BASE_OUT}{}-max.wasm", test.name);
@@ -122,8 +145,9 @@ fn run_test_internal(test: &Test) -> anyhow::Result<()> {
     let exp_path = format!("{BASE_EXP}/{}.wasm.out", test.name);
     let bytes = fs::read(in_path)?;
 
+    // the expected output is the listing of the slices
     let mut buf = TestBuffer { buf: Vec::new() };
-    do_analysis(&mut buf, &bytes, &out_max_path, &out_min_path)?;
+    do_analysis(&mut buf, &bytes, &out_max_path, &out_min_path, &AnalysisConfig::default().verbosity(Verbosity::Listing))?;
 
     // 0. Check the expected output information.
     println!("[test] Is output as expected?");
@@ -142,11 +166,17 @@ fn run_test_internal(test: &Test) -> anyhow::Result<()> {
     run_wasm(SliceType::Max, test, &engine, wasm_max)?;
     run_wasm(SliceType::Min, test, &engine, wasm_min)?;
 
+    // 3. Can the generated modules be wired together with the original program?
+    println!("[test] Does it link with the original program?");
+    let wasm_orig = test_validity(&engine, &format!("{BASE_IN}{}.wasm", test.name))?;
+    instantiate_with_original(&engine, &wasm_orig, &test_validity(&engine, &out_max_path)?, &test.imports)?;
+    instantiate_with_original(&engine, &wasm_orig, &test_validity(&engine, &out_min_path)?, &test.imports)?;
+
     Ok(())
 }
 
 fn test_validity(engine: &Engine, path: &str) -> anyhow::Result<Module> {
-    Ok(Module::from_file(engine, path)?)
+    Module::from_file(engine, path)
 }
 
 fn run_wasm(slice_ty: SliceType, test: &Test, engine: &Engine, wasm: Module) -> anyhow::Result<()> {
@@ -154,7 +184,7 @@ fn run_wasm(slice_ty: SliceType, test: &Test, engine: &Engine, wasm: Module) ->
     for export in wasm.exports() {
         if let ExternType::Func(func_ty) = export.ty() {
             let name = export.name();
-            if let Some((ty, fid, loop_idx)) = get_func_metadata(name) {
+            if let Some((_, fid, loop_idx)) = get_func_metadata(name) {
                 let test_case = test.expected.get(&fid).unwrap();
                 let Exp { exact_on_true: base_true, exact_on_false: base_false, .. } = if let Some(loop_idx) = loop_idx {
                     checked_loops_per_func.entry(fid).and_modify(|loops| {
@@ -171,8 +201,8 @@ fn run_wasm(slice_ty: SliceType, test: &Test, engine: &Engine, wasm: Module) ->
                         SliceType::Min => &test_case.for_min.base
                     }
                 };
-                test_run(name, &format!("{slice_ty}-on_true"), *base_true, gen_true, &func_ty, &engine, &wasm)?;
-                test_run(name, &format!("{slice_ty}-on_false"), *base_false, gen_false, &func_ty, &engine, &wasm)?;
+                test_run(name, &format!("{slice_ty}-on_true"), *base_true, gen_true, &func_ty, engine, &wasm, &test.imports)?;
+                test_run(name, &format!("{slice_ty}-on_false"), *base_false, gen_false, &func_ty, engine, &wasm, &test.imports)?;
             }
         }
     }
@@ -181,18 +211,26 @@ fn run_wasm(slice_ty: SliceType, test: &Test, engine: &Engine, wasm: Module) ->
     for (fid, case) in test.expected.iter() {
         let exp_count_max = case.for_max.loops.len();
         let exp_count_min = case.for_min.loops.len();
-        assert_eq!(exp_count_max, exp_count_max);
+        assert_eq!(exp_count_max, exp_count_min);
         if exp_count_max > 0 {
-            assert_eq!(exp_count_max, *checked_loops_per_func.get(&fid).unwrap());
+            assert_eq!(exp_count_max, checked_loops_per_func[fid]);
         }
     }
     Ok(())
 }
 
-fn test_run(func_name: &str, case_name: &str, exp_fuel: i64, gen_val: fn(ValType) -> Val, func_ty: &FuncType, engine: &Engine, wasm: &Module) -> anyhow::Result<()> {
+#[allow(clippy::too_many_arguments)]
+fn test_run(func_name: &str, case_name: &str, exp_fuel: i64, gen_val: fn(ValType) -> Val, func_ty: &FuncType, engine: &Engine, wasm: &Module, imports: &HostImports) -> anyhow::Result<()> {
     // Run each of the exported functions with some input to them (just generate values)
     // Is the output what I expect for each of these values?
-    let (instance, mut store) = instantiate(engine, wasm)?;
+    let (args, actual_fuel) = call_fuel(func_name, gen_val, func_ty, engine, wasm, imports)?;
+    assert_eq!(exp_fuel, actual_fuel, "[{func_name}::{case_name}] fuel was not calculated correctly!\n\tRan with: {:?}", args);
+
+    Ok(())
+}
+
+fn call_fuel(func_name: &str, gen_val: fn(ValType) -> Val, func_ty: &FuncType, engine: &Engine, wasm: &Module, imports: &HostImports) -> anyhow::Result<(Vec<Val>, i64)> {
+    let (instance, mut store) = instantiate(engine, wasm, imports)?;
 
     let mut args = Vec::new();
     let mut results = vec![Val::I64(0)];
@@ -201,16 +239,14 @@ fn test_run(func_name: &str, case_name: &str, exp_fuel: i64, gen_val: fn(ValType
         for dt in func_ty.params() {
             args.push(gen_val(dt));
         }
-        func.call(&mut store, &mut args, &mut results)?;
+        func.call(&mut store, &args, &mut results)?;
     }
 
     // to check the fuel amount:
-    let Some(Val::I64(actual_fuel)) = results.get(0) else {
+    let Some(Val::I64(actual_fuel)) = results.first() else {
         Err(anyhow::anyhow!("expected fuel to be an i64"))?
     };
-    assert_eq!(exp_fuel, *actual_fuel, "[{func_name}::{case_name}] fuel was not calculated correctly!\n\tRan with: {:?}", args);
-
-    Ok(())
+    Ok((args, *actual_fuel))
 }
 
 fn gen_true(ty: ValType) -> Val {
@@ -231,7 +267,37 @@ fn gen_val(literal: i32, ty: ValType) -> Val {
     }
 }
 
-fn instantiate(engine: &Engine, wasm: &Module) -> anyhow::Result<(Instance, Store<WasiCtx>)> {
+/// The module name the original program's exports are registered under when it is
+/// instantiated alongside a generated module.
+pub(crate) const ORIG_MODULE: &str = "orig";
+
+fn instantiate(engine: &Engine, wasm: &Module, imports: &HostImports) -> anyhow::Result<(Instance, Store<WasiCtx>)> {
+    let (mut linker, mut store) = new_linker(engine)?;
+    define_imports(&mut linker, &mut store, wasm, imports, &mut HashSet::new())?;
+
+    // Instantiate the module with the linker (this links in WASI)
+    let instance = linker.instantiate(&mut store, wasm)?;
+
+    Ok((instance, store))
+}
+
+/// Instantiate the original program and a generated module in the same store.
+/// The original program's exports are made available to the generated module under
+/// the `ORIG_MODULE` namespace and any memory/global imported by both modules is shared.
+pub(crate) fn instantiate_with_original(engine: &Engine, orig: &Module, generated: &Module, imports: &HostImports) -> anyhow::Result<(Instance, Instance, Store<WasiCtx>)> {
+    let (mut linker, mut store) = new_linker(engine)?;
+    let mut defined = HashSet::new();
+    define_imports(&mut linker, &mut store, orig, imports, &mut defined)?;
+    let orig_instance = linker.instantiate(&mut store, orig)?;
+    linker.instance(&mut store, ORIG_MODULE, orig_instance)?;
+
+    define_imports(&mut linker, &mut store, generated, imports, &mut defined)?;
+    let gen_instance = linker.instantiate(&mut store, generated)?;
+
+    Ok((orig_instance, gen_instance, store))
+}
+
+fn new_linker(engine: &Engine) -> anyhow::Result<(Linker<WasiCtx>, Store<WasiCtx>)> {
     // Provide WASI imports/store (if there are any); all instances in the store
     // share this context. `WasiCtxBuilder` provides a number of ways to
     // configure what the target program will have access to.
@@ -241,16 +307,41 @@ fn instantiate(engine: &Engine, wasm: &Module) -> anyhow::Result<(Instance, Stor
         .inherit_env()?
         .build();
 
-    let mut store = Store::new(engine, wasi);
+    let store = Store::new(engine, wasi);
 
     // Set up a linker that knows about WASI
     let mut linker = Linker::new(engine);
     add_to_linker(&mut linker, |ctx: &mut WasiCtx| ctx)?;
 
-    // Instantiate the module with the linker (this links in WASI)
-    let instance = linker.instantiate(&mut store, wasm)?;
+    Ok((linker, store))
+}
 
-    Ok((instance, store))
+/// Define the memories and globals imported by `wasm` (that haven't already been `defined`).
+fn define_imports(linker: &mut Linker<WasiCtx>, store: &mut Store<WasiCtx>, wasm: &Module, imports: &HostImports, defined: &mut HashSet<(String, String)>) -> anyhow::Result<()> {
+    for import in wasm.imports() {
+        let key = (import.module().to_string(), import.name().to_string());
+        if defined.contains(&key) {
+            continue;
+        }
+        match import.ty() {
+            ExternType::Memory(ty) => {
+                let min_pages = imports.memories.get(&key).copied().unwrap_or(ty.minimum() as u32);
+                let memory = Memory::new(&mut *store, MemoryType::new(min_pages, ty.maximum().map(|max| max as u32)))?;
+                linker.define(&*store, &key.0, &key.1, memory)?;
+            }
+            ExternType::Global(ty) => {
+                let (val, mutable) = imports.globals.get(&key).cloned()
+                    .unwrap_or((gen_val(0, ty.content().clone()), ty.mutability() == Mutability::Var));
+                let mutability = if mutable { Mutability::Var } else { Mutability::Const };
+                let global = Global::new(&mut *store, GlobalType::new(ty.content().clone(), mutability), val)?;
+                linker.define(&*store, &key.0, &key.1, global)?;
+            }
+            // functions are provided by WASI (or the original program)
+            _ => continue
+        }
+        defined.insert(key);
+    }
+    Ok(())
 }
 
 fn get_func_metadata(s: &str) -> Option<(CompType, u32, Option<usize>)> {
@@ -267,7 +358,7 @@ fn get_func_metadata(s: &str) -> Option<(CompType, u32, Option<usize>)> {
     let parts: Vec<&str> = rest.split("_loop_at_").collect();
 
     // Parse the u32 immediately following the prefix
-    let number = parts.get(0)?.parse::<u32>().ok()?;
+    let number = parts.first()?.parse::<u32>().ok()?;
 
     // Parse the optional loop number
     let loop_num = if parts.len() > 1 {
@@ -279,6 +370,235 @@ fn get_func_metadata(s: &str) -> Option<(CompType, u32, Option<usize>)> {
     Some((ctype, number, loop_num))
 }
 
+// ===========================
+// = Dual-Module Test Runner =
+// ===========================
+
+/// Drives the original program alongside its generated slices: each case runs an export
+/// of the original program, harvests the state requested by the slice and checks the
+/// fuel computed by the slice against the instruction count of the original execution.
+pub(crate) struct DualTest {
+    name: &'static str,
+    cases: Vec<DualCase>
+}
+impl DualTest {
+    pub(crate) fn new(name: &'static str) -> Self {
+        Self { name, cases: vec![] }
+    }
+    pub(crate) fn add_case(&mut self, case: DualCase) {
+        self.cases.push(case);
+    }
+}
+pub(crate) struct DualCase {
+    /// The export of the original program to drive
+    pub(crate) export: &'static str,
+    /// The function ID of the export (to find its generated slices)
+    pub(crate) fid: Fid,
+    pub(crate) inputs: Vec<Val>,
+    /// Maps the inputs to the state requested by the max/min slice.
+    /// NOTE: This stands in for the instrumentation artifact until one is generated.
+    pub(crate) harvest_max: fn(&[Val]) -> Vec<Val>,
+    pub(crate) harvest_min: fn(&[Val]) -> Vec<Val>
+}
+
+/// The export of the counter that `count_instrs` adds
+const INSTR_COUNTER: &str = "instr_count";
+
+/// Instrument the original program to count the instructions that it executes: every instruction is
+/// preceded by an increment of the exported global `INSTR_COUNTER`, a branch to the end of a block
+/// skips its `end` like it skips the increment before it. A bulk op also counts its (32-bit) length,
+/// the default cost per element.
+fn count_instrs(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    use whamm_fuel::wirm::{self, DataType, Opcode};
+    use whamm_fuel::wirm::ir::id::FunctionID;
+    use whamm_fuel::wirm::ir::types::{InitExpr, InitInstr, Location, Value};
+    use whamm_fuel::wirm::module_builder::AddLocal;
+    use whamm_fuel::wirm::opcode::Instrumenter;
+    use whamm_fuel::wirm::wasmparser::Operator;
+
+    let mut wasm = wirm::Module::parse(bytes, false, true).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+    let counter = wasm.add_global(InitExpr::new(vec![InitInstr::Value(Value::I64(0))]), DataType::I64, true, false);
+    wasm.exports.add_export_global(INSTR_COUNTER.to_string(), *counter);
+    let fids: Vec<FunctionID> = (0..wasm.functions.iter().count() as u32).map(FunctionID).filter(|fid| wasm.functions.is_local(*fid)).collect();
+    for fid in fids {
+        let bulk_ops: Vec<bool> = wasm.functions.unwrap_local(fid).body.instructions.get_ops().iter()
+            .map(|op| matches!(op, Operator::TableGrow { .. } | Operator::TableFill { .. } | Operator::TableCopy { .. } | Operator::TableInit { .. }
+                | Operator::MemoryFill { .. } | Operator::MemoryCopy { .. } | Operator::MemoryInit { .. }))
+            .collect();
+        let mut func = wasm.functions.get_fn_modifier(fid).unwrap();
+        let len = func.add_local(DataType::I32);
+        for (instr_idx, is_bulk) in bulk_ops.into_iter().enumerate() {
+            func.before_at(Location::Module { func_idx: fid, instr_idx })
+                .global_get(counter)
+                .i64_const(1)
+                .i64_add()
+                .global_set(counter);
+            if is_bulk {
+                // the length is on top of the stack
+                func.local_tee(len)
+                    .i64_extend_i32u()
+                    .global_get(counter)
+                    .i64_add()
+                    .global_set(counter)
+                    .local_get(len);
+            }
+        }
+    }
+    Ok(wasm.encode())
+}
+
+#[cfg(feature = "fs")]
+pub fn run_dual_test(test: DualTest) {
+    if let Err(e) = run_dual_test_internal(&test) {
+        panic!("Failed to run dual test `{}`\nError: {}", test.name, e);
+    }
+}
+
+#[cfg(feature = "fs")]
+fn run_dual_test_internal(test: &DualTest) -> anyhow::Result<()> {
+    let bytes = wat::parse_str(read_wat(test.name)?)?;
+    let out_max_path = format!("{BASE_DUAL_OUT}{}-max.wasm", test.name);
+    let out_min_path = format!("{BASE_DUAL_OUT}{}-min.wasm", test.name);
+
+    let mut buf = TestBuffer { buf: Vec::new() };
+    do_analysis(&mut buf, &bytes, &out_max_path, &out_min_path, &AnalysisConfig::default())?;
+
+    let engine = Engine::default();
+    let orig = Module::new(&engine, &bytes)?;
+    let counting = Module::new(&engine, count_instrs(&bytes)?)?;
+    for (slice_ty, path) in [(SliceType::Max, &out_max_path), (SliceType::Min, &out_min_path)] {
+        let generated = test_validity(&engine, path)?;
+        for case in test.cases.iter() {
+            let (orig_instance, gen_instance, mut store) = instantiate_with_original(&engine, &orig, &generated, &HostImports::default())?;
+
+            // 1. Drive the original program
+            let Some(orig_func) = orig_instance.get_func(&mut store, case.export) else {
+                Err(anyhow::anyhow!("original program does not export `{}`", case.export))?
+            };
+            let mut orig_results: Vec<Val> = orig_func.ty(&store).results().map(|ty| gen_val(0, ty)).collect();
+            orig_func.call(&mut store, &case.inputs, &mut orig_results)?;
+
+            // the oracle: the instructions that the original program executes on the inputs
+            let (counting_instance, mut counting_store) = instantiate(&engine, &counting, &HostImports::default())?;
+            let Some(counting_func) = counting_instance.get_func(&mut counting_store, case.export) else {
+                Err(anyhow::anyhow!("original program does not export `{}`", case.export))?
+            };
+            counting_func.call(&mut counting_store, &case.inputs, &mut orig_results)?;
+            let Some(counter) = counting_instance.get_global(&mut counting_store, INSTR_COUNTER) else {
+                Err(anyhow::anyhow!("the counting program does not export `{INSTR_COUNTER}`"))?
+            };
+            let oracle = counter.get(&mut counting_store).unwrap_i64();
+
+            // 2. Harvest the state requested by the slice
+            let state = match slice_ty {
+                SliceType::Max => (case.harvest_max)(&case.inputs),
+                SliceType::Min => (case.harvest_min)(&case.inputs)
+            };
+
+            // 3. Invoke the slice and compare against the oracle
+            let slice_name = format!("{}{}", Exact, case.fid);
+            let Some(slice_func) = gen_instance.get_func(&mut store, &slice_name) else {
+                Err(anyhow::anyhow!("generated module does not export `{slice_name}`"))?
+            };
+            let mut results = vec![Val::I64(0)];
+            slice_func.call(&mut store, &state, &mut results)?;
+            let Some(Val::I64(actual_fuel)) = results.first() else {
+                Err(anyhow::anyhow!("expected fuel to be an i64"))?
+            };
+            assert_eq!(oracle, *actual_fuel, "[{}::{slice_ty}::{}] slice fuel does not match the original execution!\n\tRan with: {:?}", test.name, case.export, case.inputs);
+        }
+    }
+    Ok(())
+}
+
+// ===========================
+// = Property Test Utilities =
+// ===========================
+
+/// Identifies one generated function across analysis runs: (slice type, fid, loop ordinal).
+/// The loop ordinal is used rather than the loop's instr_idx since that shifts when
+/// instructions are inserted into the program.
+pub(crate) type GenFuncKey = (String, Fid, usize);
+
+/// Read the WAT version of a test program
+pub(crate) fn read_wat(name: &str) -> anyhow::Result<String> {
+    Ok(fs::read_to_string(format!("{BASE_IN}{name}.wat"))?)
+}
+
+/// The lines of a WAT program that a non-control instruction can be placed in front of
+/// without changing the program's semantics.
+pub(crate) fn insertion_points(wat: &str) -> Vec<usize> {
+    const NOT_INSTRS: [&str; 14] = ["(module", "(type", "(func", "(param", "(result", "(local ",
+        "(global ", "(memory", "(table", "(elem", "(data", "(import", "(export", "(start"];
+    wat.lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.trim_start();
+            let is_plain = line.starts_with(|c: char| c.is_ascii_lowercase());
+            let is_folded = line.starts_with('(') && !NOT_INSTRS.iter().any(|kw| line.starts_with(kw));
+            is_plain || is_folded
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Insert a `nop` in front of each of the requested lines (a line can be requested more than once).
+pub(crate) fn insert_nops(wat: &str, at: &[usize]) -> String {
+    let mut out = String::new();
+    for (i, line) in wat.lines().enumerate() {
+        for _ in at.iter().filter(|l| **l == i) {
+            out.push_str("nop\n");
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(feature = "fs")]
+/// Assemble the passed WAT, generate the slices, and compute the fuel reported by every
+/// generated function when run on the on_true and on_false inputs.
+pub(crate) fn compute_fuel(name: &str, wat: &str) -> anyhow::Result<HashMap<GenFuncKey, (Cost, Cost)>> {
+    let bytes = wat::parse_str(wat)?;
+    let out_max_path = format!("{BASE_PROP_OUT}{name}-max.wasm");
+    let out_min_path = format!("{BASE_PROP_OUT}{name}-min.wasm");
+
+    let mut buf = TestBuffer { buf: Vec::new() };
+    do_analysis(&mut buf, &bytes, &out_max_path, &out_min_path, &AnalysisConfig::default())?;
+
+    let engine = Engine::default();
+    let mut fuel = HashMap::new();
+    for (slice_ty, path) in [(SliceType::Max, out_max_path), (SliceType::Min, out_min_path)] {
+        let wasm = test_validity(&engine, &path)?;
+
+        let mut funcs = Vec::new();
+        for export in wasm.exports() {
+            if let ExternType::Func(func_ty) = export.ty() {
+                if let Some((_, fid, loop_idx)) = get_func_metadata(export.name()) {
+                    funcs.push((fid, loop_idx, export.name().to_string(), func_ty));
+                }
+            }
+        }
+        // so that loop ordinals are stable
+        funcs.sort_by_key(|(fid, loop_idx, ..)| (*fid, *loop_idx));
+
+        let mut loops_per_func: HashMap<Fid, usize> = HashMap::new();
+        for (fid, loop_idx, name, func_ty) in funcs.iter() {
+            let ordinal = if loop_idx.is_some() {
+                let count = loops_per_func.entry(*fid).or_default();
+                *count += 1;
+                *count
+            } else {
+                0
+            };
+            let (_, on_true) = call_fuel(name, gen_true, func_ty, &engine, &wasm, &HostImports::default())?;
+            let (_, on_false) = call_fuel(name, gen_false, func_ty, &engine, &wasm, &HostImports::default())?;
+            fuel.insert((slice_ty.to_string(), *fid, ordinal), (on_true, on_false));
+        }
+    }
+    Ok(fuel)
+}
+
 struct TestBuffer {
     buf: Vec<u8>,
 }
@@ -296,3 +616,81 @@ impl WriteColor for TestBuffer {
     fn set_color(&mut self, _spec: &ColorSpec) -> std::io::Result<()> { Ok(()) }
     fn reset(&mut self) -> std::io::Result<()> { Ok(()) }
 }
+
+/// Parse the module and run the steps of `run::run_analysis` up to the codegen: the analysis, then
+/// the slices with their block structure and their min slices
+pub fn pipeline(bytes: &[u8]) -> (whamm_fuel::wirm::Module<'_>, Vec<FuncState>, Vec<SliceResult>) {
+    let mut wasm = whamm_fuel::wirm::Module::parse(bytes, false, true).unwrap();
+    let funcs = whamm_fuel::analyze(&mut wasm).unwrap();
+    let mut slices = whamm_fuel::slice_program(&funcs, &[], &wasm).unwrap();
+    whamm_fuel::save_structure(&mut slices, &funcs, &wasm).unwrap();
+    whamm_fuel::reduce_slice(&mut slices, &funcs, &wasm).unwrap();
+    (wasm, funcs, slices)
+}
+
+/// Generate the fuel functions of the `variant` of the `slices` (from `pipeline`), returns the
+/// encoded module with the result of the codegen
+pub fn generate(config: &AnalysisConfig, variant: Variant, wasm: &whamm_fuel::wirm::Module, funcs: &[FuncState], slices: &mut [SliceResult]) -> (Vec<u8>, CodeGenResult) {
+    let mut gen_wasm = whamm_fuel::wirm::Module::default();
+    let result = whamm_fuel::codegen(config, variant, slices, funcs, wasm, &mut gen_wasm).unwrap();
+    (gen_wasm.encode(), result)
+}
+
+/// Instantiate the generated module `wasm` (it imports nothing) and call its function `name` with
+/// the `args`, returns the fuel it computes (see `Instantiated`)
+pub fn fuel_of(wasm: &[u8], name: &str, args: &[Val]) -> i64 {
+    Instantiated::new(wasm).fuel(name, args)
+}
+
+/// An instance of a module that imports nothing (e.g. a generated module), to also read what the calls
+/// leave behind (e.g. the globals they write). The GC proposal is enabled for the references it may take.
+pub struct Instantiated {
+    pub store: Store<()>,
+    pub instance: Instance
+}
+impl Instantiated {
+    pub fn new(wasm: &[u8]) -> Self {
+        let engine = Engine::new(Config::new().wasm_gc(true).wasm_function_references(true)).unwrap();
+        let module = Module::new(&engine, wasm).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[]).unwrap();
+        Self { store, instance }
+    }
+
+    /// Call the function `name` with the `args`, returns its results (an error if it traps)
+    pub fn call(&mut self, name: &str, args: &[Val]) -> anyhow::Result<Vec<Val>> {
+        let func = self.instance.get_func(&mut self.store, name).unwrap();
+        let mut results = vec![Val::I32(0); func.ty(&self.store).results().len()];
+        func.call(&mut self.store, args, &mut results)?;
+        Ok(results)
+    }
+
+    /// Call the generated function `name` with the `args`, returns the fuel it computes
+    pub fn fuel(&mut self, name: &str, args: &[Val]) -> i64 {
+        self.call(name, args).unwrap()[0].unwrap_i64()
+    }
+
+    /// The value of the exported global `name`, None if it isn't exported
+    pub fn global(&mut self, name: &str) -> Option<Val> {
+        let global = self.instance.get_global(&mut self.store, name)?;
+        Some(global.get(&mut self.store))
+    }
+}
+
+/// The files of the (tar) repro bundle at `path`, by name (see `repro::write_repro_bundle`)
+#[cfg(feature = "no-panic")]
+pub fn read_repro_bundle(path: &std::path::Path) -> HashMap<String, Vec<u8>> {
+    const BLOCK: usize = 512;
+    let archive = fs::read(path).unwrap();
+    let mut files = HashMap::new();
+    let mut offset = 0;
+    // the archive ends with empty headers
+    while archive[offset] != 0 {
+        let header = &archive[offset..offset + BLOCK];
+        let name = String::from_utf8_lossy(&header[..100]).trim_end_matches('\0').to_string();
+        let size = usize::from_str_radix(std::str::from_utf8(&header[124..135]).unwrap(), 8).unwrap();
+        files.insert(name, archive[offset + BLOCK..offset + BLOCK + size].to_vec());
+        offset += BLOCK + size.div_ceil(BLOCK) * BLOCK;
+    }
+    files
+}